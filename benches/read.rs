@@ -8,6 +8,7 @@ use bencher::Bencher;
 use std::fs;
 use std::io::Cursor;
 use exr::image::pixel_vec::PixelVec;
+use exr::image::interleaved::InterleavedPixels;
 
 /// Read uncompressed (always single core)
 fn read_single_image_uncompressed_non_parallel_rgba(bench: &mut Bencher) {
@@ -158,15 +159,68 @@ fn read_single_image_zips_non_parallel_rgba(bench: &mut Bencher) {
     })
 }
 
+/// Read with the interleaved fast path, in parallel
+fn read_single_image_uncompressed_interleaved_rgba(bench: &mut Bencher) {
+    let mut file = fs::read("tests/images/valid/custom/crowskull/crow_uncompressed.exr").unwrap();
+
+    bench.iter(||{
+        bencher::black_box(&mut file);
+
+        let image = InterleavedPixels::<f32, 4>::read_from_buffered(
+            Cursor::new(file.as_slice()),
+            [ Text::from("R"), Text::from("G"), Text::from("B"), Text::from("A") ]
+        ).unwrap();
+
+        bencher::black_box(image);
+    })
+}
+
+/// Read with the interleaved fast path and multi-core RLE decompression
+fn read_single_image_rle_interleaved_rgba(bench: &mut Bencher) {
+    let mut file = fs::read("tests/images/valid/custom/crowskull/crow_rle.exr").unwrap();
+
+    bench.iter(||{
+        bencher::black_box(&mut file);
+
+        let image = InterleavedPixels::<f32, 4>::read_from_buffered(
+            Cursor::new(file.as_slice()),
+            [ Text::from("R"), Text::from("G"), Text::from("B"), Text::from("A") ]
+        ).unwrap();
+
+        bencher::black_box(image);
+    })
+}
+
+/// Read a tiny image consisting of a single chunk, like a light probe
+fn read_tiny_image_single_chunk_all_channels(bench: &mut Bencher) {
+    let mut file = Vec::new();
+    let layer = Layer::new((8, 8), LayerAttributes::default(), Encoding::SMALL_LOSSLESS, SpecificChannels::rgba(|_| (0.5_f32, 0.5_f32, 0.5_f32, 1.0_f32)));
+    Image::from_layer(layer).write().to_buffered(Cursor::new(&mut file)).unwrap();
+
+    bench.iter(||{
+        bencher::black_box(&mut file);
+
+        let image = exr::prelude::read()
+            .no_deep_data().largest_resolution_level().all_channels()
+            .first_valid_layer().all_attributes()
+            .from_buffered(Cursor::new(file.as_slice())).unwrap();
+
+        bencher::black_box(image);
+    })
+}
+
 benchmark_group!(read,
     read_single_image_uncompressed_rgba,
     read_single_image_uncompressed_non_parallel_rgba,
+    read_single_image_uncompressed_interleaved_rgba,
     read_single_image_rle_rgba,
     read_single_image_rle_non_parallel_rgba,
+    read_single_image_rle_interleaved_rgba,
     read_single_image_rle_all_channels,
     read_single_image_rle_non_parallel_all_channels,
     read_single_image_zips_rgba,
     read_single_image_zips_non_parallel_rgba,
+    read_tiny_image_single_chunk_all_channels,
 );
 
 benchmark_main!(read);
\ No newline at end of file
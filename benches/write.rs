@@ -6,6 +6,8 @@ use exr::prelude::*;
 
 use bencher::Bencher;
 use std::io::Cursor;
+use exr::image::interleaved::InterleavedPixels;
+use exr::image::pixel_vec::PixelVec;
 
 fn write_parallel_any_channels_to_buffered(bench: &mut Bencher) {
     let path = "tests/images/valid/custom/crowskull/crow_rle.exr";
@@ -69,12 +71,38 @@ fn write_uncompressed_to_buffered(bench: &mut Bencher) {
     })
 }
 
+fn write_uncompressed_rgba_to_buffered(bench: &mut Bencher) {
+    let path = "tests/images/valid/custom/crowskull/crow_uncompressed.exr";
+    let mut image = read_first_rgba_layer_from_file(path, PixelVec::<(f32,f32,f32,f32)>::constructor, PixelVec::set_pixel).unwrap();
+    image.layer_data.encoding = Encoding::UNCOMPRESSED;
+
+    bench.iter(||{
+        let mut result = Vec::new();
+        image.write().to_buffered(Cursor::new(&mut result)).unwrap();
+        bencher::black_box(result);
+    })
+}
+
+fn write_uncompressed_interleaved_rgba_to_buffered(bench: &mut Bencher) {
+    let path = "tests/images/valid/custom/crowskull/crow_uncompressed.exr";
+    let rgba = [ Text::from("R"), Text::from("G"), Text::from("B"), Text::from("A") ];
+    let image = InterleavedPixels::<f32, 4>::read_from_file(path, rgba).unwrap();
+
+    bench.iter(||{
+        let mut result = Vec::new();
+        image.write_to_buffered(Cursor::new(&mut result), Encoding::UNCOMPRESSED).unwrap();
+        bencher::black_box(result);
+    })
+}
+
 benchmark_group!(write,
     write_parallel_any_channels_to_buffered,
     write_nonparallel_zip1_to_buffered,
     write_parallel_zip1_to_buffered,
     write_parallel_zip16_to_buffered,
-    write_uncompressed_to_buffered
+    write_uncompressed_to_buffered,
+    write_uncompressed_rgba_to_buffered,
+    write_uncompressed_interleaved_rgba_to_buffered
 );
 
 benchmark_main!(write);
\ No newline at end of file
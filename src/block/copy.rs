@@ -0,0 +1,260 @@
+//! Copy some of the chunks of a file into a new file, without decompressing any pixels.
+//! This makes it cheap to repack archived frames, for example to drop unused layers or mip map levels.
+//! Channels within a layer cannot be dropped this way, as each chunk contains all channels of its layer.
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Seek, Write};
+use std::ops::Range;
+use std::path::Path;
+
+use crate::block::chunk::{CompressedBlock, TileCoordinates};
+use crate::block::writer::ChunksWriter;
+use crate::error::{Error, Result, UnitResult};
+use crate::math::Vec2;
+use crate::meta::{compute_chunk_count, BlockDescription, Headers};
+use crate::meta::attribute::LevelMode;
+use crate::meta::header::Header;
+
+
+/// Selects which chunks of a file are copied.
+/// The default selection copies everything.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct CopySelection {
+
+    /// The indices of the layers to copy, in the order they should appear in the new file.
+    /// All layers are copied if this is `None`.
+    pub layers: Option<Vec<usize>>,
+
+    /// Only copy the largest resolution level of each layer.
+    /// Mip maps and rip maps are converted to tiled layers with a single level.
+    pub largest_level_only: bool,
+
+    /// Only copy the blocks that contain some of these rows of pixels, in absolute coordinates.
+    /// The data window of each layer is reduced to the copied blocks,
+    /// so it may contain some more rows than requested.
+    /// Not supported for layers with multiple resolution levels, unless `largest_level_only` is set.
+    pub rows: Option<Range<i32>>,
+}
+
+/// Copy the selected chunks of the file into a new file, without decompressing them.
+/// See `copy_chunks` for details.
+pub fn copy_chunks_of_file(source: impl AsRef<Path>, target: impl AsRef<Path>, selection: &CopySelection, pedantic: bool) -> UnitResult {
+    let read = BufReader::new(File::open(source)?);
+
+    crate::io::attempt_delete_file_on_write_error(target.as_ref(), move |write|
+        copy_chunks(read, BufWriter::new(write), selection, pedantic)
+    )
+}
+
+/// Copy the selected chunks of the image into a new image, without decompressing them.
+/// The headers of the new image are the headers of the selected layers,
+/// with the data window, level mode and chunk count adjusted to the selection.
+/// The chunks are written in the order they appear in the source.
+pub fn copy_chunks(read: impl Read + Seek, write: impl Write + Seek, selection: &CopySelection, pedantic: bool) -> UnitResult {
+    let reader = crate::block::read(read, pedantic)?;
+    let source_headers = reader.headers().to_vec();
+
+    let layers: Vec<usize> = match &selection.layers {
+        Some(layers) => layers.clone(),
+        None => (0 .. source_headers.len()).collect(),
+    };
+
+    // for each layer of the source file, the layer in the new file
+    let mut target_layers: Vec<Option<usize>> = vec![ None; source_headers.len() ];
+    for (target_index, &source_index) in layers.iter().enumerate() {
+        let target_layer = target_layers.get_mut(source_index).ok_or(Error::invalid("copied layer index"))?;
+        if target_layer.is_some() { return Err(Error::invalid("copied layer index appears twice")); }
+        *target_layer = Some(target_index);
+    }
+
+    let (headers, first_blocks): (Headers, Vec<usize>) = layers.iter()
+        .map(|&index| select_header(&source_headers[index], selection))
+        .collect::<Result<Vec<_>>>()?.into_iter().unzip();
+
+    // for each layer of the new file, the index of each block in increasing line order
+    let block_indices: Vec<HashMap<TileCoordinates, usize>> = headers.iter()
+        .map(|header| header.blocks_increasing_y_order().enumerate().map(|(index, tile)| (tile.location, index)).collect())
+        .collect();
+
+    let chunks = reader.filter_chunks(pedantic, |_, tile, block| {
+        target_layers[block.layer].map_or(false, |target| {
+            let first_block = first_blocks[target];
+            let moved = TileCoordinates { tile_index: Vec2(tile.tile_index.x(), tile.tile_index.y().wrapping_sub(first_block)), .. tile };
+            tile.tile_index.y() >= first_block && block_indices[target].contains_key(&moved)
+        })
+    })?;
+
+    crate::block::write(write, headers, true, |_, chunk_writer| {
+        for chunk in chunks {
+            let mut chunk = chunk?;
+            let target = target_layers.get(chunk.layer_index).copied().flatten()
+                .ok_or(Error::invalid("chunk layer index"))?;
+
+            let tile = source_headers[chunk.layer_index].get_block_data_indices(&chunk.compressed_block)?;
+            let moved = TileCoordinates { tile_index: Vec2(tile.tile_index.x(), tile.tile_index.y() - first_blocks[target]), .. tile };
+            let index_in_header_increasing_y = *block_indices[target].get(&moved).ok_or(Error::invalid("chunk tile index"))?;
+
+            // scan line blocks store their absolute position, which does not change
+            match &mut chunk.compressed_block {
+                CompressedBlock::Tile(tile) => tile.coordinates = moved,
+                CompressedBlock::DeepTile(tile) => tile.coordinates = moved,
+                CompressedBlock::ScanLine(_) | CompressedBlock::DeepScanLine(_) => {},
+            }
+
+            chunk.layer_index = target;
+            chunk_writer.write_chunk(index_in_header_increasing_y, chunk)?;
+        }
+
+        Ok(())
+    })
+}
+
+/// The header of the new file, and the vertical index of the first copied block within the source layer.
+fn select_header(source: &Header, selection: &CopySelection) -> Result<(Header, usize)> {
+    let mut header = source.clone();
+
+    if let BlockDescription::Tiles(tiles) = &mut header.blocks {
+        if selection.largest_level_only { tiles.level_mode = LevelMode::Singular; }
+
+        if tiles.level_mode != LevelMode::Singular && selection.rows.is_some() {
+            return Err(Error::unsupported("copying some rows of multiple resolution levels"));
+        }
+    }
+
+    let mut first_block = 0;
+
+    if let Some(rows) = &selection.rows {
+        let block_height = header.max_block_pixel_size().height();
+        let data_window = header.data_window();
+
+        let start = rows.start.max(data_window.position.y());
+        let end = rows.end.min(data_window.end().y());
+        if start >= end { return Err(Error::invalid("copied rows are outside of the data window")); }
+
+        // round outwards to whole blocks
+        first_block = (start - data_window.position.y()) as usize / block_height;
+        let end_block = ((end - data_window.position.y()) as usize + block_height - 1) / block_height;
+
+        let first_row = first_block * block_height;
+        let end_row = (end_block * block_height).min(header.layer_size.height());
+
+        header.own_attributes.layer_position.1 = data_window.position.y() + first_row as i32;
+        header.layer_size.1 = end_row - first_row;
+    }
+
+    header.chunk_count = compute_chunk_count(header.compression, header.layer_size, header.blocks);
+    Ok((header, first_block))
+}
+
+
+#[cfg(test)]
+mod test {
+    use std::io::Cursor;
+    use crate::prelude::*;
+    use crate::math::RoundingMode;
+    use super::*;
+
+    fn layer(name: &str, blocks: Blocks, offset: f32) -> Layer<AnyChannels<FlatSamples>> {
+        let size = Vec2(13, 40);
+        let samples = (0 .. size.area()).map(|index| index as f32 + offset).collect();
+
+        Layer::new(
+            size, LayerAttributes { layer_position: Vec2(-3, 7), .. LayerAttributes::named(name) },
+            Encoding { compression: Compression::ZIP16, blocks, line_order: LineOrder::Increasing },
+            AnyChannels::sort(smallvec::smallvec![ AnyChannel::new("Y", FlatSamples::F32(samples)) ])
+        )
+    }
+
+    fn source() -> Vec<u8> {
+        let layers = vec![ layer("beauty", Blocks::ScanLines, 0.0), layer("normals", Blocks::Tiles(Vec2(8, 8)), 1000.0) ];
+        let mut bytes = Vec::new();
+        Image::from_layers(ImageAttributes::new(IntegerBounds::new((0, 0), (20, 50))), layers)
+            .write().to_buffered(Cursor::new(&mut bytes)).unwrap();
+
+        bytes
+    }
+
+    fn copy(selection: CopySelection) -> Result<Vec<Layer<AnyChannels<FlatSamples>>>> {
+        let mut bytes = Vec::new();
+        copy_chunks(Cursor::new(source()), Cursor::new(&mut bytes), &selection, true)?;
+
+        let image = read().no_deep_data().largest_resolution_level().all_channels().all_layers().all_attributes()
+            .pedantic().from_buffered(Cursor::new(bytes))?;
+
+        Ok(image.layer_data.into_vec())
+    }
+
+    fn rows(layer: &Layer<AnyChannels<FlatSamples>>, rows: Range<usize>) -> Vec<f32> {
+        let width = layer.size.width();
+        layer.channel_data.list[0].sample_data.values_as_f32().skip(rows.start * width).take(rows.len() * width).collect()
+    }
+
+    #[test]
+    fn copy_layers_in_new_order(){
+        let copied = copy(CopySelection { layers: Some(vec![ 1, 0 ]), .. CopySelection::default() }).unwrap();
+        let original = [ layer("beauty", Blocks::ScanLines, 0.0), layer("normals", Blocks::Tiles(Vec2(8, 8)), 1000.0) ];
+
+        assert_eq!(copied.len(), 2);
+        assert_eq!(copied[0].attributes.layer_name, original[1].attributes.layer_name);
+        assert_eq!(copied[0].channel_data, original[1].channel_data);
+        assert_eq!(copied[1].channel_data, original[0].channel_data);
+
+        assert!(copy(CopySelection { layers: Some(vec![ 0, 0 ]), .. CopySelection::default() }).is_err());
+        assert!(copy(CopySelection { layers: Some(vec![ 2 ]), .. CopySelection::default() }).is_err());
+    }
+
+    #[test]
+    fn copy_rows_of_scan_lines_and_tiles(){
+        // rows 20 to 30 of the data window, which starts at 7
+        let copied = copy(CopySelection { rows: Some(27 .. 37), .. CopySelection::default() }).unwrap();
+        let original = [ layer("beauty", Blocks::ScanLines, 0.0), layer("normals", Blocks::Tiles(Vec2(8, 8)), 1000.0) ];
+
+        // scan line blocks contain 16 rows, so rows 16 to 32 are copied
+        assert_eq!(copied[0].attributes.layer_position, Vec2(-3, 7 + 16));
+        assert_eq!(copied[0].size, Vec2(13, 16));
+        assert_eq!(rows(&copied[0], 0 .. 16), rows(&original[0], 16 .. 32));
+
+        // tiles contain 8 rows, so rows 16 to 32 are copied
+        assert_eq!(copied[1].attributes.layer_position, Vec2(-3, 7 + 16));
+        assert_eq!(copied[1].size, Vec2(13, 16));
+        assert_eq!(rows(&copied[1], 0 .. 16), rows(&original[1], 16 .. 32));
+
+        // the last block is smaller than the others
+        let end = copy(CopySelection { rows: Some(45 .. 100), layers: Some(vec![ 0 ]), .. CopySelection::default() }).unwrap();
+        assert_eq!(end[0].size, Vec2(13, 8));
+        assert_eq!(rows(&end[0], 0 .. 8), rows(&original[0], 32 .. 40));
+
+        assert!(copy(CopySelection { rows: Some(0 .. 7), .. CopySelection::default() }).is_err());
+    }
+
+    #[test]
+    fn drop_mip_levels(){
+        let size = Vec2(24, 16);
+        let level = |size: Vec2<usize>| FlatSamples::F16(vec![ f16::from_f32(size.width() as f32); size.area() ]);
+        let levels = Levels::Mip { rounding_mode: RoundingMode::Down, level_data: vec![ level(size), level(Vec2(12, 8)), level(Vec2(6, 4)), level(Vec2(3, 2)), level(Vec2(1, 1)) ] };
+
+        let layer = Layer::new(
+            size, LayerAttributes::default(),
+            Encoding { compression: Compression::RLE, blocks: Blocks::Tiles(Vec2(8, 8)), line_order: LineOrder::Increasing },
+            AnyChannels::sort(smallvec::smallvec![ AnyChannel::new("Y", levels) ])
+        );
+
+        let mut source = Vec::new();
+        Image::from_layer(layer).write().to_buffered(Cursor::new(&mut source)).unwrap();
+
+        let mut copied = Vec::new();
+        let selection = CopySelection { largest_level_only: true, .. CopySelection::default() };
+        copy_chunks(Cursor::new(source.clone()), Cursor::new(&mut copied), &selection, true).unwrap();
+        assert!(copied.len() < source.len());
+
+        let image = read().no_deep_data().all_resolution_levels().all_channels().first_valid_layer().all_attributes()
+            .pedantic().from_buffered(Cursor::new(copied)).unwrap();
+
+        assert_eq!(image.layer_data.channel_data.list[0].sample_data, Levels::Singular(level(size)));
+
+        let rows_of_mip_map = CopySelection { rows: Some(0 .. 8), .. CopySelection::default() };
+        let result = copy_chunks(Cursor::new(source), Cursor::new(Vec::new()), &rows_of_mip_map, true);
+        assert!(matches!(result, Err(Error::NotSupported(_))));
+    }
+}
@@ -0,0 +1,368 @@
+//! Inspect the deep data of a file without decoding it into an image.
+//! Malformed deep data is a frequent cause of crashes in compositing software,
+//! so this reports broken sample count tables and unsorted or overlapping depth samples,
+//! together with a few statistics about the number of samples.
+
+use std::io::{Read, Seek, BufReader};
+use std::fs::File;
+use std::path::Path;
+use half::f16;
+
+use crate::block::chunk::CompressedBlock;
+use crate::error::{Result, UnitResult, Error};
+use crate::io::Data;
+use crate::math::Vec2;
+use crate::meta::attribute::{IntegerBounds, SampleType, Text};
+use crate::meta::header::Header;
+
+
+/// The deep data statistics and problems of all deep layers in a file.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct DeepReport {
+
+    /// One report for each deep layer in the file. Flat layers are not included.
+    pub layers: Vec<DeepLayerReport>,
+}
+
+/// The deep data statistics and problems of a single deep layer.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeepLayerReport {
+
+    /// The index of the header of this layer in the file.
+    pub layer_index: usize,
+
+    /// The maximum number of samples per pixel, as declared in the header.
+    pub declared_max_samples_per_pixel: Option<usize>,
+
+    /// The actual maximum number of samples of any pixel in this layer.
+    pub max_samples_per_pixel: usize,
+
+    /// The number of samples of all pixels in this layer.
+    pub total_sample_count: u64,
+
+    /// The pixel bounds of the blocks whose sample count table cannot be decompressed,
+    /// is not increasing, or does not match the size of the sample data.
+    pub broken_blocks: Vec<IntegerBounds>,
+
+    /// The positions of the pixels with more samples than declared in the header.
+    pub pixels_exceeding_declared_max: Vec<Vec2<i32>>,
+
+    /// The positions of the pixels whose samples are not sorted by increasing `Z`.
+    pub unsorted_pixels: Vec<Vec2<i32>>,
+
+    /// The positions of the pixels where the `Z` of a sample is smaller than the `ZBack` of the previous sample.
+    pub overlapping_pixels: Vec<Vec2<i32>>,
+}
+
+
+impl DeepReport {
+
+    /// Inspect all deep layers in the file.
+    /// Blocks with broken sample count tables are reported instead of returning an error,
+    /// but an error is returned if the file itself cannot be read.
+    pub fn read_from_file(path: impl AsRef<Path>) -> Result<Self> {
+        Self::read_from_buffered(BufReader::new(File::open(path)?))
+    }
+
+    /// Inspect all deep layers in the byte source.
+    /// Blocks with broken sample count tables are reported instead of returning an error,
+    /// but an error is returned if the file itself cannot be read.
+    pub fn read_from_buffered(read: impl Read + Seek) -> Result<Self> {
+        let reader = crate::block::read(read, false)?;
+        let headers = reader.headers().to_vec();
+
+        let mut layers: Vec<Option<DeepLayerReport>> = headers.iter().enumerate()
+            .map(|(layer_index, header)| if header.deep { Some(DeepLayerReport::new(layer_index, header)) } else { None })
+            .collect();
+
+        for chunk in reader.all_chunks(false)? {
+            let chunk = chunk?;
+            let header = headers.get(chunk.layer_index).ok_or(Error::invalid("chunk layer index"))?;
+
+            if let Some(layer) = &mut layers[chunk.layer_index] {
+                layer.add_block(header, &chunk.compressed_block)?;
+            }
+        }
+
+        Ok(DeepReport { layers: layers.into_iter().flatten().collect() })
+    }
+
+    /// Whether no problems were found in any deep layer.
+    pub fn is_valid(&self) -> bool {
+        self.layers.iter().all(DeepLayerReport::is_valid)
+    }
+}
+
+impl DeepLayerReport {
+
+    /// Create a report without any blocks.
+    pub fn new(layer_index: usize, header: &Header) -> Self {
+        DeepLayerReport {
+            layer_index,
+            declared_max_samples_per_pixel: header.max_samples_per_pixel,
+            max_samples_per_pixel: 0,
+            total_sample_count: 0,
+            broken_blocks: Vec::new(),
+            pixels_exceeding_declared_max: Vec::new(),
+            unsorted_pixels: Vec::new(),
+            overlapping_pixels: Vec::new(),
+        }
+    }
+
+    /// Whether no problems were found in this layer.
+    pub fn is_valid(&self) -> bool {
+        self.broken_blocks.is_empty() && self.pixels_exceeding_declared_max.is_empty()
+            && self.unsorted_pixels.is_empty() && self.overlapping_pixels.is_empty()
+    }
+
+    /// Inspect a single deep block of this layer.
+    /// Returns an error only if the block cannot be located or uses an unsupported compression method.
+    pub fn add_block(&mut self, header: &Header, block: &CompressedBlock) -> UnitResult {
+        let (offset_table, sample_data, sample_data_size) = deep_block_contents(block)?;
+
+        let bounds = header.get_block_data_window_pixel_coordinates(header.get_block_data_indices(block)?)?;
+
+        match self.inspect_block(header, bounds, offset_table, sample_data, sample_data_size) {
+            Err(Error::Invalid(_)) => { self.broken_blocks.push(bounds); Ok(()) },
+            result => result,
+        }
+    }
+
+    fn inspect_block(
+        &mut self, header: &Header, bounds: IntegerBounds,
+        offset_table: &[i8], sample_data: &[u8], sample_data_size: usize
+    ) -> UnitResult
+    {
+        let sample_counts = decompress_sample_counts(header, bounds.size.area(), offset_table, sample_data_size)?;
+        let total_sample_count: usize = sample_counts.iter().sum();
+
+        let pixel_position = |pixel_index: usize| {
+            bounds.position + Vec2(pixel_index % bounds.size.width(), pixel_index / bounds.size.width()).to_i32()
+        };
+
+        self.total_sample_count += total_sample_count as u64;
+        for (pixel_index, &sample_count) in sample_counts.iter().enumerate() {
+            self.max_samples_per_pixel = self.max_samples_per_pixel.max(sample_count);
+
+            if self.declared_max_samples_per_pixel.map_or(false, |max| sample_count > max) {
+                self.pixels_exceeding_declared_max.push(pixel_position(pixel_index));
+            }
+        }
+
+        let channel_index = |name: &str| header.channels.list.iter().position(|channel| channel.name == Text::from(name));
+        let (front_index, back_index) = match channel_index("Z") {
+            Some(front_index) => (front_index, channel_index("ZBack")),
+            None => return Ok(()), // without depth, the samples cannot be out of order
+        };
+
+        let sample_data = header.compression.decompress_deep_bytes(sample_data.to_vec(), sample_data_size, false)?;
+
+        // the samples of one channel are stored after all samples of the previous channel
+        let depth_at = |channel_index: usize, sample_index: usize| -> f32 {
+            let channel_start: usize = header.channels.list[.. channel_index].iter()
+                .map(|channel| channel.sample_type.bytes_per_sample() * total_sample_count).sum();
+
+            let sample_type = header.channels.list[channel_index].sample_type;
+            sample_to_f32(&sample_data[channel_start ..], sample_type, sample_index)
+        };
+
+        let mut first_sample = 0;
+        for (pixel_index, &sample_count) in sample_counts.iter().enumerate() {
+            let next_samples = first_sample + 1 .. first_sample + sample_count;
+
+            if next_samples.clone().any(|sample| depth_at(front_index, sample) < depth_at(front_index, sample - 1)) {
+                self.unsorted_pixels.push(pixel_position(pixel_index));
+            }
+
+            if let Some(back_index) = back_index {
+                if next_samples.clone().any(|sample| depth_at(front_index, sample) < depth_at(back_index, sample - 1)) {
+                    self.overlapping_pixels.push(pixel_position(pixel_index));
+                }
+            }
+
+            first_sample += sample_count;
+        }
+
+        Ok(())
+    }
+}
+
+
+/// The compressed sample count table, the compressed sample data, and the decompressed size of the sample data.
+pub(crate) fn deep_block_contents(block: &CompressedBlock) -> Result<(&[i8], &[u8], usize)> {
+    match block {
+        CompressedBlock::DeepScanLine(block) => Ok((&block.compressed_pixel_offset_table, &block.compressed_sample_data, block.decompressed_sample_data_size)),
+        CompressedBlock::DeepTile(block) => Ok((&block.compressed_pixel_offset_table, &block.compressed_sample_data, block.decompressed_sample_data_size)),
+        _ => Err(Error::invalid("flat block in deep layer")),
+    }
+}
+
+/// Decompress the sample count table of a deep block and return the number of samples of each pixel.
+/// Fails if the table is not increasing or does not match the size of the sample data.
+pub(crate) fn decompress_sample_counts(header: &Header, pixel_count: usize, offset_table: &[i8], sample_data_size: usize) -> Result<Vec<usize>> {
+    let offset_table = offset_table.iter().map(|&byte| byte as u8).collect();
+    let offset_table = header.compression.decompress_deep_bytes(offset_table, pixel_count * i32::BYTE_SIZE, false)?;
+
+    // the table contains the number of samples of each pixel and all pixels before it in the block
+    let mut sample_counts = Vec::with_capacity(pixel_count);
+    let mut previous_offset = 0_i32;
+
+    for offset_bytes in offset_table.chunks_exact(i32::BYTE_SIZE) {
+        let offset = i32::from_le_bytes([ offset_bytes[0], offset_bytes[1], offset_bytes[2], offset_bytes[3] ]);
+        if offset < previous_offset { return Err(Error::invalid("deep sample count table is not increasing")); }
+
+        sample_counts.push((offset - previous_offset) as usize);
+        previous_offset = offset;
+    }
+
+    let total_sample_count = previous_offset as usize;
+    let bytes_per_sample: usize = header.channels.list.iter().map(|channel| channel.sample_type.bytes_per_sample()).sum();
+
+    if total_sample_count * bytes_per_sample != sample_data_size {
+        return Err(Error::invalid("deep sample data size does not match the sample count table"));
+    }
+
+    Ok(sample_counts)
+}
+
+/// Read the sample at the index from the little endian bytes of a deep channel.
+pub(crate) fn sample_to_f32(channel_bytes: &[u8], sample_type: SampleType, sample_index: usize) -> f32 {
+    let bytes = &channel_bytes[sample_index * sample_type.bytes_per_sample() ..];
+
+    match sample_type {
+        SampleType::F16 => f16::from_le_bytes([ bytes[0], bytes[1] ]).to_f32(),
+        SampleType::F32 => f32::from_le_bytes([ bytes[0], bytes[1], bytes[2], bytes[3] ]),
+        SampleType::U32 => u32::from_le_bytes([ bytes[0], bytes[1], bytes[2], bytes[3] ]) as f32,
+    }
+}
+
+
+#[cfg(test)]
+pub(crate) mod test {
+    use std::io::Cursor;
+    use crate::prelude::*;
+    use crate::block::chunk::{Chunk, CompressedBlock, CompressedDeepScanLineBlock};
+    use crate::block::writer::ChunksWriter;
+    use crate::meta::BlockDescription;
+    use crate::meta::attribute::ChannelDescription;
+    use crate::meta::header::Header;
+    use super::*;
+
+    /// Writes a deep scan line file with a single layer and the specified channels.
+    /// Each line contains the number of samples in each pixel,
+    /// and the sample data of all pixels, one channel after another.
+    /// Each line is stored as a separate block, so compression methods
+    /// with multiple lines per block can only be used for a single line.
+    pub(crate) fn deep_scan_line_file(
+        compression: Compression, channels: smallvec::SmallVec<[ChannelDescription; 5]>,
+        max_samples_per_pixel: usize, lines: &[(Vec<usize>, Vec<u8>)]
+    ) -> Vec<u8> {
+        assert!(compression.scan_lines_per_block() == 1 || lines.len() == 1, "one block per line");
+
+        let width = lines.first().map_or(0, |(sample_counts, _)| sample_counts.len());
+        let header = Header::new(Text::from("deep"), (width, lines.len()), channels);
+
+        let header = Header {
+            deep: true, deep_data_version: Some(1), max_samples_per_pixel: Some(max_samples_per_pixel),
+            .. header.with_encoding(compression, BlockDescription::ScanLines, LineOrder::Increasing)
+        };
+
+        let mut bytes = Cursor::new(Vec::new());
+        crate::block::write(&mut bytes, smallvec::smallvec![ header.clone() ], true, |_, writer| {
+            for (y, (sample_counts, sample_data)) in lines.iter().enumerate() {
+                let bounds = IntegerBounds::new((0, y as i32), (width, 1));
+                let offsets = sample_counts.iter().scan(0, |offset, &count| { *offset += count as i32; Some(*offset) });
+                let offsets = compression.compress_image_section(&header, offsets.flat_map(i32::to_le_bytes).collect(), bounds)?;
+
+                writer.write_chunk(y, Chunk { layer_index: 0, compressed_block: CompressedBlock::DeepScanLine(CompressedDeepScanLineBlock {
+                    y_coordinate: y as i32,
+                    decompressed_sample_data_size: sample_data.len(),
+                    compressed_pixel_offset_table: offsets.into_iter().map(|byte| byte as i8).collect(),
+                    compressed_sample_data: compression.compress_image_section(&header, sample_data.clone(), bounds)?,
+                })})?;
+            }
+
+            Ok(())
+        }).unwrap();
+
+        bytes.into_inner()
+    }
+
+    /// Writes a deep file with `Z` and `ZBack` channels, two pixels wide,
+    /// with one line for each list of pixels, each pixel containing a list of `(Z, ZBack)` samples.
+    fn deep_file(compression: Compression, max_samples_per_pixel: usize, lines: &[[&[(f32, f32)]; 2]]) -> Vec<u8> {
+        let lines: Vec<(Vec<usize>, Vec<u8>)> = lines.iter().map(|pixels| {
+            let samples = pixels.iter().flat_map(|samples| samples.iter());
+            let fronts = samples.clone().map(|&(front, _)| front);
+            let backs = samples.map(|&(_, back)| back);
+
+            (pixels.iter().map(|samples| samples.len()).collect(), fronts.chain(backs).flat_map(f32::to_le_bytes).collect())
+        }).collect();
+
+        let channels = smallvec::smallvec![ ChannelDescription::named("Z", SampleType::F32), ChannelDescription::named("ZBack", SampleType::F32) ];
+        deep_scan_line_file(compression, channels, max_samples_per_pixel, &lines)
+    }
+
+    #[test]
+    fn report_statistics_of_valid_deep_data(){
+        let file = deep_file(Compression::Uncompressed, 3, &[
+            [ &[ (1.0, 2.0), (3.0, 4.0) ], &[ (5.0, 5.0) ] ],
+            [ &[], &[ (1.0, 1.0), (1.0, 2.0), (2.0, 3.0) ] ],
+        ]);
+
+        let report = DeepReport::read_from_buffered(Cursor::new(file)).unwrap();
+        assert!(report.is_valid(), "{:?}", report);
+        assert_eq!(report.layers.len(), 1);
+        assert_eq!(report.layers[0].total_sample_count, 6);
+        assert_eq!(report.layers[0].max_samples_per_pixel, 3);
+    }
+
+    #[test]
+    fn report_zip_compressed_deep_data(){
+        let samples = [ (1.0, 1.0); 16 ];
+
+        for &compression in &[ Compression::ZIP1, Compression::ZIP16 ] {
+            let file = deep_file(compression, 16, &[ [ &samples, &samples[.. 8] ] ]);
+
+            let report = DeepReport::read_from_buffered(Cursor::new(file)).unwrap();
+            assert!(report.is_valid(), "{:?}", report);
+            assert_eq!(report.layers[0].total_sample_count, 24);
+        }
+    }
+
+    #[test]
+    fn report_unsorted_overlapping_and_too_many_samples(){
+        let file = deep_file(Compression::Uncompressed, 2, &[
+            [ &[ (3.0, 4.0), (1.0, 2.0) ], &[ (1.0, 3.0), (2.0, 4.0) ] ],
+            [ &[ (1.0, 1.0), (2.0, 2.0), (3.0, 3.0) ], &[] ],
+        ]);
+
+        let layer = &DeepReport::read_from_buffered(Cursor::new(file)).unwrap().layers[0];
+        assert_eq!(layer.unsorted_pixels, vec![ Vec2(0, 0) ]);
+        assert_eq!(layer.overlapping_pixels, vec![ Vec2(0, 0), Vec2(1, 0) ]);
+        assert_eq!(layer.pixels_exceeding_declared_max, vec![ Vec2(0, 1) ]);
+        assert!(layer.broken_blocks.is_empty());
+    }
+
+    #[test]
+    fn report_broken_sample_count_tables(){
+        let header = Header::new(Text::from("depth"), (2, 1), smallvec::smallvec![ ChannelDescription::named("Z", SampleType::F32) ]);
+        let header = Header { deep: true, deep_data_version: Some(1), max_samples_per_pixel: Some(4), .. header };
+        let mut report = DeepLayerReport::new(0, &header);
+
+        let block = |offsets: [i32; 2], sample_count: usize| CompressedBlock::DeepScanLine(CompressedDeepScanLineBlock {
+            y_coordinate: 0,
+            decompressed_sample_data_size: sample_count * 4,
+            compressed_pixel_offset_table: offsets.iter().flat_map(|offset| offset.to_le_bytes()).map(|byte| byte as i8).collect(),
+            compressed_sample_data: vec![ 0; sample_count * 4 ],
+        });
+
+        report.add_block(&header, &block([ 2, 1 ], 1)).unwrap();
+        report.add_block(&header, &block([ 1, 2 ], 3)).unwrap();
+        assert_eq!(report.broken_blocks, vec![ IntegerBounds::new((0, 0), (2, 1)); 2 ]);
+
+        report.add_block(&header, &block([ 1, 2 ], 2)).unwrap();
+        assert_eq!(report.broken_blocks.len(), 2);
+        assert_eq!(report.total_sample_count, 2);
+    }
+}
@@ -14,6 +14,9 @@ pub mod reader;
 pub mod lines;
 pub mod samples;
 pub mod chunk;
+pub mod deep;
+pub mod copy;
+pub mod optimize;
 
 
 use std::io::{Read, Seek, Write};
@@ -24,7 +27,7 @@ use crate::compression::ByteVec;
 use crate::block::chunk::{CompressedBlock, CompressedTileBlock, CompressedScanLineBlock, Chunk, TileCoordinates};
 use crate::meta::header::Header;
 use crate::block::lines::{LineIndex, LineRef, LineSlice, LineRefMut};
-use crate::meta::attribute::ChannelList;
+use crate::meta::attribute::{ChannelList, IntegerBounds};
 
 
 /// Specifies where a block of pixel data should be placed in the actual image.
@@ -33,7 +36,7 @@ use crate::meta::attribute::ChannelList;
 #[derive(Clone, Copy, Eq, Hash, PartialEq, Debug)]
 pub struct BlockIndex {
 
-    /// Index of the layer.
+    /// Index of the layer, also called part in multi-part files.
     pub layer: usize,
 
     /// Index of the top left pixel from the block within the data window.
@@ -47,6 +50,38 @@ pub struct BlockIndex {
     pub level: Vec2<usize>,
 }
 
+impl BlockIndex {
+
+    /// The pixel rectangle of this block, relative to the top left pixel of its resolution level.
+    /// Blocks of one level never overlap, so this can be used to place
+    /// the block into any data structure, regardless of the order in which the blocks were decoded.
+    pub fn pixel_bounds(&self) -> IntegerBounds {
+        IntegerBounds::new(self.pixel_position.to_i32(), self.pixel_size)
+    }
+
+    /// The pixel rectangle of this block in the coordinate system of the data window,
+    /// which is offset by the layer position of the header.
+    /// For smaller resolution levels, the level is assumed to start at the layer position.
+    pub fn data_window_bounds(&self, header: &Header) -> IntegerBounds {
+        let bounds = self.pixel_bounds();
+        IntegerBounds::new(bounds.position + header.own_attributes.layer_position, bounds.size)
+    }
+
+    /// The tile index and level index of this block, as stored in the file.
+    /// For scan line blocks, the tile index is the index of the scan line block.
+    pub fn tile_coordinates(&self, header: &Header) -> TileCoordinates {
+        TileCoordinates {
+            tile_index: self.pixel_position / header.max_block_pixel_size(), // TODO sampling??
+            level_index: self.level,
+        }
+    }
+
+    /// Whether this block belongs to the full resolution level of its layer.
+    pub fn is_largest_resolution_level(&self) -> bool {
+        self.level == Vec2(0, 0)
+    }
+}
+
 /// Contains a block of pixel data and where that data should be placed in the actual image.
 #[derive(Clone, Eq, PartialEq, Debug)]
 pub struct UncompressedBlock {
@@ -83,6 +118,17 @@ pub fn write<W: Write + Seek>(
     self::writer::write_chunks_with(buffered_write, headers, compatibility_checks, write_chunks)
 }
 
+/// Like `write`, but does not validate the headers at all.
+/// Only use this if the headers are known to be valid,
+/// for example because they were constructed by code that already produced valid files.
+/// __Invalid headers will produce a broken file without any error.__
+pub fn write_unchecked<W: Write + Seek>(
+    buffered_write: W, headers: Headers,
+    write_chunks: impl FnOnce(MetaData, &mut self::writer::ChunkWriter<W>) -> UnitResult
+) -> UnitResult {
+    self::writer::write_chunks_with_validation(buffered_write, headers, None, write_chunks)
+}
+
 
 
 
@@ -117,6 +163,27 @@ impl UncompressedBlock {
     #[inline]
     #[must_use]
     pub fn decompress_chunk(chunk: Chunk, meta_data: &MetaData, pedantic: bool) -> Result<Self> {
+        Self::decompress_chunk_with(chunk, meta_data, |header, compressed, section|
+            header.compression.decompress_image_section(header, compressed, section, pedantic)
+        )
+    }
+
+    /// Decompress the possibly compressed chunk and returns an `UncompressedBlock`.
+    /// If the chunk cannot be decompressed, attempts to recover the pixels from the damaged data.
+    /// See `Compression::decompress_image_section_with_recovery` for the strategies.
+    #[must_use]
+    pub fn decompress_chunk_with_recovery(chunk: Chunk, meta_data: &MetaData) -> Result<Self> {
+        Self::decompress_chunk_with(chunk, meta_data, |header, compressed, section|
+            header.compression.decompress_image_section_with_recovery(header, compressed, section)
+        )
+    }
+
+    #[inline]
+    fn decompress_chunk_with(
+        chunk: Chunk, meta_data: &MetaData,
+        decompress: impl FnOnce(&Header, ByteVec, IntegerBounds) -> Result<ByteVec>
+    ) -> Result<Self>
+    {
         let header: &Header = meta_data.headers.get(chunk.layer_index)
             .ok_or(Error::invalid("chunk layer index"))?;
 
@@ -129,7 +196,7 @@ impl UncompressedBlock {
             CompressedBlock::Tile(CompressedTileBlock { compressed_pixels, .. }) |
             CompressedBlock::ScanLine(CompressedScanLineBlock { compressed_pixels, .. }) => {
                 Ok(UncompressedBlock {
-                    data: header.compression.decompress_image_section(header, compressed_pixels, absolute_indices, pedantic)?,
+                    data: decompress(header, compressed_pixels, absolute_indices)?,
                     index: BlockIndex {
                         layer: chunk.layer_index,
                         pixel_position: absolute_indices.position.to_usize("data indices start")?,
@@ -158,11 +225,7 @@ impl UncompressedBlock {
             panic!("get_line byte size should be {} but was {}", expected_byte_size, data.len());
         }
 
-        let tile_coordinates = TileCoordinates {
-            // FIXME this calculation should not be made here but elsewhere instead (in meta::header?)
-            tile_index: index.pixel_position / header.max_block_pixel_size(), // TODO sampling??
-            level_index: index.level,
-        };
+        let tile_coordinates = index.tile_coordinates(header);
 
         let absolute_indices = header.get_absolute_block_pixel_coordinates(tile_coordinates)?;
         absolute_indices.validate(Some(header.layer_size))?;
@@ -254,4 +317,82 @@ impl UncompressedBlock {
             data: Self::collect_block_data_from_lines(channels, block_index, extract_line)
         }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Cursor;
+    use crate::prelude::*;
+    use crate::block::reader::ChunksReader;
+    use crate::block::writer::ParallelBlocksCompressor;
+
+    #[test]
+    fn reassemble_blocks_by_data_window_bounds(){
+        let size = Vec2(10, 7);
+        let position = Vec2(3, -2);
+        let values: Vec<f32> = (0 .. size.area()).map(|index| index as f32).collect();
+
+        let mut layer = Layer::new(
+            size, LayerAttributes::named("tiles"),
+            Encoding { blocks: Blocks::Tiles(Vec2(4, 4)), line_order: LineOrder::Decreasing, .. Encoding::UNCOMPRESSED },
+            AnyChannels::sort(smallvec::smallvec![ AnyChannel::new("Y", FlatSamples::F32(values.clone())) ])
+        );
+
+        layer.attributes.layer_position = position;
+
+        let mut bytes = Vec::new();
+        Image::from_layer(layer).write().to_buffered(Cursor::new(&mut bytes)).unwrap();
+
+        let reader = crate::block::read(Cursor::new(bytes), true).unwrap();
+        let header = reader.headers()[0].clone();
+        let mut reassembled = vec![ -1.0_f32; size.area() ];
+
+        reader.all_chunks(true).unwrap().decompress_sequential(true, |meta, block| {
+            assert!(block.index.is_largest_resolution_level());
+            assert_eq!(block.index.tile_coordinates(&header).level_index, Vec2(0, 0));
+
+            let bounds = block.index.data_window_bounds(&header);
+            for line in block.lines(&meta.headers[0].channels) {
+                let row_in_block = line.location.position.y() - block.index.pixel_position.y();
+                let y = (bounds.position.y() - position.y()) as usize + row_in_block;
+                let x = (bounds.position.x() - position.x()) as usize;
+                let target = &mut reassembled[y * size.width() + x ..][.. line.location.sample_count];
+                line.read_samples_into_slice(target)?;
+            }
+
+            Ok(())
+        }).unwrap();
+
+        assert_eq!(reassembled, values);
+    }
+
+    #[test]
+    fn small_images_do_not_use_threads(){
+        let image_bytes = |size: (usize, usize)| {
+            let layer = Layer::new(size, LayerAttributes::default(), Encoding::SMALL_LOSSLESS, SpecificChannels::rgb(|_| (0.5_f32, 0.5_f32, 0.5_f32)));
+            let mut bytes = Vec::new();
+            Image::from_layer(layer).write().to_buffered(Cursor::new(&mut bytes)).unwrap();
+            bytes
+        };
+
+        let is_parallel = |bytes: Vec<u8>| {
+            let reader = crate::block::read(Cursor::new(bytes), true).unwrap();
+            let meta = reader.meta_data().clone();
+            let chunks = reader.all_chunks(true).unwrap();
+            let is_parallel = chunks.parallel_decompressor(true).is_ok();
+
+            // only check the compressor, without writing any chunks
+            let mut is_compressor_parallel = false;
+            crate::block::write(Cursor::new(Vec::new()), meta.headers, true, |meta, chunk_writer| {
+                is_compressor_parallel = ParallelBlocksCompressor::new(&meta, chunk_writer).is_some();
+                Err(Error::Aborted)
+            }).unwrap_err();
+
+            assert_eq!(is_compressor_parallel, is_parallel);
+            is_parallel
+        };
+
+        assert!(!is_parallel(image_bytes((8, 8))));
+        assert!(is_parallel(image_bytes((8, 80))));
+    }
+}
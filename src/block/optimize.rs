@@ -0,0 +1,267 @@
+//! Reduce the size of existing files, for example before archiving them, without changing any pixel.
+//! The chunks are recompressed with the same compression method, but with more effort,
+//! and attributes that only repeat the default values are removed.
+//! Recompressed chunks are only used if they are smaller and decompress to exactly the same pixels,
+//! so the result is never larger than the original and always contains the same pixels.
+//! The block layout of each layer is not changed. To use a different block size,
+//! read the image and write it with a new `Encoding`.
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Seek, Write};
+use std::path::Path;
+
+use crate::block::chunk::CompressedBlock;
+use crate::block::writer::ChunksWriter;
+use crate::compression::Compression;
+use crate::error::{Result, Error};
+use crate::math::Vec2;
+use crate::meta::attribute::Chromaticities;
+use crate::meta::Headers;
+use crate::meta::header::Header;
+
+
+/// Controls how much effort is spent optimizing a file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OptimizeOptions {
+
+    /// The deflate level used to recompress chunks with `ZIP1`, `ZIP16` or `PXR24` compression,
+    /// ranging from `0` (fastest) to `10` (smallest). Chunks with other compression methods are copied.
+    pub deflate_level: u8,
+
+    /// Remove the `chromaticities` attribute if it contains the default Rec. 709 primaries,
+    /// and the `originalDataWindow` attribute if it equals the data window.
+    pub strip_redundant_attributes: bool,
+
+    /// Remove the preview image, which can be recomputed from the pixels.
+    pub strip_preview: bool,
+}
+
+/// How much a file was reduced in size.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct OptimizeReport {
+
+    /// The number of compressed pixel bytes in the original file.
+    pub original_pixel_bytes: usize,
+
+    /// The number of compressed pixel bytes in the optimized file.
+    pub optimized_pixel_bytes: usize,
+
+    /// The number of chunks that were replaced by smaller chunks.
+    pub recompressed_chunks: usize,
+
+    /// The number of attributes that were removed from the headers.
+    /// Own attributes are counted once per header. Shared attributes,
+    /// which are repeated in every header, are counted only once.
+    pub stripped_attributes: usize,
+}
+
+impl Default for OptimizeOptions {
+    fn default() -> Self {
+        OptimizeOptions { deflate_level: 10, strip_redundant_attributes: true, strip_preview: false }
+    }
+}
+
+
+/// Optimize the file and write the result to a new file. See `optimize`.
+pub fn optimize_file(source: impl AsRef<Path>, target: impl AsRef<Path>, options: OptimizeOptions, pedantic: bool) -> Result<OptimizeReport> {
+    let read = BufReader::new(File::open(source)?);
+    let mut report = OptimizeReport::default();
+
+    crate::io::attempt_delete_file_on_write_error(target.as_ref(), |write| {
+        report = optimize(read, BufWriter::new(write), options, pedantic)?;
+        Ok(())
+    })?;
+
+    Ok(report)
+}
+
+/// Recompress all chunks of the image with more effort, and remove redundant attributes.
+/// The layers, resolution levels, block layout and compression methods are not changed.
+/// Deep data chunks are copied without modification.
+pub fn optimize(read: impl Read + Seek, write: impl Write + Seek, options: OptimizeOptions, pedantic: bool) -> Result<OptimizeReport> {
+    let reader = crate::block::read(read, pedantic)?;
+    let source_headers = reader.headers().to_vec();
+    let mut report = OptimizeReport::default();
+
+    let mut headers: Headers = source_headers.iter().cloned().collect();
+    strip_attributes(&mut headers, options, &mut report);
+
+    let chunks = reader.all_chunks(pedantic)?;
+
+    crate::block::write(write, headers, true, |meta, chunk_writer| {
+        let block_indices: Vec<HashMap<_, usize>> = meta.headers.iter()
+            .map(|header| header.blocks_increasing_y_order().enumerate().map(|(index, tile)| (tile.location, index)).collect())
+            .collect();
+
+        for chunk in chunks {
+            let mut chunk = chunk?;
+            let header = &source_headers[chunk.layer_index];
+
+            let tile = header.get_block_data_indices(&chunk.compressed_block)?;
+            let index_in_header_increasing_y = *block_indices[chunk.layer_index].get(&tile)
+                .ok_or(Error::invalid("chunk tile index"))?;
+
+            report.original_pixel_bytes += compressed_byte_size(&chunk.compressed_block);
+            if recompress(header, &mut chunk.compressed_block, options.deflate_level, pedantic)? {
+                report.recompressed_chunks += 1;
+            }
+
+            report.optimized_pixel_bytes += compressed_byte_size(&chunk.compressed_block);
+            chunk_writer.write_chunk(index_in_header_increasing_y, chunk)?;
+        }
+
+        Ok(())
+    })?;
+
+    Ok(report)
+}
+
+/// Replace the pixels of the block with a smaller version, if possible.
+/// Returns whether the block was replaced.
+fn recompress(header: &Header, block: &mut CompressedBlock, deflate_level: u8, pedantic: bool) -> Result<bool> {
+    let uses_deflate = matches!(header.compression, Compression::ZIP1 | Compression::ZIP16 | Compression::PXR24);
+    if !uses_deflate || header.deep { return Ok(false); }
+
+    let section = header.get_absolute_block_pixel_coordinates(header.get_block_data_indices(block)?)?;
+    let compressed_pixels = match block {
+        CompressedBlock::ScanLine(block) => &mut block.compressed_pixels,
+        CompressedBlock::Tile(block) => &mut block.compressed_pixels,
+        CompressedBlock::DeepScanLine(_) | CompressedBlock::DeepTile(_) => return Ok(false),
+    };
+
+    let compression = header.compression;
+    let pixels = compression.decompress_image_section(header, compressed_pixels.clone(), section, pedantic)?;
+    let recompressed = compression.compress_image_section_with_deflate_level(header, pixels.clone(), section, deflate_level)?;
+    if recompressed.len() >= compressed_pixels.len() { return Ok(false); }
+
+    // lossy methods may change the pixels again when recompressing them
+    let unchanged = compression.decompress_image_section(header, recompressed.clone(), section, true)
+        .map_or(false, |recompressed_pixels| recompressed_pixels == pixels);
+
+    if unchanged { *compressed_pixels = recompressed; }
+    Ok(unchanged)
+}
+
+fn compressed_byte_size(block: &CompressedBlock) -> usize {
+    match block {
+        CompressedBlock::ScanLine(block) => block.compressed_pixels.len(),
+        CompressedBlock::Tile(block) => block.compressed_pixels.len(),
+        CompressedBlock::DeepScanLine(block) => block.compressed_pixel_offset_table.len() + block.compressed_sample_data.len(),
+        CompressedBlock::DeepTile(block) => block.compressed_pixel_offset_table.len() + block.compressed_sample_data.len(),
+    }
+}
+
+fn strip_attributes(headers: &mut [Header], options: OptimizeOptions, report: &mut OptimizeReport) {
+    let mut strip = |strip: bool| { if strip { report.stripped_attributes += 1; } strip };
+
+    // shared attributes are equal in all headers, so they are removed and counted only once
+    let shared_chromaticities = headers.first().and_then(|header| header.shared_attributes.chromaticities);
+    if strip(options.strip_redundant_attributes && shared_chromaticities == Some(rec_709_primaries())) {
+        for header in headers.iter_mut() { header.shared_attributes.chromaticities = None; }
+    }
+
+    for header in headers {
+        if strip(options.strip_redundant_attributes && header.own_attributes.original_data_window == Some(header.data_window())) {
+            header.own_attributes.original_data_window = None;
+        }
+
+        if strip(options.strip_preview && header.own_attributes.preview.is_some()) {
+            header.own_attributes.preview = None;
+        }
+    }
+}
+
+/// The primaries that are assumed if a file does not contain the `chromaticities` attribute.
+fn rec_709_primaries() -> Chromaticities {
+    Chromaticities {
+        red: Vec2(0.64, 0.33), green: Vec2(0.30, 0.60),
+        blue: Vec2(0.15, 0.06), white: Vec2(0.3127, 0.3290),
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use std::io::Cursor;
+    use crate::prelude::*;
+    use super::*;
+
+    fn image(compression: Compression) -> Image<Layer<AnyChannels<FlatSamples>>> {
+        let size = Vec2(64, 48);
+        let pattern = |index: usize| ((index * 7919) % 97) as f32 / 8.0;
+
+        let mut attributes = LayerAttributes::named("beauty");
+        attributes.original_data_window = Some(IntegerBounds::from_dimensions(size));
+
+        let channels = AnyChannels::sort(smallvec::smallvec![
+            AnyChannel::new("R", FlatSamples::F16((0 .. size.area()).map(|index| f16::from_f32(pattern(index))).collect())),
+            AnyChannel::new("Z", FlatSamples::F32((0 .. size.area()).map(|index| pattern(index / 3)).collect())),
+        ]);
+
+        let encoding = Encoding { compression, blocks: Blocks::Tiles(Vec2(16, 16)), line_order: LineOrder::Increasing };
+        let mut image = Image::from_layer(Layer::new(size, attributes, encoding, channels));
+        image.attributes.chromaticities = Some(rec_709_primaries());
+        image
+    }
+
+    fn read_image(bytes: &[u8]) -> Image<Layer<AnyChannels<FlatSamples>>> {
+        read().no_deep_data().largest_resolution_level().all_channels().first_valid_layer().all_attributes()
+            .from_buffered(Cursor::new(bytes)).unwrap()
+    }
+
+    #[test]
+    fn optimized_files_are_smaller_with_identical_pixels(){
+        for &compression in &[ Compression::ZIP16, Compression::PXR24, Compression::RLE ] {
+            let mut original = Vec::new();
+            image(compression).write().to_buffered(Cursor::new(&mut original)).unwrap();
+
+            let mut optimized = Vec::new();
+            let report = optimize(Cursor::new(&original), Cursor::new(&mut optimized), OptimizeOptions::default(), true).unwrap();
+
+            assert!(optimized.len() < original.len(), "{}", compression);
+            assert!(report.optimized_pixel_bytes <= report.original_pixel_bytes);
+            assert_eq!(report.stripped_attributes, 2);
+
+            let (original, optimized) = (read_image(&original), read_image(&optimized));
+            assert_eq!(optimized.layer_data.channel_data, original.layer_data.channel_data);
+            assert_eq!(optimized.layer_data.encoding, original.layer_data.encoding);
+            assert_eq!(optimized.attributes.chromaticities, None);
+            assert_eq!(optimized.layer_data.attributes.original_data_window, None);
+            assert_eq!(optimized.layer_data.attributes.layer_name, original.layer_data.attributes.layer_name);
+        }
+    }
+
+    #[test]
+    fn keep_attributes_that_are_not_redundant(){
+        let mut image = image(Compression::ZIP1);
+        image.attributes.chromaticities.as_mut().unwrap().white = Vec2(0.32168, 0.33767);
+        image.layer_data.attributes.original_data_window = Some(IntegerBounds::from_dimensions((128, 96)));
+
+        let mut original = Vec::new();
+        image.write().to_buffered(Cursor::new(&mut original)).unwrap();
+
+        let mut optimized = Vec::new();
+        let report = optimize(Cursor::new(&original), Cursor::new(&mut optimized), OptimizeOptions::default(), true).unwrap();
+        assert_eq!(report.stripped_attributes, 0);
+
+        let optimized = read_image(&optimized);
+        assert_eq!(optimized.attributes.chromaticities, image.attributes.chromaticities);
+        assert_eq!(optimized.layer_data.attributes.original_data_window, image.layer_data.attributes.original_data_window);
+    }
+
+    #[test]
+    fn count_shared_attributes_once(){
+        let layer = image(Compression::ZIP16).layer_data;
+        let other = Layer { attributes: LayerAttributes::named("other"), .. layer.clone() };
+
+        let mut image = Image::from_layers(ImageAttributes::new(layer.absolute_bounds()), smallvec::smallvec![ layer, other ]);
+        image.attributes.chromaticities = Some(rec_709_primaries());
+
+        let mut original = Vec::new();
+        image.write().to_buffered(Cursor::new(&mut original)).unwrap();
+
+        let report = optimize(Cursor::new(&original), Cursor::new(Vec::new()), OptimizeOptions::default(), true).unwrap();
+        assert_eq!(report.stripped_attributes, 1 + 1, "chromaticities once, and the original data window of the first layer");
+    }
+}
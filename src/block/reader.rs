@@ -11,10 +11,11 @@ use smallvec::alloc::sync::Arc;
 use crate::block::{BlockIndex, UncompressedBlock};
 use crate::block::chunk::{Chunk, TileCoordinates};
 use crate::compression::Compression;
-use crate::error::{Error, Result, u64_to_usize, UnitResult};
+use crate::error::{Error, Result, u64_to_usize, UnitResult, Warning};
 use crate::io::{PeekRead, Tracking};
-use crate::meta::{MetaData, OffsetTables};
+use crate::meta::{BlockDescription, MetaData, OffsetTables};
 use crate::meta::header::Header;
+use crate::meta::attribute::LineOrder;
 
 /// Decode the meta data from a byte source, keeping the source ready for further reading.
 /// Continue decoding the remaining bytes by calling `filtered_chunks` or `all_chunks`.
@@ -22,6 +23,7 @@ use crate::meta::header::Header;
 pub struct Reader<R> {
     meta_data: MetaData,
     remaining_reader: PeekRead<Tracking<R>>, // TODO does R need to be Seek or is Tracking enough?
+    warnings: Vec<Warning>,
 }
 
 impl<R: Read + Seek> Reader<R> {
@@ -31,8 +33,9 @@ impl<R: Read + Seek> Reader<R> {
     /// Access it via`meta_data()`.
     pub fn read_from_buffered(read: R, pedantic: bool) -> Result<Self> {
         let mut remaining_reader = PeekRead::new(Tracking::new(read));
-        let meta_data = MetaData::read_validated_from_buffered_peekable(&mut remaining_reader, pedantic)?;
-        Ok(Self { meta_data, remaining_reader })
+        let mut warnings = Vec::new();
+        let meta_data = MetaData::read_validated_from_buffered_peekable(&mut remaining_reader, pedantic, &mut warnings)?;
+        Ok(Self { meta_data, remaining_reader, warnings })
     }
 
     // must not be mutable, as reading the file later on relies on the meta data
@@ -42,9 +45,16 @@ impl<R: Read + Seek> Reader<R> {
     /// The decoded exr meta data from the file.
     pub fn headers(&self) -> &[Header] { &self.meta_data.headers }
 
+    /// Modify the decoded headers before reading the chunks, for example to parse custom attributes.
+    /// Changing the layout of the headers will corrupt the following chunks.
+    pub(crate) fn headers_mut(&mut self) -> &mut [Header] { &mut self.meta_data.headers }
+
     /// Obtain the meta data ownership.
     pub fn into_meta_data(self) -> MetaData { self.meta_data }
 
+    /// The anomalies in the meta data that were tolerated, because reading is not pedantic.
+    pub fn warnings(&self) -> &[Warning] { &self.warnings }
+
     /// Prepare to read all the chunks from the file.
     /// Does not decode the chunks now, but returns a decoder.
     /// Reading all chunks reduces seeking the file, but some chunks might be read without being used.
@@ -65,6 +75,7 @@ impl<R: Read + Seek> Reader<R> {
             meta_data: self.meta_data,
             remaining_chunks: 0 .. total_chunk_count,
             remaining_bytes: self.remaining_reader,
+            warnings: self.warnings,
             pedantic
         })
     }
@@ -77,11 +88,21 @@ impl<R: Read + Seek> Reader<R> {
         let offset_tables = MetaData::read_offset_tables(&mut self.remaining_reader, &self.meta_data.headers)?;
 
         // TODO regardless of pedantic, if invalid, read all chunks instead, and filter after reading each chunk?
-        if pedantic {
-            validate_offset_tables(
-                self.meta_data.headers.as_slice(), &offset_tables,
-                self.remaining_reader.byte_position()
-            )?;
+        let validation = validate_offset_tables(
+            self.meta_data.headers.as_slice(), &offset_tables,
+            self.remaining_reader.byte_position()
+        );
+
+        match validation {
+            Err(error) if pedantic => return Err(error),
+            Err(Error::Invalid(message)) => self.warnings.push(Warning::InvalidMetaData(message)),
+            _ => {},
+        }
+
+        for (layer_index, (header, offsets)) in self.meta_data.headers.iter().zip(&offset_tables).enumerate() {
+            if !chunks_are_in_line_order(header, offsets) {
+                self.warnings.push(Warning::UnexpectedChunkOrder { layer_index });
+            }
         }
 
         let mut filtered_offsets = Vec::with_capacity(
@@ -109,15 +130,15 @@ impl<R: Read + Seek> Reader<R> {
 
         filtered_offsets.sort_unstable(); // enables reading continuously if possible (already sorted where line order increasing)
 
-        if pedantic {
-            // table is sorted. if any two neighbours are equal, we have duplicates. this is invalid.
-            if filtered_offsets.windows(2).any(|pair| pair[0] == pair[1]) {
-                return Err(Error::invalid("chunk offset table"))
-            }
+        // table is sorted. if any two neighbours are equal, we have duplicates. this is invalid.
+        if filtered_offsets.windows(2).any(|pair| pair[0] == pair[1]) {
+            if pedantic { return Err(Error::invalid("chunk offset table")) }
+            self.warnings.push(Warning::InvalidMetaData("duplicate chunk offsets".into()));
         }
 
         Ok(FilteredChunksReader {
             meta_data: self.meta_data,
+            warnings: self.warnings,
             expected_filtered_chunk_count: filtered_offsets.len(),
             remaining_filtered_chunk_indices: filtered_offsets.into_iter(),
             remaining_bytes: self.remaining_reader
@@ -126,13 +147,52 @@ impl<R: Read + Seek> Reader<R> {
 }
 
 
+/// Whether the chunks of a header are stored in the order of its line order attribute.
+/// Tiles are only compared row by row within each level, because writers
+/// store the tiles within a row and the levels in different orders.
+fn chunks_are_in_line_order(header: &Header, offsets: &[u64]) -> bool {
+    let is_ordered = |ranges: &[(u64, u64)]| match header.line_order {
+        LineOrder::Increasing => ranges.windows(2).all(|pair| pair[0].1 < pair[1].0),
+        LineOrder::Decreasing => ranges.windows(2).all(|pair| pair[0].0 > pair[1].1),
+        LineOrder::Unspecified => true,
+    };
+
+    if header.blocks == BlockDescription::ScanLines {
+        let lines: Vec<(u64, u64)> = offsets.iter().map(|&offset| (offset, offset)).collect();
+        return is_ordered(&lines);
+    }
+
+    // the smallest and largest offset of each tile row, for each level, in increasing y order
+    let mut levels: Vec<Vec<(u64, u64)>> = Vec::new();
+    let mut previous_location: Option<TileCoordinates> = None;
+
+    for (tile, &offset) in header.blocks_increasing_y_order().zip(offsets) {
+        let location = tile.location;
+        let same_level = previous_location.map_or(false, |previous| previous.level_index == location.level_index);
+        let same_row = same_level && previous_location.map_or(false, |previous| previous.tile_index.y() == location.tile_index.y());
+
+        if !same_level { levels.push(Vec::new()); }
+        let rows = levels.last_mut().expect("level bug");
+
+        match rows.last_mut() {
+            Some((min, max)) if same_row => { *min = offset.min(*min); *max = offset.max(*max); },
+            _ => rows.push((offset, offset)),
+        }
+
+        previous_location = Some(location);
+    }
+
+    levels.iter().all(|rows| is_ordered(rows))
+}
+
 fn validate_offset_tables(headers: &[Header], offset_tables: &OffsetTables, chunks_start_byte: usize) -> UnitResult {
-    let max_pixel_bytes: usize = headers.iter() // when compressed, chunks are smaller, but never larger than max
-        .map(|header| header.max_pixel_file_bytes())
-        .sum();
+    // when compressed, chunks are smaller, but never larger than max.
+    // deep data without a maximum sample count has no upper bound
+    let max_pixel_bytes = headers.iter()
+        .fold(0_usize, |sum, header| sum.saturating_add(header.max_pixel_file_bytes()));
 
     // check that each offset is within the bounds
-    let end_byte = chunks_start_byte + max_pixel_bytes;
+    let end_byte = chunks_start_byte.saturating_add(max_pixel_bytes);
     let is_invalid = offset_tables.iter().flatten().map(|&u64| u64_to_usize(u64))
         .any(|chunk_start| chunk_start < chunks_start_byte || chunk_start > end_byte);
 
@@ -154,6 +214,7 @@ pub struct FilteredChunksReader<R> {
     expected_filtered_chunk_count: usize,
     remaining_filtered_chunk_indices: std::vec::IntoIter<u64>,
     remaining_bytes: PeekRead<Tracking<R>>,
+    warnings: Vec<Warning>,
 }
 
 /// Decode all chunks in the file without seeking.
@@ -166,6 +227,7 @@ pub struct AllChunksReader<R> {
     meta_data: MetaData,
     remaining_chunks: std::ops::Range<usize>,
     remaining_bytes: PeekRead<Tracking<R>>,
+    warnings: Vec<Warning>,
     pedantic: bool,
 }
 
@@ -198,6 +260,9 @@ pub trait ChunksReader: Sized + Iterator<Item=Result<Chunk>> + ExactSizeIterator
     /// Can be less than the total number of chunks in the file, if some chunks are skipped.
     fn expected_chunk_count(&self) -> usize;
 
+    /// The anomalies in the file that were tolerated, because reading is not pedantic.
+    fn warnings(&self) -> &[Warning] { &[] }
+
     /// Read the next compressed chunk from the file.
     /// Equivalent to `.next()`, as this also is an iterator.
     /// Returns `None` if all chunks have been read.
@@ -261,13 +326,14 @@ pub trait ChunksReader: Sized + Iterator<Item=Result<Chunk>> + ExactSizeIterator
 
     /// Prepare reading the chunks sequentially, only a single thread, but with less memory overhead.
     fn sequential_decompressor(self, pedantic: bool) -> SequentialBlockDecompressor<Self> {
-        SequentialBlockDecompressor { remaining_chunks_reader: self, pedantic }
+        SequentialBlockDecompressor { remaining_chunks_reader: self, pedantic, attempt_recovery: false }
     }
 }
 
 impl<R, F> ChunksReader for OnProgressChunksReader<R, F> where R: ChunksReader, F: FnMut(f64) {
     fn meta_data(&self) -> &MetaData { self.chunks_reader.meta_data() }
     fn expected_chunk_count(&self) -> usize { self.chunks_reader.expected_chunk_count() }
+    fn warnings(&self) -> &[Warning] { self.chunks_reader.warnings() }
 }
 
 impl<R, F> ExactSizeIterator for OnProgressChunksReader<R, F> where R: ChunksReader, F: FnMut(f64) {}
@@ -305,6 +371,7 @@ impl<R, F> Iterator for OnProgressChunksReader<R, F> where R: ChunksReader, F: F
 impl<R: Read + Seek> ChunksReader for AllChunksReader<R> {
     fn meta_data(&self) -> &MetaData { &self.meta_data }
     fn expected_chunk_count(&self) -> usize { self.remaining_chunks.end }
+    fn warnings(&self) -> &[Warning] { &self.warnings }
 }
 
 impl<R: Read + Seek> ExactSizeIterator for AllChunksReader<R> {}
@@ -332,6 +399,7 @@ impl<R: Read + Seek> Iterator for AllChunksReader<R> {
 impl<R: Read + Seek> ChunksReader for FilteredChunksReader<R> {
     fn meta_data(&self) -> &MetaData { &self.meta_data }
     fn expected_chunk_count(&self) -> usize { self.expected_filtered_chunk_count }
+    fn warnings(&self) -> &[Warning] { &self.warnings }
 }
 
 impl<R: Read + Seek> ExactSizeIterator for FilteredChunksReader<R> {}
@@ -364,6 +432,7 @@ impl<R: Read + Seek> Iterator for FilteredChunksReader<R> {
 pub struct SequentialBlockDecompressor<R: ChunksReader> {
     remaining_chunks_reader: R,
     pedantic: bool,
+    attempt_recovery: bool,
 }
 
 impl<R: ChunksReader> SequentialBlockDecompressor<R> {
@@ -371,10 +440,17 @@ impl<R: ChunksReader> SequentialBlockDecompressor<R> {
     /// The extracted meta data from the image file.
     pub fn meta_data(&self) -> &MetaData { self.remaining_chunks_reader.meta_data() }
 
+    /// If a chunk cannot be decompressed, attempt to recover the pixels from the damaged data.
+    /// See `UncompressedBlock::decompress_chunk_with_recovery`.
+    pub fn attempt_recovery(self) -> Self { Self { attempt_recovery: true, ..self } }
+
     /// Read and then decompress a single block of pixels from the byte source.
     pub fn decompress_next_block(&mut self) -> Option<Result<UncompressedBlock>> {
         self.remaining_chunks_reader.read_next_chunk().map(|compressed_chunk|{
-            UncompressedBlock::decompress_chunk(compressed_chunk?, &self.remaining_chunks_reader.meta_data(), self.pedantic)
+            let meta_data = self.remaining_chunks_reader.meta_data();
+
+            if self.attempt_recovery { UncompressedBlock::decompress_chunk_with_recovery(compressed_chunk?, meta_data) }
+            else { UncompressedBlock::decompress_chunk(compressed_chunk?, meta_data, self.pedantic) }
         })
     }
 }
@@ -394,6 +470,7 @@ pub struct ParallelBlockDecompressor<R: ChunksReader> {
 
     shared_meta_data_ref: Arc<MetaData>,
     pedantic: bool,
+    attempt_recovery: bool,
 
     pool: ThreadPool,
 }
@@ -426,6 +503,11 @@ impl<R: ChunksReader> ParallelBlockDecompressor<R> {
             return Err(chunks);
         }
 
+        // one chunk cannot be decompressed in parallel
+        if chunks.len() < 2 {
+            return Err(chunks);
+        }
+
         // in case thread pool creation fails (for example on WASM currently),
         // we revert to sequential decompression
         let pool = match try_create_thread_pool() {
@@ -446,12 +528,17 @@ impl<R: ChunksReader> ParallelBlockDecompressor<R> {
             sender: send,
             receiver: recv,
             pedantic,
+            attempt_recovery: false,
             max_threads,
 
             pool,
         })
     }
 
+    /// If a chunk cannot be decompressed, attempt to recover the pixels from the damaged data.
+    /// See `UncompressedBlock::decompress_chunk_with_recovery`.
+    pub fn attempt_recovery(self) -> Self { Self { attempt_recovery: true, ..self } }
+
     /// Fill the pool with decompression jobs. Returns the first job that finishes.
     pub fn decompress_next_block(&mut self) -> Option<Result<UncompressedBlock>> {
 
@@ -466,13 +553,14 @@ impl<R: ChunksReader> ParallelBlockDecompressor<R> {
                 let sender = self.sender.clone();
                 let meta = self.shared_meta_data_ref.clone();
                 let pedantic = self.pedantic;
+                let attempt_recovery = self.attempt_recovery;
 
                 self.currently_decompressing_count += 1;
 
                 self.pool.spawn(move || {
-                    let decompressed_or_err = UncompressedBlock::decompress_chunk(
-                        block, &meta, pedantic
-                    );
+                    let decompressed_or_err =
+                        if attempt_recovery { UncompressedBlock::decompress_chunk_with_recovery(block, &meta) }
+                        else { UncompressedBlock::decompress_chunk(block, &meta, pedantic) };
 
                     // by now, decompressing could have failed in another thread.
                     // the error is then already handled, so we simply
@@ -502,6 +590,10 @@ impl<R: ChunksReader> ParallelBlockDecompressor<R> {
 
     /// The extracted meta data of the image file.
     pub fn meta_data(&self) -> &MetaData { self.remaining_chunks.meta_data() }
+
+    /// Return the thread pool, so that it can be used again. Call this after all blocks have been decompressed.
+    #[cfg(feature = "benchmark")]
+    pub(crate) fn into_thread_pool(self) -> ThreadPool { self.pool }
 }
 
 impl<R: ChunksReader> ExactSizeIterator for SequentialBlockDecompressor<R> {}
@@ -522,6 +614,62 @@ impl<R: ChunksReader> Iterator for ParallelBlockDecompressor<R> {
 }
 
 
+#[cfg(test)]
+mod test {
+    use std::io::Cursor;
+    use crate::prelude::*;
+    use crate::block::chunk::{Chunk, CompressedBlock, CompressedTileBlock};
+    use crate::block::writer::ChunksWriter;
+    use crate::meta::{BlockDescription, TileIndices};
+    use crate::meta::attribute::{LevelMode, TileDescription};
+    use crate::math::RoundingMode;
+    use crate::meta::attribute::ChannelDescription;
+    use crate::meta::header::Header;
+    use super::*;
+
+    /// Write a decreasing mip mapped file, with the tiles in the order of the sort key.
+    fn decreasing_tiles(sort_key: fn(&TileIndices) -> (usize, i64, i64)) -> Vec<u8> {
+        let header = Header::new(Text::from("tiles"), (12, 12), smallvec::smallvec![ ChannelDescription::named("Y", SampleType::F32) ]);
+        let tiles = TileDescription { tile_size: Vec2(4, 4), level_mode: LevelMode::MipMap, rounding_mode: RoundingMode::Down };
+        let header = header.with_encoding(Compression::Uncompressed, BlockDescription::Tiles(tiles), LineOrder::Decreasing);
+
+        let mut tiles: Vec<(usize, TileIndices)> = header.blocks_increasing_y_order().enumerate().collect();
+        tiles.sort_by_key(|(_, tile)| sort_key(tile));
+
+        let mut bytes = Cursor::new(Vec::new());
+        crate::block::write(&mut bytes, smallvec::smallvec![ header ], true, |_, writer| {
+            for (index_in_header_increasing_y, tile) in tiles {
+                writer.write_chunk(index_in_header_increasing_y, Chunk { layer_index: 0, compressed_block: CompressedBlock::Tile(CompressedTileBlock {
+                    coordinates: tile.location,
+                    compressed_pixels: vec![ 0; tile.size.area() * 4 ],
+                })})?;
+            }
+
+            Ok(())
+        }).unwrap();
 
+        bytes.into_inner()
+    }
+
+    fn chunk_order_warnings(bytes: Vec<u8>) -> usize {
+        let reader = crate::block::read(Cursor::new(bytes), false).unwrap();
+        let chunks = reader.filter_chunks(false, |_, _, _| true).unwrap();
+        chunks.warnings().iter().filter(|warning| matches!(warning, Warning::UnexpectedChunkOrder { .. })).count()
+    }
 
+    #[test]
+    fn decreasing_tiles_are_ordered_by_rows(){
+        fn level(tile: &TileIndices) -> usize { tile.location.level_index.x() }
+        fn row(tile: &TileIndices) -> i64 { tile.location.tile_index.y() as i64 }
+        fn column(tile: &TileIndices) -> i64 { tile.location.tile_index.x() as i64 }
 
+        // the reference implementation writes rows from the bottom up, but each row from left to right, and the largest level first
+        assert_eq!(chunk_order_warnings(decreasing_tiles(|tile| (level(tile), -row(tile), column(tile)))), 0);
+
+        // this library writes the tiles in exactly the reverse increasing order
+        assert_eq!(chunk_order_warnings(decreasing_tiles(|tile| (usize::MAX - level(tile), -row(tile), -column(tile)))), 0);
+
+        // rows from the top down are not decreasing
+        assert_eq!(chunk_order_warnings(decreasing_tiles(|tile| (level(tile), row(tile), column(tile)))), 1);
+    }
+}
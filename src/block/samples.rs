@@ -51,6 +51,30 @@ impl Sample {
         }
     }
 
+    /// Interpret the bits of a `u32` sample as an `f32`, without any numeric conversion.
+    /// Use this for float data that was packed into an integer channel.
+    /// Float samples are not reinterpreted, but only converted to `f32`, which is lossless.
+    #[inline]
+    pub fn reinterpret_as_f32(self) -> f32 {
+        match self {
+            Sample::F16(sample) => sample.to_f32(),
+            Sample::F32(sample) => sample,
+            Sample::U32(sample) => f32::from_bits(sample),
+        }
+    }
+
+    /// The raw bits of the sample, without any numeric conversion.
+    /// Use this to pack float data, such as normals or bitfields, into an integer channel.
+    /// The 16 bits of an `f16` sample are zero-extended.
+    #[inline]
+    pub fn as_u32_bits(self) -> u32 {
+        match self {
+            Sample::F16(sample) => sample.to_bits() as u32,
+            Sample::F32(sample) => sample.to_bits(),
+            Sample::U32(sample) => sample,
+        }
+    }
+
     /// Convert the sample to a u32. Rounds floats to integers the same way that `3.1 as u32` does.
     #[inline]
     pub fn to_u32(self) -> u32 {
@@ -246,3 +270,40 @@ impl IntoNativeSample for Sample {
 
 
 
+
+
+#[cfg(test)]
+mod test {
+    use crate::prelude::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn reinterpret_sample_bits(){
+        let payload = f32::from_bits(0x7fc0_1234); // a nan with payload
+        assert_eq!(Sample::F32(payload).as_u32_bits(), 0x7fc0_1234);
+        assert_eq!(Sample::U32(0x7fc0_1234).reinterpret_as_f32().to_bits(), 0x7fc0_1234);
+        assert_eq!(Sample::F16(f16::ONE).as_u32_bits(), f16::ONE.to_bits() as u32);
+        assert_eq!(Sample::U32(7).to_f32(), 7.0);
+    }
+
+    #[test]
+    fn packed_bits_survive_write_and_read(){
+        let values = vec![ f32::from_bits(0x7fc0_1234), -0.0, f32::MIN_POSITIVE / 4.0, 1.5 ];
+        let packed = FlatSamples::F32(values.clone()).as_u32_bits();
+
+        let image = Image::from_channels((2, 2), AnyChannels::sort(smallvec::smallvec![ AnyChannel::new("N", packed) ]));
+        let mut bytes = Vec::new();
+        image.write().to_buffered(Cursor::new(&mut bytes)).unwrap();
+
+        let image = read().no_deep_data().largest_resolution_level().all_channels().first_valid_layer().all_attributes()
+            .from_buffered(Cursor::new(bytes)).unwrap();
+
+        let restored = image.layer_data.channel_data.list[0].sample_data.clone().reinterpret_as_f32();
+        let bits = |samples: &[f32]| samples.iter().map(|sample| sample.to_bits()).collect::<Vec<u32>>();
+
+        match restored {
+            FlatSamples::F32(restored) => assert_eq!(bits(&restored), bits(&values)),
+            other => panic!("unexpected sample type: {:?}", other),
+        }
+    }
+}
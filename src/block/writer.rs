@@ -4,18 +4,16 @@
 use std::fmt::Debug;
 use std::io::Seek;
 use std::iter::Peekable;
-use std::ops::Not;
 use rayon_core::{ThreadPool, ThreadPoolBuildError};
 
 use smallvec::alloc::collections::BTreeMap;
 
 use crate::block::UncompressedBlock;
-use crate::block::chunk::{Chunk};
+use crate::block::chunk::{Chunk, CompressedBlock};
 use crate::compression::Compression;
 use crate::error::{Error, Result, UnitResult, usize_to_u64};
 use crate::io::{Data, Tracking, Write};
 use crate::meta::{Headers, MetaData, OffsetTables};
-use crate::meta::attribute::LineOrder;
 
 /// Write an exr file by writing one chunk after another in a closure.
 /// In the closure, you are provided a chunk writer, which should be used to write all the chunks.
@@ -23,9 +21,18 @@ use crate::meta::attribute::LineOrder;
 pub fn write_chunks_with<W: Write + Seek>(
     buffered_write: W, headers: Headers, pedantic: bool,
     write_chunks: impl FnOnce(MetaData, &mut ChunkWriter<W>) -> UnitResult
+) -> UnitResult {
+    write_chunks_with_validation(buffered_write, headers, Some(pedantic), write_chunks)
+}
+
+/// Like `write_chunks_with`, but if `validation` is `None`, the headers are not validated at all.
+/// Otherwise, the option contains whether the validation is pedantic.
+pub(crate) fn write_chunks_with_validation<W: Write + Seek>(
+    buffered_write: W, headers: Headers, validation: Option<bool>,
+    write_chunks: impl FnOnce(MetaData, &mut ChunkWriter<W>) -> UnitResult
 ) -> UnitResult {
     // this closure approach ensures that after writing all chunks, the file is always completed and checked and flushed
-    let (meta, mut writer) = ChunkWriter::new_for_buffered(buffered_write, headers, pedantic)?;
+    let (meta, mut writer) = ChunkWriter::new_for_buffered(buffered_write, headers, validation)?;
     write_chunks(meta, &mut writer)?;
     writer.complete_meta_data()
 }
@@ -44,6 +51,7 @@ pub struct ChunkWriter<W> {
     chunk_indices_byte_location: std::ops::Range<usize>,
     chunk_indices_increasing_y: OffsetTables,
     chunk_count: usize, // TODO compose?
+    layer_is_deep: Vec<bool>,
 }
 
 /// A new writer that triggers a callback
@@ -131,6 +139,16 @@ impl<W> ChunksWriter for ChunkWriter<W> where W: Write + Seek {
     /// may remain in an invalid state and should not be used further.
     /// Errors when the chunk at this index was already written.
     fn write_chunk(&mut self, index_in_header_increasing_y: usize, chunk: Chunk) -> UnitResult {
+        let chunk_is_deep = match chunk.compressed_block {
+            CompressedBlock::DeepScanLine(_) | CompressedBlock::DeepTile(_) => true,
+            CompressedBlock::ScanLine(_) | CompressedBlock::Tile(_) => false,
+        };
+
+        // flat and deep parts can be mixed in a multi-part file, but not within a single part
+        if self.layer_is_deep.get(chunk.layer_index) != Some(&chunk_is_deep) {
+            return Err(Error::invalid("chunk type does not match the type of its layer"));
+        }
+
         let header_chunk_indices = &mut self.chunk_indices_increasing_y[chunk.layer_index];
 
         if index_in_header_increasing_y >= header_chunk_indices.len() {
@@ -152,9 +170,13 @@ impl<W> ChunkWriter<W> where W: Write + Seek {
     // -- the following functions are private, because they must be called in a strict order --
 
     /// Writes the meta data and zeroed offset tables as a placeholder.
-    fn new_for_buffered(buffered_byte_writer: W, headers: Headers, pedantic: bool) -> Result<(MetaData, Self)> {
+    /// Does not validate the headers if `validation` is `None`.
+    fn new_for_buffered(buffered_byte_writer: W, headers: Headers, validation: Option<bool>) -> Result<(MetaData, Self)> {
         let mut write = Tracking::new(buffered_byte_writer);
-        let requirements = MetaData::write_validating_to_buffered(&mut write, headers.as_slice(), pedantic)?;
+        let requirements = match validation {
+            Some(pedantic) => MetaData::write_validating_to_buffered(&mut write, headers.as_slice(), pedantic)?,
+            None => MetaData::write_unvalidated_to_buffered(&mut write, headers.as_slice())?,
+        };
 
         // TODO: use increasing line order where possible, but this requires us to know whether we want to be parallel right now
         /*// if non-parallel compression, we always use increasing order anyways
@@ -178,6 +200,7 @@ impl<W> ChunkWriter<W> where W: Write + Seek {
         let chunk_indices_increasing_y = headers.iter()
             .map(|header| vec![0_u64; header.chunk_count]).collect();
 
+        let layer_is_deep = headers.iter().map(|header| header.deep).collect();
         let meta_data = MetaData { requirements, headers };
 
         Ok((meta_data, ChunkWriter {
@@ -186,6 +209,7 @@ impl<W> ChunkWriter<W> where W: Write + Seek {
             chunk_count: offset_table_size,
             chunk_indices_byte_location: offset_table_start_byte .. offset_table_end_byte,
             chunk_indices_increasing_y,
+            layer_is_deep,
         }))
     }
 
@@ -239,39 +263,34 @@ impl<'w, W, F> ChunksWriter for OnProgressChunkWriter<'w, W, F> where W: 'w + Ch
 
 
 /// Write blocks that appear in any order and reorder them before writing.
+/// The chunks are always written in the order in which the blocks were added,
+/// even if the line order is unspecified, so that parallel compression
+/// produces exactly the same file as sequential compression.
 #[derive(Debug)]
 #[must_use]
 pub struct SortedBlocksWriter<'w, W> {
     chunk_writer: &'w mut W,
     pending_chunks: BTreeMap<usize, (usize, Chunk)>,
     unwritten_chunk_indices: Peekable<std::ops::Range<usize>>,
-    requires_sorting: bool, // using this instead of Option, because of borrowing
 }
 
 
 impl<'w, W> SortedBlocksWriter<'w, W> where W: ChunksWriter {
 
-    /// New sorting writer. Returns `None` if sorting is not required.
-    pub fn new(meta_data: &MetaData, chunk_writer: &'w mut W) -> SortedBlocksWriter<'w, W> {
-        let requires_sorting = meta_data.headers.iter()
-            .any(|header| header.line_order != LineOrder::Unspecified);
-
+    /// New sorting writer. The meta data is not used anymore,
+    /// because the chunks are sorted regardless of the line order.
+    pub fn new(_meta_data: &MetaData, chunk_writer: &'w mut W) -> SortedBlocksWriter<'w, W> {
         let total_chunk_count = chunk_writer.total_chunks_count();
 
         SortedBlocksWriter {
             pending_chunks: BTreeMap::new(),
             unwritten_chunk_indices: (0 .. total_chunk_count).peekable(),
-            requires_sorting,
             chunk_writer
         }
     }
 
     /// Write the chunk or stash it. In the closure, write all chunks that can be written now.
     pub fn write_or_stash_chunk(&mut self, chunk_index_in_file: usize, chunk_y_index: usize, chunk: Chunk) -> UnitResult {
-        if self.requires_sorting.not() {
-            return self.chunk_writer.write_chunk(chunk_y_index, chunk);
-        }
-
         // write this chunk now if possible
         if self.unwritten_chunk_indices.peek() == Some(&chunk_index_in_file){
             self.chunk_writer.write_chunk(chunk_y_index, chunk)?;
@@ -369,6 +388,11 @@ impl<'w, W> ParallelBlocksCompressor<'w, W> where W: 'w + ChunksWriter {
             return None;
         }
 
+        // spawning threads would only slow down images with a single chunk
+        if chunks_writer.total_chunks_count() < 2 {
+            return None;
+        }
+
         // in case thread pool creation fails (for example on WASM currently),
         // we revert to sequential compression
         let pool = match try_create_thread_pool() {
@@ -397,6 +421,10 @@ impl<'w, W> ParallelBlocksCompressor<'w, W> where W: 'w + ChunksWriter {
     /// This is where the compressed blocks are written to.
     pub fn inner_chunks_writer(&'w self) -> &'w W { self.sorted_writer.inner_chunks_writer() }
 
+    /// Return the thread pool, so that it can be used again. Call this after all blocks have been written.
+    #[cfg(feature = "benchmark")]
+    pub(crate) fn into_thread_pool(self) -> ThreadPool { self.pool }
+
     // private, as may underflow counter in release mode
     fn write_next_queued_chunk(&mut self) -> UnitResult {
         debug_assert!(self.currently_compressing_count > 0, "cannot wait for chunks as there are none left");
@@ -466,3 +494,126 @@ impl<'w, W> ParallelBlocksCompressor<'w, W> where W: 'w + ChunksWriter {
 
 
 
+
+
+#[cfg(test)]
+mod test {
+    use std::io::Cursor;
+    use std::convert::TryInto;
+    use crate::prelude::*;
+    use crate::block::chunk::{Chunk, CompressedBlock, CompressedScanLineBlock, CompressedDeepScanLineBlock};
+    use crate::block::writer::ChunksWriter;
+    use crate::meta::BlockDescription;
+    use crate::meta::attribute::ChannelDescription;
+    use crate::meta::header::Header;
+
+    fn header(name: &str, deep: bool) -> Header {
+        let header = Header::new(
+            Text::from(name), (4, 4),
+            smallvec::smallvec![ ChannelDescription::named("Y", SampleType::F32) ]
+        );
+
+        let header = header.with_encoding(Compression::Uncompressed, BlockDescription::ScanLines, LineOrder::Increasing);
+        if !deep { return header }
+
+        Header { deep: true, deep_data_version: Some(1), max_samples_per_pixel: Some(1), .. header }
+    }
+
+    fn flat_chunk(y: usize) -> Chunk {
+        Chunk { layer_index: 0, compressed_block: CompressedBlock::ScanLine(CompressedScanLineBlock {
+            y_coordinate: y as i32,
+            compressed_pixels: vec![ 0; 4 * 4 ],
+        })}
+    }
+
+    fn deep_chunk(y: usize) -> Chunk {
+        let offsets = [1_i32, 2, 3, 4].iter().flat_map(|count| count.to_le_bytes()).map(|byte| byte as i8).collect();
+        let samples: Vec<u8> = [0.5_f32; 4].iter().flat_map(|sample| sample.to_le_bytes()).collect();
+
+        Chunk { layer_index: 1, compressed_block: CompressedBlock::DeepScanLine(CompressedDeepScanLineBlock {
+            y_coordinate: y as i32,
+            decompressed_sample_data_size: samples.len(),
+            compressed_pixel_offset_table: offsets,
+            compressed_sample_data: samples,
+        })}
+    }
+
+    #[test]
+    fn write_flat_and_deep_parts_into_one_file(){
+        let headers = smallvec::smallvec![ header("beauty", false), header("id", true) ];
+        let mut bytes = Cursor::new(Vec::new());
+
+        crate::block::write(&mut bytes, headers, true, |_, writer| {
+            for y in 0 .. 4 {
+                writer.write_chunk(y, flat_chunk(y))?;
+                writer.write_chunk(y, deep_chunk(y))?;
+            }
+
+            Ok(())
+        }).unwrap();
+
+        bytes.set_position(0);
+        let reader = crate::block::read(&mut bytes, true).unwrap();
+        assert!(reader.meta_data().requirements.has_deep_data);
+        assert!(reader.meta_data().requirements.has_multiple_layers);
+        assert_eq!(reader.headers().iter().map(|header| header.deep).collect::<Vec<_>>(), vec![ false, true ]);
+
+        let chunks: Vec<Chunk> = reader.all_chunks(true).unwrap().collect::<Result<_>>().unwrap();
+        let deep_samples: Vec<&[u8]> = chunks.iter()
+            .filter_map(|chunk| match chunk.compressed_block {
+                CompressedBlock::DeepScanLine(ref block) => Some(block.compressed_sample_data.as_slice()),
+                _ => None,
+            })
+            .collect();
+
+        assert_eq!(deep_samples.len(), 4);
+        assert!(deep_samples.iter().all(|&samples| samples == [0.5_f32; 4].iter().flat_map(|sample| sample.to_le_bytes()).collect::<Vec<u8>>()));
+
+        let flat_layer = read().no_deep_data().largest_resolution_level().all_channels()
+            .first_valid_layer().all_attributes()
+            .from_buffered(Cursor::new(bytes.into_inner())).unwrap();
+
+        assert_eq!(flat_layer.layer_data.attributes.layer_name, Some(Text::from("beauty")));
+    }
+
+    #[test]
+    fn refuse_invalid_flat_offset_next_to_deep_part(){
+        let headers = smallvec::smallvec![ header("beauty", false), header("id", true) ];
+        let mut bytes = Cursor::new(Vec::new());
+
+        crate::block::write(&mut bytes, headers, true, |_, writer| {
+            for y in 0 .. 4 {
+                writer.write_chunk(y, flat_chunk(y))?;
+                writer.write_chunk(y, deep_chunk(y))?;
+            }
+
+            Ok(())
+        }).unwrap();
+
+        // the first chunk is written directly after the offset tables of both layers
+        let mut bytes = bytes.into_inner();
+        let table_start = (0 .. bytes.len() - 8)
+            .find(|&start| u64::from_le_bytes(bytes[start .. start + 8].try_into().unwrap()) == (start + 8 * 8) as u64)
+            .unwrap();
+
+        let second_flat_offset = table_start + 8;
+        bytes[second_flat_offset .. second_flat_offset + 8].copy_from_slice(&(1_u64 << 40).to_le_bytes());
+
+        let reader = crate::block::read(Cursor::new(bytes), true).unwrap();
+        assert!(reader.headers()[1].max_pixel_file_bytes() < 1 << 40);
+        assert!(reader.all_chunks(true).is_err());
+    }
+
+    #[test]
+    fn refuse_chunk_of_wrong_type(){
+        let headers = smallvec::smallvec![ header("beauty", false), header("id", true) ];
+
+        let result = crate::block::write(Cursor::new(Vec::new()), headers, true, |_, writer| {
+            let mut chunk = flat_chunk(0);
+            chunk.layer_index = 1;
+            writer.write_chunk(0, chunk)
+        });
+
+        assert!(result.is_err());
+    }
+}
@@ -8,7 +8,6 @@ use crate::prelude::*;
 use std::cmp::min;
 use std::mem::size_of;
 use table::{EXP_TABLE, LOG_TABLE};
-use lebe::io::{ReadPrimitive, WriteEndian};
 
 const BLOCK_SAMPLE_COUNT: usize = 4;
 
@@ -251,6 +250,22 @@ fn memcpy_u8_to_u16(mut src: &[u8], dst: &mut [u16]) {
     src.read_from_native_endian_into(dst).expect("byte copy error");
 }
 
+/// Copy 16-bit values, converting little endian bytes to native endian bytes or vice versa.
+/// This is a plain copy on little endian processors.
+#[inline]
+fn copy_u16_bytes_between_native_and_little_endian(source: &[u8], target: &mut [u8]) {
+    debug_assert_eq!(source.len(), target.len());
+
+    #[cfg(target_endian = "little")]
+    target.copy_from_slice(source);
+
+    #[cfg(target_endian = "big")]
+    for (source, target) in source.chunks_exact(2).zip(target.chunks_exact_mut(2)) {
+        target[0] = source[1];
+        target[1] = source[0];
+    }
+}
+
 #[inline]
 fn cpy_u8(src: &[u16], src_i: usize, dst: &mut [u8], dst_i: usize, n: usize) {
     memcpy_u16_to_u8(&src[src_i..src_i + n], &mut dst[dst_i..dst_i + 2 * n]);
@@ -448,19 +463,11 @@ pub fn decompress(
 
             channel.tmp_end_index = next_tmp_end_index;
 
-            // TODO do not convert endianness for f16-only images
-            //      see https://github.com/AcademySoftwareFoundation/openexr/blob/3bd93f85bcb74c77255f28cdbb913fdbfbb39dfe/OpenEXR/IlmImf/ImfTiledOutputFile.cpp#L750-L842
-            // We can support uncompressed data in the machine's native format
-            // if all image channels are of type HALF, and if the Xdr and the
-            // native representations of a half have the same size.
-
             if channel.sample_type == SampleType::F16 {
-                // TODO simplify this and make it memcpy on little endian systems
-                // https://github.com/AcademySoftwareFoundation/openexr/blob/a03aca31fa1ce85d3f28627dbb3e5ded9494724a/src/lib/OpenEXR/ImfB44Compressor.cpp#L943
-                for mut f16_bytes in channel_bytes.chunks(std::mem::size_of::<f16>()) {
-                    let native_endian_f16_bits = u16::read_from_little_endian(&mut f16_bytes).expect("memory read failed");
-                    out.write_as_native_endian(&native_endian_f16_bits).expect("memory write failed");
-                }
+                // the temporary buffer contains native endian f16 bits, but we output little endian bytes
+                let start = out.len();
+                out.resize(start + channel_bytes.len(), 0);
+                copy_u16_bytes_between_native_and_little_endian(channel_bytes, &mut out[start..]);
             }
             else {
                 u8::write_slice(&mut out, channel_bytes)
@@ -478,9 +485,7 @@ pub fn decompress(
 
     debug_assert_eq!(out.len(), expected_byte_size);
 
-    // TODO do not convert endianness for f16-only images
-    //      see https://github.com/AcademySoftwareFoundation/openexr/blob/3bd93f85bcb74c77255f28cdbb913fdbfbb39dfe/OpenEXR/IlmImf/ImfTiledOutputFile.cpp#L750-L842
-    Ok(super::convert_little_endian_to_current(out, channels, rectangle))
+    Ok(out)
 }
 
 pub fn compress(
@@ -493,10 +498,7 @@ pub fn compress(
         return Ok(Vec::new());
     }
 
-    // TODO do not convert endianness for f16-only images
-    //      see https://github.com/AcademySoftwareFoundation/openexr/blob/3bd93f85bcb74c77255f28cdbb913fdbfbb39dfe/OpenEXR/IlmImf/ImfTiledOutputFile.cpp#L750-L842
-    let uncompressed = super::convert_current_to_little_endian(uncompressed, channels, rectangle);
-    let uncompressed = uncompressed.as_slice(); // TODO no alloc
+    let uncompressed = uncompressed.as_slice();
 
     let mut channel_data = Vec::new();
 
@@ -540,21 +542,11 @@ pub fn compress(
 
             channel.tmp_end_index = next_tmp_end_index;
 
-            // TODO do not convert endianness for f16-only images
-            //      see https://github.com/AcademySoftwareFoundation/openexr/blob/3bd93f85bcb74c77255f28cdbb913fdbfbb39dfe/OpenEXR/IlmImf/ImfTiledOutputFile.cpp#L750-L842
-            // We can support uncompressed data in the machine's native format
-            // if all image channels are of type HALF, and if the Xdr and the
-            // native representations of a half have the same size.
-
             if channel.sample_type == SampleType::F16 {
-
-                // TODO simplify this and make it memcpy on little endian systems
-                // https://github.com/AcademySoftwareFoundation/openexr/blob/a03aca31fa1ce85d3f28627dbb3e5ded9494724a/src/lib/OpenEXR/ImfB44Compressor.cpp#L640
-
-                for mut out_f16_bytes in target.chunks_mut(2) {
-                    let native_endian_f16_bits = u16::read_from_native_endian(&mut remaining_uncompressed_bytes).expect("memory read failed");
-                    out_f16_bytes.write_as_little_endian(&native_endian_f16_bits).expect("memory write failed");
-                }
+                // the input contains little endian bytes, but the temporary buffer requires native endian f16 bits
+                let (line_bytes, rest) = remaining_uncompressed_bytes.split_at(target.len());
+                copy_u16_bytes_between_native_and_little_endian(line_bytes, target);
+                remaining_uncompressed_bytes = rest;
             }
             else {
                 u8::read_slice(&mut remaining_uncompressed_bytes, target)
@@ -678,6 +670,24 @@ mod test {
     use crate::prelude::f16;
     use crate::prelude::*;
 
+    #[test]
+    fn copy_u16_bytes_independent_of_native_endianness() {
+        let values = [ 1_u16, 0x1234, 0xabcd ];
+        let little_endian: Vec<u8> = values.iter().flat_map(|value| value.to_le_bytes()).collect();
+
+        let mut native = vec![ 0_u8; little_endian.len() ];
+        b44::copy_u16_bytes_between_native_and_little_endian(&little_endian, &mut native);
+
+        let native_values: Vec<u16> = native.chunks_exact(2)
+            .map(|bytes| u16::from_ne_bytes([ bytes[0], bytes[1] ])).collect();
+
+        assert_eq!(native_values, values);
+
+        let mut converted_back = vec![ 0_u8; native.len() ];
+        b44::copy_u16_bytes_between_native_and_little_endian(&native, &mut converted_back);
+        assert_eq!(converted_back, little_endian);
+    }
+
     #[test]
     fn test_convert_from_to_linear() {
         // Create two identical arrays with random floats.
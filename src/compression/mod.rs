@@ -11,9 +11,6 @@ mod pxr24;
 mod b44;
 
 
-use std::convert::TryInto;
-use std::mem::size_of;
-use half::f16;
 use crate::meta::attribute::{IntegerBounds, SampleType, ChannelList};
 use crate::error::{Result, Error, usize_to_i32};
 use crate::meta::header::Header;
@@ -25,6 +22,10 @@ pub type ByteVec = Vec<u8>;
 /// A byte slice.
 pub type Bytes<'s> = &'s [u8];
 
+/// The deflate level used when writing images with `ZIP1`, `ZIP16` or `PXR24` compression.
+/// Balances the file size and the time required to compress the pixels.
+pub const DEFAULT_DEFLATE_LEVEL: u8 = 4;
+
 /// Specifies which compression method to use.
 /// Use uncompressed data for fastest loading and writing speeds.
 /// Use RLE compression for fast loading and writing with slight memory savings.
@@ -159,7 +160,20 @@ impl std::fmt::Display for Compression {
 impl Compression {
 
     /// Compress the image section of bytes.
-    pub fn compress_image_section(self, header: &Header, uncompressed_native_endian: ByteVec, pixel_section: IntegerBounds) -> Result<ByteVec> {
+    /// The uncompressed bytes are always little endian, regardless of the processor architecture,
+    /// just like the bytes produced by the `io::Data` trait.
+    pub fn compress_image_section(self, header: &Header, uncompressed_little_endian: ByteVec, pixel_section: IntegerBounds) -> Result<ByteVec> {
+        self.compress_image_section_with_deflate_level(header, uncompressed_little_endian, pixel_section, DEFAULT_DEFLATE_LEVEL)
+    }
+
+    /// Compress the image section of bytes, using the specified effort for the methods based on deflate,
+    /// which are `ZIP1`, `ZIP16` and `PXR24`. The level ranges from `0` (fastest) to `10` (smallest).
+    /// Other methods ignore the level. The level does not affect the decompressed pixels.
+    pub fn compress_image_section_with_deflate_level(
+        self, header: &Header, uncompressed_little_endian: ByteVec,
+        pixel_section: IntegerBounds, deflate_level: u8
+    ) -> Result<ByteVec>
+    {
         let max_tile_size = header.max_block_pixel_size();
 
         assert!(pixel_section.validate(Some(max_tile_size)).is_ok(), "decompress tile coordinate bug");
@@ -167,20 +181,16 @@ impl Compression {
 
         use self::Compression::*;
         let compressed_little_endian = match self {
-            Uncompressed => {
-                return Ok(convert_current_to_little_endian(
-                    uncompressed_native_endian, &header.channels, pixel_section
-                ))
-            },
+            Uncompressed => return Ok(uncompressed_little_endian),
 
             // we need to clone here, because we might have to fallback to the uncompressed data later (when compressed data is larger than raw data)
-            ZIP16 => zip::compress_bytes(&header.channels, uncompressed_native_endian.clone(), pixel_section),
-            ZIP1 => zip::compress_bytes(&header.channels, uncompressed_native_endian.clone(), pixel_section),
-            RLE => rle::compress_bytes(&header.channels, uncompressed_native_endian.clone(), pixel_section),
-            PIZ => piz::compress(&header.channels, uncompressed_native_endian.clone(), pixel_section),
-            PXR24 => pxr24::compress(&header.channels, uncompressed_native_endian.clone(), pixel_section),
-            B44 => b44::compress(&header.channels, uncompressed_native_endian.clone(), pixel_section, false),
-            B44A => b44::compress(&header.channels, uncompressed_native_endian.clone(), pixel_section, true),
+            ZIP16 => zip::compress_bytes(uncompressed_little_endian.clone(), deflate_level),
+            ZIP1 => zip::compress_bytes(uncompressed_little_endian.clone(), deflate_level),
+            RLE => rle::compress_bytes(uncompressed_little_endian.clone()),
+            PIZ => piz::compress(&header.channels, uncompressed_little_endian.clone(), pixel_section),
+            PXR24 => pxr24::compress(&header.channels, uncompressed_little_endian.clone(), pixel_section, deflate_level),
+            B44 => b44::compress(&header.channels, uncompressed_little_endian.clone(), pixel_section, false),
+            B44A => b44::compress(&header.channels, uncompressed_little_endian.clone(), pixel_section, true),
             _ => return Err(Error::unsupported(format!("yet unimplemented compression method: {}", self)))
         };
 
@@ -188,17 +198,19 @@ impl Compression {
             Error::invalid(format!("pixels cannot be compressed ({})", self))
         )?;
 
-        if self == Uncompressed || compressed_little_endian.len() < uncompressed_native_endian.len() {
+        if self == Uncompressed || compressed_little_endian.len() < uncompressed_little_endian.len() {
             // only write compressed if it actually is smaller than raw
             Ok(compressed_little_endian)
         }
         else {
-            // if we do not use compression, manually convert uncompressed data
-            Ok(convert_current_to_little_endian(uncompressed_native_endian, &header.channels, pixel_section))
+            // the raw data is already in the file format
+            Ok(uncompressed_little_endian)
         }
     }
 
     /// Decompress the image section of bytes.
+    /// The decompressed bytes are always little endian, regardless of the processor architecture,
+    /// just like the bytes expected by the `io::Data` trait.
     pub fn decompress_image_section(self, header: &Header, compressed: ByteVec, pixel_section: IntegerBounds, pedantic: bool) -> Result<ByteVec> {
         let max_tile_size = header.max_block_pixel_size();
 
@@ -210,15 +222,15 @@ impl Compression {
         // note: always true where self == Uncompressed
         if compressed.len() == expected_byte_size {
             // the compressed data was larger than the raw data, so the small raw data has been written
-            Ok(convert_little_endian_to_current(compressed, &header.channels, pixel_section))
+            Ok(compressed)
         }
         else {
             use self::Compression::*;
             let bytes = match self {
-                Uncompressed => Ok(convert_little_endian_to_current(compressed, &header.channels, pixel_section)),
-                ZIP16 => zip::decompress_bytes(&header.channels, compressed, pixel_section, expected_byte_size, pedantic),
-                ZIP1 => zip::decompress_bytes(&header.channels, compressed, pixel_section, expected_byte_size, pedantic),
-                RLE => rle::decompress_bytes(&header.channels, compressed, pixel_section, expected_byte_size, pedantic),
+                Uncompressed => Ok(compressed),
+                ZIP16 => zip::decompress_bytes(compressed, expected_byte_size, pedantic),
+                ZIP1 => zip::decompress_bytes(compressed, expected_byte_size, pedantic),
+                RLE => rle::decompress_bytes(compressed, expected_byte_size, pedantic),
                 PIZ => piz::decompress(&header.channels, compressed, pixel_section, expected_byte_size, pedantic),
                 PXR24 => pxr24::decompress(&header.channels, compressed, pixel_section, expected_byte_size, pedantic),
                 B44 | B44A => b44::decompress(&header.channels, compressed, pixel_section, expected_byte_size, pedantic),
@@ -245,6 +257,65 @@ impl Compression {
         }
     }
 
+    /// Decompress the image section of bytes, and attempt to recover damaged data if that fails.
+    /// The following strategies are tried in order, until one of them succeeds:
+    /// 1. If the data is at least as large as the uncompressed section, it is treated as uncompressed,
+    ///    because compressed data is only stored if it is smaller than the uncompressed data.
+    ///    Any trailing bytes are ignored.
+    /// 2. Zip and RLE data is decompressed ignoring the checksum and any trailing bytes.
+    /// 3. PIZ data is decompressed with invalid huffman codes skipped and out-of-range values clamped.
+    ///
+    /// Returns the original error if no strategy succeeds.
+    /// The recovered pixels may be partially wrong, so this should only be used
+    /// to rescue as much as possible from damaged files.
+    pub fn decompress_image_section_with_recovery(self, header: &Header, compressed: ByteVec, pixel_section: IntegerBounds) -> Result<ByteVec> {
+        let error = match self.decompress_image_section(header, compressed.clone(), pixel_section, false) {
+            Ok(decompressed) => return Ok(decompressed),
+            Err(error) => error,
+        };
+
+        let expected_byte_size = pixel_section.size.area() * header.channels.bytes_per_pixel;
+
+        if compressed.len() >= expected_byte_size {
+            let mut raw = compressed;
+            raw.truncate(expected_byte_size);
+            return Ok(raw);
+        }
+
+        use self::Compression::*;
+        let recovered = match self {
+            ZIP16 | ZIP1 => zip::decompress_bytes_ignoring_trailing_data(compressed, expected_byte_size),
+            RLE => rle::decompress_bytes_ignoring_trailing_data(compressed, expected_byte_size),
+            PIZ => piz::decompress_clamping_invalid_codes(&header.channels, compressed, pixel_section, expected_byte_size),
+            _ => return Err(error),
+        };
+
+        match recovered {
+            Ok(bytes) if bytes.len() == expected_byte_size => Ok(bytes),
+            _ => Err(error),
+        }
+    }
+
+    /// Decompress the pixel offset table or the sample data of a deep block.
+    /// Deep data is compressed as a plain sequence of bytes, without regard to the channels.
+    /// As with flat data, the bytes are stored uncompressed if compression would not make them smaller.
+    pub fn decompress_deep_bytes(self, compressed: ByteVec, expected_byte_size: usize, pedantic: bool) -> Result<ByteVec> {
+        if compressed.len() == expected_byte_size {
+            return Ok(compressed);
+        }
+
+        use self::Compression::*;
+        let bytes = match self {
+            Uncompressed => Ok(compressed),
+            ZIP1 | ZIP16 => zip::decompress_bytes(compressed, expected_byte_size, pedantic),
+            RLE => rle::decompress_bytes(compressed, expected_byte_size, pedantic),
+            _ => return Err(Error::unsupported(format!("deep data compressed with {}", self)))
+        }?;
+
+        if bytes.len() != expected_byte_size { Err(Error::invalid("decompressed deep data")) }
+        else { Ok(bytes) }
+    }
+
     /// For scan line images and deep scan line images, one or more scan lines may be
     /// stored together as a scan line block. The number of scan lines per block
     /// depends on how the pixel data are compressed.
@@ -262,7 +333,7 @@ impl Compression {
     pub fn supports_deep_data(self) -> bool {
         use self::Compression::*;
         match self {
-            Uncompressed | RLE | ZIP1 => true,
+            Uncompressed | RLE | ZIP1 | ZIP16 => true,
             _ => false,
         }
     }
@@ -301,72 +372,6 @@ impl Compression {
 
 }
 
-// see https://github.com/AcademySoftwareFoundation/openexr/blob/6a9f8af6e89547bcd370ae3cec2b12849eee0b54/OpenEXR/IlmImf/ImfMisc.cpp#L1456-L1541
-
-#[allow(unused)] // allows the extra parameters to be unused
-fn convert_current_to_little_endian(mut bytes: ByteVec, channels: &ChannelList, rectangle: IntegerBounds) -> ByteVec {
-    #[cfg(target = "big_endian")]
-    reverse_block_endianness(&mut byte_vec, channels, rectangle);
-
-    bytes
-}
-
-#[allow(unused)] // allows the extra parameters to be unused
-fn convert_little_endian_to_current(mut bytes: ByteVec, channels: &ChannelList, rectangle: IntegerBounds) -> ByteVec {
-    #[cfg(target = "big_endian")]
-    reverse_block_endianness(&mut bytes, channels, rectangle);
-
-    bytes
-}
-
-#[allow(unused)] // unused when on little endian system
-fn reverse_block_endianness(bytes: &mut [u8], channels: &ChannelList, rectangle: IntegerBounds){
-    let mut remaining_bytes: &mut [u8] = bytes;
-
-    for y in rectangle.position.y() .. rectangle.end().y() {
-        for channel in &channels.list {
-            let line_is_subsampled = mod_p(y, usize_to_i32(channel.sampling.y())) != 0;
-            if line_is_subsampled { continue; }
-
-            let sample_count = rectangle.size.width() / channel.sampling.x();
-
-            match channel.sample_type {
-                SampleType::F16 => remaining_bytes = chomp_convert_n::<f16>(reverse_2_bytes, remaining_bytes, sample_count),
-                SampleType::F32 => remaining_bytes = chomp_convert_n::<f32>(reverse_4_bytes, remaining_bytes, sample_count),
-                SampleType::U32 => remaining_bytes = chomp_convert_n::<u32>(reverse_4_bytes, remaining_bytes, sample_count),
-            }
-        }
-    }
-
-    #[inline]
-    fn chomp_convert_n<T>(convert_single_value: fn(&mut[u8]), mut bytes: &mut [u8], count: usize) -> &mut [u8] {
-        let type_size = size_of::<T>();
-        let (line_bytes, rest) = bytes.split_at_mut(count * type_size);
-        let value_byte_chunks = line_bytes.chunks_exact_mut(type_size);
-
-        for value_bytes in value_byte_chunks {
-            convert_single_value(value_bytes);
-        }
-
-        rest
-    }
-
-    debug_assert!(remaining_bytes.is_empty(), "not all bytes were converted to little endian");
-}
-
-#[inline]
-fn reverse_2_bytes(bytes: &mut [u8]){
-    // this code seems like it could be optimized easily by the compiler
-    let two_bytes: [u8; 2] = bytes.try_into().expect("invalid byte count");
-    bytes.copy_from_slice(&[two_bytes[1], two_bytes[0]]);
-}
-
-#[inline]
-fn reverse_4_bytes(bytes: &mut [u8]){
-    let four_bytes: [u8; 4] = bytes.try_into().expect("invalid byte count");
-    bytes.copy_from_slice(&[four_bytes[3], four_bytes[2], four_bytes[1], four_bytes[0]]);
-}
-
 #[inline]
 fn div_p (x: i32, y: i32) -> i32 {
     if x >= 0 {
@@ -623,44 +628,155 @@ pub fn separate_bytes_fragments(source: &mut [u8]) {
 #[cfg(test)]
 pub mod test {
     use super::*;
-    use crate::meta::attribute::ChannelDescription;
+    use crate::meta::attribute::{ChannelDescription, LineOrder, TileDescription, LevelMode};
+    use crate::math::{Vec2, RoundingMode};
+    use crate::meta::BlockDescription;
     use crate::block::samples::IntoNativeSample;
+    use crate::io::Data;
+    use lebe::io::WriteEndian;
+    use std::convert::TryInto;
+    use half::f16;
 
-    #[test]
-    fn roundtrip_endianness_mixed_channels(){
+    // the samples of a 3x2 block with one f32 and one f16 channel, line by line
+    fn mixed_channels() -> ChannelList {
         let a32 = ChannelDescription::new("A", SampleType::F32, true);
         let y16 = ChannelDescription::new("Y", SampleType::F16, true);
-        let channels = ChannelList::new(smallvec![ a32, y16 ]);
-
-        let data = vec![
-            23582740683_f32.to_ne_bytes().as_slice(),
-            35827420683_f32.to_ne_bytes().as_slice(),
-            27406832358_f32.to_f16().to_ne_bytes().as_slice(),
-            74062358283_f32.to_f16().to_ne_bytes().as_slice(),
-
-            52582740683_f32.to_ne_bytes().as_slice(),
-            45827420683_f32.to_ne_bytes().as_slice(),
-            15406832358_f32.to_f16().to_ne_bytes().as_slice(),
-            65062358283_f32.to_f16().to_ne_bytes().as_slice(),
-        ].into_iter().flatten().map(|x| *x).collect();
-
-        roundtrip_convert_endianness(
-            data, &channels,
-            IntegerBounds::from_dimensions((2, 2))
-        );
+        ChannelList::new(smallvec![ a32, y16 ])
+    }
+
+    fn mixed_samples() -> (Vec<f32>, Vec<f16>) {
+        let f32s = vec![ 23582740683_f32, 35827420683_f32, -0.25, 52582740683_f32, 45827420683_f32, 1.0 ];
+        let f16s = [ 27406.832358_f32, 7406.2358283, 0.5, 15406.832358, 65.062358283, -3.0 ];
+        (f32s, f16s.iter().map(|&value| value.to_f16()).collect())
+    }
+
+    /// Write the samples in the layout of an uncompressed block,
+    /// either using `io::Data` (always little endian),
+    /// or simulating the native memory of a big endian processor.
+    fn mixed_block_bytes(big_endian: bool) -> ByteVec {
+        let (f32s, f16s) = mixed_samples();
+        let mut bytes = Vec::new();
+
+        for (f32_line, f16_line) in f32s.chunks(3).zip(f16s.chunks(3)) {
+            if big_endian {
+                bytes.write_as_big_endian(f32_line).unwrap();
+                bytes.write_as_big_endian(f16_line.iter().map(|value| value.to_bits()).collect::<Vec<u16>>().as_slice()).unwrap();
+            }
+            else {
+                f32::write_slice(&mut bytes, f32_line).unwrap();
+                f16::write_slice(&mut bytes, f16_line).unwrap();
+            }
+        }
+
+        bytes
+    }
+
+    #[test]
+    fn reverse_endianness_mixed_channels(){
+        let rectangle = IntegerBounds::from_dimensions((3, 2));
+        let little_endian = mixed_block_bytes(false);
+        let big_endian = mixed_block_bytes(true);
+        assert_ne!(little_endian, big_endian);
+
+        let mut reversed = little_endian.clone();
+        reverse_block_endianness(&mut reversed, &mixed_channels(), rectangle);
+        assert_eq!(reversed, big_endian, "endianness conversion failed");
+
+        reverse_block_endianness(&mut reversed, &mixed_channels(), rectangle);
+        assert_eq!(reversed, little_endian, "endianness conversion failed");
+    }
+
+    #[test]
+    fn lossless_roundtrip_is_independent_of_native_endianness(){
+        let rectangle = IntegerBounds::from_dimensions((3, 2));
+        let (f32s, f16s) = mixed_samples();
+
+        let tiles = BlockDescription::Tiles(TileDescription {
+            tile_size: Vec2(16, 16), level_mode: LevelMode::Singular, rounding_mode: RoundingMode::Down
+        });
+
+        for &compression in &[ Compression::Uncompressed, Compression::RLE, Compression::ZIP1, Compression::ZIP16, Compression::PIZ ] {
+            let header = Header::new("".try_into().unwrap(), rectangle.size, mixed_channels().list)
+                .with_encoding(compression, tiles, LineOrder::Increasing);
+
+            let compressed = compression.compress_image_section(&header, mixed_block_bytes(false), rectangle).unwrap();
+            let decompressed = compression.decompress_image_section(&header, compressed, rectangle, true).unwrap();
+
+            // the decompressed bytes must be readable by `io::Data`, on any processor
+            let mut read = decompressed.as_slice();
+            for (f32_line, f16_line) in f32s.chunks(3).zip(f16s.chunks(3)) {
+                assert_eq!(f32::read_vec(&mut read, 3, 3, None, "test").unwrap(), f32_line, "{}", compression);
+                assert_eq!(f16::read_vec(&mut read, 3, 3, None, "test").unwrap(), f16_line, "{}", compression);
+            }
+
+            assert!(read.is_empty());
+        }
     }
 
-    fn roundtrip_convert_endianness(
-        current_endian: ByteVec, channels: &ChannelList, rectangle: IntegerBounds
-    ){
-        let little_endian = convert_current_to_little_endian(
-            current_endian.clone(), channels, rectangle
-        );
+    #[test]
+    fn recovery_rescues_damaged_sections(){
+        let rectangle = IntegerBounds::from_dimensions((64, 64));
+        let channels = ChannelList::new(smallvec![ ChannelDescription::new("Y", SampleType::F16, true) ]);
+
+        let tiles = BlockDescription::Tiles(TileDescription {
+            tile_size: Vec2(64, 64), level_mode: LevelMode::Singular, rounding_mode: RoundingMode::Down
+        });
+
+        let mut uncompressed = Vec::new();
+        let samples: Vec<f16> = (0 .. 64 * 64).map(|index| f16::from_f32((index % 64) as f32)).collect();
+        f16::write_slice(&mut uncompressed, &samples).unwrap();
+
+        for &compression in &[ Compression::RLE, Compression::ZIP16, Compression::PIZ ] {
+            let header = Header::new("".try_into().unwrap(), rectangle.size, channels.list.clone())
+                .with_encoding(compression, tiles, LineOrder::Increasing);
+
+            let mut damaged = compression.compress_image_section(&header, uncompressed.clone(), rectangle).unwrap();
+            assert!(damaged.len() < uncompressed.len(), "{} did not compress", compression);
+
+            // zip verifies a checksum at the end of the stream
+            if compression == Compression::ZIP16 { *damaged.last_mut().unwrap() ^= 1; }
+            damaged.extend_from_slice(&[ 0xff; 7 ]);
+
+            assert!(compression.decompress_image_section(&header, damaged.clone(), rectangle, true).is_err(), "{}", compression);
+
+            let recovered = compression.decompress_image_section_with_recovery(&header, damaged, rectangle);
+            if compression == Compression::PIZ { assert_eq!(recovered.unwrap().len(), uncompressed.len()); }
+            else { assert_eq!(recovered.unwrap(), uncompressed, "{}", compression); }
+        }
+
+        // data larger than the raw section cannot be compressed, so it must be raw data followed by garbage
+        let header = Header::new("".try_into().unwrap(), rectangle.size, channels.list.clone())
+            .with_encoding(Compression::ZIP16, tiles, LineOrder::Increasing);
 
-        let current_endian_decoded = convert_little_endian_to_current(
-            little_endian.clone(), channels, rectangle
-        );
+        let mut raw_with_garbage = uncompressed.clone();
+        raw_with_garbage.push(0);
 
-        assert_eq!(current_endian, current_endian_decoded, "endianness conversion failed");
+        let recovered = Compression::ZIP16.decompress_image_section_with_recovery(&header, raw_with_garbage, rectangle);
+        assert_eq!(recovered.unwrap(), uncompressed);
     }
-}
\ No newline at end of file
+
+    /// Convert the little endian bytes of a block to
+    /// the bytes a big endian processor would hold in memory, or the other way around.
+    fn reverse_block_endianness(bytes: &mut [u8], channels: &ChannelList, rectangle: IntegerBounds){
+        let mut remaining_bytes: &mut [u8] = bytes;
+
+        for y in rectangle.position.y() .. rectangle.end().y() {
+            for channel in &channels.list {
+                let line_is_subsampled = mod_p(y, usize_to_i32(channel.sampling.y())) != 0;
+                if line_is_subsampled { continue; }
+
+                let sample_count = rectangle.size.width() / channel.sampling.x();
+                let sample_size = channel.sample_type.bytes_per_sample();
+
+                let (line_bytes, rest) = remaining_bytes.split_at_mut(sample_count * sample_size);
+                for value_bytes in line_bytes.chunks_exact_mut(sample_size) {
+                    value_bytes.reverse();
+                }
+
+                remaining_bytes = rest;
+            }
+        }
+
+        assert!(remaining_bytes.is_empty(), "not all bytes were converted");
+    }
+}
@@ -16,6 +16,16 @@ use smallvec::SmallVec;
 
 
 pub fn decompress(compressed: &[u8], expected_size: usize) -> Result<Vec<u16>> {
+    decompress_with(compressed, expected_size, false)
+}
+
+/// Decode damaged data as well as possible: codes that are not in the table are skipped,
+/// values and run lengths that are out of range are clamped, and missing values are zero.
+pub fn decompress_clamping_invalid_codes(compressed: &[u8], expected_size: usize) -> Result<Vec<u16>> {
+    decompress_with(compressed, expected_size, true)
+}
+
+fn decompress_with(compressed: &[u8], expected_size: usize, clamp_invalid_codes: bool) -> Result<Vec<u16>> {
     let mut remaining_compressed = compressed;
 
     let min_code_index = usize::try_from(u32::read(&mut remaining_compressed)?)?;
@@ -45,6 +55,7 @@ pub fn decompress(compressed: &[u8], expected_size: usize) -> Result<Vec<u16>> {
         i32::try_from(bit_count)?,
         max_code_index_32,
         expected_size,
+        clamp_invalid_codes,
     )?;
 
     Ok(result)
@@ -127,6 +138,7 @@ fn decode_with_tables(
     input_bit_count: i32,
     run_length_code: u32,
     expected_output_size: usize,
+    clamp_invalid_codes: bool,
 ) -> Result<Vec<u16>>
 {
     let mut output = Vec::with_capacity(expected_output_size);
@@ -153,6 +165,7 @@ fn decode_with_tables(
                     &mut input,
                     &mut output,
                     expected_output_size,
+                    clamp_invalid_codes,
                 )?;
             }
             else if let Code::Long(ref long_codes) = code {
@@ -180,19 +193,30 @@ fn decode_with_tables(
                         None
 
                     })
-                    .next()
-                    .ok_or(Error::invalid(INVALID_CODE))?;
+                    .next();
+
+                let long_code = match long_code {
+                    Some(long_code) => long_code?,
+
+                    // skip a single bit and attempt to find the next valid code
+                    None if clamp_invalid_codes => { code_bit_count -= 1; continue; },
+                    None => return Err(Error::invalid(INVALID_CODE)),
+                };
 
                 read_code_into_vec(
-                    long_code?,
+                    long_code,
                     run_length_code,
                     &mut code_bits,
                     &mut code_bit_count,
                     &mut input,
                     &mut output,
                     expected_output_size,
+                    clamp_invalid_codes,
                 )?;
             }
+            else if clamp_invalid_codes {
+                code_bit_count -= 1;
+            }
             else {
                 return Err(Error::invalid(INVALID_CODE));
             }
@@ -201,32 +225,38 @@ fn decode_with_tables(
 
     let count = u64::try_from((8 - input_bit_count) & 7)?;
     code_bits >>= count;
-    code_bit_count -= count;
+    code_bit_count = if clamp_invalid_codes { code_bit_count.saturating_sub(count) } else { code_bit_count - count };
 
     while code_bit_count > 0 {
         let index = (code_bits << (DECODE_BITS - code_bit_count)) & DECODE_MASK;
         let code = &decoding_table[u64_to_usize(index)];
 
-        if let Code::Short(short_code) = code {
-            if short_code.len() > code_bit_count { return Err(Error::invalid("code")) }; // FIXME why does this happen??
-            code_bit_count -= short_code.len(); // FIXME may throw "attempted to subtract with overflow"
+        match code {
+            Code::Short(short_code) if short_code.len() <= code_bit_count => {
+                code_bit_count -= short_code.len(); // FIXME may throw "attempted to subtract with overflow"
 
-            read_code_into_vec(
-                short_code.value,
-                run_length_code,
-                &mut code_bits,
-                &mut code_bit_count,
-                &mut input,
-                &mut output,
-                expected_output_size,
-            )?;
-        }
-        else {
-            return Err(Error::invalid(INVALID_CODE));
+                read_code_into_vec(
+                    short_code.value,
+                    run_length_code,
+                    &mut code_bits,
+                    &mut code_bit_count,
+                    &mut input,
+                    &mut output,
+                    expected_output_size,
+                    clamp_invalid_codes,
+                )?;
+            },
+
+            _ if clamp_invalid_codes => break,
+            Code::Short(_) => return Err(Error::invalid("code")), // FIXME why does this happen??
+            _ => return Err(Error::invalid(INVALID_CODE)),
         }
     }
 
-    if output.len() != expected_output_size {
+    if clamp_invalid_codes {
+        output.resize(expected_output_size, 0);
+    }
+    else if output.len() != expected_output_size {
         return Err(Error::invalid(NOT_ENOUGH_DATA));
     }
 
@@ -368,6 +398,7 @@ fn read_code_into_vec(
     read: &mut impl Read,
     out: &mut Vec<u16>,
     max_len: usize,
+    clamp_invalid_codes: bool,
 ) -> UnitResult
 {
     if code == run_length_code { // code may be too large for u16
@@ -377,22 +408,26 @@ fn read_code_into_vec(
 
         *code_bit_count -= 8;
 
-        let code_repetitions = usize::from((*code_bits >> *code_bit_count) as u8);
+        let mut code_repetitions = usize::from((*code_bits >> *code_bit_count) as u8);
 
         if out.len() + code_repetitions > max_len {
-            return Err(Error::invalid(TOO_MUCH_DATA));
+            if clamp_invalid_codes { code_repetitions = max_len - out.len(); }
+            else { return Err(Error::invalid(TOO_MUCH_DATA)); }
         }
-        else if out.is_empty() {
-            return Err(Error::invalid(NOT_ENOUGH_DATA));
+
+        if out.is_empty() {
+            if clamp_invalid_codes { return Ok(()); }
+            else { return Err(Error::invalid(NOT_ENOUGH_DATA)); }
         }
 
         let repeated_code = *out.last().unwrap();
         out.extend(std::iter::repeat(repeated_code).take(code_repetitions));
     }
     else if out.len() < max_len { // implies that code is not larger than u16???
-        out.push(u16::try_from(code)?);
+        if clamp_invalid_codes { out.push(u16::try_from(code).unwrap_or(u16::MAX)); }
+        else { out.push(u16::try_from(code)?); }
     }
-    else {
+    else if !clamp_invalid_codes {
         return Err(Error::invalid(TOO_MUCH_DATA));
     }
 
@@ -979,6 +1014,30 @@ mod test {
         assert_eq!(uncompressed, decompressed.as_slice());
     }
 
+    #[test]
+    fn clamping_invalid_codes_always_yields_expected_size(){
+        let mut random = rand::rngs::StdRng::from_seed(SEED);
+        let raw = fill(&mut random, 4096);
+        let compressed = compress(&raw).unwrap();
+
+        let mut recovered_count = 0;
+
+        for position in (20 .. compressed.len()).step_by(97) {
+            let mut damaged = compressed.clone();
+            damaged[position] ^= 0b1010_0101;
+
+            // a damaged code table cannot be recovered, but damaged codes can
+            if let Ok(recovered) = decompress_clamping_invalid_codes(&damaged, raw.len()) {
+                assert_eq!(recovered.len(), raw.len());
+                recovered_count += 1;
+            }
+        }
+
+        assert_ne!(recovered_count, 0);
+
+        assert_eq!(decompress_clamping_invalid_codes(&compressed, raw.len()).unwrap(), raw);
+    }
+
     const SEED: [u8; 32] = [
         12,155,32,34,112,109,98,54,
         12,255,32,34,112,109,98,55,
@@ -36,6 +36,29 @@ pub fn decompress(
     expected_byte_size: usize, // TODO remove expected byte size as it can be computed with `rectangle.size.area() * channels.bytes_per_pixel`
     pedantic: bool
 ) -> Result<ByteVec>
+{
+    decompress_with(channels, compressed, rectangle, expected_byte_size, pedantic, false)
+}
+
+/// Decompress damaged data as well as possible, see `huffman::decompress_clamping_invalid_codes`.
+pub fn decompress_clamping_invalid_codes(
+    channels: &ChannelList,
+    compressed: ByteVec,
+    rectangle: IntegerBounds,
+    expected_byte_size: usize,
+) -> Result<ByteVec>
+{
+    decompress_with(channels, compressed, rectangle, expected_byte_size, false, true)
+}
+
+fn decompress_with(
+    channels: &ChannelList,
+    compressed: ByteVec,
+    rectangle: IntegerBounds,
+    expected_byte_size: usize,
+    pedantic: bool,
+    clamp_invalid_codes: bool,
+) -> Result<ByteVec>
 {
     let expected_u16_count = expected_byte_size / 2;
     debug_assert_eq!(expected_byte_size, rectangle.size.area() * channels.bytes_per_pixel);
@@ -71,7 +94,9 @@ pub fn decompress(
         }
     }
 
-    let mut tmp_u16_buffer = huffman::decompress(remaining_input, expected_u16_count)?;
+    let mut tmp_u16_buffer =
+        if clamp_invalid_codes { huffman::decompress_clamping_invalid_codes(remaining_input, expected_u16_count)? }
+        else { huffman::decompress(remaining_input, expected_u16_count)? };
 
     let mut channel_data: SmallVec<[ChannelData; 6]> = {
         let mut tmp_read_index = 0;
@@ -124,11 +149,8 @@ pub fn decompress(
             let values = &tmp_u16_buffer[channel.tmp_end_index .. next_tmp_end_index];
             channel.tmp_end_index = next_tmp_end_index;
 
-            // TODO do not convert endianness for f16-only images
-            //      see https://github.com/AcademySoftwareFoundation/openexr/blob/3bd93f85bcb74c77255f28cdbb913fdbfbb39dfe/OpenEXR/IlmImf/ImfTiledOutputFile.cpp#L750-L842
-            // We can support uncompressed data in the machine's native format
-            // if all image channels are of type HALF, and if the Xdr and the
-            // native representations of a half have the same size.
+            // the uncompressed bytes are little endian, and 32-bit samples
+            // are split into two 16-bit halves, so this is correct on any processor
             u16::write_slice(&mut out, values).expect("write to in-memory failed");
         }
     }
@@ -140,10 +162,7 @@ pub fn decompress(
     debug_assert_eq!(channel_data.last().unwrap().tmp_end_index, tmp_u16_buffer.len());
     debug_assert_eq!(out.len(), expected_byte_size);
 
-    // TODO optimize for when all channels are f16!
-    //      we should be able to omit endianness conversions in that case
-    //      see https://github.com/AcademySoftwareFoundation/openexr/blob/3bd93f85bcb74c77255f28cdbb913fdbfbb39dfe/OpenEXR/IlmImf/ImfTiledOutputFile.cpp#L750-L842
-    Ok(super::convert_little_endian_to_current(out, channels, rectangle))
+    Ok(out)
 }
 
 
@@ -158,10 +177,7 @@ pub fn compress(
         return Ok(Vec::new());
     }
 
-    // TODO do not convert endianness for f16-only images
-    //      see https://github.com/AcademySoftwareFoundation/openexr/blob/3bd93f85bcb74c77255f28cdbb913fdbfbb39dfe/OpenEXR/IlmImf/ImfTiledOutputFile.cpp#L750-L842
-    let uncompressed = super::convert_current_to_little_endian(uncompressed, channels, rectangle);
-    let uncompressed = uncompressed.as_slice();// TODO no alloc
+    let uncompressed = uncompressed.as_slice();
 
     let mut tmp = vec![0_u16; uncompressed.len() / 2 ];
     let mut channel_data: SmallVec<[ChannelData; 6]> = {
@@ -197,11 +213,8 @@ pub fn compress(
             let target = &mut tmp[channel.tmp_end_index .. next_tmp_end_index];
             channel.tmp_end_index = next_tmp_end_index;
 
-            // TODO do not convert endianness for f16-only images
-            //      see https://github.com/AcademySoftwareFoundation/openexr/blob/3bd93f85bcb74c77255f28cdbb913fdbfbb39dfe/OpenEXR/IlmImf/ImfTiledOutputFile.cpp#L750-L842
-            // We can support uncompressed data in the machine's native format
-            // if all image channels are of type HALF, and if the Xdr and the
-            // native representations of a half have the same size.
+            // the uncompressed bytes are little endian, and 32-bit samples
+            // are split into two 16-bit halves, so this is correct on any processor
             u16::read_slice(&mut remaining_uncompressed_bytes, target).expect("in-memory read failed");
         }
     }
@@ -39,19 +39,13 @@ use lebe::io::ReadPrimitive;
 // 4. Fill the frame buffer with pixel data, respective to sampling and whatnot
 
 
-#[cfg_attr(target_endian = "big", allow(unused, unreachable_code))]
-pub fn compress(channels: &ChannelList, remaining_bytes: ByteVec, area: IntegerBounds) -> Result<ByteVec> {
-    #[cfg(target_endian = "big")] {
-        return Err(Error::unsupported(
-            "PXR24 compression method not supported yet on big endian processor architecture"
-        ))
-    }
+// the uncompressed bytes are little endian, while the transposed bytes
+// in the compressed data are stored with the most significant byte first.
+// both are independent of the processor architecture.
 
+pub fn compress(channels: &ChannelList, remaining_bytes: ByteVec, area: IntegerBounds, deflate_level: u8) -> Result<ByteVec> {
     if remaining_bytes.is_empty() { return Ok(Vec::new()); }
-
-    // see https://github.com/AcademySoftwareFoundation/openexr/blob/3bd93f85bcb74c77255f28cdbb913fdbfbb39dfe/OpenEXR/IlmImf/ImfTiledOutputFile.cpp#L750-L842
-    let remaining_bytes = super::convert_current_to_little_endian(remaining_bytes, channels, area);
-    let mut remaining_bytes = remaining_bytes.as_slice(); // TODO less allocation
+    let mut remaining_bytes = remaining_bytes.as_slice();
 
     let bytes_per_pixel: usize = channels.list.iter()
         .map(|channel| match channel.sample_type {
@@ -85,8 +79,8 @@ pub fn compress(channels: &ChannelList, remaining_bytes: ByteVec, area: IntegerB
                             .zip(split_off_write_slice!());
 
                         for (out_byte_0, out_byte_1) in out_byte_tuples {
-                            let pixel = u16::read_from_native_endian(&mut remaining_bytes).unwrap() as u32;
-                            let [byte_1, byte_0] = (pixel.wrapping_sub(previous_pixel) as u16).to_ne_bytes();
+                            let pixel = u16::read_from_little_endian(&mut remaining_bytes).unwrap() as u32;
+                            let [byte_0, byte_1] = (pixel.wrapping_sub(previous_pixel) as u16).to_be_bytes();
 
                             *out_byte_0 = byte_0;
                             *out_byte_1 = byte_1;
@@ -101,8 +95,8 @@ pub fn compress(channels: &ChannelList, remaining_bytes: ByteVec, area: IntegerB
                             .zip(split_off_write_slice!());
 
                         for (((out_byte_0, out_byte_1), out_byte_2), out_byte_3) in out_byte_quadruplets {
-                            let pixel = u32::read_from_native_endian(&mut remaining_bytes).unwrap();
-                            let [byte_3, byte_2, byte_1, byte_0] = pixel.wrapping_sub(previous_pixel).to_ne_bytes();
+                            let pixel = u32::read_from_little_endian(&mut remaining_bytes).unwrap();
+                            let [byte_0, byte_1, byte_2, byte_3] = pixel.wrapping_sub(previous_pixel).to_be_bytes();
 
                             *out_byte_0 = byte_0;
                             *out_byte_1 = byte_1;
@@ -118,8 +112,8 @@ pub fn compress(channels: &ChannelList, remaining_bytes: ByteVec, area: IntegerB
                             .zip(split_off_write_slice!());
 
                         for ((out_byte_0, out_byte_1), out_byte_2) in out_byte_triplets {
-                            let pixel = f32_to_f24(f32::read_from_native_endian(&mut remaining_bytes).unwrap());
-                            let [byte_2, byte_1, byte_0, _] = pixel.wrapping_sub(previous_pixel).to_ne_bytes();
+                            let pixel = f32_to_f24(f32::read_from_little_endian(&mut remaining_bytes).unwrap());
+                            let [_, byte_0, byte_1, byte_2] = pixel.wrapping_sub(previous_pixel).to_be_bytes();
                             previous_pixel = pixel;
 
                             *out_byte_0 = byte_0;
@@ -134,17 +128,10 @@ pub fn compress(channels: &ChannelList, remaining_bytes: ByteVec, area: IntegerB
         debug_assert_eq!(write.len(), 0, "bytes left after compression");
     }
 
-    Ok(miniz_oxide::deflate::compress_to_vec_zlib(raw.as_slice(), 4))
+    Ok(miniz_oxide::deflate::compress_to_vec_zlib(raw.as_slice(), deflate_level))
 }
 
-#[cfg_attr(target_endian = "big", allow(unused, unreachable_code))]
 pub fn decompress(channels: &ChannelList, bytes: ByteVec, area: IntegerBounds, expected_byte_size: usize, pedantic: bool) -> Result<ByteVec> {
-    #[cfg(target_endian = "big")] {
-        return Err(Error::unsupported(
-            "PXR24 decompression method not supported yet on big endian processor architecture"
-        ))
-    }
-
     let options = zune_inflate::DeflateOptions::default().set_limit(expected_byte_size).set_size_hint(expected_byte_size);
     let mut decoder = zune_inflate::DeflateDecoder::new_with_options(&bytes, options);
     let raw = decoder.decode_zlib()
@@ -173,9 +160,9 @@ pub fn decompress(channels: &ChannelList, bytes: ByteVec, area: IntegerBounds, e
                         .zip(read_sample_line()?);
 
                     for (&in_byte_0, &in_byte_1) in sample_byte_pairs {
-                        let difference = u16::from_ne_bytes([in_byte_1, in_byte_0]) as u32;
+                        let difference = u16::from_be_bytes([in_byte_0, in_byte_1]) as u32;
                         pixel_accumulation = pixel_accumulation.overflowing_add(difference).0;
-                        out.extend_from_slice(&(pixel_accumulation as u16).to_ne_bytes());
+                        out.extend_from_slice(&(pixel_accumulation as u16).to_le_bytes());
                     }
                 },
 
@@ -186,9 +173,9 @@ pub fn decompress(channels: &ChannelList, bytes: ByteVec, area: IntegerBounds, e
                         .zip(read_sample_line()?);
 
                     for (((&in_byte_0, &in_byte_1), &in_byte_2), &in_byte_3) in sample_byte_quads {
-                        let difference = u32::from_ne_bytes([in_byte_3, in_byte_2, in_byte_1, in_byte_0]);
+                        let difference = u32::from_be_bytes([in_byte_0, in_byte_1, in_byte_2, in_byte_3]);
                         pixel_accumulation = pixel_accumulation.overflowing_add(difference).0;
-                        out.extend_from_slice(&pixel_accumulation.to_ne_bytes());
+                        out.extend_from_slice(&pixel_accumulation.to_le_bytes());
                     }
                 },
 
@@ -197,9 +184,9 @@ pub fn decompress(channels: &ChannelList, bytes: ByteVec, area: IntegerBounds, e
                         .zip(read_sample_line()?).zip(read_sample_line()?);
 
                     for ((&in_byte_0, &in_byte_1), &in_byte_2) in sample_byte_triplets {
-                        let difference = u32::from_ne_bytes([0, in_byte_2, in_byte_1, in_byte_0]);
+                        let difference = u32::from_be_bytes([in_byte_0, in_byte_1, in_byte_2, 0]);
                         pixel_accumulation = pixel_accumulation.overflowing_add(difference).0;
-                        out.extend_from_slice(&pixel_accumulation.to_ne_bytes());
+                        out.extend_from_slice(&pixel_accumulation.to_le_bytes());
                     }
                 }
             }
@@ -210,7 +197,7 @@ pub fn decompress(channels: &ChannelList, bytes: ByteVec, area: IntegerBounds, e
         return Err(Error::invalid("too much data"));
     }
 
-    Ok(super::convert_little_endian_to_current(out, channels, area))
+    Ok(out)
 }
 
 
@@ -258,4 +245,27 @@ pub fn f32_to_f24(float: f32) -> u32 {
     };
 
     return (sign >> 8) | result;
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::meta::attribute::ChannelDescription;
+
+    #[test]
+    fn transposed_bytes_are_big_endian_on_any_processor(){
+        let channels = ChannelList::new(smallvec![ ChannelDescription::new("Y", SampleType::F16, false) ]);
+        let area = IntegerBounds::from_dimensions((2, 1));
+
+        // the little endian samples 0x0101 and 0x0203
+        let uncompressed = vec![ 0x01, 0x01,   0x03, 0x02 ];
+        let compressed = compress(&channels, uncompressed.clone(), area, DEFAULT_DEFLATE_LEVEL).unwrap();
+
+        // the differences 0x0101 and 0x0102, all most significant bytes first
+        let transposed = miniz_oxide::inflate::decompress_to_vec_zlib(&compressed).unwrap();
+        assert_eq!(transposed, vec![ 0x01, 0x01,   0x01, 0x02 ]);
+
+        let decompressed = decompress(&channels, compressed, area, uncompressed.len(), true).unwrap();
+        assert_eq!(decompressed, uncompressed);
+    }
+}
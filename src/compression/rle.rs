@@ -10,9 +10,7 @@ const MAX_RUN_LENGTH : usize = 127;
 
 
 pub fn decompress_bytes(
-    channels: &ChannelList,
     compressed: ByteVec,
-    rectangle: IntegerBounds,
     expected_byte_size: usize,
     pedantic: bool,
 ) -> Result<ByteVec> {
@@ -40,12 +38,36 @@ pub fn decompress_bytes(
 
     differences_to_samples(&mut decompressed);
     interleave_byte_blocks(&mut decompressed);
-    Ok(super::convert_little_endian_to_current(decompressed, channels, rectangle))// TODO no alloc
+    Ok(decompressed)
 }
 
-pub fn compress_bytes(channels: &ChannelList, uncompressed: ByteVec, rectangle: IntegerBounds) -> Result<ByteVec> {
-    // see https://github.com/AcademySoftwareFoundation/openexr/blob/3bd93f85bcb74c77255f28cdbb913fdbfbb39dfe/OpenEXR/IlmImf/ImfTiledOutputFile.cpp#L750-L842
-    let mut data = super::convert_current_to_little_endian(uncompressed, channels, rectangle);// TODO no alloc
+/// Decompress damaged data as well as possible.
+/// Ignores runs that exceed the expected number of bytes and any data after that.
+pub fn decompress_bytes_ignoring_trailing_data(compressed: ByteVec, expected_byte_size: usize) -> Result<ByteVec> {
+    let mut remaining = compressed.as_slice();
+    let mut decompressed = Vec::with_capacity(expected_byte_size.min(8*2048));
+
+    while decompressed.len() < expected_byte_size {
+        let count = take_1(&mut remaining)? as i8 as i32;
+
+        if count < 0 {
+            let values = take_n(&mut remaining, (-count) as usize)?;
+            decompressed.extend_from_slice(values);
+        }
+        else {
+            let value = take_1(&mut remaining)?;
+            decompressed.resize(decompressed.len() + count as usize + 1, value);
+        }
+    }
+
+    decompressed.truncate(expected_byte_size);
+    differences_to_samples(&mut decompressed);
+    interleave_byte_blocks(&mut decompressed);
+    Ok(decompressed)
+}
+
+pub fn compress_bytes(uncompressed: ByteVec) -> Result<ByteVec> {
+    let mut data = uncompressed;
 
     separate_bytes_fragments(&mut data);
     samples_to_differences(&mut data);
@@ -13,9 +13,7 @@ use crate::error::Result;
 
 
 pub fn decompress_bytes(
-    channels: &ChannelList,
     data: ByteVec,
-    rectangle: IntegerBounds,
     expected_byte_size: usize,
     _pedantic: bool,
 ) -> Result<ByteVec> {
@@ -27,15 +25,37 @@ pub fn decompress_bytes(
     differences_to_samples(&mut decompressed);
     interleave_byte_blocks(&mut decompressed);
 
-    Ok(super::convert_little_endian_to_current(decompressed, channels, rectangle))// TODO no alloc
+    Ok(decompressed)
 }
 
-pub fn compress_bytes(channels: &ChannelList, uncompressed: ByteVec, rectangle: IntegerBounds) -> Result<ByteVec> {
-    // see https://github.com/AcademySoftwareFoundation/openexr/blob/3bd93f85bcb74c77255f28cdbb913fdbfbb39dfe/OpenEXR/IlmImf/ImfTiledOutputFile.cpp#L750-L842
-    let mut packed = convert_current_to_little_endian(uncompressed, channels, rectangle);
+/// Decompress damaged data as well as possible.
+/// Ignores the checksum and any data after the expected number of bytes.
+pub fn decompress_bytes_ignoring_trailing_data(data: ByteVec, expected_byte_size: usize) -> Result<ByteVec> {
+    let options = zune_inflate::DeflateOptions::default()
+        .set_limit(expected_byte_size).set_size_hint(expected_byte_size)
+        .set_confirm_checksum(false);
+
+    let mut decoder = zune_inflate::DeflateDecoder::new_with_options(&data, options);
+
+    // the partially decoded data may still contain all the bytes we need
+    let mut decompressed = decoder.decode_zlib().unwrap_or_else(|error| error.data);
+
+    if decompressed.len() < expected_byte_size {
+        return Err(Error::invalid("zlib-compressed data malformed"));
+    }
+
+    decompressed.truncate(expected_byte_size);
+    differences_to_samples(&mut decompressed);
+    interleave_byte_blocks(&mut decompressed);
+
+    Ok(decompressed)
+}
+
+pub fn compress_bytes(uncompressed: ByteVec, deflate_level: u8) -> Result<ByteVec> {
+    let mut packed = uncompressed;
 
     separate_bytes_fragments(&mut packed);
     samples_to_differences(&mut packed);
 
-    Ok(miniz_oxide::deflate::compress_to_vec_zlib(packed.as_slice(), 4))
+    Ok(miniz_oxide::deflate::compress_to_vec_zlib(packed.as_slice(), deflate_level))
 }
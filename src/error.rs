@@ -9,6 +9,7 @@ use std::convert::TryFrom;
 use std::error;
 use std::fmt;
 use std::num::TryFromIntError;
+use crate::meta::attribute::Text;
 
 
 // Export types
@@ -50,12 +51,13 @@ pub enum Error {
 impl Error {
 
     /// Create an error of the variant `Invalid`.
-    pub(crate) fn invalid(message: impl Into<Cow<'static, str>>) -> Self {
+    /// Use this when implementing custom attribute types or codecs on top of `exr::io::Data`.
+    pub fn invalid(message: impl Into<Cow<'static, str>>) -> Self {
         Error::Invalid(message.into())
     }
 
     /// Create an error of the variant `NotSupported`.
-    pub(crate) fn unsupported(message: impl Into<Cow<'static, str>>) -> Self {
+    pub fn unsupported(message: impl Into<Cow<'static, str>>) -> Self {
         Error::NotSupported(message.into())
     }
 }
@@ -99,6 +101,155 @@ impl fmt::Display for Error {
     }
 }
 
+
+/// The kind of an error, without its message.
+/// Use `error.category()` to obtain it, for example to decide how to report a failure.
+/// The numbers will not change in future versions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ErrorCategory {
+
+    /// See `Error::Aborted`.
+    Aborted = 1,
+
+    /// See `Error::NotSupported`.
+    NotSupported = 2,
+
+    /// See `Error::Invalid`.
+    Invalid = 3,
+
+    /// See `Error::Io`.
+    Io = 4,
+}
+
+impl ErrorCategory {
+
+    /// A name that will not change in future versions, for example `not_supported`.
+    pub fn name(self) -> &'static str {
+        match self {
+            ErrorCategory::Aborted => "aborted",
+            ErrorCategory::NotSupported => "not_supported",
+            ErrorCategory::Invalid => "invalid",
+            ErrorCategory::Io => "io",
+        }
+    }
+}
+
+impl Error {
+
+    /// The kind of this error, without its message.
+    pub fn category(&self) -> ErrorCategory {
+        match self {
+            Error::Aborted => ErrorCategory::Aborted,
+            Error::NotSupported(_) => ErrorCategory::NotSupported,
+            Error::Invalid(_) => ErrorCategory::Invalid,
+            Error::Io(_) => ErrorCategory::Io,
+        }
+    }
+
+    /// A number that identifies the kind of this error and will not change in future versions,
+    /// for example to return it through a C interface or to log it, instead of the message.
+    /// The hundreds are the number of the category, and the remainder further distinguishes io errors:
+    ///
+    /// | code | error |
+    /// |------|-------|
+    /// | 100  | aborted |
+    /// | 200  | not supported |
+    /// | 300  | invalid |
+    /// | 400  | other io error |
+    /// | 401  | io: not found |
+    /// | 402  | io: permission denied |
+    /// | 403  | io: already exists |
+    /// | 404  | io: interrupted |
+    /// | 405  | io: write zero |
+    /// | 406  | io: invalid input |
+    /// | 407  | io: invalid data |
+    /// | 408  | io: timed out |
+    /// | 409  | io: broken pipe |
+    pub fn code(&self) -> u32 {
+        let detail = match self {
+            Error::Io(error) => match error.kind() {
+                ErrorKind::NotFound => 1,
+                ErrorKind::PermissionDenied => 2,
+                ErrorKind::AlreadyExists => 3,
+                ErrorKind::Interrupted => 4,
+                ErrorKind::WriteZero => 5,
+                ErrorKind::InvalidInput => 6,
+                ErrorKind::InvalidData => 7,
+                ErrorKind::TimedOut => 8,
+                ErrorKind::BrokenPipe => 9,
+                _ => 0,
+            },
+
+            _ => 0,
+        };
+
+        self.category() as u32 * 100 + detail
+    }
+}
+
+
+/// An anomaly in a file that was tolerated while reading, because reading was not pedantic.
+/// Reading pedantically would have returned an error instead.
+/// Collected in the `ReadReport` of the image reader, so that pipelines can log the quality of their inputs.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Warning {
+
+    /// An attribute could not be parsed and was skipped.
+    SkippedAttribute {
+
+        /// The index of the header that contained the attribute.
+        layer_index: usize,
+
+        /// The name of the skipped attribute.
+        name: Text,
+
+        /// Why the attribute could not be parsed.
+        message: Cow<'static, str>,
+    },
+
+    /// The chunk count attribute of a header does not match the size of the layer.
+    /// The computed chunk count was used instead.
+    ChunkCountMismatch {
+
+        /// The index of the header.
+        layer_index: usize,
+
+        /// The chunk count from the attribute in the file.
+        declared: usize,
+
+        /// The chunk count computed from the layer size.
+        computed: usize,
+    },
+
+    /// The chunks of a layer are not stored in the line order that the header declares.
+    UnexpectedChunkOrder {
+
+        /// The index of the header.
+        layer_index: usize,
+    },
+
+    /// The meta data would not be accepted by pedantic reading,
+    /// for example because two layers have the same name, or the offset tables contain invalid values.
+    InvalidMetaData(Cow<'static, str>),
+}
+
+impl fmt::Display for Warning {
+    fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Warning::SkippedAttribute { layer_index, name, message } =>
+                write!(formatter, "skipped attribute `{}` in layer {}: {}", name, layer_index, message),
+
+            Warning::ChunkCountMismatch { layer_index, declared, computed } =>
+                write!(formatter, "layer {} declares {} chunks, but contains {} chunks", layer_index, declared, computed),
+
+            Warning::UnexpectedChunkOrder { layer_index } =>
+                write!(formatter, "chunks of layer {} are not in the declared line order", layer_index),
+
+            Warning::InvalidMetaData(message) => write!(formatter, "invalid meta data: {}", message),
+        }
+    }
+}
+
 /// Return error on invalid range.
 #[inline]
 pub(crate) fn i32_to_usize(value: i32, error_message: &'static str) -> Result<usize> {
@@ -134,3 +285,23 @@ pub(crate) fn usize_to_i32(value: usize) -> i32 {
 pub(crate) fn usize_to_u64(value: usize) -> u64 {
     u64::try_from(value).expect("(usize as u64) overflowed")
 }
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn stable_error_codes(){
+        assert_eq!(Error::Aborted.code(), 100);
+        assert_eq!(Error::unsupported("deep data").code(), 200);
+        assert_eq!(Error::invalid("chunk").code(), 300);
+        assert_eq!(Error::from(IoError::new(ErrorKind::NotFound, "missing")).code(), 401);
+        assert_eq!(Error::from(IoError::new(ErrorKind::Other, "disk")).code(), 400);
+
+        // unexpected end of file is converted to an invalid file
+        let end_of_file = Error::from(IoError::new(ErrorKind::UnexpectedEof, "end"));
+        assert_eq!(end_of_file.category(), ErrorCategory::Invalid);
+        assert_eq!(end_of_file.category().name(), "invalid");
+    }
+}
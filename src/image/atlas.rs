@@ -0,0 +1,351 @@
+
+//! Pack many small images into a single texture atlas layer, for example lightmaps or sprites.
+//! The position of each packed image is stored in a custom attribute of the atlas layer,
+//! so that the atlas can be unpacked again after reading it from a file.
+
+use crate::image::{FlatSamples, Layer, AnyChannels, AnyChannel};
+use crate::meta::attribute::{Text, IntegerBounds, AttributeValue};
+use crate::meta::header::LayerAttributes;
+use crate::math::Vec2;
+use crate::error::{Result, Error, UnitResult, i32_to_usize};
+use crate::io::Data;
+use half::f16;
+use std::convert::TryFrom;
+
+
+/// The name of the layer attribute that contains the placements of an atlas.
+pub const PLACEMENTS_ATTRIBUTE_NAME: &str = "atlasPlacements";
+
+/// The type name of the custom attribute that contains the placements of an atlas.
+pub const PLACEMENTS_ATTRIBUTE_TYPE: &str = "atlasPlacementList";
+
+
+/// Where an image has been placed inside the atlas.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AtlasPlacement {
+
+    /// The layer name of the packed image. If it had no name, this is its index in the input list.
+    pub name: Text,
+
+    /// The pixel rectangle inside the atlas, relative to the top left corner of the atlas.
+    pub bounds: IntegerBounds,
+}
+
+/// Controls the layout of a texture atlas.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct AtlasOptions {
+
+    /// The width of the atlas in pixels.
+    /// If `None`, a width is chosen such that the atlas is roughly square.
+    pub width: Option<usize>,
+
+    /// The number of empty pixels to the right of and below each image.
+    /// Prevents filtering from bleeding into neighbouring images.
+    pub padding: usize,
+}
+
+
+/// Pack all images into a single layer. The images are placed in rows, tallest images first.
+/// The placements are stored in the `atlasPlacements` attribute of the resulting layer,
+/// in the same order as the input images. Use `atlas_placements` to read them.
+/// The encoding of the first image is used for the atlas. Uncovered pixels are zero.
+///
+/// All images must have the same channels with the same sample types,
+/// and must neither be empty nor subsampled.
+pub fn pack_atlas(images: &[Layer<AnyChannels<FlatSamples>>], options: AtlasOptions) -> Result<Layer<AnyChannels<FlatSamples>>> {
+    let first = images.first().ok_or(Error::invalid("atlas without images"))?;
+
+    for image in images {
+        validate_channels(&first.channel_data, &image.channel_data)?;
+
+        if image.size.area() == 0 {
+            return Err(Error::invalid("empty atlas image"));
+        }
+    }
+
+    let slot_size = |image: &Layer<AnyChannels<FlatSamples>>| image.size + Vec2(options.padding, options.padding);
+
+    let widest_slot = images.iter().map(|image| slot_size(image).width()).max().unwrap_or(0);
+    let width = options.width.unwrap_or_else(|| {
+        let area: usize = images.iter().map(|image| slot_size(image).area()).sum();
+        ((area as f64).sqrt().ceil() as usize).max(widest_slot)
+    });
+
+    if width < widest_slot {
+        return Err(Error::invalid("atlas width is smaller than an image"));
+    }
+
+    let mut order: Vec<usize> = (0 .. images.len()).collect();
+    order.sort_by_key(|&index| std::cmp::Reverse(images[index].size.height()));
+
+    // place the images in shelves, each as tall as its first image
+    let mut positions = vec![ Vec2(0, 0); images.len() ];
+    let (mut cursor, mut shelf_height) = (Vec2(0, 0), 0);
+
+    for index in order {
+        let slot = slot_size(&images[index]);
+
+        if cursor.x() + slot.width() > width {
+            cursor = Vec2(0, cursor.y() + shelf_height);
+            shelf_height = 0;
+        }
+
+        positions[index] = cursor;
+        cursor.0 += slot.width();
+        shelf_height = shelf_height.max(slot.height());
+    }
+
+    let size = Vec2(width, cursor.y() + shelf_height);
+
+    let mut list = first.channel_data.list.iter()
+        .map(|channel| AnyChannel { sample_data: zeroes_like(&channel.sample_data, size.area()), .. channel.clone_description() })
+        .collect::<smallvec::SmallVec<[AnyChannel<FlatSamples>; 4]>>();
+
+    let mut placements = Vec::with_capacity(images.len());
+
+    for (image_index, (image, &position)) in images.iter().zip(&positions).enumerate() {
+        for (target, source) in list.iter_mut().zip(&image.channel_data.list) {
+            copy_rectangle(
+                &source.sample_data, image.size, Vec2(0, 0),
+                &mut target.sample_data, size, position,
+                image.size
+            );
+        }
+
+        let name = image.attributes.layer_name.clone()
+            .unwrap_or_else(|| Text::from(image_index.to_string().as_str()));
+
+        placements.push(AtlasPlacement {
+            name, bounds: IntegerBounds::new(position.to_i32(), image.size)
+        });
+    }
+
+    let mut attributes = LayerAttributes::default();
+    attributes.other.insert(Text::from(PLACEMENTS_ATTRIBUTE_NAME), placements_to_attribute(&placements)?);
+
+    Ok(Layer { channel_data: AnyChannels { list }, attributes, size, encoding: first.encoding })
+}
+
+/// Read the placements of an atlas that was created by `pack_atlas`.
+/// Returns `None` if the attributes contain no placements.
+pub fn atlas_placements(attributes: &LayerAttributes) -> Option<Result<Vec<AtlasPlacement>>> {
+    attributes.other.get(&Text::from(PLACEMENTS_ATTRIBUTE_NAME)).map(placements_from_attribute)
+}
+
+/// Serialize the placements into a custom attribute value.
+pub fn placements_to_attribute(placements: &[AtlasPlacement]) -> Result<AttributeValue> {
+    let mut bytes = Vec::new();
+    write_placements(placements, &mut bytes)?;
+    Ok(AttributeValue::Custom { kind: Text::from(PLACEMENTS_ATTRIBUTE_TYPE), bytes })
+}
+
+/// Deserialize the placements from a custom attribute value.
+pub fn placements_from_attribute(value: &AttributeValue) -> Result<Vec<AtlasPlacement>> {
+    match value {
+        AttributeValue::Custom { kind, bytes } if kind == &Text::from(PLACEMENTS_ATTRIBUTE_TYPE) => {
+            let read = &mut bytes.as_slice();
+            let count = i32_to_usize(i32::read(read)?, "atlas placement count")?;
+
+            // each placement needs at least 20 bytes, so the count cannot exceed the byte count
+            if count > bytes.len() { return Err(Error::invalid("atlas placement count")); }
+
+            (0 .. count).map(|_| Ok(AtlasPlacement {
+                name: Text::read_i32_sized(read, bytes.len())?,
+                bounds: IntegerBounds::read(read)?,
+            })).collect()
+        },
+
+        _ => Err(Error::invalid("atlas placement attribute type")),
+    }
+}
+
+fn write_placements(placements: &[AtlasPlacement], write: &mut Vec<u8>) -> UnitResult {
+    i32::write(i32::try_from(placements.len()).map_err(|_| Error::invalid("atlas placement count"))?, write)?;
+
+    for placement in placements {
+        placement.name.write_i32_sized(write)?;
+        placement.bounds.write(write)?;
+    }
+
+    Ok(())
+}
+
+
+impl Layer<AnyChannels<FlatSamples>> {
+
+    /// Split this layer into tiles of the specified size, for example to repack them with `pack_atlas`.
+    /// The tiles at the right and bottom border are smaller if the layer size is not divisible by the tile size.
+    /// Each tile is named `x_y` after its tile index, and keeps the channels and encoding of this layer.
+    /// Fails if any channel is subsampled.
+    pub fn split_into_tiles(&self, tile_size: Vec2<usize>) -> Result<Vec<Self>> {
+        if tile_size.area() == 0 { return Err(Error::invalid("tile size")); }
+
+        if self.channel_data.list.iter().any(|channel| channel.sampling != Vec2(1, 1)) {
+            return Err(Error::invalid("splitting subsampled channels"));
+        }
+
+        let tile_count = Vec2(
+            crate::math::RoundingMode::Up.divide(self.size.width(), tile_size.width()),
+            crate::math::RoundingMode::Up.divide(self.size.height(), tile_size.height()),
+        );
+
+        let mut tiles = Vec::with_capacity(tile_count.area());
+
+        for tile_y in 0 .. tile_count.height() {
+            for tile_x in 0 .. tile_count.width() {
+                let position = Vec2(tile_x * tile_size.width(), tile_y * tile_size.height());
+                let size = Vec2(
+                    tile_size.width().min(self.size.width() - position.x()),
+                    tile_size.height().min(self.size.height() - position.y()),
+                );
+
+                let list = self.channel_data.list.iter().map(|channel| {
+                    let mut sample_data = zeroes_like(&channel.sample_data, size.area());
+                    copy_rectangle(&channel.sample_data, self.size, position, &mut sample_data, size, Vec2(0, 0), size);
+                    AnyChannel { sample_data, .. channel.clone_description() }
+                }).collect();
+
+                let name = format!("{}_{}", tile_x, tile_y);
+                tiles.push(Layer {
+                    channel_data: AnyChannels { list },
+                    attributes: LayerAttributes::named(name.as_str()),
+                    size, encoding: self.encoding,
+                });
+            }
+        }
+
+        Ok(tiles)
+    }
+}
+
+impl AnyChannel<FlatSamples> {
+    fn clone_description(&self) -> AnyChannel<FlatSamples> {
+        AnyChannel {
+            name: self.name.clone(),
+            sample_data: FlatSamples::F32(Vec::new()),
+            quantize_linearly: self.quantize_linearly,
+            sampling: self.sampling,
+        }
+    }
+}
+
+fn validate_channels(expected: &AnyChannels<FlatSamples>, channels: &AnyChannels<FlatSamples>) -> UnitResult {
+    let same_channels = expected.list.len() == channels.list.len() &&
+        expected.list.iter().zip(&channels.list).all(|(expected, channel)|
+            expected.name == channel.name && channel.sampling == Vec2(1, 1) &&
+                std::mem::discriminant(&expected.sample_data) == std::mem::discriminant(&channel.sample_data)
+        );
+
+    if same_channels { Ok(()) }
+    else { Err(Error::invalid("atlas images must have the same channels")) }
+}
+
+fn zeroes_like(samples: &FlatSamples, count: usize) -> FlatSamples {
+    match samples {
+        FlatSamples::F16(_) => FlatSamples::F16(vec![ f16::ZERO; count ]),
+        FlatSamples::F32(_) => FlatSamples::F32(vec![ 0.0; count ]),
+        FlatSamples::U32(_) => FlatSamples::U32(vec![ 0; count ]),
+    }
+}
+
+/// Both sample buffers must have the same type.
+fn copy_rectangle(
+    source: &FlatSamples, source_size: Vec2<usize>, source_position: Vec2<usize>,
+    target: &mut FlatSamples, target_size: Vec2<usize>, target_position: Vec2<usize>,
+    size: Vec2<usize>
+){
+    fn copy_rows<T: Copy>(
+        source: &[T], source_size: Vec2<usize>, source_position: Vec2<usize>,
+        target: &mut [T], target_size: Vec2<usize>, target_position: Vec2<usize>,
+        size: Vec2<usize>
+    ){
+        for y in 0 .. size.height() {
+            let source_start = (source_position.y() + y) * source_size.width() + source_position.x();
+            let target_start = (target_position.y() + y) * target_size.width() + target_position.x();
+
+            target[target_start .. target_start + size.width()]
+                .copy_from_slice(&source[source_start .. source_start + size.width()]);
+        }
+    }
+
+    match (source, target) {
+        (FlatSamples::F16(source), FlatSamples::F16(target)) => copy_rows(source, source_size, source_position, target, target_size, target_position, size),
+        (FlatSamples::F32(source), FlatSamples::F32(target)) => copy_rows(source, source_size, source_position, target, target_size, target_position, size),
+        (FlatSamples::U32(source), FlatSamples::U32(target)) => copy_rows(source, source_size, source_position, target, target_size, target_position, size),
+        _ => unreachable!("atlas channel sample types were validated"),
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::image::Encoding;
+
+    fn sprite(name: &str, size: (usize, usize), value: f32) -> Layer<AnyChannels<FlatSamples>> {
+        let size = Vec2::from(size);
+        let channels = AnyChannels::sort(smallvec::smallvec![
+            AnyChannel::new("A", FlatSamples::F32(vec![ 1.0; size.area() ])),
+            AnyChannel::new("Y", FlatSamples::F32(vec![ value; size.area() ])),
+        ]);
+
+        Layer::new(size, LayerAttributes::named(name), Encoding::UNCOMPRESSED, channels)
+    }
+
+    #[test]
+    fn packed_images_do_not_overlap(){
+        let sprites = vec![
+            sprite("small", (2, 2), 1.0),
+            sprite("wide", (6, 1), 2.0),
+            sprite("tall", (1, 5), 3.0),
+            sprite("square", (3, 3), 4.0),
+        ];
+
+        let atlas = pack_atlas(&sprites, AtlasOptions { width: None, padding: 1 }).unwrap();
+        let placements = atlas_placements(&atlas.attributes).unwrap().unwrap();
+        assert_eq!(placements.len(), sprites.len());
+
+        for (sprite, placement) in sprites.iter().zip(&placements) {
+            assert_eq!(Some(&placement.name), sprite.attributes.layer_name.as_ref());
+            assert_eq!(placement.bounds.size, sprite.size);
+            assert!(IntegerBounds::from_dimensions(atlas.size).contains(placement.bounds));
+
+            for other in placements.iter().filter(|other| other.name != placement.name) {
+                let (a, b) = (placement.bounds, other.bounds);
+                let overlaps = a.position.x() < b.end().x() && b.position.x() < a.end().x()
+                    && a.position.y() < b.end().y() && b.position.y() < a.end().y();
+
+                assert!(!overlaps, "{:?} overlaps {:?}", a, b);
+            }
+
+            let corner = placement.bounds.position.to_usize("test").unwrap();
+            let expected = sprite.channel_data.list[1].sample_data.value_by_flat_index(0).to_f32();
+            assert_eq!(atlas.sample_vec_at(corner)[1].to_f32(), expected);
+        }
+    }
+
+    #[test]
+    fn split_and_pack_roundtrip(){
+        let mut image = sprite("image", (5, 3), 0.0);
+        image.channel_data.list[1].sample_data = FlatSamples::F32((0 .. 15).map(|value| value as f32).collect());
+
+        let tiles = image.split_into_tiles(Vec2(2, 2)).unwrap();
+        assert_eq!(tiles.len(), 6);
+        assert_eq!(tiles[5].size, Vec2(1, 1));
+        assert_eq!(tiles[5].sample_vec_at(Vec2(0, 0))[1].to_f32(), 14.0);
+
+        let atlas = pack_atlas(&tiles, AtlasOptions { width: Some(8), padding: 0 }).unwrap();
+        assert_eq!(atlas.size.width(), 8);
+
+        let placements = atlas_placements(&atlas.attributes).unwrap().unwrap();
+        assert_eq!(placements[3].name, Text::from("0_1"));
+        assert_eq!(placements[3].bounds.size, Vec2(2, 1));
+    }
+
+    #[test]
+    fn mismatched_channels_are_rejected(){
+        let mut other = sprite("other", (1, 1), 0.0);
+        other.channel_data.list[0].sample_data = FlatSamples::F16(vec![ f16::ONE ]);
+        assert!(pack_atlas(&[ sprite("sprite", (1, 1), 0.0), other ], AtlasOptions::default()).is_err());
+    }
+}
@@ -0,0 +1,256 @@
+//! Composite the deep samples within a depth range into a flat image,
+//! for example to preview a section of a deep render.
+//!
+//! The samples of each pixel are sorted by depth and composited front to back with the `over` operation.
+//! Volume samples that are only partially inside the range are split,
+//! adjusting their alpha as described in the OpenEXR document "Interpreting OpenEXR Deep Pixels".
+//! Colors are assumed to be premultiplied by the alpha channel `A`. Samples without alpha are opaque.
+
+use std::io::{Read, Seek, BufReader};
+use std::fs::File;
+use std::ops::Range;
+use std::path::Path;
+
+use crate::block::chunk::CompressedBlock;
+use crate::block::deep::{deep_block_contents, decompress_sample_counts, sample_to_f32};
+use crate::image::*;
+use crate::meta::attribute::SampleType;
+use crate::meta::header::Header;
+use crate::error::{Result, Error, UnitResult};
+
+
+/// Read all deep layers of the file, and composite the samples within the depth range of each pixel.
+/// The range includes `near` and excludes `far`. Returns one flat layer for each deep layer of the file.
+/// See `read_depth_slice_from_buffered`.
+pub fn read_depth_slice_from_file(path: impl AsRef<Path>, depth_range: Range<f32>) -> Result<Vec<Layer<AnyChannels<FlatSamples>>>> {
+    read_depth_slice_from_buffered(BufReader::new(File::open(path)?), depth_range)
+}
+
+/// Read all deep layers of the byte source, and composite the samples within the depth range of each pixel.
+/// The range includes `near` and excludes `far`. Returns one flat layer for each deep layer of the file.
+/// Flat layers and lower resolution levels are skipped.
+///
+/// Each resulting layer contains all channels of the deep layer except `Z` and `ZBack`.
+/// Float channels are composited and stored as `f32` samples.
+/// Channels with `u32` samples, such as ids, contain the value of the front-most sample within the range.
+/// Pixels without samples in the range are zero.
+pub fn read_depth_slice_from_buffered(read: impl Read + Seek, depth_range: Range<f32>) -> Result<Vec<Layer<AnyChannels<FlatSamples>>>> {
+    if !(depth_range.start < depth_range.end) { return Err(Error::invalid("depth slice range")); }
+
+    let reader = crate::block::read(read, false)?;
+    let headers = reader.headers().to_vec();
+
+    let mut slices: Vec<Option<DepthSlice>> = headers.iter()
+        .map(|header| if header.deep { DepthSlice::new(header).map(Some) } else { Ok(None) })
+        .collect::<Result<_>>()?;
+
+    let chunks = reader.filter_chunks(false, |meta, tile, block| {
+        tile.level_index == Vec2(0, 0) && meta.headers[block.layer].deep
+    })?;
+
+    for chunk in chunks {
+        let chunk = chunk?;
+        let header = headers.get(chunk.layer_index).ok_or(Error::invalid("chunk layer index"))?;
+
+        if let Some(slice) = &mut slices[chunk.layer_index] {
+            slice.add_block(header, &chunk.compressed_block, &depth_range)?;
+        }
+    }
+
+    Ok(headers.iter().zip(slices)
+        .filter_map(|(header, slice)| slice.map(|slice| slice.into_layer(header)))
+        .collect())
+}
+
+
+/// The composited channels of a single deep layer.
+struct DepthSlice {
+    front_channel: usize,
+    back_channel: Option<usize>,
+    alpha_channel: Option<usize>,
+    width: usize,
+
+    /// The header index of each channel, and its composited samples.
+    channels: Vec<(usize, FlatSamples)>,
+}
+
+impl DepthSlice {
+    fn new(header: &Header) -> Result<Self> {
+        let list = &header.channels.list;
+        let find = |name: &str| list.iter().position(|channel| channel.name == Text::from(name));
+        let front_channel = find("Z").ok_or(Error::invalid("deep layer without depth channel `Z`"))?;
+        let back_channel = find("ZBack");
+
+        let pixel_count = header.layer_size.area();
+        let channels = list.iter().enumerate()
+            .filter(|&(index, _)| index != front_channel && Some(index) != back_channel)
+            .map(|(index, channel)| (index, match channel.sample_type {
+                SampleType::U32 => FlatSamples::U32(vec![ 0; pixel_count ]),
+                SampleType::F16 | SampleType::F32 => FlatSamples::F32(vec![ 0.0; pixel_count ]),
+            }))
+            .collect();
+
+        Ok(DepthSlice { front_channel, back_channel, alpha_channel: find("A"), width: header.layer_size.width(), channels })
+    }
+
+    fn add_block(&mut self, header: &Header, block: &CompressedBlock, depth_range: &Range<f32>) -> UnitResult {
+        let (offset_table, sample_data, sample_data_size) = deep_block_contents(block)?;
+        let bounds = header.get_absolute_block_pixel_coordinates(header.get_block_data_indices(block)?)?;
+        let position = bounds.position.to_usize("deep block position")?;
+
+        let sample_counts = decompress_sample_counts(header, bounds.size.area(), offset_table, sample_data_size)?;
+        let total_sample_count: usize = sample_counts.iter().sum();
+        let sample_data = header.compression.decompress_deep_bytes(sample_data.to_vec(), sample_data_size, false)?;
+
+        // the samples of one channel are stored after all samples of the previous channel
+        let mut channel_bytes = Vec::with_capacity(header.channels.list.len());
+        let mut channel_start = 0;
+
+        for channel in &header.channels.list {
+            let byte_count = channel.sample_type.bytes_per_sample() * total_sample_count;
+            channel_bytes.push((channel.sample_type, &sample_data[channel_start .. channel_start + byte_count]));
+            channel_start += byte_count;
+        }
+
+        let value = |channel: usize, sample: usize| sample_to_f32(channel_bytes[channel].1, channel_bytes[channel].0, sample);
+
+        let mut first_sample = 0;
+        let mut samples = Vec::new();
+
+        for (pixel_index, &sample_count) in sample_counts.iter().enumerate() {
+            let pixel = position + Vec2(pixel_index % bounds.size.width(), pixel_index / bounds.size.width());
+            let flat_index = pixel.y() * self.width + pixel.x();
+
+            // collect the portion of each sample within the range, as (front, sample index, alpha, color scale)
+            samples.clear();
+            for sample in first_sample .. first_sample + sample_count {
+                let front = value(self.front_channel, sample);
+                let back = self.back_channel.map_or(front, |back| value(back, sample).max(front));
+                let alpha = self.alpha_channel.map_or(1.0, |alpha| value(alpha, sample));
+
+                if back <= front {
+                    if depth_range.contains(&front) { samples.push((front, sample, alpha, 1.0)); }
+                }
+                else {
+                    let (inside_front, inside_back) = (front.max(depth_range.start), back.min(depth_range.end));
+                    if inside_back <= inside_front { continue; }
+
+                    let fraction = (inside_back - inside_front) / (back - front);
+                    let split_alpha = if alpha >= 1.0 { 1.0 } else { 1.0 - (1.0 - alpha).powf(fraction) };
+                    let color_scale = if alpha > 0.0 { split_alpha / alpha } else { fraction };
+                    samples.push((inside_front, sample, split_alpha, color_scale));
+                }
+            }
+
+            samples.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap_or(std::cmp::Ordering::Equal));
+
+            // composite front to back
+            let mut accumulated_alpha = 0.0_f32;
+            for (index, &(_, sample, alpha, color_scale)) in samples.iter().enumerate() {
+                let visibility = 1.0 - accumulated_alpha;
+
+                for (channel, composited) in &mut self.channels {
+                    match composited {
+                        FlatSamples::F32(composited) => composited[flat_index] += visibility * if Some(*channel) == self.alpha_channel { alpha } else { value(*channel, sample) * color_scale },
+                        FlatSamples::U32(composited) => if index == 0 { composited[flat_index] = read_u32(channel_bytes[*channel].1, sample) },
+                        FlatSamples::F16(_) => unreachable!("composited samples are f32"),
+                    }
+                }
+
+                accumulated_alpha += visibility * alpha;
+            }
+
+            first_sample += sample_count;
+        }
+
+        Ok(())
+    }
+
+    fn into_layer(self, header: &Header) -> Layer<AnyChannels<FlatSamples>> {
+        let list = self.channels.into_iter().map(|(index, sample_data)| {
+            let channel = &header.channels.list[index];
+            AnyChannel { name: channel.name.clone(), sample_data, quantize_linearly: channel.quantize_linearly, sampling: channel.sampling }
+        }).collect();
+
+        Layer::new(header.layer_size, header.own_attributes.clone(), Encoding::FAST_LOSSLESS, AnyChannels::sort(list))
+    }
+}
+
+fn read_u32(channel_bytes: &[u8], sample_index: usize) -> u32 {
+    let bytes = &channel_bytes[sample_index * 4 ..];
+    u32::from_le_bytes([ bytes[0], bytes[1], bytes[2], bytes[3] ])
+}
+
+
+#[cfg(test)]
+mod test {
+    use std::io::Cursor;
+    use crate::block::deep::test::deep_scan_line_file;
+    use crate::meta::attribute::ChannelDescription;
+    use super::*;
+
+    /// Writes a deep file with the channels `A`, `R`, `Z`, `ZBack` and `id`, two pixels wide and one pixel high,
+    /// each pixel containing a list of `(A, R, Z, ZBack, id)` samples.
+    fn deep_file(pixels: [&[(f32, f32, f32, f32, u32)]; 2]) -> Vec<u8> {
+        let channels = smallvec::smallvec![
+            ChannelDescription::named("A", SampleType::F16), ChannelDescription::named("R", SampleType::F32),
+            ChannelDescription::named("Z", SampleType::F32), ChannelDescription::named("ZBack", SampleType::F32),
+            ChannelDescription::named("id", SampleType::U32),
+        ];
+
+        let samples = pixels.iter().flat_map(|samples| samples.iter());
+        let floats = |select: fn(&(f32, f32, f32, f32, u32)) -> f32| samples.clone().map(select).flat_map(f32::to_le_bytes).collect::<Vec<u8>>();
+
+        let mut sample_data: Vec<u8> = samples.clone().flat_map(|sample| f16::from_f32(sample.0).to_le_bytes()).collect();
+        sample_data.extend(floats(|sample| sample.1));
+        sample_data.extend(floats(|sample| sample.2));
+        sample_data.extend(floats(|sample| sample.3));
+        sample_data.extend(samples.flat_map(|sample| sample.4.to_le_bytes()));
+
+        deep_scan_line_file(Compression::Uncompressed, channels, 4, &[ (vec![ pixels[0].len(), pixels[1].len() ], sample_data) ])
+    }
+
+    fn channel<'l>(layer: &'l Layer<AnyChannels<FlatSamples>>, name: &str) -> &'l FlatSamples {
+        &layer.channel_data.list.iter().find(|channel| channel.name == Text::from(name)).unwrap().sample_data
+    }
+
+    #[test]
+    fn composite_samples_within_the_range(){
+        let file = deep_file([
+            // unsorted point samples, the first one is in front of the range
+            &[ (0.5, 0.5, 3.0, 3.0, 3), (0.5, 0.25, 1.0, 1.0, 1), (1.0, 1.0, 2.0, 2.0, 2) ],
+
+            // a volume sample from 0 to 4, half of which is inside the range
+            &[ (0.75, 0.75, 0.0, 4.0, 7) ],
+        ]);
+
+        let layers = read_depth_slice_from_buffered(Cursor::new(file), 2.0 .. 4.0).unwrap();
+        assert_eq!(layers.len(), 1);
+
+        let layer = &layers[0];
+        let names: Vec<&Text> = layer.channel_data.list.iter().map(|channel| &channel.name).collect();
+        assert_eq!(names, vec![ &Text::from("A"), &Text::from("R"), &Text::from("id") ]);
+
+        // the opaque sample at depth 2 hides the sample at depth 3
+        assert_eq!(channel(layer, "A").value_by_flat_index(0), Sample::F32(1.0));
+        assert_eq!(channel(layer, "R").value_by_flat_index(0), Sample::F32(1.0));
+        assert_eq!(channel(layer, "id").value_by_flat_index(0), Sample::U32(2));
+
+        // splitting an alpha of 0.75 in half results in an alpha of 0.5
+        assert_eq!(channel(layer, "A").value_by_flat_index(1), Sample::F32(0.5));
+        assert!((channel(layer, "R").value_by_flat_index(1).to_f32() - 0.5).abs() < 0.0001);
+        assert_eq!(channel(layer, "id").value_by_flat_index(1), Sample::U32(7));
+    }
+
+    #[test]
+    fn empty_pixels_are_zero(){
+        let file = deep_file([ &[ (1.0, 1.0, 5.0, 5.0, 1) ], &[] ]);
+        let layers = read_depth_slice_from_buffered(Cursor::new(file.clone()), 0.0 .. 5.0).unwrap();
+
+        for name in &[ "A", "R" ] {
+            assert!(channel(&layers[0], name).values_as_f32().all(|value| value == 0.0));
+        }
+
+        assert!(read_depth_slice_from_buffered(Cursor::new(file), 5.0 .. 5.0).is_err());
+    }
+}
@@ -0,0 +1,169 @@
+
+//! Inspect and convert depth channels.
+//! Renderers disagree on how depth is stored in the "Z" channel:
+//! some store the distance to the camera, some store the reciprocal `1/z`,
+//! and some normalize the distance between the near and far clipping planes.
+//! Use `FlatSamples::convert_depth` to move depth from one convention to another.
+
+use crate::image::{FlatSamples, Layer, AnyChannels, AnyChannel};
+use crate::block::samples::Sample;
+use crate::meta::attribute::Text;
+use half::f16;
+
+
+/// How depth values are encoded in a channel.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DepthConvention {
+
+    /// The distance from the camera. Larger values are further away.
+    /// Infinity represents the background.
+    Linear,
+
+    /// The reciprocal of the distance, `1/z`. Larger values are closer to the camera.
+    /// Zero represents the background.
+    Inverse,
+
+    /// The distance, remapped such that the near plane is zero and the far plane is one.
+    Normalized {
+
+        /// The distance that is mapped to zero.
+        near: f32,
+
+        /// The distance that is mapped to one.
+        far: f32,
+    },
+}
+
+impl DepthConvention {
+
+    /// Convert a value in this convention to the linear distance from the camera.
+    #[inline]
+    pub fn to_linear(self, value: f32) -> f32 {
+        match self {
+            DepthConvention::Linear => value,
+            DepthConvention::Inverse => 1.0 / value,
+            DepthConvention::Normalized { near, far } => near + value * (far - near),
+        }
+    }
+
+    /// Convert the linear distance from the camera to a value in this convention.
+    #[inline]
+    pub fn from_linear(self, distance: f32) -> f32 {
+        match self {
+            DepthConvention::Linear => distance,
+            DepthConvention::Inverse => 1.0 / distance,
+            DepthConvention::Normalized { near, far } => (distance - near) / (far - near),
+        }
+    }
+}
+
+
+impl FlatSamples {
+
+    /// The smallest and largest finite depth in this channel.
+    /// Infinite and `NaN` values are ignored, as they usually mark the background.
+    /// Returns `None` if no finite value exists.
+    pub fn depth_range(&self) -> Option<(f32, f32)> {
+        self.values_as_f32()
+            .filter(|value| value.is_finite())
+            .fold(None, |range, value| match range {
+                None => Some((value, value)),
+                Some((min, max)) => Some((min.min(value), max.max(value))),
+            })
+    }
+
+    /// Convert all depth values from one convention to another.
+    /// The sample type is not changed, so `f16` samples may overflow to infinity.
+    pub fn convert_depth(&mut self, from: DepthConvention, to: DepthConvention) {
+        if from != to {
+            self.map_values_as_f32(|value| to.from_linear(from.to_linear(value)));
+        }
+    }
+
+    /// Replace each linear depth value `z` by `1/z`.
+    /// Applying this function twice yields the original values.
+    pub fn invert_depth(&mut self) {
+        self.convert_depth(DepthConvention::Linear, DepthConvention::Inverse)
+    }
+
+    /// Remap linear depth values such that `near` becomes zero and `far` becomes one.
+    /// Values outside of the range are not clamped.
+    pub fn normalize_depth(&mut self, near: f32, far: f32) {
+        self.convert_depth(DepthConvention::Linear, DepthConvention::Normalized { near, far })
+    }
+
+    pub(crate) fn map_values_as_f32(&mut self, map: impl Fn(f32) -> f32) {
+        match self {
+            FlatSamples::F16(values) => for value in values {
+                *value = f16::from_f32(map(value.to_f32()));
+            },
+
+            FlatSamples::F32(values) => for value in values {
+                *value = map(*value);
+            },
+
+            FlatSamples::U32(values) => for value in values {
+                *value = Sample::F32(map(Sample::U32(*value).to_f32())).to_u32();
+            },
+        }
+    }
+}
+
+
+/// Whether this channel name denotes depth, either `Z` or a layer-prefixed `*.Z`.
+fn is_depth_channel_name(name: &Text) -> bool {
+    let bytes = name.bytes();
+    bytes == b"Z" || bytes.ends_with(b".Z")
+}
+
+impl Layer<AnyChannels<FlatSamples>> {
+
+    /// Find the first channel named `Z` or ending with `.Z`.
+    pub fn depth_channel(&self) -> Option<&AnyChannel<FlatSamples>> {
+        self.channel_data.list.iter().find(|channel| is_depth_channel_name(&channel.name))
+    }
+
+    /// Find the first channel named `Z` or ending with `.Z`.
+    pub fn depth_channel_mut(&mut self) -> Option<&mut AnyChannel<FlatSamples>> {
+        self.channel_data.list.iter_mut().find(|channel| is_depth_channel_name(&channel.name))
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn depth_range_ignores_background(){
+        let depth = FlatSamples::F32(vec![ 3.0, f32::INFINITY, 0.5, f32::NAN, 8.0 ]);
+        assert_eq!(depth.depth_range(), Some((0.5, 8.0)));
+
+        let background = FlatSamples::F32(vec![ f32::INFINITY; 3 ]);
+        assert_eq!(background.depth_range(), None);
+    }
+
+    #[test]
+    fn inverse_depth_roundtrip(){
+        let mut depth = FlatSamples::F32(vec![ 2.0, 4.0, f32::INFINITY ]);
+
+        depth.invert_depth();
+        assert_eq!(depth, FlatSamples::F32(vec![ 0.5, 0.25, 0.0 ]));
+
+        depth.invert_depth();
+        assert_eq!(depth, FlatSamples::F32(vec![ 2.0, 4.0, f32::INFINITY ]));
+    }
+
+    #[test]
+    fn convert_between_conventions(){
+        let mut depth = FlatSamples::F16(vec![ f16::from_f32(0.0), f16::from_f32(0.5), f16::from_f32(1.0) ]);
+
+        depth.convert_depth(
+            DepthConvention::Normalized { near: 1.0, far: 5.0 },
+            DepthConvention::Inverse
+        );
+
+        let inverse: Vec<f32> = depth.values_as_f32().collect();
+        assert_eq!(inverse, vec![ 1.0, 1.0 / 3.0, 0.2 ].into_iter().map(|v| f16::from_f32(v).to_f32()).collect::<Vec<f32>>());
+    }
+}
@@ -0,0 +1,538 @@
+//! Assemble and split environment maps for image based lighting.
+//! A cube map is stored as a single layer that contains the six square faces,
+//! stacked vertically in the order `+X, -X, +Y, -Y, +Z, -Z`.
+//! The layer is marked with the `envmap` attribute, so that other applications recognize the layout.
+//!
+//! Use `assemble_cube_map` to create such a layer from six separate faces,
+//! and `Layer::split_cube_map` to obtain the faces of a layer that was read from a file.
+//! Use `Layer::latlong_to_cube_map` and `Layer::cube_map_to_latlong` to resample between the two layouts.
+//! The directions of the pixels follow the conventions of the OpenEXR reference implementation.
+
+use std::convert::TryFrom;
+use std::f32::consts::PI;
+use smallvec::SmallVec;
+use half::f16;
+
+use crate::image::{FlatSamples, Layer, AnyChannels, AnyChannel};
+use crate::meta::attribute::EnvironmentMap;
+use crate::math::Vec2;
+use crate::error::{Result, Error, UnitResult};
+
+
+/// One side of a cube map.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum CubeFace {
+
+    /// The face in the direction of the positive x axis.
+    PositiveX,
+
+    /// The face in the direction of the negative x axis.
+    NegativeX,
+
+    /// The face in the direction of the positive y axis.
+    PositiveY,
+
+    /// The face in the direction of the negative y axis.
+    NegativeY,
+
+    /// The face in the direction of the positive z axis.
+    PositiveZ,
+
+    /// The face in the direction of the negative z axis.
+    NegativeZ,
+}
+
+impl CubeFace {
+
+    /// All faces, in the order they are stored in the file, from top to bottom.
+    pub const ALL: [CubeFace; 6] = [
+        CubeFace::PositiveX, CubeFace::NegativeX,
+        CubeFace::PositiveY, CubeFace::NegativeY,
+        CubeFace::PositiveZ, CubeFace::NegativeZ,
+    ];
+
+    /// The position of this face in the file, from top to bottom.
+    pub fn index(self) -> usize {
+        CubeFace::ALL.iter().position(|&face| face == self).expect("all faces are listed")
+    }
+
+    /// The face that is hit by the direction, and the position within that face,
+    /// where each coordinate ranges from `0` to `face_size - 1`.
+    fn from_direction(direction: [f32; 3], face_size: usize) -> (Self, Vec2<f32>) {
+        let [x, y, z] = direction;
+        let (abs_x, abs_y, abs_z) = (x.abs(), y.abs(), z.abs());
+        let max = (face_size - 1) as f32;
+        let position = |a: f32, b: f32, length: f32| Vec2((a / length + 1.0) / 2.0 * max, (b / length + 1.0) / 2.0 * max);
+
+        if abs_x >= abs_y && abs_x >= abs_z {
+            if abs_x == 0.0 { return (CubeFace::PositiveX, Vec2(0.0, 0.0)); }
+            (if x >= 0.0 { CubeFace::PositiveX } else { CubeFace::NegativeX }, position(y, z, abs_x))
+        }
+        else if abs_y >= abs_z {
+            (if y >= 0.0 { CubeFace::PositiveY } else { CubeFace::NegativeY }, position(x, z, abs_y))
+        }
+        else {
+            (if z >= 0.0 { CubeFace::PositiveZ } else { CubeFace::NegativeZ }, position(x, y, abs_z))
+        }
+    }
+
+    /// The direction that points through the position within this face.
+    fn direction(self, position_in_face: Vec2<f32>, face_size: usize) -> [f32; 3] {
+        let position = if face_size > 1 {
+            let max = (face_size - 1) as f32;
+            Vec2(position_in_face.x() / max * 2.0 - 1.0, position_in_face.y() / max * 2.0 - 1.0)
+        } else { Vec2(0.0, 0.0) };
+
+        let Vec2(a, b) = position;
+        match self {
+            CubeFace::PositiveX => [ 1.0, a, b ],
+            CubeFace::NegativeX => [ -1.0, a, b ],
+            CubeFace::PositiveY => [ a, 1.0, b ],
+            CubeFace::NegativeY => [ a, -1.0, b ],
+            CubeFace::PositiveZ => [ a, b, 1.0 ],
+            CubeFace::NegativeZ => [ a, b, -1.0 ],
+        }
+    }
+
+    /// Convert the position within this face to the pixel position within the face,
+    /// as each face is stored with a different orientation.
+    fn pixel_in_face(self, position_in_face: Vec2<f32>, face_size: usize) -> Vec2<f32> {
+        let max = (face_size - 1) as f32;
+        let Vec2(a, b) = position_in_face;
+
+        match self {
+            CubeFace::PositiveX => Vec2(b, max - a),
+            CubeFace::NegativeX => Vec2(max - b, max - a),
+            CubeFace::PositiveY => Vec2(a, max - b),
+            CubeFace::NegativeY => Vec2(a, b),
+            CubeFace::PositiveZ => Vec2(max - a, max - b),
+            CubeFace::NegativeZ => Vec2(a, max - b),
+        }
+    }
+
+    /// The inverse of `pixel_in_face`.
+    fn position_of_pixel_in_face(self, pixel: Vec2<f32>, face_size: usize) -> Vec2<f32> {
+        let max = (face_size - 1) as f32;
+        let Vec2(x, y) = pixel;
+
+        match self {
+            CubeFace::PositiveX => Vec2(max - y, x),
+            CubeFace::NegativeX => Vec2(max - y, max - x),
+            CubeFace::PositiveY => Vec2(x, max - y),
+            CubeFace::NegativeY => Vec2(x, y),
+            CubeFace::PositiveZ => Vec2(max - x, max - y),
+            CubeFace::NegativeZ => Vec2(x, max - y),
+        }
+    }
+}
+
+/// How the pixels of an environment map are sampled when converting it to another layout.
+/// Channels with `u32` samples always use the nearest pixel, as they often contain ids.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum EnvironmentFilter {
+
+    /// Use the value of the closest pixel. Fast, but produces jagged edges.
+    Nearest,
+
+    /// Interpolate linearly between the four closest pixels.
+    Bilinear,
+}
+
+
+/// Stack the six faces into a single cube map layer, in the order of `CubeFace::ALL`.
+/// The attributes and encoding of the first face are used for the cube map,
+/// and its `environment_map` attribute is set to `EnvironmentMap::Cube`.
+///
+/// All faces must be square, have the same size, and have the same channels with the same sample types.
+/// Subsampled channels are not supported.
+pub fn assemble_cube_map(faces: [Layer<AnyChannels<FlatSamples>>; 6]) -> Result<Layer<AnyChannels<FlatSamples>>> {
+    let [first, rest @ ..] = &faces;
+    let face_size = first.size.width();
+
+    if first.size != Vec2(face_size, face_size) {
+        return Err(Error::invalid("cube map faces must be square"));
+    }
+
+    for face in &faces {
+        if face.size != first.size { return Err(Error::invalid("cube map faces must have the same size")); }
+        validate_channels(&first.channel_data, &face.channel_data)?;
+    }
+
+    // the faces have the same width, so stacking them vertically
+    // simply appends the samples of each face to the previous faces
+    let list = first.channel_data.list.iter().enumerate().map(|(channel_index, channel)| {
+        let mut sample_data = channel.sample_data.clone();
+
+        for face in rest {
+            append_samples(&mut sample_data, &face.channel_data.list[channel_index].sample_data);
+        }
+
+        AnyChannel { sample_data, .. channel.clone() }
+    }).collect();
+
+    let mut attributes = first.attributes.clone();
+    attributes.environment_map = Some(EnvironmentMap::Cube);
+
+    Ok(Layer {
+        channel_data: AnyChannels { list },
+        size: Vec2(face_size, face_size * 6),
+        encoding: first.encoding,
+        attributes,
+    })
+}
+
+
+impl Layer<AnyChannels<FlatSamples>> {
+
+    /// The size of each face, if this layer has the layout of a cube map.
+    /// Does not check the `environment_map` attribute.
+    pub fn cube_face_size(&self) -> Option<usize> {
+        let face_size = self.size.width();
+        if face_size > 0 && self.size.height() == face_size * 6 { Some(face_size) }
+        else { None }
+    }
+
+    /// Split a cube map layer into its six faces, in the order of `CubeFace::ALL`.
+    /// Each face keeps the attributes and encoding of this layer, without the `environment_map` attribute.
+    /// Fails if the layer is not six times as high as wide, if it is marked as a latitude-longitude map,
+    /// or if any channel is subsampled.
+    pub fn split_cube_map(&self) -> Result<[Self; 6]> {
+        if self.attributes.environment_map == Some(EnvironmentMap::LatitudeLongitude) {
+            return Err(Error::invalid("layer is a latitude-longitude map, not a cube map"));
+        }
+
+        let face_size = self.cube_face_size()
+            .ok_or(Error::invalid("cube map must be six times as high as wide"))?;
+
+        if self.channel_data.list.iter().any(|channel| channel.sampling != Vec2(1, 1)) {
+            return Err(Error::unsupported("splitting subsampled channels"));
+        }
+
+        let mut attributes = self.attributes.clone();
+        attributes.environment_map = None;
+
+        let face_sample_count = face_size * face_size;
+        let faces: Vec<Self> = (0 .. 6).map(|face_index| {
+            let samples = face_index * face_sample_count .. (face_index + 1) * face_sample_count;
+
+            let list = self.channel_data.list.iter().map(|channel| AnyChannel {
+                sample_data: slice_samples(&channel.sample_data, samples.clone()),
+                name: channel.name.clone(),
+                quantize_linearly: channel.quantize_linearly,
+                sampling: channel.sampling,
+            }).collect();
+
+            Layer {
+                channel_data: AnyChannels { list },
+                size: Vec2(face_size, face_size),
+                attributes: attributes.clone(),
+                encoding: self.encoding,
+            }
+        }).collect();
+
+        Ok(<[Self; 6]>::try_from(faces).expect("six faces"))
+    }
+
+    /// Resample this latitude-longitude map to a cube map with the specified face size.
+    /// Keeps the channels, sample types, attributes and encoding of this layer,
+    /// and sets the `environment_map` attribute to `EnvironmentMap::Cube`.
+    /// Fails if this layer is marked as a cube map, or if any channel is subsampled.
+    pub fn latlong_to_cube_map(&self, face_size: usize, filter: EnvironmentFilter) -> Result<Self> {
+        if self.attributes.environment_map == Some(EnvironmentMap::Cube) {
+            return Err(Error::invalid("layer is a cube map, not a latitude-longitude map"));
+        }
+
+        if face_size == 0 { return Err(Error::invalid("cube map face size")); }
+        let source_size = self.size;
+
+        let channel_data = self.resample(Vec2(face_size, face_size * 6), |pixel| {
+            let face = CubeFace::ALL[pixel.y() / face_size];
+            let pixel_in_face = Vec2(pixel.x() as f32, (pixel.y() % face_size) as f32);
+            let direction = face.direction(face.position_of_pixel_in_face(pixel_in_face, face_size), face_size);
+
+            let position = latlong_pixel_position(direction, source_size);
+            filter_taps(position, source_size, 0, source_size.width(), true, filter)
+        })?;
+
+        let mut attributes = self.attributes.clone();
+        attributes.environment_map = Some(EnvironmentMap::Cube);
+        Ok(Layer { channel_data, attributes, size: Vec2(face_size, face_size * 6), encoding: self.encoding })
+    }
+
+    /// Resample this cube map to a latitude-longitude map with the specified size.
+    /// Keeps the channels, sample types, attributes and encoding of this layer,
+    /// and sets the `environment_map` attribute to `EnvironmentMap::LatitudeLongitude`.
+    /// Fails if this layer does not have the layout of a cube map, or if any channel is subsampled.
+    pub fn cube_map_to_latlong(&self, size: impl Into<Vec2<usize>>, filter: EnvironmentFilter) -> Result<Self> {
+        let size = size.into();
+
+        if self.attributes.environment_map == Some(EnvironmentMap::LatitudeLongitude) {
+            return Err(Error::invalid("layer is a latitude-longitude map, not a cube map"));
+        }
+
+        let face_size = self.cube_face_size()
+            .ok_or(Error::invalid("cube map must be six times as high as wide"))?;
+
+        if size.area() == 0 { return Err(Error::invalid("latitude-longitude map size")); }
+
+        let channel_data = self.resample(size, |pixel| {
+            let direction = latlong_direction(Vec2(pixel.x() as f32, pixel.y() as f32), size);
+            let (face, position_in_face) = CubeFace::from_direction(direction, face_size);
+            let pixel_in_face = face.pixel_in_face(position_in_face, face_size);
+
+            // never interpolate across the border of a face
+            let face_size = Vec2(face_size, face_size);
+            filter_taps(pixel_in_face, face_size, face.index() * face_size.height(), face_size.width(), false, filter)
+        })?;
+
+        let mut attributes = self.attributes.clone();
+        attributes.environment_map = Some(EnvironmentMap::LatitudeLongitude);
+        Ok(Layer { channel_data, attributes, size, encoding: self.encoding })
+    }
+
+    /// Compute each pixel of the target size by combining the source pixels returned by the function.
+    fn resample(&self, size: Vec2<usize>, taps_of_pixel: impl Fn(Vec2<usize>) -> Taps) -> Result<AnyChannels<FlatSamples>> {
+        if self.size.area() == 0 { return Err(Error::invalid("empty environment map")); }
+
+        if self.channel_data.list.iter().any(|channel| channel.sampling != Vec2(1, 1)) {
+            return Err(Error::unsupported("resampling subsampled channels"));
+        }
+
+        let taps: Vec<Taps> = (0 .. size.height())
+            .flat_map(|y| (0 .. size.width()).map(move |x| Vec2(x, y)))
+            .map(taps_of_pixel).collect();
+
+        let interpolate = |samples: &[f32], taps: &Taps| -> f32 {
+            taps.iter().map(|&(index, weight)| samples[index] * weight).sum()
+        };
+
+        let list = self.channel_data.list.iter().map(|channel| {
+            let sample_data = match &channel.sample_data {
+                FlatSamples::F16(samples) => {
+                    let samples: Vec<f32> = samples.iter().map(|sample| sample.to_f32()).collect();
+                    FlatSamples::F16(taps.iter().map(|taps| f16::from_f32(interpolate(&samples, taps))).collect())
+                },
+
+                FlatSamples::F32(samples) => FlatSamples::F32(taps.iter().map(|taps| interpolate(samples, taps)).collect()),
+
+                FlatSamples::U32(samples) => FlatSamples::U32(taps.iter().map(|taps| {
+                    let nearest = taps.iter().fold(taps[0], |nearest, &tap| if tap.1 > nearest.1 { tap } else { nearest });
+                    samples[nearest.0]
+                }).collect()),
+            };
+
+            AnyChannel { sample_data, name: channel.name.clone(), quantize_linearly: channel.quantize_linearly, sampling: channel.sampling }
+        }).collect();
+
+        Ok(AnyChannels { list })
+    }
+}
+
+
+/// The flat indices of the source pixels, and the weight of each pixel.
+type Taps = SmallVec<[(usize, f32); 4]>;
+
+/// The source pixels that contribute to the position within the region.
+/// The region starts at the row `first_row` of the layer, and covers `size` pixels.
+/// Coordinates outside the region are clamped, except for horizontally wrapping regions.
+fn filter_taps(position: Vec2<f32>, size: Vec2<usize>, first_row: usize, layer_width: usize, wrap_x: bool, filter: EnvironmentFilter) -> Taps {
+    let clamp = |value: i64, length: usize| value.max(0).min(length as i64 - 1) as usize;
+    let column = |x: i64| if wrap_x { x.rem_euclid(size.width() as i64) as usize } else { clamp(x, size.width()) };
+    let index = |x: i64, y: i64| (first_row + clamp(y, size.height())) * layer_width + column(x);
+
+    match filter {
+        EnvironmentFilter::Nearest => {
+            let mut taps = Taps::new();
+            taps.push((index(position.x().round() as i64, position.y().round() as i64), 1.0));
+            taps
+        },
+
+        EnvironmentFilter::Bilinear => {
+            let (left, top) = (position.x().floor(), position.y().floor());
+            let (right_weight, bottom_weight) = (position.x() - left, position.y() - top);
+            let (left, top) = (left as i64, top as i64);
+
+            [
+                (index(left, top), (1.0 - right_weight) * (1.0 - bottom_weight)),
+                (index(left + 1, top), right_weight * (1.0 - bottom_weight)),
+                (index(left, top + 1), (1.0 - right_weight) * bottom_weight),
+                (index(left + 1, top + 1), right_weight * bottom_weight),
+            ].iter().copied().collect()
+        },
+    }
+}
+
+/// The direction that points through the pixel of a latitude-longitude map.
+fn latlong_direction(pixel: Vec2<f32>, size: Vec2<usize>) -> [f32; 3] {
+    let latitude = if size.height() > 1 { -PI * (pixel.y() / (size.height() - 1) as f32 - 0.5) } else { 0.0 };
+    let longitude = if size.width() > 1 { -2.0 * PI * (pixel.x() / (size.width() - 1) as f32 - 0.5) } else { 0.0 };
+    [ longitude.sin() * latitude.cos(), latitude.sin(), longitude.cos() * latitude.cos() ]
+}
+
+/// The pixel position of a direction in a latitude-longitude map.
+fn latlong_pixel_position(direction: [f32; 3], size: Vec2<usize>) -> Vec2<f32> {
+    let [x, y, z] = direction;
+    let length = (x * x + y * y + z * z).sqrt();
+    let radius = (z * z + x * x).sqrt();
+
+    let latitude = if radius < y.abs() { (radius / length).acos() * y.signum() } else { (y / length).asin() };
+    let longitude = if z == 0.0 && x == 0.0 { 0.0 } else { x.atan2(z) };
+
+    Vec2(
+        (longitude / (-2.0 * PI) + 0.5) * size.width().saturating_sub(1) as f32,
+        (latitude / -PI + 0.5) * size.height().saturating_sub(1) as f32,
+    )
+}
+
+
+fn validate_channels(expected: &AnyChannels<FlatSamples>, channels: &AnyChannels<FlatSamples>) -> UnitResult {
+    let same_channels = expected.list.len() == channels.list.len() &&
+        expected.list.iter().zip(&channels.list).all(|(expected, channel)|
+            expected.name == channel.name && channel.sampling == Vec2(1, 1) &&
+                std::mem::discriminant(&expected.sample_data) == std::mem::discriminant(&channel.sample_data)
+        );
+
+    if same_channels { Ok(()) }
+    else { Err(Error::invalid("cube map faces must have the same channels")) }
+}
+
+/// Both sample buffers must have the same type.
+fn append_samples(target: &mut FlatSamples, source: &FlatSamples) {
+    match (target, source) {
+        (FlatSamples::F16(target), FlatSamples::F16(source)) => target.extend_from_slice(source),
+        (FlatSamples::F32(target), FlatSamples::F32(source)) => target.extend_from_slice(source),
+        (FlatSamples::U32(target), FlatSamples::U32(source)) => target.extend_from_slice(source),
+        _ => unreachable!("cube face sample types were validated"),
+    }
+}
+
+fn slice_samples(samples: &FlatSamples, range: std::ops::Range<usize>) -> FlatSamples {
+    match samples {
+        FlatSamples::F16(samples) => FlatSamples::F16(samples[range].to_vec()),
+        FlatSamples::F32(samples) => FlatSamples::F32(samples[range].to_vec()),
+        FlatSamples::U32(samples) => FlatSamples::U32(samples[range].to_vec()),
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use std::io::Cursor;
+    use crate::prelude::*;
+    use super::*;
+
+    fn face(value: f32) -> Layer<AnyChannels<FlatSamples>> {
+        let channels = AnyChannels::sort(smallvec::smallvec![
+            AnyChannel::new("Y", FlatSamples::F32((0 .. 9).map(|index| value + index as f32).collect())),
+        ]);
+
+        Layer::new((3, 3), LayerAttributes::named("sky"), Encoding::FAST_LOSSLESS, channels)
+    }
+
+    #[test]
+    fn cube_map_file_round_trip(){
+        let faces = [ face(0.0), face(10.0), face(20.0), face(30.0), face(40.0), face(50.0) ];
+        let cube = assemble_cube_map(faces.clone()).unwrap();
+
+        assert_eq!(cube.size, Vec2(3, 18));
+        assert_eq!(cube.attributes.environment_map, Some(EnvironmentMap::Cube));
+        assert_eq!(cube.channel_data.list[0].sample_data.value_by_flat_index(CubeFace::NegativeY.index() * 9 + 4), Sample::F32(34.0));
+
+        let mut bytes = Vec::new();
+        Image::from_layer(cube).write().to_buffered(Cursor::new(&mut bytes)).unwrap();
+
+        let image = read().no_deep_data().largest_resolution_level().all_channels().first_valid_layer().all_attributes()
+            .from_buffered(Cursor::new(bytes)).unwrap();
+
+        assert_eq!(image.layer_data.attributes.environment_map, Some(EnvironmentMap::Cube));
+
+        let split = image.layer_data.split_cube_map().unwrap();
+        for (split, original) in split.iter().zip(&faces) {
+            assert_eq!(split.size, original.size);
+            assert_eq!(split.channel_data, original.channel_data);
+            assert_eq!(split.attributes.environment_map, None);
+        }
+    }
+
+    #[test]
+    fn refuse_invalid_faces(){
+        let mut wide = face(0.0);
+        wide.size = Vec2(9, 1);
+        assert!(assemble_cube_map([ wide, face(1.0), face(2.0), face(3.0), face(4.0), face(5.0) ]).is_err());
+
+        let mut renamed = face(0.0);
+        renamed.channel_data.list[0].name = Text::from("Z");
+        assert!(assemble_cube_map([ face(0.0), face(1.0), renamed, face(3.0), face(4.0), face(5.0) ]).is_err());
+
+        let mut latlong = assemble_cube_map([ face(0.0), face(1.0), face(2.0), face(3.0), face(4.0), face(5.0) ]).unwrap();
+        latlong.attributes.environment_map = Some(EnvironmentMap::LatitudeLongitude);
+        assert!(latlong.split_cube_map().is_err());
+        assert!(face(0.0).split_cube_map().is_err());
+    }
+
+    fn direction_map(size: Vec2<usize>) -> Layer<AnyChannels<FlatSamples>> {
+        let directions: Vec<[f32; 3]> = (0 .. size.height())
+            .flat_map(|y| (0 .. size.width()).map(move |x| latlong_direction(Vec2(x as f32, y as f32), size)))
+            .collect();
+
+        let channel = |name: &str, axis: usize| AnyChannel::new(name, FlatSamples::F32(directions.iter().map(|direction| direction[axis]).collect()));
+        let mut attributes = LayerAttributes::named("sky");
+        attributes.environment_map = Some(EnvironmentMap::LatitudeLongitude);
+
+        Layer::new(size, attributes, Encoding::FAST_LOSSLESS, AnyChannels::sort(smallvec::smallvec![
+            channel("X", 0), channel("Y", 1), channel("Z", 2),
+            AnyChannel::new("id", FlatSamples::U32((0 .. size.area() as u32).collect())),
+        ]))
+    }
+
+    #[test]
+    fn convert_between_latlong_and_cube_map(){
+        let latlong = direction_map(Vec2(64, 32));
+        assert!(latlong.cube_map_to_latlong((8, 4), EnvironmentFilter::Bilinear).is_err());
+
+        let face_size = 8;
+        let cube = latlong.latlong_to_cube_map(face_size, EnvironmentFilter::Bilinear).unwrap();
+        assert_eq!(cube.size, Vec2(8, 48));
+        assert_eq!(cube.attributes.environment_map, Some(EnvironmentMap::Cube));
+        assert!(cube.latlong_to_cube_map(4, EnvironmentFilter::Nearest).is_err());
+
+        // each pixel of the cube map contains the direction it points to
+        let [x, y, z, _] = [0, 1, 2, 3].map(|index| &cube.channel_data.list[index].sample_data);
+        for (flat_index, face) in CubeFace::ALL.iter().flat_map(|&face| std::iter::repeat(face).take(face_size * face_size)).enumerate() {
+            let pixel = Vec2((flat_index % face_size) as f32, ((flat_index / face_size) % face_size) as f32);
+            let [expected_x, expected_y, expected_z] = face.direction(face.position_of_pixel_in_face(pixel, face_size), face_size);
+            let length = (expected_x * expected_x + expected_y * expected_y + expected_z * expected_z).sqrt();
+
+            let actual = [ x, y, z ].map(|samples| samples.value_by_flat_index(flat_index).to_f32());
+            let expected = [ expected_x / length, expected_y / length, expected_z / length ];
+            let error: f32 = actual.iter().zip(&expected).map(|(actual, expected)| (actual - expected).abs()).sum();
+            assert!(error < 0.15, "face {:?}, pixel {:?}: {:?} != {:?}", face, pixel, actual, expected);
+        }
+
+        // the top row of a latitude-longitude map points up, so the +Y face is in the sky
+        let up = (CubeFace::PositiveY.index() * face_size + face_size / 2) * face_size + face_size / 2;
+        assert!(y.value_by_flat_index(up).to_f32() > 0.95);
+
+        let back = cube.cube_map_to_latlong((64, 32), EnvironmentFilter::Nearest).unwrap();
+        assert_eq!(back.attributes.environment_map, Some(EnvironmentMap::LatitudeLongitude));
+
+        for index in 0 .. back.size.area() {
+            let original = latlong.channel_data.list[1].sample_data.value_by_flat_index(index).to_f32();
+            let converted = back.channel_data.list[1].sample_data.value_by_flat_index(index).to_f32();
+            assert!((original - converted).abs() < 0.3);
+        }
+    }
+
+    #[test]
+    fn use_nearest_ids_when_interpolating(){
+        let latlong = direction_map(Vec2(16, 8));
+        let ids = latlong.channel_data.list[3].sample_data.clone();
+        let cube = latlong.latlong_to_cube_map(4, EnvironmentFilter::Bilinear).unwrap();
+
+        let original_ids: Vec<u32> = match ids { FlatSamples::U32(ids) => ids, _ => unreachable!() };
+        match &cube.channel_data.list[3].sample_data {
+            FlatSamples::U32(ids) => assert!(ids.iter().all(|id| original_ids.contains(id))),
+            _ => panic!("sample type changed"),
+        }
+    }
+}
@@ -0,0 +1,304 @@
+//! Composite all layers of an image into a single RGBA layer, like the "flatten" operation of a compositor.
+//! Useful for quick previews of layered exr files.
+//!
+//! Each group of color channels is treated as one layer. The group is identified by the layer name
+//! and the channel name prefix, for example the channels `diffuse.R`, `diffuse.G` and `diffuse.B`
+//! of a layer named `beauty` form the layer `beauty.diffuse`.
+//! Layers with only a luminance channel `Y` are treated as gray.
+//! Groups without any of the channels `R`, `G`, `B` or `Y`, such as depth, are ignored.
+//! Colors are assumed to be premultiplied by alpha, as is the convention in exr files.
+//! Layers without alpha are opaque. Currently does not support subsampled channels.
+
+use crate::image::*;
+use crate::error::{Result, Error};
+
+
+/// How the color of a layer is combined with the layers below it.
+/// All modes expect and produce colors that are premultiplied by alpha.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub enum BlendMode {
+
+    /// The layer covers the layers below it, according to its alpha.
+    Over,
+
+    /// The colors and alpha of the layer are added to the layers below it.
+    Add,
+
+    /// The colors of the layer are multiplied with the layers below it, darkening the result.
+    Multiply,
+
+    /// The inverted colors of the layer are multiplied with the inverted layers below it, brightening the result.
+    Screen,
+}
+
+/// The order in which the layers are stacked.
+#[derive(Debug, Clone, PartialEq)]
+pub enum LayerOrder {
+
+    /// The first layer in the file is at the bottom, and the last layer is on top.
+    BottomToTop,
+
+    /// The first layer in the file is on top, and the last layer is at the bottom.
+    TopToBottom,
+
+    /// Only the layers with these names, from the bottom to the top.
+    /// Names are the layer name and the channel prefix, separated by `.`.
+    /// The unnamed main layer has an empty name.
+    Named(Vec<Text>),
+}
+
+/// Describes how the layers are flattened.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FlattenOptions {
+
+    /// How the color of each layer is combined with the layers below it.
+    pub blend: BlendMode,
+
+    /// The order in which the layers are stacked.
+    pub order: LayerOrder,
+}
+
+impl Default for FlattenOptions {
+    fn default() -> Self {
+        FlattenOptions { blend: BlendMode::Over, order: LayerOrder::BottomToTop }
+    }
+}
+
+impl BlendMode {
+
+    /// Combine the premultiplied `[r, g, b, a]` color of a layer with the color below it.
+    pub fn blend(self, top: [f32; 4], bottom: [f32; 4]) -> [f32; 4] {
+        let (top_alpha, bottom_alpha) = (top[3], bottom[3]);
+        let alpha = top_alpha + bottom_alpha * (1.0 - top_alpha);
+
+        let color = |index: usize| {
+            let (top, bottom) = (top[index], bottom[index]);
+
+            match self {
+                BlendMode::Over => top + bottom * (1.0 - top_alpha),
+                BlendMode::Add => top + bottom,
+                BlendMode::Multiply => top * bottom + top * (1.0 - bottom_alpha) + bottom * (1.0 - top_alpha),
+                BlendMode::Screen => top + bottom - top * bottom,
+            }
+        };
+
+        let alpha = if self == BlendMode::Add { top_alpha + bottom_alpha } else { alpha };
+        [ color(0), color(1), color(2), alpha ]
+    }
+}
+
+
+impl Image<Layers<AnyChannels<FlatSamples>>> {
+
+    /// Composite the color channels of all layers into a single RGBA layer with `f32` samples.
+    /// The resulting layer covers the data windows of all composited layers.
+    pub fn flatten_layers(&self, options: &FlattenOptions) -> Result<Layer<AnyChannels<FlatSamples>>> {
+        let sources = self.layer_data.iter()
+            .map(flatten_sources).collect::<Result<Vec<_>>>()?
+            .into_iter().flatten().collect();
+
+        flatten(sources, options)
+    }
+
+    /// The names of all layers that would be composited by `flatten_layers`, in file order.
+    pub fn flattenable_layer_names(&self) -> Result<Vec<Text>> {
+        let mut names = Vec::new();
+        for layer in &self.layer_data { names.extend(flatten_sources(layer)?.into_iter().map(|source| source.name)); }
+        Ok(names)
+    }
+}
+
+impl Layer<AnyChannels<FlatSamples>> {
+
+    /// Composite the color channel groups of this layer into a single RGBA layer with `f32` samples.
+    pub fn flatten_layers(&self, options: &FlattenOptions) -> Result<Layer<AnyChannels<FlatSamples>>> {
+        flatten(flatten_sources(self)?, options)
+    }
+}
+
+
+/// A group of color channels within a layer.
+struct Source<'i> {
+    name: Text,
+    bounds: IntegerBounds,
+    rgba: [Option<&'i FlatSamples>; 4],
+}
+
+impl Source<'_> {
+    fn pixel(&self, index: usize) -> [f32; 4] {
+        let sample = |channel: Option<&FlatSamples>, default: f32| channel.map_or(default, |samples| samples.value_by_flat_index(index).to_f32());
+        [ sample(self.rgba[0], 0.0), sample(self.rgba[1], 0.0), sample(self.rgba[2], 0.0), sample(self.rgba[3], 1.0) ]
+    }
+}
+
+fn flatten_sources(layer: &Layer<AnyChannels<FlatSamples>>) -> Result<Vec<Source<'_>>> {
+    if layer.channel_data.list.iter().any(|channel| channel.sampling != Vec2(1, 1)) {
+        return Err(Error::unsupported("flattening subsampled channels"));
+    }
+
+    let split = |name: &Text| -> (Vec<u8>, Vec<u8>) {
+        let bytes = name.bytes();
+        match bytes.iter().rposition(|&byte| byte == b'.') {
+            Some(index) => (bytes[.. index].to_vec(), bytes[index + 1 ..].to_vec()),
+            None => (Vec::new(), bytes.to_vec()),
+        }
+    };
+
+    // sorting by the full name does not keep the channels of one group together, for example `B, Diffuse.R, G`
+    let mut prefixes: Vec<Vec<u8>> = Vec::new();
+    for channel in &layer.channel_data.list {
+        let prefix = split(&channel.name).0;
+        if !prefixes.contains(&prefix) { prefixes.push(prefix); }
+    }
+
+    let sources = prefixes.into_iter().filter_map(|prefix| {
+        let channel = |name: &str| layer.channel_data.list.iter()
+            .find(|channel| split(&channel.name) == (prefix.clone(), name.as_bytes().to_vec()))
+            .map(|channel| &channel.sample_data);
+
+        let luminance = channel("Y");
+        let rgba = [ channel("R").or(luminance), channel("G").or(luminance), channel("B").or(luminance), channel("A") ];
+        if rgba[.. 3].iter().all(Option::is_none) { return None; }
+
+        let components: Vec<&[u8]> = layer.attributes.layer_name.iter().map(|name| name.bytes())
+            .chain(Some(prefix.as_slice()).filter(|prefix| !prefix.is_empty()))
+            .collect();
+
+        let name = Text::from_bytes_unchecked(components.join(&b'.').into_iter().collect());
+        Some(Source { name, bounds: layer.absolute_bounds(), rgba })
+    });
+
+    Ok(sources.collect())
+}
+
+fn flatten(mut sources: Vec<Source<'_>>, options: &FlattenOptions) -> Result<Layer<AnyChannels<FlatSamples>>> {
+    match &options.order {
+        LayerOrder::BottomToTop => {},
+        LayerOrder::TopToBottom => sources.reverse(),
+        LayerOrder::Named(names) => {
+            sources = names.iter().map(|name| {
+                let index = sources.iter().position(|source| &source.name == name)
+                    .ok_or(Error::invalid("flatten order contains a layer that does not exist"))?;
+
+                Ok(sources.swap_remove(index))
+            }).collect::<Result<_>>()?;
+        },
+    }
+
+    let (first, others) = sources.split_first().ok_or(Error::invalid("no color layers to flatten"))?;
+    let (start, end) = others.iter().fold((first.bounds.position, first.bounds.end()), |(start, end), source|
+        (start.min(source.bounds.position), end.max(source.bounds.end()))
+    );
+
+    let bounds = IntegerBounds::new(start, (end - start).to_usize("flattened bounds")?);
+    let mut pixels = vec![ [0.0_f32; 4]; bounds.size.area() ];
+
+    for source in &sources {
+        let offset = (source.bounds.position - bounds.position).to_usize("flattened layer position")?;
+
+        for y in 0 .. source.bounds.size.height() {
+            for x in 0 .. source.bounds.size.width() {
+                let target = &mut pixels[(offset.y() + y) * bounds.size.width() + offset.x() + x];
+                *target = options.blend.blend(source.pixel(y * source.bounds.size.width() + x), *target);
+            }
+        }
+    }
+
+    let channel = |name: &str, index: usize| AnyChannel::new(name, FlatSamples::F32(pixels.iter().map(|pixel| pixel[index]).collect()));
+
+    Ok(Layer::new(
+        bounds.size,
+        LayerAttributes { layer_position: bounds.position, .. LayerAttributes::default() },
+        Encoding::FAST_LOSSLESS,
+        AnyChannels::sort(smallvec![ channel("R", 0), channel("G", 1), channel("B", 2), channel("A", 3) ])
+    ))
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn layer(name: &str, position: (i32, i32), channels: &[(&str, f32)]) -> Layer<AnyChannels<FlatSamples>> {
+        Layer::new(
+            (2, 1), LayerAttributes { layer_position: position.into(), .. LayerAttributes::named(name) },
+            Encoding::UNCOMPRESSED,
+            AnyChannels::sort(channels.iter().map(|&(name, value)| AnyChannel::new(name, FlatSamples::F32(vec![ value; 2 ]))).collect())
+        )
+    }
+
+    fn pixel(layer: &Layer<AnyChannels<FlatSamples>>, x: usize) -> Vec<f32> {
+        ["R", "G", "B", "A"].iter()
+            .map(|&name| layer.channel_data.list.iter().find(|channel| channel.name == Text::from(name)).unwrap())
+            .map(|channel| channel.sample_data.value_by_flat_index(x).to_f32())
+            .collect()
+    }
+
+    #[test]
+    fn blend_modes(){
+        let top = [ 0.25, 0.5, 0.0, 0.5 ];
+        let bottom = [ 1.0, 1.0, 1.0, 1.0 ];
+
+        assert_eq!(BlendMode::Over.blend(top, bottom), [ 0.75, 1.0, 0.5, 1.0 ]);
+        assert_eq!(BlendMode::Add.blend(top, bottom), [ 1.25, 1.5, 1.0, 1.5 ]);
+        assert_eq!(BlendMode::Multiply.blend(top, bottom), [ 0.75, 1.0, 0.5, 1.0 ]);
+        assert_eq!(BlendMode::Screen.blend(top, bottom), [ 1.0, 1.0, 1.0, 1.0 ]);
+        assert_eq!(BlendMode::Over.blend([0.0; 4], bottom), bottom);
+    }
+
+    #[test]
+    fn flatten_layers_of_different_size_and_order(){
+        let image = Image::from_layers(
+            ImageAttributes::new(IntegerBounds::from_dimensions((3, 1))),
+            smallvec![
+                layer("background", (0, 0), &[ ("R", 1.0), ("G", 1.0), ("B", 1.0) ]),
+                layer("overlay", (1, 0), &[ ("R", 0.0), ("G", 0.5), ("B", 0.0), ("A", 0.5), ("Z", 3.0) ]),
+            ]
+        );
+
+        assert_eq!(image.flattenable_layer_names().unwrap(), vec![ Text::from("background"), Text::from("overlay") ]);
+
+        let flat = image.flatten_layers(&FlattenOptions::default()).unwrap();
+        assert_eq!(flat.absolute_bounds(), IntegerBounds::new((0, 0), (3, 1)));
+        assert_eq!(pixel(&flat, 0), vec![ 1.0, 1.0, 1.0, 1.0 ]);
+        assert_eq!(pixel(&flat, 1), vec![ 0.5, 1.0, 0.5, 1.0 ]);
+        assert_eq!(pixel(&flat, 2), vec![ 0.0, 0.5, 0.0, 0.5 ]);
+
+        let reversed = image.flatten_layers(&FlattenOptions { order: LayerOrder::TopToBottom, .. FlattenOptions::default() }).unwrap();
+        assert_eq!(pixel(&reversed, 1), vec![ 1.0, 1.0, 1.0, 1.0 ]);
+
+        let named = FlattenOptions { order: LayerOrder::Named(vec![ Text::from("overlay") ]), .. FlattenOptions::default() };
+        let overlay = image.flatten_layers(&named).unwrap();
+        assert_eq!(overlay.absolute_bounds(), IntegerBounds::new((1, 0), (2, 1)));
+        assert_eq!(pixel(&overlay, 0), vec![ 0.0, 0.5, 0.0, 0.5 ]);
+    }
+
+    #[test]
+    fn flatten_channel_groups_within_a_layer(){
+        let layer = layer("beauty", (0, 0), &[ ("Y", 0.5), ("specular.R", 0.25), ("specular.G", 0.25), ("specular.B", 0.25), ("depth.Z", 1.0) ]);
+
+        let image = Image::from_layers(ImageAttributes::new(layer.absolute_bounds()), smallvec![ layer.clone() ]);
+        assert_eq!(image.flattenable_layer_names().unwrap(), vec![ Text::from("beauty"), Text::from("beauty.specular") ]);
+
+        let added = layer.flatten_layers(&FlattenOptions { blend: BlendMode::Add, .. FlattenOptions::default() }).unwrap();
+        assert_eq!(pixel(&added, 0), vec![ 0.75, 0.75, 0.75, 2.0 ]);
+
+        let missing = FlattenOptions { order: LayerOrder::Named(vec![ Text::from("diffuse") ]), .. FlattenOptions::default() };
+        assert!(layer.flatten_layers(&missing).is_err());
+    }
+
+    #[test]
+    fn flatten_each_channel_group_once(){
+        // the prefixed channels are sorted between the unprefixed channels
+        let layer = layer("beauty", (0, 0), &[
+            ("R", 0.5), ("G", 0.5), ("B", 0.5), ("A", 0.5),
+            ("Diffuse.R", 0.25), ("Diffuse.G", 0.25), ("Diffuse.B", 0.25),
+        ]);
+
+        let image = Image::from_layers(ImageAttributes::new(layer.absolute_bounds()), smallvec![ layer.clone() ]);
+        assert_eq!(image.flattenable_layer_names().unwrap(), vec![ Text::from("beauty"), Text::from("beauty.Diffuse") ]);
+
+        let flat = layer.flatten_layers(&FlattenOptions::default()).unwrap();
+        assert_eq!(pixel(&flat, 0), vec![ 0.25, 0.25, 0.25, 1.0 ]);
+    }
+}
@@ -0,0 +1,290 @@
+//! A fast path for the common case of a few channels with the same sample type, such as RGBA.
+//! The pixels are stored interleaved, as `[T; N]` arrays.
+//! The code is monomorphized over the channel count and the sample type,
+//! which avoids the per-channel dynamic dispatch and per-sample type conversion of the generic readers.
+//! Use `SpecificChannels` or `AnyChannels` for all other cases.
+
+use crate::image::*;
+use crate::meta::header::Header;
+use crate::meta::{Headers, BlockDescription};
+use crate::meta::attribute::{ChannelDescription, SampleType};
+use crate::error::{Result, UnitResult, Error};
+use crate::block::{BlockIndex, UncompressedBlock};
+use crate::block::reader::ChunksReader;
+use crate::block::writer::ChunksWriter;
+use crate::math::Vec2;
+use std::io::{Read, Seek, Write, BufReader, BufWriter};
+use std::path::Path;
+
+
+/// A sample type that can be read and written by the interleaved fast path.
+/// Implemented for `f16`, `f32` and `u32`.
+pub trait InterleavedSample: Copy + Default + Send + Sync + 'static {
+
+    /// The sample type that the channels in the file must have.
+    const SAMPLE_TYPE: SampleType;
+
+    /// Read the sample from the little endian bytes. The slice has exactly the size of this sample.
+    fn from_le_slice(bytes: &[u8]) -> Self;
+
+    /// Write the sample as little endian bytes. The slice has exactly the size of this sample.
+    fn write_le_slice(self, bytes: &mut [u8]);
+}
+
+impl InterleavedSample for f16 {
+    const SAMPLE_TYPE: SampleType = SampleType::F16;
+    #[inline] fn from_le_slice(bytes: &[u8]) -> Self { f16::from_bits(u16::from_le_bytes([bytes[0], bytes[1]])) }
+    #[inline] fn write_le_slice(self, bytes: &mut [u8]) { bytes.copy_from_slice(&self.to_bits().to_le_bytes()) }
+}
+
+impl InterleavedSample for f32 {
+    const SAMPLE_TYPE: SampleType = SampleType::F32;
+    #[inline] fn from_le_slice(bytes: &[u8]) -> Self { f32::from_bits(u32::from_le_slice(bytes)) }
+    #[inline] fn write_le_slice(self, bytes: &mut [u8]) { self.to_bits().write_le_slice(bytes) }
+}
+
+impl InterleavedSample for u32 {
+    const SAMPLE_TYPE: SampleType = SampleType::U32;
+    #[inline] fn from_le_slice(bytes: &[u8]) -> Self { u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) }
+    #[inline] fn write_le_slice(self, bytes: &mut [u8]) { bytes.copy_from_slice(&self.to_le_bytes()) }
+}
+
+
+/// The pixels of a single layer, with `N` channels of type `T` each, stored row by row.
+#[derive(Debug, Clone, PartialEq)]
+pub struct InterleavedPixels<T, const N: usize> {
+
+    /// The resolution of the layer.
+    pub size: Vec2<usize>,
+
+    /// The names of the channels, in the order of the samples in each pixel.
+    /// For example `R`, `G`, `B`, `A`. The file stores the channels sorted alphabetically.
+    pub channels: [Text; N],
+
+    /// All pixels of the layer, row by row.
+    pub pixels: Vec<[T; N]>,
+}
+
+impl<T: InterleavedSample, const N: usize> InterleavedPixels<T, N> {
+
+    /// Create pixels by calling the closure for each pixel position.
+    pub fn from_fn(size: impl Into<Vec2<usize>>, channels: [Text; N], mut get_pixel: impl FnMut(Vec2<usize>) -> [T; N]) -> Self {
+        let size = size.into();
+        let pixels = (0 .. size.area()).map(|index| get_pixel(Vec2(index % size.width(), index / size.width()))).collect();
+        InterleavedPixels { size, channels, pixels }
+    }
+
+    /// Read the largest resolution level of the first layer that contains all the channels from the file.
+    /// All channels must have the sample type `T` and must not be subsampled.
+    /// Returns `Error::NotSupported` otherwise, in which case the generic readers should be used.
+    pub fn read_from_file(path: impl AsRef<Path>, channels: [Text; N]) -> Result<Self> {
+        Self::read_from_buffered(BufReader::new(std::fs::File::open(path)?), channels)
+    }
+
+    /// Read the largest resolution level of the first layer that contains all the channels from the buffered byte source.
+    /// See `read_from_file`.
+    pub fn read_from_buffered(read: impl Read + Seek + Send, channels: [Text; N]) -> Result<Self> {
+        let reader = crate::block::read(read, false)?;
+
+        let (layer_index, header) = reader.headers().iter().enumerate()
+            .find(|(_, header)| !header.deep && channels.iter().all(|name| header.channels.find_index_of_channel(name).is_some()))
+            .ok_or(Error::invalid("no layer in the image contains all specified channels"))?;
+
+        let mut byte_offsets = [0_usize; N];
+        for (byte_offset, name) in byte_offsets.iter_mut().zip(channels.iter()) {
+            let (offset, channel) = header.channels.channels_with_byte_offset()
+                .find(|(_, channel)| &channel.name == name)
+                .expect("channel existence bug");
+
+            if channel.sample_type != T::SAMPLE_TYPE || channel.sampling != Vec2(1, 1) {
+                return Err(Error::unsupported("interleaved fast path requires unsampled channels of the same type"));
+            }
+
+            *byte_offset = offset;
+        }
+
+        let size = header.layer_size;
+        let bytes_per_pixel = header.channels.bytes_per_pixel;
+        let mut pixels = vec![ [T::default(); N]; size.area() ];
+
+        reader
+            .filter_chunks(false, |_, tile, block| block.layer == layer_index && tile.is_largest_resolution_level())?
+            .decompress_parallel(false, |_, block| {
+                insert_block(&mut pixels, size.width(), bytes_per_pixel, &byte_offsets, &block);
+                Ok(())
+            })?;
+
+        Ok(InterleavedPixels { size, channels, pixels })
+    }
+
+    /// Write the pixels as a single layer file, compressing the blocks in parallel.
+    pub fn write_to_file(&self, path: impl AsRef<Path>, encoding: Encoding) -> UnitResult {
+        self.write_to_buffered(BufWriter::new(std::fs::File::create(path)?), encoding)
+    }
+
+    /// Write the pixels as a single layer file to the buffered byte destination, compressing the blocks in parallel.
+    pub fn write_to_buffered(&self, write: impl Write + Seek, encoding: Encoding) -> UnitResult {
+        if self.pixels.len() != self.size.area() {
+            return Err(Error::invalid("pixel count does not match the resolution"));
+        }
+
+        let mut channel_list: SmallVec<[ChannelDescription; 5]> = self.channels.iter()
+            .map(|name| ChannelDescription::named(name.clone(), T::SAMPLE_TYPE))
+            .collect();
+
+        channel_list.sort_unstable_by(|a, b| a.name.cmp(&b.name));
+        if channel_list.windows(2).any(|pair| pair[0].name == pair[1].name) {
+            return Err(Error::invalid("duplicate channel name"));
+        }
+
+        let blocks = match encoding.blocks {
+            Blocks::ScanLines => BlockDescription::ScanLines,
+            Blocks::Tiles(tile_size) => BlockDescription::Tiles(TileDescription {
+                tile_size, level_mode: LevelMode::Singular, rounding_mode: RoundingMode::Down
+            }),
+        };
+
+        let header = Header {
+            own_attributes: LayerAttributes::default(),
+            .. Header::new(Text::default(), self.size, channel_list)
+                .with_encoding(encoding.compression, blocks, encoding.line_order)
+        };
+
+        let headers: Headers = smallvec::smallvec![ header ];
+
+        crate::block::write(write, headers, true, |meta, chunk_writer| {
+            let header = &meta.headers[0];
+            let bytes_per_pixel = header.channels.bytes_per_pixel;
+
+            // the channels in the file are sorted, so find where each interleaved channel goes
+            let mut byte_offsets = [0_usize; N];
+            for (byte_offset, name) in byte_offsets.iter_mut().zip(self.channels.iter()) {
+                *byte_offset = header.channels.channels_with_byte_offset()
+                    .find(|(_, channel)| &channel.name == name)
+                    .map(|(offset, _)| offset)
+                    .expect("the channel list was built from the interleaved channels");
+            }
+
+            let blocks = meta.collect_ordered_block_data(|block_index|
+                extract_block(&self.pixels, self.size.width(), bytes_per_pixel, &byte_offsets, block_index)
+            );
+
+            chunk_writer.on_progress(ignore_progress).compress_all_blocks_parallel(&meta, blocks)
+        })
+    }
+}
+
+/// Copy the samples of each line of the block into the interleaved pixels.
+/// In each line of the block, all samples of one channel are stored next to each other.
+#[inline]
+fn insert_block<T: InterleavedSample, const N: usize>(
+    pixels: &mut [[T; N]], width: usize, bytes_per_pixel: usize,
+    byte_offsets: &[usize; N], block: &UncompressedBlock
+) {
+    let block_width = block.index.pixel_size.width();
+    let sample_size = T::SAMPLE_TYPE.bytes_per_sample();
+
+    for (y, line_bytes) in block.data.chunks_exact(bytes_per_pixel * block_width).enumerate() {
+        let start = (block.index.pixel_position.y() + y) * width + block.index.pixel_position.x();
+        let line_pixels = &mut pixels[start .. start + block_width];
+
+        for (channel, &byte_offset) in byte_offsets.iter().enumerate() {
+            let channel_bytes = &line_bytes[byte_offset * block_width ..][.. sample_size * block_width];
+
+            for (pixel, sample_bytes) in line_pixels.iter_mut().zip(channel_bytes.chunks_exact(sample_size)) {
+                pixel[channel] = T::from_le_slice(sample_bytes);
+            }
+        }
+    }
+}
+
+/// Collect the bytes of a block from the interleaved pixels.
+#[inline]
+fn extract_block<T: InterleavedSample, const N: usize>(
+    pixels: &[[T; N]], width: usize, bytes_per_pixel: usize,
+    byte_offsets: &[usize; N], block: BlockIndex
+) -> Vec<u8> {
+    let block_width = block.pixel_size.width();
+    let sample_size = T::SAMPLE_TYPE.bytes_per_sample();
+    let mut bytes = vec![ 0_u8; bytes_per_pixel * block.pixel_size.area() ];
+
+    for (y, line_bytes) in bytes.chunks_exact_mut(bytes_per_pixel * block_width).enumerate() {
+        let start = (block.pixel_position.y() + y) * width + block.pixel_position.x();
+        let line_pixels = &pixels[start .. start + block_width];
+
+        for (channel, &byte_offset) in byte_offsets.iter().enumerate() {
+            let channel_bytes = &mut line_bytes[byte_offset * block_width ..][.. sample_size * block_width];
+
+            for (pixel, sample_bytes) in line_pixels.iter().zip(channel_bytes.chunks_exact_mut(sample_size)) {
+                pixel[channel].write_le_slice(sample_bytes);
+            }
+        }
+    }
+
+    bytes
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::Cursor;
+    use crate::prelude::pixel_vec::PixelVec;
+    use crate::image::read::layers::ReadChannels;
+    use crate::image::read::image::ReadLayers;
+
+    fn rgba() -> [Text; 4] {
+        [ Text::from("R"), Text::from("G"), Text::from("B"), Text::from("A") ]
+    }
+
+    #[test]
+    fn round_trip_tiled_f16(){
+        let image = InterleavedPixels::from_fn((35, 17), rgba(), |pos|
+            [ pos.x() as f32, pos.y() as f32, 0.5, 1.0 ].map(f16::from_f32)
+        );
+
+        let encoding = Encoding { blocks: Blocks::Tiles(Vec2(16, 16)), .. Encoding::SMALL_LOSSLESS };
+        let mut bytes = Vec::new();
+        image.write_to_buffered(Cursor::new(&mut bytes), encoding).unwrap();
+
+        let result = InterleavedPixels::<f16, 4>::read_from_buffered(Cursor::new(&bytes), rgba()).unwrap();
+        assert_eq!(result, image);
+    }
+
+    #[test]
+    fn equals_generic_reader(){
+        let image = InterleavedPixels::from_fn((20, 40), [ Text::from("R"), Text::from("G"), Text::from("B") ], |pos|
+            [ pos.x() as f32 * 0.5, pos.y() as f32, 3.0 ]
+        );
+
+        let mut bytes = Vec::new();
+        image.write_to_buffered(Cursor::new(&mut bytes), Encoding::FAST_LOSSLESS).unwrap();
+
+        let generic = crate::prelude::read().no_deep_data().largest_resolution_level()
+            .rgb_channels(PixelVec::<(f32, f32, f32)>::constructor, PixelVec::set_pixel)
+            .first_valid_layer().all_attributes()
+            .from_buffered(Cursor::new(&bytes)).unwrap();
+
+        let generic_pixels: Vec<[f32; 3]> = generic.layer_data.channel_data.pixels.pixels.iter()
+            .map(|&(r, g, b)| [r, g, b]).collect();
+
+        assert_eq!(generic_pixels, image.pixels);
+    }
+
+    #[test]
+    fn refuse_other_sample_types(){
+        let image = InterleavedPixels::from_fn((4, 4), [ Text::from("Y") ], |_| [ 1.0_f32 ]);
+        let mut bytes = Vec::new();
+        image.write_to_buffered(Cursor::new(&mut bytes), Encoding::UNCOMPRESSED).unwrap();
+
+        let result = InterleavedPixels::<f16, 1>::read_from_buffered(Cursor::new(&bytes), [ Text::from("Y") ]);
+        assert!(matches!(result, Err(Error::NotSupported(_))));
+    }
+
+    #[test]
+    fn refuse_duplicate_channel_names(){
+        let image = InterleavedPixels::from_fn((4, 4), [ Text::from("Y"), Text::from("Y") ], |_| [ 1.0_f32; 2 ]);
+        let result = image.write_to_buffered(Cursor::new(Vec::new()), Encoding::UNCOMPRESSED);
+        assert!(matches!(result, Err(Error::Invalid(_))));
+    }
+}
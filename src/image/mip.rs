@@ -0,0 +1,352 @@
+
+//! Generate the smaller resolution levels of a layer from its full resolution pixels.
+//! Naive box filtering makes cutout textures, like foliage or fences,
+//! grow dark fringes and fade away in the distance.
+//! Use `MipFilter::AlphaWeighted` or `MipFilter::CoveragePreserving` for such layers.
+
+use crate::image::{FlatSamples, Layer, AnyChannels, AnyChannel, Levels, Blocks};
+use crate::meta::attribute::Text;
+use crate::meta::mip_map_levels;
+use crate::math::{Vec2, RoundingMode};
+use crate::error::{Result, Error};
+use half::f16;
+use std::ops::Range;
+
+
+/// How the samples of one level are combined into a sample of the next smaller level.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MipFilter {
+
+    /// Average all samples equally.
+    /// This is correct for premultiplied colors, which is what exr files usually contain.
+    Box,
+
+    /// Weight each color sample by its alpha value before averaging.
+    /// Fully transparent pixels do not bleed their color into visible pixels.
+    /// Use this for unassociated alpha, for example in imported sRGB textures.
+    /// The alpha channel itself is averaged equally.
+    AlphaWeighted,
+
+    /// Like `AlphaWeighted`, but additionally scales the alpha values of each smaller level,
+    /// such that the fraction of pixels with an alpha of at least `alpha_reference`
+    /// is the same as in the full resolution level.
+    /// Keeps alpha-tested geometry from thinning out in the distance.
+    CoveragePreserving {
+
+        /// The alpha test threshold that the texture will be rendered with, usually `0.5`.
+        alpha_reference: f32
+    },
+}
+
+/// Controls how the mip levels of a single layer are generated.
+/// Each layer can be given different options.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MipGenerationOptions {
+
+    /// How to combine the pixels of a level into the next smaller level.
+    pub filter: MipFilter,
+
+    /// Whether to round up or down when computing the size of the smaller levels.
+    pub rounding_mode: RoundingMode,
+
+    /// The channel that is used as alpha by the filter. Usually "A".
+    /// If the layer has no channel with this name, the box filter is used.
+    pub alpha_channel: Text,
+}
+
+impl Default for MipGenerationOptions {
+    fn default() -> Self {
+        Self {
+            filter: MipFilter::Box,
+            rounding_mode: RoundingMode::Down,
+            alpha_channel: Text::from("A"),
+        }
+    }
+}
+
+impl MipGenerationOptions {
+
+    /// Use the specified filter and the default rounding mode and alpha channel.
+    pub fn with_filter(filter: MipFilter) -> Self {
+        Self { filter, .. Self::default() }
+    }
+}
+
+
+impl Layer<AnyChannels<FlatSamples>> {
+
+    /// Compute all mip levels of this layer, down to a single pixel.
+    /// Each level is computed from the previous level.
+    /// The sample type of each channel is preserved.
+    /// `u32` channels, such as object ids, are never averaged:
+    /// the top left sample of each footprint is used instead.
+    ///
+    /// Mip maps can only be stored in tiles.
+    /// If this layer uses scan line blocks, the result uses 64×64 tiles.
+    /// Fails if any channel is subsampled.
+    pub fn generate_mip_maps(self, options: &MipGenerationOptions) -> Result<Layer<AnyChannels<Levels<FlatSamples>>>> {
+        let Layer { channel_data, attributes, size, mut encoding } = self;
+
+        if channel_data.list.iter().any(|channel| channel.sampling != Vec2(1, 1)) {
+            return Err(Error::invalid("mip maps of subsampled channels"));
+        }
+
+        let alpha_index = channel_data.list.iter()
+            .position(|channel| channel.name == options.alpha_channel);
+
+        let (weight_by_alpha, alpha_reference) = match options.filter {
+            MipFilter::Box => (false, None),
+            MipFilter::AlphaWeighted => (true, None),
+            MipFilter::CoveragePreserving { alpha_reference } => (true, Some(alpha_reference)),
+        };
+
+        let weighting_alpha = alpha_index.filter(|_| weight_by_alpha);
+        let coverage_alpha = alpha_index.and_then(|index| alpha_reference.map(|reference| {
+            let full_resolution_coverage = alpha_coverage(&channel_data.list[index].sample_data, reference);
+            (index, reference, full_resolution_coverage)
+        }));
+
+        let level_sizes: Vec<Vec2<usize>> = mip_map_levels(options.rounding_mode, size)
+            .map(|(_, level_size)| level_size).collect();
+
+        let (descriptions, mut current_level): (Vec<AnyChannel<()>>, Vec<FlatSamples>) = channel_data.list.into_iter()
+            .map(|channel| (
+                AnyChannel { name: channel.name, sample_data: (), quantize_linearly: channel.quantize_linearly, sampling: channel.sampling },
+                channel.sample_data
+            ))
+            .unzip();
+
+        let mut levels_per_channel: Vec<Vec<FlatSamples>> = descriptions.iter()
+            .map(|_| Vec::with_capacity(level_sizes.len())).collect();
+
+        for (level_index, sizes) in level_sizes.windows(2).enumerate() {
+            let next_level = downsample_level(&current_level, weighting_alpha, sizes[0], sizes[1]);
+            let finished_level = std::mem::replace(&mut current_level, next_level);
+            push_level(&mut levels_per_channel, finished_level, level_index, coverage_alpha);
+        }
+
+        push_level(&mut levels_per_channel, current_level, level_sizes.len() - 1, coverage_alpha);
+
+        let list = descriptions.into_iter().zip(levels_per_channel)
+            .map(|(description, level_data)| AnyChannel {
+                name: description.name,
+                quantize_linearly: description.quantize_linearly,
+                sampling: description.sampling,
+                sample_data: Levels::Mip { rounding_mode: options.rounding_mode, level_data },
+            })
+            .collect();
+
+        if encoding.blocks == Blocks::ScanLines {
+            encoding.blocks = Blocks::Tiles(Vec2(64, 64));
+        }
+
+        Ok(Layer { channel_data: AnyChannels { list }, attributes, size, encoding })
+    }
+}
+
+/// Adds one level to each channel. The alpha of all levels except the
+/// full resolution level is scaled to match the original coverage, if requested.
+/// The scaled alpha is not used to compute the next level, so that errors do not accumulate.
+fn push_level(
+    levels_per_channel: &mut [Vec<FlatSamples>], mut level: Vec<FlatSamples>,
+    level_index: usize, coverage_alpha: Option<(usize, f32, f32)>
+){
+    if let Some((alpha_index, reference, target_coverage)) = coverage_alpha {
+        if level_index != 0 {
+            scale_alpha_to_coverage(&mut level[alpha_index], reference, target_coverage);
+        }
+    }
+
+    for (levels, samples) in levels_per_channel.iter_mut().zip(level) {
+        levels.push(samples);
+    }
+}
+
+fn downsample_level(
+    channels: &[FlatSamples], weighting_alpha: Option<usize>,
+    source_size: Vec2<usize>, target_size: Vec2<usize>
+) -> Vec<FlatSamples>
+{
+    let alpha: Option<Vec<f32>> = weighting_alpha
+        .map(|alpha_index| channels[alpha_index].values_as_f32().collect());
+
+    channels.iter().enumerate().map(|(channel_index, samples)| {
+        let weights = alpha.as_deref().filter(|_| Some(channel_index) != weighting_alpha);
+
+        match samples {
+            FlatSamples::F16(values) => FlatSamples::F16(
+                average(|index| values[index].to_f32(), weights, source_size, target_size)
+                    .into_iter().map(f16::from_f32).collect()
+            ),
+
+            FlatSamples::F32(values) => FlatSamples::F32(
+                average(|index| values[index], weights, source_size, target_size)
+            ),
+
+            FlatSamples::U32(values) => FlatSamples::U32(
+                top_left(values, source_size, target_size)
+            ),
+        }
+    }).collect()
+}
+
+/// The source pixels that contribute to a target pixel, along one dimension.
+/// Neighbouring footprints overlap if the source size is odd.
+fn footprint(target_index: usize, source_length: usize, target_length: usize) -> Range<usize> {
+    let start = target_index * source_length / target_length;
+    let end = ((target_index + 1) * source_length + target_length - 1) / target_length;
+    start .. end.max(start + 1).min(source_length)
+}
+
+fn average(
+    value: impl Fn(usize) -> f32, weights: Option<&[f32]>,
+    source_size: Vec2<usize>, target_size: Vec2<usize>
+) -> Vec<f32>
+{
+    let mut result = Vec::with_capacity(target_size.area());
+
+    for target_y in 0 .. target_size.height() {
+        let rows = footprint(target_y, source_size.height(), target_size.height());
+
+        for target_x in 0 .. target_size.width() {
+            let columns = footprint(target_x, source_size.width(), target_size.width());
+
+            let (mut sum, mut count) = (0.0, 0.0);
+            let (mut weighted_sum, mut weight_sum) = (0.0, 0.0);
+
+            for y in rows.clone() {
+                for x in columns.clone() {
+                    let index = y * source_size.width() + x;
+                    let sample = value(index);
+
+                    sum += sample;
+                    count += 1.0;
+
+                    if let Some(weights) = weights {
+                        let weight = weights[index].max(0.0);
+                        weighted_sum += sample * weight;
+                        weight_sum += weight;
+                    }
+                }
+            }
+
+            // fully transparent footprints fall back to the unweighted average
+            result.push(if weight_sum > 0.0 { weighted_sum / weight_sum } else { sum / count });
+        }
+    }
+
+    result
+}
+
+fn top_left(values: &[u32], source_size: Vec2<usize>, target_size: Vec2<usize>) -> Vec<u32> {
+    let mut result = Vec::with_capacity(target_size.area());
+
+    for target_y in 0 .. target_size.height() {
+        let y = footprint(target_y, source_size.height(), target_size.height()).start;
+
+        for target_x in 0 .. target_size.width() {
+            let x = footprint(target_x, source_size.width(), target_size.width()).start;
+            result.push(values[y * source_size.width() + x]);
+        }
+    }
+
+    result
+}
+
+/// The fraction of samples that pass the alpha test.
+fn alpha_coverage(alpha: &FlatSamples, reference: f32) -> f32 {
+    let count = alpha.len().max(1) as f32;
+    alpha.values_as_f32().filter(|&value| value >= reference).count() as f32 / count
+}
+
+/// Find the scale factor for which the alpha coverage matches the target coverage, and apply it.
+fn scale_alpha_to_coverage(alpha: &mut FlatSamples, reference: f32, target_coverage: f32) {
+    // search the threshold that yields the target coverage in the unscaled alpha,
+    // then scale alpha such that this threshold moves onto the reference value
+    let (mut lower, mut upper) = (0.0_f32, 1.0_f32);
+
+    for _ in 0 .. 16 {
+        let threshold = (lower + upper) * 0.5;
+        if alpha_coverage(alpha, threshold) > target_coverage { lower = threshold; }
+        else { upper = threshold; }
+    }
+
+    let threshold = (lower + upper) * 0.5;
+    if threshold <= 0.0 { return; }
+
+    let scale = reference / threshold;
+    alpha.map_values_as_f32(|value| (value * scale).min(1.0));
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::image::Encoding;
+
+    fn layer(size: (usize, usize), channels: Vec<(&str, Vec<f32>)>) -> Layer<AnyChannels<FlatSamples>> {
+        let channels = channels.into_iter()
+            .map(|(name, values)| AnyChannel::new(name, FlatSamples::F32(values)))
+            .collect();
+
+        Layer::new(size, crate::image::LayerAttributes::named("test"), Encoding::UNCOMPRESSED, AnyChannels::sort(channels))
+    }
+
+    fn level_values(layer: &Layer<AnyChannels<Levels<FlatSamples>>>, channel: &str, level: usize) -> Vec<f32> {
+        let channel = layer.channel_data.list.iter().find(|c| c.name == Text::from(channel)).unwrap();
+        match &channel.sample_data {
+            Levels::Mip { level_data, .. } => level_data[level].values_as_f32().collect(),
+            _ => panic!("expected mip maps"),
+        }
+    }
+
+    #[test]
+    fn box_filter_computes_all_levels(){
+        let layer = layer((5, 4), vec![ ("Y", vec![ 2.0; 20 ]) ]);
+        let mips = layer.generate_mip_maps(&MipGenerationOptions::default()).unwrap();
+
+        assert_eq!(mips.encoding.blocks, Blocks::Tiles(Vec2(64, 64)));
+        assert_eq!(level_values(&mips, "Y", 0).len(), 20);
+        assert_eq!(level_values(&mips, "Y", 1), vec![ 2.0; 4 ]);
+        assert_eq!(level_values(&mips, "Y", 2), vec![ 2.0 ]);
+    }
+
+    #[test]
+    fn alpha_weighted_ignores_transparent_color(){
+        let channels = || vec![ ("R", vec![ 1.0, 0.0 ]), ("A", vec![ 1.0, 0.0 ]) ];
+
+        let boxed = layer((2, 1), channels())
+            .generate_mip_maps(&MipGenerationOptions::with_filter(MipFilter::Box)).unwrap();
+
+        let weighted = layer((2, 1), channels())
+            .generate_mip_maps(&MipGenerationOptions::with_filter(MipFilter::AlphaWeighted)).unwrap();
+
+        assert_eq!(level_values(&boxed, "R", 1), vec![ 0.5 ]);
+        assert_eq!(level_values(&weighted, "R", 1), vec![ 1.0 ]);
+        assert_eq!(level_values(&weighted, "A", 1), vec![ 0.5 ]);
+    }
+
+    #[test]
+    fn coverage_is_preserved(){
+        let alpha: Vec<f32> = (0 .. 16).map(|index| if index % 2 == 0 { 0.6 } else { 0.0 }).collect();
+        let filter = MipFilter::CoveragePreserving { alpha_reference: 0.5 };
+
+        let boxed = layer((4, 4), vec![ ("A", alpha.clone()) ])
+            .generate_mip_maps(&MipGenerationOptions::default()).unwrap();
+
+        let preserved = layer((4, 4), vec![ ("A", alpha) ])
+            .generate_mip_maps(&MipGenerationOptions::with_filter(filter)).unwrap();
+
+        assert!(level_values(&boxed, "A", 1).iter().all(|&alpha| alpha < 0.5));
+        assert!(level_values(&preserved, "A", 1).iter().all(|&alpha| alpha >= 0.499));
+        assert_eq!(level_values(&preserved, "A", 0), level_values(&boxed, "A", 0));
+    }
+
+    #[test]
+    fn footprints_cover_odd_sizes(){
+        assert_eq!(footprint(0, 5, 2), 0 .. 3);
+        assert_eq!(footprint(1, 5, 2), 2 .. 5);
+        assert_eq!(footprint(2, 5, 3), 3 .. 5);
+        assert_eq!(footprint(0, 1, 1), 0 .. 1);
+    }
+}
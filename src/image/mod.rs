@@ -28,6 +28,22 @@ pub mod write;
 pub mod crop;
 pub mod pixel_vec;
 pub mod recursive;
+pub mod srgb;
+pub mod depth;
+pub mod mip;
+pub mod atlas;
+pub mod sparse;
+pub mod statistics;
+pub mod naming;
+pub mod subset;
+pub mod raw;
+pub mod interleaved;
+pub mod view;
+pub mod flatten;
+pub mod stitch;
+pub mod texture;
+pub mod environment;
+pub mod deep_slice;
 // pub mod channel_groups;
 
 
@@ -37,7 +53,7 @@ use half::f16;
 use crate::math::{Vec2, RoundingMode};
 use crate::compression::Compression;
 use smallvec::{SmallVec};
-use crate::error::Error;
+use crate::error::{Error, Warning};
 
 /// Don't do anything
 pub(crate) fn ignore_progress(_progress: f64){}
@@ -66,6 +82,10 @@ pub type RgbaImage<Storage> = PixelImage<Storage, RgbaChannels>;
 /// The alpha channel is not required. May be `None` if the image did not contain an alpha channel.
 pub type RgbaChannels = (ChannelDescription, ChannelDescription, ChannelDescription, Option<ChannelDescription>);
 
+/// Contains information about the channels in an rgba image, in the order `(red, green, blue, alpha)`.
+/// Each channel is `None` if the image did not contain it, and it was filled with a default sample instead.
+pub type OptionalRgbaChannels = (Option<ChannelDescription>, Option<ChannelDescription>, Option<ChannelDescription>, Option<ChannelDescription>);
+
 /// Contains information about the channels in an rgb image, in the order `(red, green, blue)`.
 pub type RgbChannels = (ChannelDescription, ChannelDescription, ChannelDescription);
 
@@ -85,6 +105,35 @@ pub struct Image<Layers> {
     pub layer_data: Layers,
 }
 
+/// Information about reading an image that is not part of the image itself.
+/// Returned beside the image by `from_file_with_report` and similar methods of the image reader.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct ReadReport {
+
+    /// The pixel blocks that could not be decoded and have been filled with a placeholder value instead.
+    /// Always empty, unless the image was read with `recover_damaged_chunks`.
+    /// Viewers can use this to mark the damaged areas instead of refusing the whole file.
+    pub damaged_regions: Vec<DamagedRegion>,
+
+    /// The anomalies in the file that were tolerated while reading, because reading was not pedantic.
+    /// Always empty when reading pedantically.
+    pub warnings: Vec<Warning>,
+}
+
+/// A block of pixels that could not be decoded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct DamagedRegion {
+
+    /// The index of the layer in the image.
+    pub layer_index: usize,
+
+    /// The mip or rip level index of the damaged block. Zero for the full resolution level.
+    pub level: Vec2<usize>,
+
+    /// The damaged pixels inside the level, relative to the top left corner of the layer.
+    pub bounds: IntegerBounds,
+}
+
 /// A list of layers. `Channels` can be `SpecificChannels` or `AnyChannels`.
 pub type Layers<Channels> = SmallVec<[Layer<Channels>; 2]>;
 
@@ -678,6 +727,29 @@ impl FlatSamples {
         (0..self.len()).map(move |index| self.value_by_flat_index(index))
     }
 
+    /// Interpret the bits of `u32` samples as `f32` samples, without any numeric conversion.
+    /// Use this for float data that was packed into an integer channel.
+    /// Float samples are returned unchanged.
+    pub fn reinterpret_as_f32(self) -> Self {
+        match self {
+            FlatSamples::U32(vec) => FlatSamples::F32(vec.into_iter().map(f32::from_bits).collect()),
+            float => float,
+        }
+    }
+
+    /// Store the raw bits of the samples in `u32` samples, without any numeric conversion.
+    /// Writing the result packs the data into an integer channel,
+    /// where it survives any reading and writing bit for bit,
+    /// until it is restored using `reinterpret_as_f32`.
+    /// The 16 bits of `f16` samples are zero-extended.
+    pub fn as_u32_bits(self) -> Self {
+        match self {
+            FlatSamples::F16(vec) => FlatSamples::U32(vec.into_iter().map(|sample| sample.to_bits() as u32).collect()),
+            FlatSamples::F32(vec) => FlatSamples::U32(vec.into_iter().map(f32::to_bits).collect()),
+            integers => integers,
+        }
+    }
+
     /// Lookup a single value, by flat index.
     /// The flat index can be obtained using `Vec2::flatten_for_width`
     /// which computes the index in a flattened array of pixel rows.
@@ -878,7 +950,10 @@ pub mod validate_results {
     use smallvec::Array;
     use crate::prelude::recursive::*;
     use crate::image::write::samples::WritableSamples;
+    use crate::image::write::layers::WritableLayers;
     use std::ops::Not;
+    use std::io::{Read, Seek, Cursor};
+    use std::path::Path;
     use crate::block::samples::IntoNativeSample;
 
 
@@ -922,6 +997,64 @@ pub mod validate_results {
     pub struct ValidationOptions {
         allow_lossy: bool,
         nan_converted_to_zero: bool,
+        bit_exact: bool,
+    }
+
+    impl ValidationOptions {
+
+        /// Require samples of lossless channels to be equal bit by bit,
+        /// instead of considering all NaN values and both zeroes equal.
+        /// Lossy channels are still compared approximately.
+        pub fn bit_exact() -> Self {
+            ValidationOptions { bit_exact: true, .. Self::default() }
+        }
+    }
+
+    /// Read all layers, levels and attributes of the file, write the image to memory, and read it again.
+    /// Compares both images, requiring lossless channels to be preserved bit by bit.
+    /// Returns `Ok(Err(message))` describing the first divergence,
+    /// or an error if the file cannot be read or written at all. Deep data is not supported.
+    pub fn validate_round_trip_of_file(path: impl AsRef<Path>) -> Result<ValidationResult> {
+        validate_round_trip_from_buffered(std::io::BufReader::new(std::fs::File::open(path)?))
+    }
+
+    /// Read all layers, levels and attributes from the buffered byte source, write the image to memory, and read it again.
+    /// See `validate_round_trip_of_file`.
+    pub fn validate_round_trip_from_buffered(buffered: impl Read + Seek + Send) -> Result<ValidationResult> {
+        let read_image = read()
+            .no_deep_data().all_resolution_levels().all_channels().all_layers().all_attributes();
+
+        let image = read_image.clone().from_buffered(buffered)?;
+
+        let mut bytes = Vec::new();
+        image.write().to_buffered(Cursor::new(&mut bytes))?;
+
+        let round_tripped = read_image.pedantic().from_buffered(Cursor::new(bytes))?;
+        Ok(image.validate_result(&round_tripped, ValidationOptions::bit_exact(), || String::from("round trip")))
+    }
+
+    /// Write the image with sequential and with parallel compression,
+    /// and check that both files are identical byte by byte.
+    /// Returns `Ok(Err(message))` containing the first differing byte position,
+    /// or an error if the image cannot be written at all.
+    pub fn validate_parallel_compression<'img, Layers>(image: &'img Image<Layers>) -> Result<ValidationResult>
+        where Layers: WritableLayers<'img>
+    {
+        let mut sequential = Vec::new();
+        image.write().non_parallel().to_buffered(Cursor::new(&mut sequential))?;
+
+        let mut parallel = Vec::new();
+        image.write().to_buffered(Cursor::new(&mut parallel))?;
+
+        let first_difference = sequential.iter().zip(&parallel).position(|(a, b)| a != b);
+
+        Ok(match first_difference {
+            Some(index) => Err(format!("parallel compression: byte {} differs from sequential compression", index)),
+            None if sequential.len() != parallel.len() => Err(format!(
+                "parallel compression: file size {} differs from sequential file size {}", parallel.len(), sequential.len()
+            )),
+            None => Ok(()),
+        })
     }
 
     /// If invalid, contains the error message.
@@ -938,7 +1071,7 @@ pub mod validate_results {
     impl<S> ValidateResult for Layer<AnyChannels<S>>
         where AnyChannel<S>: ValidateResult, S: for<'a> WritableSamples<'a>
     {
-        fn validate_result(&self, other: &Self, _overridden: ValidationOptions, location: impl Fn()->String) -> ValidationResult {
+        fn validate_result(&self, other: &Self, overridden: ValidationOptions, location: impl Fn()->String) -> ValidationResult {
             let location = || format!("{} (layer `{:?}`)", location(), self.attributes.layer_name);
             if self.attributes != other.attributes { Err(location() + " > attributes") }
             else if self.encoding != other.encoding { Err(location() + " > encoding") }
@@ -955,7 +1088,9 @@ pub mod validate_results {
                                 .is_lossless_for(other_chan.sample_data.sample_type()).not(),
 
                             // consider nan and zero equal if the compression method does not support nan
-                            nan_converted_to_zero: other.encoding.compression.supports_nan().not()
+                            nan_converted_to_zero: other.encoding.compression.supports_nan().not(),
+
+                            bit_exact: overridden.bit_exact,
                         },
 
                         || format!("{} > channel `{}`", location(), own_chan.name)
@@ -971,7 +1106,7 @@ pub mod validate_results {
     {
         /// This does an approximate comparison for all channels,
         /// even if some channels can be compressed without loss.
-        fn validate_result(&self, other: &Self, _overridden: ValidationOptions, location: impl Fn()->String) -> ValidationResult {
+        fn validate_result(&self, other: &Self, overridden: ValidationOptions, location: impl Fn()->String) -> ValidationResult {
             let location = || format!("{} (layer `{:?}`)", location(), self.attributes.layer_name);
 
             // TODO dedup with above
@@ -985,7 +1120,9 @@ pub mod validate_results {
                     allow_lossy: other.encoding.compression.may_loose_data(),// TODO check specific channels sample types
 
                     // consider nan and zero equal if the compression method does not support nan
-                    nan_converted_to_zero: other.encoding.compression.supports_nan().not()
+                    nan_converted_to_zero: other.encoding.compression.supports_nan().not(),
+
+                    bit_exact: overridden.bit_exact,
                 };
 
                 self.channel_data.validate_result(&other.channel_data, options, || location() + " > channel_data")?;
@@ -1109,6 +1246,11 @@ pub mod validate_results {
 
     impl ValidateResult for f32 {
         fn validate_result(&self, other: &Self, options: ValidationOptions, location: impl Fn()->String) -> ValidationResult {
+            if options.bit_exact && !options.allow_lossy {
+                return if self.to_bits() == other.to_bits() { Ok(()) }
+                else { Err(format!("{}: expected bits {:#010x} ({}), found {:#010x} ({})", location(), self.to_bits(), self, other.to_bits(), other)) };
+            }
+
             if self == other || (self.is_nan() && other.is_nan()) || (options.nan_converted_to_zero && !self.is_normal() && *other == 0.0) {
                 return Ok(());
             }
@@ -1131,7 +1273,11 @@ pub mod validate_results {
 
     impl ValidateResult for f16 {
         fn validate_result(&self, other: &Self, options: ValidationOptions, location: impl Fn()->String) -> ValidationResult {
-            if self.to_bits() == other.to_bits() { Ok(()) } else {
+            if self.to_bits() == other.to_bits() { Ok(()) }
+            else if options.bit_exact && !options.allow_lossy {
+                Err(format!("{}: expected bits {:#06x} ({}), found {:#06x} ({})", location(), self.to_bits(), self, other.to_bits(), other))
+            }
+            else {
                 self.to_f32().validate_result(&other.to_f32(), options, location)
             }
         }
@@ -1170,7 +1316,7 @@ pub mod validate_results {
         fn expect_valid<T>(original: &T, result: &T, allow_lossy: bool, nan_converted_to_zero: bool) where T: ValidateResult {
             original.validate_result(
                 result,
-                ValidationOptions { allow_lossy, nan_converted_to_zero },
+                ValidationOptions { allow_lossy, nan_converted_to_zero, bit_exact: false },
                 || String::new()
             ).unwrap();
         }
@@ -1178,7 +1324,7 @@ pub mod validate_results {
         fn expect_invalid<T>(original: &T, result: &T, allow_lossy: bool, nan_converted_to_zero: bool) where T: ValidateResult {
             assert!(original.validate_result(
                 result,
-                ValidationOptions { allow_lossy, nan_converted_to_zero },
+                ValidationOptions { allow_lossy, nan_converted_to_zero, bit_exact: false },
                 || String::new()
             ).is_err());
         }
@@ -1209,6 +1355,50 @@ pub mod validate_results {
             expect_invalid(&33_120_f32, &20_120_f32, true, false);
         }
 
+        #[test]
+        fn test_bit_exact(){
+            let exact = ValidationOptions::bit_exact();
+            let positive_zero: &[f32] = &[ 1.0, 0.0, f32::NAN ];
+            let negative_zero: &[f32] = &[ 1.0, -0.0, f32::NAN ];
+            let other_nan: &[f32] = &[ 1.0, 0.0, f32::from_bits(f32::NAN.to_bits() | 1) ];
+
+            expect_valid(&positive_zero, &negative_zero, false, false);
+            expect_valid(&positive_zero, &other_nan, false, false);
+            assert!(positive_zero.validate_result(&positive_zero, exact, String::new).is_ok());
+
+            let message = positive_zero.validate_result(&negative_zero, exact, String::new).unwrap_err();
+            assert!(message.contains("element [1]"), "{}", message);
+            assert!(positive_zero.validate_result(&other_nan, exact, String::new).is_err());
+        }
+
+        #[test]
+        fn round_trip_file_bit_exact(){
+            let result = crate::image::validate_results::validate_round_trip_of_file(
+                "tests/images/valid/custom/compression_methods/f32/rle.exr"
+            ).unwrap();
+
+            assert_eq!(result, Ok(()));
+        }
+
+        #[test]
+        fn parallel_compression_is_deterministic(){
+            use crate::prelude::*;
+
+            let size = Vec2(512, 256);
+            let samples = |seed: usize| FlatSamples::F32((0 .. size.area()).map(|index| ((index * seed) % 1013) as f32).collect());
+            let channels = AnyChannels::sort(smallvec::smallvec![
+                AnyChannel::new("R", samples(3)), AnyChannel::new("G", samples(7)), AnyChannel::new("B", samples(11)),
+            ]);
+
+            for &line_order in &[ LineOrder::Unspecified, LineOrder::Increasing, LineOrder::Decreasing ] {
+                let encoding = Encoding { compression: Compression::ZIP1, blocks: Blocks::Tiles(Vec2(32, 32)), line_order };
+                let image = Image::from_encoded_channels(size, encoding, channels.clone());
+
+                let result = crate::image::validate_results::validate_parallel_compression(&image).unwrap();
+                assert_eq!(result, Ok(()), "line order {:?}", line_order);
+            }
+        }
+
         #[test]
         fn test_nan(){
             let original:&[f32] = &[ 0.0, f32::NAN, f32::NAN ];
@@ -0,0 +1,202 @@
+//! Normalize the layer and channel names that different applications use for the same content.
+//! For example, `rgba.red`, `RGBA.R` and `R` all describe the red channel of the main layer,
+//! and `Beauty.RGBA.R` describes the red channel of the `Beauty` layer.
+//! Normalize the names after reading an image, or before writing a transcoded image.
+
+use crate::image::{Image, Layer, Layers, AnyChannels};
+use crate::meta::attribute::Text;
+use crate::error::{UnitResult, Error};
+
+
+/// Describes how to rename layers and channels. Names are split into components at each `.`.
+/// The last component is the channel name, all other components describe the layer.
+/// All names are compared ignoring ascii case.
+#[derive(Debug, Clone, PartialEq)]
+pub struct NamingRules {
+
+    /// Replaces the last component of a channel name, for example `red` with `R`.
+    pub channel_aliases: Vec<(Text, Text)>,
+
+    /// Replaces a layer component of a channel name, or a component of the layer name attribute.
+    /// Replacing with an empty name removes the component, which moves the channels to the main layer.
+    pub layer_aliases: Vec<(Text, Text)>,
+
+    /// Layer components that only group channels and are removed, for example `RGBA` in `Beauty.RGBA.R`.
+    pub redundant_components: Vec<Text>,
+}
+
+impl Default for NamingRules {
+
+    /// Converts common variants of red, green, blue, alpha, luminance and depth
+    /// to `R`, `G`, `B`, `A`, `Y` and `Z`, and removes `rgba`, `rgb`, `color` and `colour` components.
+    fn default() -> Self {
+        let alias = |alias: &str, name: &str| (Text::from(alias), Text::from(name));
+
+        NamingRules {
+            channel_aliases: vec![
+                alias("red", "R"), alias("r", "R"),
+                alias("green", "G"), alias("g", "G"),
+                alias("blue", "B"), alias("b", "B"),
+                alias("alpha", "A"), alias("a", "A"),
+                alias("luminance", "Y"), alias("y", "Y"),
+                alias("depth", "Z"), alias("z", "Z"),
+            ],
+
+            layer_aliases: Vec::new(),
+
+            redundant_components: vec![
+                Text::from("rgba"), Text::from("rgb"),
+                Text::from("color"), Text::from("colour"),
+            ],
+        }
+    }
+}
+
+impl NamingRules {
+
+    /// Rename a full channel name, including the layer components.
+    pub fn normalize_channel_name(&self, name: &Text) -> Text {
+        let bytes = name.bytes();
+        let (layer, channel) = match bytes.iter().rposition(|&byte| byte == b'.') {
+            Some(index) => (&bytes[.. index], &bytes[index + 1 ..]),
+            None => (&[][..], bytes),
+        };
+
+        let channel = self.channel_aliases.iter()
+            .find(|(alias, _)| alias.bytes().eq_ignore_ascii_case(channel))
+            .map_or(channel, |(_, name)| name.bytes());
+
+        let mut components = self.normalized_layer_components(layer);
+        components.push(channel);
+        join_components(&components)
+    }
+
+    /// Rename a layer name, for example from the layer name attribute.
+    pub fn normalize_layer_name(&self, name: &Text) -> Text {
+        join_components(&self.normalized_layer_components(name.bytes()))
+    }
+
+    fn normalized_layer_components<'s>(&'s self, layer: &'s [u8]) -> Vec<&'s [u8]> {
+        if layer.is_empty() { return Vec::new(); }
+
+        layer.split(|&byte| byte == b'.')
+            .filter(|component| !self.redundant_components.iter().any(|redundant| redundant.bytes().eq_ignore_ascii_case(component)))
+            .map(|component| self.layer_aliases.iter()
+                .find(|(alias, _)| alias.bytes().eq_ignore_ascii_case(component))
+                .map_or(component, |(_, name)| name.bytes())
+            )
+            .filter(|component| !component.is_empty())
+            .collect()
+    }
+}
+
+fn join_components(components: &[&[u8]]) -> Text {
+    Text::from_bytes_unchecked(components.join(&b'.').into_iter().collect())
+}
+
+
+impl<Samples> Layer<AnyChannels<Samples>> {
+
+    /// Rename the layer and all channels of this layer, and sort the channels again.
+    /// Fails without changing anything if two channels would receive the same name.
+    pub fn normalize_names(&mut self, rules: &NamingRules) -> UnitResult {
+        let names: Vec<Text> = self.channel_data.list.iter()
+            .map(|channel| rules.normalize_channel_name(&channel.name))
+            .collect();
+
+        let mut sorted_names = names.clone();
+        sorted_names.sort_unstable();
+        if sorted_names.windows(2).any(|pair| pair[0] == pair[1]) {
+            return Err(Error::invalid("normalized channel names are not unique"));
+        }
+
+        for (channel, name) in self.channel_data.list.iter_mut().zip(names) {
+            channel.name = name;
+        }
+
+        self.channel_data.list.sort_unstable_by(|a, b| a.name.cmp(&b.name));
+
+        self.attributes.layer_name = self.attributes.layer_name.as_ref()
+            .map(|name| rules.normalize_layer_name(name))
+            .filter(|name| !name.bytes().is_empty());
+
+        Ok(())
+    }
+}
+
+impl<Samples> Image<Layers<AnyChannels<Samples>>> {
+
+    /// Rename all layers and channels in this image. See `Layer::normalize_names`.
+    /// If a layer fails, the previous layers have already been renamed.
+    pub fn normalize_names(&mut self, rules: &NamingRules) -> UnitResult {
+        for layer in &mut self.layer_data { layer.normalize_names(rules)?; }
+        Ok(())
+    }
+}
+
+impl<Samples> Image<Layer<AnyChannels<Samples>>> {
+
+    /// Rename the layer and its channels in this image. See `Layer::normalize_names`.
+    pub fn normalize_names(&mut self, rules: &NamingRules) -> UnitResult {
+        self.layer_data.normalize_names(rules)
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use crate::prelude::*;
+    use super::*;
+
+    fn normalize(name: &str) -> Text {
+        NamingRules::default().normalize_channel_name(&Text::from(name))
+    }
+
+    #[test]
+    fn normalizes_vendor_variants(){
+        assert_eq!(normalize("rgba.red"), Text::from("R"));
+        assert_eq!(normalize("RGBA.R"), Text::from("R"));
+        assert_eq!(normalize("Beauty.RGBA.R"), Text::from("Beauty.R"));
+        assert_eq!(normalize("diffuse.Color.blue"), Text::from("diffuse.B"));
+        assert_eq!(normalize("a"), Text::from("A"));
+        assert_eq!(normalize("depth"), Text::from("Z"));
+        assert_eq!(normalize("specular.roughness"), Text::from("specular.roughness"));
+    }
+
+    #[test]
+    fn custom_layer_aliases(){
+        let rules = NamingRules {
+            layer_aliases: vec![ (Text::from("Combined"), Text::from("")), (Text::from("diff"), Text::from("diffuse")) ],
+            .. NamingRules::default()
+        };
+
+        assert_eq!(rules.normalize_channel_name(&Text::from("Combined.r")), Text::from("R"));
+        assert_eq!(rules.normalize_channel_name(&Text::from("DIFF.g")), Text::from("diffuse.G"));
+        assert_eq!(rules.normalize_layer_name(&Text::from("combined")), Text::from(""));
+    }
+
+    #[test]
+    fn layer_channels_are_renamed_and_sorted(){
+        let samples = || FlatSamples::F32(vec![ 0.0; 4 ]);
+        let mut layer = Layer::new(
+            (2, 2), LayerAttributes::named("rgba"), Encoding::default(),
+            AnyChannels::sort(smallvec::smallvec![
+                AnyChannel::new("rgba.red", samples()), AnyChannel::new("rgba.alpha", samples()),
+                AnyChannel::new("rgba.green", samples()), AnyChannel::new("rgba.blue", samples()),
+            ])
+        );
+
+        layer.normalize_names(&NamingRules::default()).unwrap();
+        let names: Vec<Text> = layer.channel_data.list.iter().map(|channel| channel.name.clone()).collect();
+        assert_eq!(names, vec![ Text::from("A"), Text::from("B"), Text::from("G"), Text::from("R") ]);
+        assert_eq!(layer.attributes.layer_name, None);
+
+        let mut colliding = Layer::new(
+            (2, 2), LayerAttributes::default(), Encoding::default(),
+            AnyChannels::sort(smallvec::smallvec![ AnyChannel::new("R", samples()), AnyChannel::new("red", samples()) ])
+        );
+
+        assert!(colliding.normalize_names(&NamingRules::default()).is_err());
+        assert_eq!(colliding.channel_data.list[1].name, Text::from("red"));
+    }
+}
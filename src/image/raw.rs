@@ -0,0 +1,283 @@
+//! Copy samples from and to raw byte buffers of other libraries.
+//! The `SampleLayout` describes the order of the channels, the interleaving,
+//! the strides and the byte order of the buffer explicitly,
+//! so no manual byte shuffling is required when exchanging pixels with C or C++ code.
+
+use crate::image::*;
+use crate::meta::attribute::SampleType;
+use crate::error::{Result, UnitResult, Error};
+use crate::math::Vec2;
+use half::f16;
+
+
+/// The order of the bytes of a single sample.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub enum ByteOrder {
+
+    /// The least significant byte comes first. Used by the exr file format and most desktop platforms.
+    LittleEndian,
+
+    /// The most significant byte comes first.
+    BigEndian,
+}
+
+/// Describes where each sample is located in a raw byte buffer.
+/// The byte offset of a sample is
+/// `channel_index * channel_stride + y * row_stride + x * pixel_stride`.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct SampleLayout {
+
+    /// The names of the channels, in the order of the buffer.
+    pub channels: SmallVec<[Text; 4]>,
+
+    /// The type of all samples in the buffer.
+    /// Samples are converted if the image uses a different type.
+    pub sample_type: SampleType,
+
+    /// The byte order of each sample in the buffer.
+    pub byte_order: ByteOrder,
+
+    /// The number of pixels in the buffer.
+    pub resolution: Vec2<usize>,
+
+    /// The number of bytes between the first sample of one channel and the first sample of the next channel.
+    pub channel_stride: usize,
+
+    /// The number of bytes between two horizontally neighbouring samples of one channel.
+    pub pixel_stride: usize,
+
+    /// The number of bytes between two vertically neighbouring samples of one channel.
+    pub row_stride: usize,
+}
+
+impl ByteOrder {
+
+    /// The byte order of the current platform.
+    pub fn native() -> Self {
+        if cfg!(target_endian = "big") { ByteOrder::BigEndian } else { ByteOrder::LittleEndian }
+    }
+}
+
+impl SampleLayout {
+
+    /// Pixels stored row by row, with all channels of one pixel next to each other, like `RGBARGBA`.
+    /// Uses the byte order of the current platform.
+    pub fn interleaved(channels: SmallVec<[Text; 4]>, sample_type: SampleType, resolution: impl Into<Vec2<usize>>) -> Self {
+        let resolution = resolution.into();
+        let sample_size = sample_type.bytes_per_sample();
+        let pixel_stride = sample_size * channels.len();
+
+        SampleLayout {
+            sample_type, resolution,
+            byte_order: ByteOrder::native(),
+            channel_stride: sample_size,
+            row_stride: pixel_stride * resolution.width(),
+            pixel_stride, channels,
+        }
+    }
+
+    /// One plane for each channel, each containing all samples of that channel row by row, like `RRGGBBAA`.
+    /// Uses the byte order of the current platform.
+    pub fn planar(channels: SmallVec<[Text; 4]>, sample_type: SampleType, resolution: impl Into<Vec2<usize>>) -> Self {
+        let resolution = resolution.into();
+        let sample_size = sample_type.bytes_per_sample();
+
+        SampleLayout {
+            sample_type, resolution, channels,
+            byte_order: ByteOrder::native(),
+            channel_stride: sample_size * resolution.area(),
+            pixel_stride: sample_size,
+            row_stride: sample_size * resolution.width(),
+        }
+    }
+
+    /// Use the specified byte order instead of the byte order of the current platform.
+    pub fn with_byte_order(self, byte_order: ByteOrder) -> Self {
+        SampleLayout { byte_order, .. self }
+    }
+
+    /// The byte offset of the sample of the specified channel at the specified pixel position.
+    pub fn byte_index(&self, channel_index: usize, position: Vec2<usize>) -> usize {
+        channel_index * self.channel_stride + position.y() * self.row_stride + position.x() * self.pixel_stride
+    }
+
+    /// The minimum number of bytes that a buffer with this layout contains.
+    pub fn byte_size(&self) -> usize {
+        if self.channels.is_empty() || self.resolution.area() == 0 { return 0; }
+
+        // all strides are positive, so the last sample of the last channel has the largest offset
+        let last_sample = self.byte_index(self.channels.len() - 1, self.resolution - Vec2(1, 1));
+        last_sample + self.sample_type.bytes_per_sample()
+    }
+
+    fn validate(&self, bytes: usize) -> UnitResult {
+        let sample_size = self.sample_type.bytes_per_sample();
+
+        if self.pixel_stride < sample_size && self.resolution.width() > 1 {
+            return Err(Error::invalid("sample layout pixel stride"));
+        }
+
+        if bytes < self.byte_size() {
+            return Err(Error::invalid("byte buffer is too small for the sample layout"));
+        }
+
+        Ok(())
+    }
+
+    fn read_sample(&self, bytes: &[u8], index: usize) -> Sample {
+        let big_endian = self.byte_order == ByteOrder::BigEndian;
+
+        match self.sample_type {
+            SampleType::F16 => {
+                let sample_bytes = [bytes[index], bytes[index + 1]];
+                let bits = if big_endian { u16::from_be_bytes(sample_bytes) } else { u16::from_le_bytes(sample_bytes) };
+                Sample::F16(f16::from_bits(bits))
+            },
+
+            SampleType::F32 | SampleType::U32 => {
+                let sample_bytes = [bytes[index], bytes[index + 1], bytes[index + 2], bytes[index + 3]];
+                let bits = if big_endian { u32::from_be_bytes(sample_bytes) } else { u32::from_le_bytes(sample_bytes) };
+                if self.sample_type == SampleType::U32 { Sample::U32(bits) } else { Sample::F32(f32::from_bits(bits)) }
+            },
+        }
+    }
+
+    fn write_sample(&self, bytes: &mut [u8], index: usize, sample: Sample) {
+        let big_endian = self.byte_order == ByteOrder::BigEndian;
+
+        match self.sample_type {
+            SampleType::F16 => {
+                let bits = sample.to_f16().to_bits();
+                let sample_bytes = if big_endian { bits.to_be_bytes() } else { bits.to_le_bytes() };
+                bytes[index .. index + 2].copy_from_slice(&sample_bytes);
+            },
+
+            SampleType::F32 | SampleType::U32 => {
+                let bits = if self.sample_type == SampleType::U32 { sample.to_u32() } else { sample.to_f32().to_bits() };
+                let sample_bytes = if big_endian { bits.to_be_bytes() } else { bits.to_le_bytes() };
+                bytes[index .. index + 4].copy_from_slice(&sample_bytes);
+            },
+        }
+    }
+}
+
+
+impl AnyChannels<FlatSamples> {
+
+    /// Create all channels of the layout, copying the samples from the raw byte buffer.
+    /// The channels use the sample type of the layout.
+    pub fn import_samples(bytes: &[u8], layout: &SampleLayout) -> Result<Self> {
+        layout.validate(bytes.len())?;
+
+        let channels = layout.channels.iter().enumerate()
+            .map(|(channel_index, name)| {
+                let samples = (0 .. layout.resolution.height())
+                    .flat_map(|y| (0 .. layout.resolution.width()).map(move |x| Vec2(x, y)))
+                    .map(|position| layout.read_sample(bytes, layout.byte_index(channel_index, position)));
+
+                AnyChannel::new(name.clone(), match layout.sample_type {
+                    SampleType::F16 => FlatSamples::F16(samples.map(Sample::to_f16).collect()),
+                    SampleType::F32 => FlatSamples::F32(samples.map(Sample::to_f32).collect()),
+                    SampleType::U32 => FlatSamples::U32(samples.map(Sample::to_u32).collect()),
+                })
+            })
+            .collect();
+
+        let channels = AnyChannels::sort(channels);
+        if channels.list.windows(2).any(|pair| pair[0].name == pair[1].name) {
+            return Err(Error::invalid("duplicate channel name in sample layout"));
+        }
+
+        Ok(channels)
+    }
+
+    /// Copy the samples of the channels in the layout into the raw byte buffer,
+    /// converting them to the sample type of the layout.
+    /// Bytes between the samples are not modified.
+    /// Fails without modifying the buffer if a channel of the layout does not exist.
+    pub fn export_samples(&self, bytes: &mut [u8], layout: &SampleLayout) -> UnitResult {
+        layout.validate(bytes.len())?;
+
+        // find all channels before writing any sample, so that errors leave the buffer untouched
+        let channels = layout.channels.iter()
+            .map(|name| {
+                let channel = self.list.iter().find(|channel| &channel.name == name)
+                    .ok_or(Error::invalid("sample layout contains a channel that does not exist"))?;
+
+                if channel.sample_data.len() != layout.resolution.area() {
+                    return Err(Error::invalid("sample layout resolution does not match the channel"));
+                }
+
+                Ok(channel)
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        for (channel_index, channel) in channels.into_iter().enumerate() {
+            for y in 0 .. layout.resolution.height() {
+                for x in 0 .. layout.resolution.width() {
+                    let sample = channel.sample_data.value_by_flat_index(y * layout.resolution.width() + x);
+                    layout.write_sample(bytes, layout.byte_index(channel_index, Vec2(x, y)), sample);
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn rgba() -> SmallVec<[Text; 4]> {
+        smallvec::smallvec![ Text::from("R"), Text::from("G"), Text::from("B"), Text::from("A") ]
+    }
+
+    #[test]
+    fn interleaved_big_endian_round_trip(){
+        let layout = SampleLayout::interleaved(rgba(), SampleType::F32, (2, 1)).with_byte_order(ByteOrder::BigEndian);
+        let pixels = [ 1.0_f32, 2.0, 3.0, 4.0,   5.0, 6.0, 7.0, 8.0 ];
+        let bytes: Vec<u8> = pixels.iter().flat_map(|sample| sample.to_be_bytes()).collect();
+        assert_eq!(layout.byte_size(), bytes.len());
+
+        let channels = AnyChannels::import_samples(&bytes, &layout).unwrap();
+        let red = channels.list.iter().find(|channel| channel.name == Text::from("R")).unwrap();
+        assert_eq!(red.sample_data, FlatSamples::F32(vec![ 1.0, 5.0 ]));
+
+        let mut exported = vec![ 0; bytes.len() ];
+        channels.export_samples(&mut exported, &layout).unwrap();
+        assert_eq!(exported, bytes);
+    }
+
+    #[test]
+    fn planar_with_conversion_and_row_padding(){
+        let layout = SampleLayout {
+            row_stride: 4 * 2, // two bytes of padding after each row
+            channel_stride: 4 * 2 * 2,
+            .. SampleLayout::planar(smallvec::smallvec![ Text::from("Y") ], SampleType::F16, (3, 2))
+                .with_byte_order(ByteOrder::LittleEndian)
+        };
+
+        let channels = AnyChannels::sort(smallvec::smallvec![ AnyChannel::new("Y", FlatSamples::F32(vec![ 0.0, 0.5, 1.0, 2.0, 4.0, 8.0 ])) ]);
+        let mut bytes = vec![ 0xff; layout.byte_size() ];
+        channels.export_samples(&mut bytes, &layout).unwrap();
+
+        assert_eq!(&bytes[6 .. 8], &[ 0xff, 0xff ], "padding should not be modified");
+        assert_eq!(&bytes[8 .. 10], &f16::from_f32(2.0).to_bits().to_le_bytes());
+
+        let imported = AnyChannels::import_samples(&bytes, &layout).unwrap();
+        assert_eq!(imported.list[0].sample_data.values_as_f32().collect::<Vec<f32>>(), vec![ 0.0, 0.5, 1.0, 2.0, 4.0, 8.0 ]);
+    }
+
+    #[test]
+    fn reject_small_buffers_and_missing_channels(){
+        let layout = SampleLayout::interleaved(rgba(), SampleType::U32, (4, 4));
+        assert!(AnyChannels::import_samples(&[0; 16], &layout).is_err());
+
+        let channels = AnyChannels::sort(smallvec::smallvec![ AnyChannel::new("R", FlatSamples::U32(vec![ 1; 16 ])) ]);
+        let mut bytes = vec![ 0; layout.byte_size() ];
+        assert!(channels.export_samples(&mut bytes, &layout).is_err());
+        assert!(bytes.iter().all(|&byte| byte == 0), "failed export should not modify the buffer");
+    }
+}
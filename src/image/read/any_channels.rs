@@ -2,7 +2,7 @@
 
 use crate::image::*;
 use crate::meta::header::{Header};
-use crate::error::{Result, UnitResult};
+use crate::error::{Error, Result, UnitResult};
 use crate::block::UncompressedBlock;
 use crate::block::lines::{LineRef};
 use crate::math::Vec2;
@@ -78,6 +78,8 @@ impl<'s, S: 's + ReadSamples> ReadChannels<'s> for ReadAnyChannels<S> {
     type Reader = AnyChannelsReader<S::Reader>;
 
     fn create_channels_reader(&self, header: &Header) -> Result<Self::Reader> {
+        if header.deep { return Err(Error::unsupported("deep data not supported yet")) }
+
         let samples: Result<_> = header.channels.list.iter()
             .map(|channel: &ChannelDescription| Ok(AnyChannelReader {
                 samples: self.read_samples.create_sample_reader(header, channel)?,
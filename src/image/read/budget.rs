@@ -0,0 +1,299 @@
+//! Read an image with a limited amount of memory, for example to create thumbnails.
+//! Chooses the largest resolution level that fits into a byte budget,
+//! or skips pixels while reading if the file contains no suitable resolution level.
+
+use crate::image::*;
+use crate::meta::header::Header;
+use crate::meta::{compute_level_size, compute_level_count, BlockDescription};
+use crate::error::{Result, UnitResult, Error};
+use crate::block::lines::LineRef;
+use crate::block::chunk::TileCoordinates;
+use crate::math::{Vec2, RoundingMode};
+use crate::meta::attribute::{ChannelDescription, SampleType, LevelMode, TileDescription};
+use crate::image::read::any_channels::{SamplesReader, ReadSamples, ReadAnyChannels};
+use crate::image::read::layers::ReadChannels;
+use crate::image::read::image::ReadLayers;
+use std::io::{Read, Seek, BufReader};
+use std::path::Path;
+
+
+/// How the resolution of a layer was reduced to fit into the byte budget.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub enum Reduction {
+
+    /// The layer was read with its original resolution.
+    FullResolution,
+
+    /// A smaller resolution level of the layer was read, with the specified level index.
+    Level(Vec2<usize>),
+
+    /// The largest resolution level was read, keeping only every n-th pixel in both dimensions.
+    /// Contains the number of pixels represented by each resulting pixel, in each dimension.
+    Downsampled(usize),
+}
+
+impl Reduction {
+
+    /// The reduction of the layer when its resolution is halved the specified number of times.
+    /// Uses the resolution levels of the file if possible, and downsamples otherwise.
+    pub fn for_header(header: &Header, halving_count: usize) -> Self {
+        if halving_count == 0 { return Reduction::FullResolution; }
+
+        match header.blocks {
+            BlockDescription::Tiles(tiles) if halving_count < level_count(tiles, header.layer_size)
+                => Reduction::Level(Vec2(halving_count, halving_count)),
+
+            _ => Reduction::Downsampled(1 << halving_count.min(usize::BITS as usize - 1)),
+        }
+    }
+
+    /// The resolution of the layer after applying this reduction.
+    pub fn resolution(self, header: &Header) -> Vec2<usize> {
+        match self {
+            Reduction::FullResolution => header.layer_size,
+
+            Reduction::Level(Vec2(x, y)) => {
+                let round = match header.blocks {
+                    BlockDescription::Tiles(tiles) => tiles.rounding_mode,
+                    BlockDescription::ScanLines => RoundingMode::Down,
+                };
+
+                Vec2(
+                    compute_level_size(round, header.layer_size.width(), x),
+                    compute_level_size(round, header.layer_size.height(), y),
+                )
+            },
+
+            Reduction::Downsampled(factor) => Vec2(
+                RoundingMode::Up.divide(header.layer_size.width(), factor),
+                RoundingMode::Up.divide(header.layer_size.height(), factor),
+            ),
+        }
+    }
+}
+
+/// The number of levels with the same index in both dimensions.
+pub(crate) fn level_count(tiles: TileDescription, layer_size: Vec2<usize>) -> usize {
+    match tiles.level_mode {
+        LevelMode::Singular => 1,
+        LevelMode::MipMap => compute_level_count(tiles.rounding_mode, layer_size.width().max(layer_size.height())),
+        LevelMode::RipMap => compute_level_count(tiles.rounding_mode, layer_size.width().min(layer_size.height())),
+    }
+}
+
+/// The number of bytes required to store all flat samples of the layer, after applying the reduction.
+pub fn estimate_flat_byte_size(header: &Header, reduction: Reduction) -> usize {
+    let resolution = reduction.resolution(header);
+    header.channels.list.iter()
+        .map(|channel| resolution.area().saturating_mul(channel.sample_type.bytes_per_sample()))
+        .fold(0, usize::saturating_add)
+}
+
+/// Find the smallest number of resolution halvings, such that all layers together fit into the byte budget.
+/// Returns one reduction per layer. Fails if the image does not fit even at a single pixel per layer.
+pub fn select_reductions(headers: &[Header], max_byte_size: usize) -> Result<Vec<Reduction>> {
+    let halving_count = select_halving_count(headers, max_byte_size)?;
+    Ok(headers.iter().map(|header| Reduction::for_header(header, halving_count)).collect())
+}
+
+fn select_halving_count(headers: &[Header], max_byte_size: usize) -> Result<usize> {
+    for halving_count in 0 .. usize::BITS as usize {
+        let reductions = headers.iter().map(|header| (header, Reduction::for_header(header, halving_count)));
+
+        let byte_size = reductions.clone()
+            .map(|(header, reduction)| estimate_flat_byte_size(header, reduction))
+            .fold(0, usize::saturating_add);
+
+        if byte_size <= max_byte_size { return Ok(halving_count); }
+
+        let is_smallest = reductions.clone().all(|(header, reduction)| reduction.resolution(header) == Vec2(1, 1));
+        if is_smallest { break; }
+    }
+
+    Err(Error::invalid("byte budget too small for image"))
+}
+
+/// Read all flat layers of the file, reducing the resolution until the samples fit into the byte budget.
+/// Returns the image and the reduction that was applied to each layer.
+/// Uses parallel decompression and relaxed error handling.
+/// The layer attributes are not modified and still describe the original resolution.
+pub fn read_all_flat_layers_within_byte_budget_from_file(path: impl AsRef<Path>, max_byte_size: usize) -> Result<(FlatImage, Vec<Reduction>)> {
+    read_all_flat_layers_within_byte_budget_from_buffered(BufReader::new(std::fs::File::open(path)?), max_byte_size)
+}
+
+/// Read all flat layers of the image, reducing the resolution until the samples fit into the byte budget.
+/// Returns the image and the reduction that was applied to each layer.
+/// Uses parallel decompression and relaxed error handling.
+/// The layer attributes are not modified and still describe the original resolution.
+pub fn read_all_flat_layers_within_byte_budget_from_buffered(buffered: impl Read + Seek, max_byte_size: usize) -> Result<(FlatImage, Vec<Reduction>)> {
+    let chunks = crate::block::read(buffered, false)?;
+    let headers = &chunks.meta_data().headers;
+
+    let halving_count = select_halving_count(headers, max_byte_size)?;
+    let reductions: Vec<Reduction> = headers.iter().map(|header| Reduction::for_header(header, halving_count)).collect();
+    let resolutions: Vec<Vec2<usize>> = headers.iter().zip(&reductions)
+        .map(|(header, &reduction)| reduction.resolution(header))
+        .collect();
+
+    let mut image = ReadAnyChannels { read_samples: ReadReducedFlatSamples { halving_count } }
+        .all_layers().all_attributes()
+        .from_chunks(chunks)?;
+
+    // the layer reader always uses the original layer size
+    for (layer, resolution) in image.layer_data.iter_mut().zip(resolutions) {
+        layer.size = resolution;
+    }
+
+    Ok((image, reductions))
+}
+
+
+/// Specify to read each layer with its resolution halved the specified number of times.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+struct ReadReducedFlatSamples {
+    halving_count: usize,
+}
+
+/// Processes pixel blocks of one resolution level, keeping only every n-th pixel.
+#[derive(Debug, Clone, PartialEq)]
+struct ReducedFlatSamplesReader {
+    level: Vec2<usize>,
+    factor: usize,
+    resolution: Vec2<usize>,
+    samples: FlatSamples,
+}
+
+impl ReadSamples for ReadReducedFlatSamples {
+    type Reader = ReducedFlatSamplesReader;
+
+    fn create_sample_reader(&self, header: &Header, channel: &ChannelDescription) -> Result<Self::Reader> {
+        let reduction = Reduction::for_header(header, self.halving_count);
+        let resolution = reduction.resolution(header);
+        let (level, factor) = match reduction {
+            Reduction::FullResolution => (Vec2(0, 0), 1),
+            Reduction::Level(level) => (level, 1),
+            Reduction::Downsampled(factor) => (Vec2(0, 0), factor),
+        };
+
+        Ok(ReducedFlatSamplesReader {
+            level, factor, resolution,
+            samples: match channel.sample_type {
+                SampleType::F16 => FlatSamples::F16(vec![f16::ZERO; resolution.area()]),
+                SampleType::F32 => FlatSamples::F32(vec![0.0; resolution.area()]),
+                SampleType::U32 => FlatSamples::U32(vec![0; resolution.area()]),
+            }
+        })
+    }
+}
+
+impl SamplesReader for ReducedFlatSamplesReader {
+    type Samples = FlatSamples;
+
+    fn filter_block(&self, tile: TileCoordinates) -> bool {
+        tile.level_index == self.level
+    }
+
+    fn read_line(&mut self, line: LineRef<'_>) -> UnitResult {
+        let index = line.location;
+        debug_assert_eq!(index.level, self.level, "line should have been filtered");
+
+        let factor = self.factor;
+        if index.position.y() % factor != 0 { return Ok(()); }
+
+        let y = index.position.y() / factor;
+        let first_x = RoundingMode::Up.divide(index.position.x(), factor);
+        let end_x = RoundingMode::Up.divide(index.position.x() + index.sample_count, factor);
+        if first_x >= end_x { return Ok(()); }
+
+        let skipped = first_x * factor - index.position.x();
+        let start_index = y * self.resolution.width() + first_x;
+        let end_index = y * self.resolution.width() + end_x;
+
+        fn read_every_nth<T: crate::io::Data>(line: &LineRef<'_>, skipped: usize, factor: usize, target: &mut [T]) -> UnitResult {
+            let samples = line.read_samples::<T>().skip(skipped).step_by(factor);
+            for (target, sample) in target.iter_mut().zip(samples) { *target = sample?; }
+            Ok(())
+        }
+
+        match &mut self.samples {
+            FlatSamples::F16(samples) => read_every_nth(&line, skipped, factor, &mut samples[start_index .. end_index]),
+            FlatSamples::F32(samples) => read_every_nth(&line, skipped, factor, &mut samples[start_index .. end_index]),
+            FlatSamples::U32(samples) => read_every_nth(&line, skipped, factor, &mut samples[start_index .. end_index]),
+        }
+    }
+
+    fn into_samples(self) -> FlatSamples {
+        self.samples
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use crate::prelude::*;
+    use super::*;
+    use std::io::Cursor;
+
+    fn ramp(size: Vec2<usize>) -> Vec<f32> {
+        (0 .. size.area()).map(|index| index as f32).collect()
+    }
+
+    #[test]
+    fn downsamples_scan_lines_to_fit(){
+        let size = Vec2(100, 60);
+        let channels = AnyChannels::sort(smallvec::smallvec![ AnyChannel::new("Y", FlatSamples::F32(ramp(size))) ]);
+        let image = Image::from_encoded_channels(size, Encoding::SMALL_LOSSLESS, channels);
+        let mut bytes = Vec::new();
+        image.write().to_buffered(Cursor::new(&mut bytes)).unwrap();
+
+        let (full, reductions) = read_all_flat_layers_within_byte_budget_from_buffered(Cursor::new(&bytes), usize::MAX).unwrap();
+        assert_eq!(reductions, vec![ Reduction::FullResolution ]);
+        assert_eq!(full.layer_data[0].size, size);
+
+        let budget = 100 * 60 * 4 / 10;
+        let (reduced, reductions) = read_all_flat_layers_within_byte_budget_from_buffered(Cursor::new(&bytes), budget).unwrap();
+        assert_eq!(reductions, vec![ Reduction::Downsampled(4) ]);
+
+        let layer = &reduced.layer_data[0];
+        assert_eq!(layer.size, Vec2(25, 15));
+
+        let samples = match &layer.channel_data.list[0].sample_data { FlatSamples::F32(samples) => samples, _ => panic!() };
+        assert_eq!(samples.len(), 25 * 15);
+        assert_eq!(samples[0], 0.0);
+        assert_eq!(samples[1], 4.0);
+        assert_eq!(samples[25 + 2], (4 * 100 + 8) as f32);
+    }
+
+    #[test]
+    fn prefers_resolution_levels(){
+        let size = Vec2(64, 64);
+        let levels = mip_map_levels(RoundingMode::Down, size)
+            .map(|(_, level_size)| FlatSamples::F32(ramp(level_size)))
+            .collect();
+
+        let channels = AnyChannels::sort(smallvec::smallvec![
+            AnyChannel::new("Y", Levels::Mip { rounding_mode: RoundingMode::Down, level_data: levels })
+        ]);
+
+        let encoding = Encoding { blocks: Blocks::Tiles(Vec2(16, 16)), .. Encoding::UNCOMPRESSED };
+        let image = Image::from_encoded_channels(size, encoding, channels);
+
+        let mut bytes = Vec::new();
+        image.write().to_buffered(Cursor::new(&mut bytes)).unwrap();
+
+        let (reduced, reductions) = read_all_flat_layers_within_byte_budget_from_buffered(Cursor::new(&bytes), 16 * 16 * 4).unwrap();
+        assert_eq!(reductions, vec![ Reduction::Level(Vec2(2, 2)) ]);
+        assert_eq!(reduced.layer_data[0].size, Vec2(16, 16));
+        assert_eq!(reduced.layer_data[0].channel_data.list[0].sample_data, FlatSamples::F32(ramp(Vec2(16, 16))));
+    }
+
+    #[test]
+    fn fails_for_tiny_budget(){
+        let header = Header::new(Text::from("Y"), (4, 4), smallvec::smallvec![
+            ChannelDescription::named("Y", SampleType::F32)
+        ]);
+
+        assert_eq!(select_reductions(&[header.clone()], 4).unwrap(), vec![ Reduction::Downsampled(4) ]);
+        assert!(select_reductions(&[header], 3).is_err());
+    }
+}
@@ -10,7 +10,14 @@ use std::path::Path;
 use std::io::{Read, BufReader};
 use std::io::Seek;
 use crate::meta::MetaData;
+use crate::meta::custom::AttributeHandlers;
+use crate::image::read::maps::ChannelMaps;
+use crate::meta::attribute::{IntegerBounds, SampleType};
 use crate::block::reader::ChunksReader;
+use crate::block::lines::LineIndex;
+use crate::block::samples::Sample;
+use std::collections::HashSet;
+use half::f16;
 
 /// Specify whether to read the image in parallel,
 /// whether to use pedantic error handling,
@@ -21,6 +28,10 @@ pub struct ReadImage<OnProgress, ReadLayers> {
     read_layers: ReadLayers,
     pedantic: bool,
     parallel: bool,
+    damaged_chunk_fill_value: Option<f32>,
+    attempt_chunk_recovery: bool,
+    attribute_handlers: AttributeHandlers,
+    channel_maps: ChannelMaps,
 }
 
 impl<F, L> ReadImage<F, L> where F: FnMut(f64)
@@ -30,6 +41,10 @@ impl<F, L> ReadImage<F, L> where F: FnMut(f64)
         Self {
             on_progress, read_layers,
             pedantic: false, parallel: true,
+            damaged_chunk_fill_value: None,
+            attempt_chunk_recovery: false,
+            attribute_handlers: AttributeHandlers::new(),
+            channel_maps: ChannelMaps::new(),
         }
     }
 
@@ -45,12 +60,45 @@ impl<F, L> ReadImage<F, L> where F: FnMut(f64)
     /// an error is thrown, because this should not happen and something might be wrong with the file.
     /// Or if your application is a target of attacks, or if you want to emulate the original C++ library,
     /// you might want to switch to pedantic reading.
+    /// When not reading pedantically, the tolerated anomalies are listed in the `ReadReport`, see `from_file_with_report`.
     pub fn pedantic(self) -> Self { Self { pedantic: true, ..self } }
 
     /// Specify that multiple pixel blocks should never be decompressed using multiple threads at once.
     /// This might be slower but uses less memory and less synchronization.
     pub fn non_parallel(self) -> Self { Self { parallel: false, ..self } }
 
+    /// Specify that pixel blocks which cannot be decoded should not abort reading the image.
+    /// Instead, the damaged blocks are filled with the specified value in every channel,
+    /// and their location is recorded in the `ReadReport`, see `from_file_with_report`.
+    /// Errors in the meta data still abort reading. Has no effect when reading pedantically.
+    pub fn recover_damaged_chunks(self, fill_value: f32) -> Self {
+        Self { damaged_chunk_fill_value: Some(fill_value), ..self }
+    }
+
+    /// Specify that pixel blocks which cannot be decompressed should be decompressed again using fallback strategies,
+    /// for example ignoring trailing garbage or invalid huffman codes, to rescue as much as possible from damaged files.
+    /// See `Compression::decompress_image_section_with_recovery` for all strategies.
+    /// Blocks that still cannot be decompressed abort reading, unless `recover_damaged_chunks` is also specified.
+    /// Has no effect when reading pedantically.
+    pub fn attempt_chunk_recovery(self) -> Self {
+        Self { attempt_chunk_recovery: true, ..self }
+    }
+
+    /// Parse custom attributes with the registered types into structured values,
+    /// instead of keeping them as opaque bytes. See `exr::meta::custom`.
+    /// Replaces all previously specified handlers in this reader.
+    pub fn with_attribute_handlers(self, attribute_handlers: AttributeHandlers) -> Self {
+        Self { attribute_handlers, ..self }
+    }
+
+    /// Modify the samples of some channels while decoding, for example to scale, bias, or apply a color matrix.
+    /// The functions are applied to each block before it is copied into the image. See `exr::image::read::maps`.
+    /// Blocks filled by `recover_damaged_chunks` are not modified.
+    /// Replaces all previously specified maps in this reader.
+    pub fn map_channels(self, channel_maps: ChannelMaps) -> Self {
+        Self { channel_maps, ..self }
+    }
+
     /// Specify a function to be called regularly throughout the loading process.
     /// Replaces all previously specified progress functions in this reader.
     pub fn on_progress<OnProgress>(self, on_progress: OnProgress) -> ReadImage<OnProgress, L>
@@ -60,7 +108,11 @@ impl<F, L> ReadImage<F, L> where F: FnMut(f64)
             on_progress,
             read_layers: self.read_layers,
             pedantic: self.pedantic,
-            parallel: self.parallel
+            parallel: self.parallel,
+            damaged_chunk_fill_value: self.damaged_chunk_fill_value,
+            attempt_chunk_recovery: self.attempt_chunk_recovery,
+            attribute_handlers: self.attribute_handlers,
+            channel_maps: self.channel_maps,
         }
     }
 
@@ -104,36 +156,174 @@ impl<F, L> ReadImage<F, L> where F: FnMut(f64)
     /// Use [`ReadImage::read_from_buffered`] instead, if this is an in-memory reader.
     // TODO Use Parallel<> Wrapper to only require sendable byte source where parallel decompression is required
     #[must_use]
-    pub fn from_chunks<Layers>(mut self, chunks_reader: crate::block::reader::Reader<impl Read + Seek>) -> Result<Image<Layers>>
+    pub fn from_chunks<Layers>(self, chunks_reader: crate::block::reader::Reader<impl Read + Seek>) -> Result<Image<Layers>>
+        where for<'s> L: ReadLayers<'s, Layers = Layers>
+    {
+        self.from_chunks_with_report(chunks_reader).map(|(image, _)| image)
+    }
+
+    /// Read the exr image from a file, and also return the damaged regions and warnings.
+    /// See `from_file` and `recover_damaged_chunks`.
+    #[inline]
+    #[must_use]
+    pub fn from_file_with_report<Layers>(self, path: impl AsRef<Path>) -> Result<(Image<Layers>, ReadReport)>
+        where for<'s> L: ReadLayers<'s, Layers = Layers>
+    {
+        self.from_unbuffered_with_report(std::fs::File::open(path)?)
+    }
+
+    /// Buffer the reader and then read the exr image from it, and also return the damaged regions and warnings.
+    /// See `from_unbuffered` and `recover_damaged_chunks`.
+    #[inline]
+    #[must_use]
+    pub fn from_unbuffered_with_report<Layers>(self, unbuffered: impl Read + Seek) -> Result<(Image<Layers>, ReadReport)>
+        where for<'s> L: ReadLayers<'s, Layers = Layers>
+    {
+        self.from_buffered_with_report(BufReader::new(unbuffered))
+    }
+
+    /// Read the exr image from a buffered reader, and also return the damaged regions and warnings.
+    /// See `from_buffered` and `recover_damaged_chunks`.
+    #[must_use]
+    pub fn from_buffered_with_report<Layers>(self, buffered: impl Read + Seek) -> Result<(Image<Layers>, ReadReport)>
         where for<'s> L: ReadLayers<'s, Layers = Layers>
     {
-        let Self { pedantic, parallel, ref mut on_progress, ref mut read_layers } = self;
+        let chunks = crate::block::read(buffered, self.pedantic)?;
+        self.from_chunks_with_report(chunks)
+    }
+
+    /// Read the exr image from an initialized chunks reader, and also return the damaged regions and warnings.
+    /// See `from_chunks` and `recover_damaged_chunks`.
+    #[must_use]
+    pub fn from_chunks_with_report<Layers>(mut self, mut chunks_reader: crate::block::reader::Reader<impl Read + Seek>) -> Result<(Image<Layers>, ReadReport)>
+        where for<'s> L: ReadLayers<'s, Layers = Layers>
+    {
+        let Self { pedantic, parallel, ref mut on_progress, ref mut read_layers, damaged_chunk_fill_value, attempt_chunk_recovery, ref attribute_handlers, ref channel_maps } = self;
+        attribute_handlers.parse_headers(chunks_reader.headers_mut(), pedantic)?;
+
+        let damaged_chunk_fill_value = damaged_chunk_fill_value.filter(|_| !pedantic);
+        let attempt_chunk_recovery = attempt_chunk_recovery && !pedantic;
 
         let layers_reader = read_layers.create_layers_reader(chunks_reader.headers())?;
         let mut image_collector = ImageWithAttributesReader::new(chunks_reader.headers(), layers_reader)?;
+        let mut expected_blocks = Vec::new();
 
         let block_reader = chunks_reader
             .filter_chunks(pedantic, |meta, tile, block| {
-                image_collector.filter_block(meta, tile, block)
+                let is_desired = image_collector.filter_block(meta, tile, block);
+                if is_desired && damaged_chunk_fill_value.is_some() { expected_blocks.push(block); }
+                is_desired
             })?
             .on_progress(on_progress);
 
+        let warnings = block_reader.warnings().to_vec();
+
+        if let Some(fill_value) = damaged_chunk_fill_value {
+            let headers = block_reader.headers().to_vec();
+            let mut missing_blocks: HashSet<BlockIndex> = expected_blocks.into_iter().collect();
+
+            let insert_block = |block: Result<UncompressedBlock>| -> UnitResult {
+                // blocks that cannot be read or decompressed remain in the missing set
+                if let Ok(mut block) = block {
+                    missing_blocks.remove(&block.index);
+                    channel_maps.apply(&headers, &mut block)?;
+                    image_collector.read_block(&headers, block)?;
+                }
+
+                Ok(())
+            };
+
+            decompress_each_block(block_reader, parallel, pedantic, attempt_chunk_recovery, insert_block)?;
+
+            let mut missing_blocks: Vec<BlockIndex> = missing_blocks.into_iter().collect();
+            missing_blocks.sort_by_key(|block| (block.layer, block.level.0, block.level.1, block.pixel_position.y(), block.pixel_position.x()));
+
+            for &block in &missing_blocks {
+                image_collector.read_block(&headers, filled_block(&headers, block, fill_value))?;
+            }
+
+            let damaged_regions = missing_blocks.into_iter().map(|block| DamagedRegion {
+                layer_index: block.layer, level: block.level,
+                bounds: IntegerBounds::new(block.pixel_position.to_i32(), block.pixel_size),
+            }).collect();
+
+            return Ok((image_collector.into_image(), ReadReport { damaged_regions, warnings }));
+        }
+
+        if attempt_chunk_recovery {
+            let headers = block_reader.headers().to_vec();
+
+            decompress_each_block(block_reader, parallel, pedantic, true, |block| {
+                let mut block = block?;
+                channel_maps.apply(&headers, &mut block)?;
+                image_collector.read_block(&headers, block)
+            })?;
+
+            return Ok((image_collector.into_image(), ReadReport { damaged_regions: Vec::new(), warnings }));
+        }
+
         // TODO propagate send requirement further upwards
         if parallel {
-            block_reader.decompress_parallel(pedantic, |meta_data, block|{
+            block_reader.decompress_parallel(pedantic, |meta_data, mut block|{
+                channel_maps.apply(&meta_data.headers, &mut block)?;
                 image_collector.read_block(&meta_data.headers, block)
             })?;
         }
         else {
-            block_reader.decompress_sequential(pedantic, |meta_data, block|{
+            block_reader.decompress_sequential(pedantic, |meta_data, mut block|{
+                channel_maps.apply(&meta_data.headers, &mut block)?;
                 image_collector.read_block(&meta_data.headers, block)
             })?;
         }
 
-        Ok(image_collector.into_image())
+        Ok((image_collector.into_image(), ReadReport { damaged_regions: Vec::new(), warnings }))
     }
 }
 
+/// Decompress all blocks, also passing the errors to the closure.
+fn decompress_each_block(
+    mut block_reader: impl ChunksReader, parallel: bool, pedantic: bool, attempt_recovery: bool,
+    mut insert_block: impl FnMut(Result<UncompressedBlock>) -> UnitResult
+) -> UnitResult
+{
+    if parallel {
+        match block_reader.parallel_decompressor(pedantic) {
+            Err(reader) => block_reader = reader,
+            Ok(decompressor) => {
+                let decompressor = if attempt_recovery { decompressor.attempt_recovery() } else { decompressor };
+                for block in decompressor { insert_block(block)?; }
+                return Ok(());
+            },
+        }
+    }
+
+    let decompressor = block_reader.sequential_decompressor(pedantic);
+    let decompressor = if attempt_recovery { decompressor.attempt_recovery() } else { decompressor };
+    for block in decompressor { insert_block(block)?; }
+    Ok(())
+}
+
+/// A block of the specified size where each sample has the specified value.
+fn filled_block(headers: &[Header], index: BlockIndex, fill_value: f32) -> UncompressedBlock {
+    let channels = &headers[index.layer].channels;
+    let mut data = vec![0_u8; channels.bytes_per_pixel * index.pixel_size.area()];
+
+    for (byte_range, line) in LineIndex::lines_in_block(index, channels) {
+        let sample_bytes: [u8; 4] = match channels.list[line.channel].sample_type {
+            SampleType::F16 => { let [a, b] = f16::from_f32(fill_value).to_bits().to_le_bytes(); [a, b, 0, 0] },
+            SampleType::F32 => fill_value.to_le_bytes(),
+            SampleType::U32 => Sample::F32(fill_value).to_u32().to_le_bytes(),
+        };
+
+        let sample_size = channels.list[line.channel].sample_type.bytes_per_sample();
+        for sample in data[byte_range].chunks_exact_mut(sample_size) {
+            sample.copy_from_slice(&sample_bytes[.. sample_size]);
+        }
+    }
+
+    UncompressedBlock { index, data }
+}
+
 /// Processes blocks from a file and collects them into a complete `Image`.
 #[derive(Debug, Clone, PartialEq)]
 pub struct ImageWithAttributesReader<L> {
@@ -207,3 +397,110 @@ pub trait LayersReader {
     fn into_layers(self) -> Self::Layers;
 }
 
+
+
+#[cfg(test)]
+mod test {
+    use crate::prelude::*;
+    use crate::error::Warning;
+    use std::io::Cursor;
+
+    #[test]
+    fn damaged_chunks_are_filled_and_reported(){
+        let size = Vec2(16, 64);
+        let values = (0 .. size.area()).map(|index| ((index * 7919) % 251) as f32).collect();
+        let channels = AnyChannels::sort(smallvec::smallvec![ AnyChannel::new("Y", FlatSamples::F32(values)) ]);
+
+        let encoding = Encoding { compression: Compression::ZIP16, blocks: Blocks::ScanLines, line_order: LineOrder::Increasing };
+        let image = Image::from_encoded_channels(size, encoding, channels);
+
+        let mut bytes = Vec::new();
+        image.write().to_buffered(Cursor::new(&mut bytes)).unwrap();
+        let intact_bytes = bytes.clone();
+
+        // cut off the end of the last chunk
+        bytes.truncate(bytes.len() - 16);
+
+        let read = || read().no_deep_data().largest_resolution_level().all_channels().all_layers().all_attributes();
+        assert!(read().from_buffered(Cursor::new(&bytes)).is_err());
+
+        for parallel in [ true, false ] {
+            let reader = read().recover_damaged_chunks(-1.0);
+            let reader = if parallel { reader } else { reader.non_parallel() };
+            let (recovered, report) = reader.from_buffered_with_report(Cursor::new(&bytes)).unwrap();
+
+            let damaged = IntegerBounds::new(Vec2(0, 48), Vec2(16, 16));
+            assert_eq!(report.damaged_regions, vec![ DamagedRegion { layer_index: 0, level: Vec2(0, 0), bounds: damaged } ]);
+
+            let samples = &recovered.layer_data[0].channel_data.list[0].sample_data;
+            assert_eq!(samples.value_by_flat_index(0).to_f32(), 0.0);
+            assert_eq!(samples.value_by_flat_index(size.area() - 1).to_f32(), -1.0);
+        }
+
+        let (_, intact_report) = read().recover_damaged_chunks(-1.0)
+            .from_buffered_with_report(Cursor::new(&intact_bytes)).unwrap();
+
+        assert!(intact_report.damaged_regions.is_empty());
+    }
+
+    #[test]
+    fn relaxed_reading_tolerates_wrong_chunk_count(){
+        let layer = |name: &str| Layer::new(
+            (8, 32), LayerAttributes::named(name),
+            Encoding { compression: Compression::ZIP16, blocks: Blocks::ScanLines, line_order: LineOrder::Increasing },
+            AnyChannels::sort(smallvec::smallvec![ AnyChannel::new("Y", FlatSamples::F32(vec![ 0.5; 8 * 32 ])) ])
+        );
+
+        // two layers, so that the chunk count attribute is written
+        let image = Image::from_layers(ImageAttributes::new(IntegerBounds::from_dimensions((8, 32))), vec![ layer("a"), layer("b") ]);
+        let mut bytes = Vec::new();
+        image.write().to_buffered(Cursor::new(&mut bytes)).unwrap();
+
+        let name_and_type = b"chunkCount\0int\0";
+        let start = bytes.windows(name_and_type.len()).position(|window| window == name_and_type).unwrap() + name_and_type.len() + 4;
+        bytes[start .. start + 4].copy_from_slice(&1000_i32.to_le_bytes());
+
+        let read = || read().no_deep_data().largest_resolution_level().all_channels().all_layers().all_attributes();
+        assert!(read().pedantic().from_buffered(Cursor::new(&bytes)).is_err());
+        assert_eq!(read().from_buffered(Cursor::new(&bytes)).unwrap().layer_data.len(), 2);
+    }
+
+    #[test]
+    fn tolerated_anomalies_are_reported(){
+        let layer = |name: &str| Layer::new(
+            (8, 32), LayerAttributes::named(name),
+            Encoding { compression: Compression::ZIP16, blocks: Blocks::ScanLines, line_order: LineOrder::Increasing },
+            AnyChannels::sort(smallvec::smallvec![ AnyChannel::new("Y", FlatSamples::F32(vec![ 0.5; 8 * 32 ])) ])
+        );
+
+        // both layers have the same name, which only pedantic reading refuses
+        let image = Image::from_layers(ImageAttributes::new(IntegerBounds::from_dimensions((8, 32))), vec![ layer("a"), layer("a") ]);
+        let mut bytes = Vec::new();
+        image.write().unchecked().to_buffered(Cursor::new(&mut bytes)).unwrap();
+
+        // overwrite the value of the first attribute with the specified name and type
+        fn patch(bytes: &mut [u8], name_and_type: &[u8], value: &[u8]) {
+            let start = bytes.windows(name_and_type.len()).position(|window| window == name_and_type).unwrap();
+            let start = start + name_and_type.len() + 4; // skip byte size
+            bytes[start .. start + value.len()].copy_from_slice(value);
+        }
+
+        patch(&mut bytes, b"chunkCount\0int\0", &1000_i32.to_le_bytes());
+        patch(&mut bytes, b"lineOrder\0lineOrder\0", &[ 1 ]); // decreasing
+
+        let read = || read().no_deep_data().largest_resolution_level().all_channels().all_layers().all_attributes();
+        assert!(read().pedantic().from_buffered(Cursor::new(&bytes)).is_err());
+
+        let (image, report) = read().from_buffered_with_report(Cursor::new(&bytes)).unwrap();
+        assert_eq!(image.layer_data.len(), 2);
+
+        assert!(report.warnings.contains(&Warning::ChunkCountMismatch { layer_index: 0, declared: 1000, computed: 2 }));
+        assert!(report.warnings.contains(&Warning::UnexpectedChunkOrder { layer_index: 0 }));
+        assert!(!report.warnings.contains(&Warning::UnexpectedChunkOrder { layer_index: 1 }));
+        assert!(report.warnings.iter().any(|warning| matches!(warning, Warning::InvalidMetaData(_))));
+
+        let mut intact_bytes = Vec::new();
+        Image::from_layer(layer("a")).write().to_buffered(Cursor::new(&mut intact_bytes)).unwrap();
+        assert!(read().from_buffered_with_report(Cursor::new(&intact_bytes)).unwrap().1.warnings.is_empty());
+    }
+}
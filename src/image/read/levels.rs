@@ -61,6 +61,38 @@ impl<DeepOrFlatSamples> ReadLargestLevel<DeepOrFlatSamples> {
             .collect_pixels(create_pixels, set_pixel)
     }
 
+    /// Read only layers that contain at least one of the rgba channels. Skips any other channels in the layer.
+    /// Each channel that is missing in a layer is filled with the corresponding default sample,
+    /// for example `(0.0, 0.0, 0.0, 1.0)` to make missing colors black and missing alpha opaque.
+    /// The channel descriptions, which are passed to the first closure and stored in the image,
+    /// are `None` for each channel that was filled with the default sample.
+    ///
+    /// Using two closures, define how to store the pixels.
+    /// The first closure creates an image, and the second closure inserts a single pixel.
+    /// The type of the pixel is defined by the default samples;
+    /// it must be a tuple containing four values, each being either `f16`, `f32`, `u32` or `Sample`.
+    ///
+    /// Throws an error for images with deep data or subsampling.
+    pub fn rgba_channels_with_defaults<R,G,B,A, Create, Set, Pixels>(
+        self, default_samples: (R, G, B, A), create_pixels: Create, set_pixel: Set
+    ) -> CollectPixels<
+        ReadOptionalChannel<ReadOptionalChannel<ReadOptionalChannel<ReadOptionalChannel<NoneMore, R>, G>, B>, A>,
+        (R, G, B, A), Pixels, Create, Set
+    >
+        where
+            R: FromNativeSample, G: FromNativeSample, B: FromNativeSample, A: FromNativeSample,
+            Create: Fn(Vec2<usize>, &OptionalRgbaChannels) -> Pixels,
+            Set: Fn(&mut Pixels, Vec2<usize>, (R,G,B,A)),
+    {
+        let (red, green, blue, alpha) = default_samples;
+
+        self.specific_channels()
+            .optional("R", red).optional("G", green).optional("B", blue)
+            .optional("A", alpha)
+            .collect_pixels(create_pixels, set_pixel)
+            .requiring_any_channel()
+    }
+
     /// Read only layers that contain rgb channels. Skips any other channels in the layer.
     ///
     /// Using two closures, define how to store the pixels.
@@ -0,0 +1,227 @@
+//! Modify the samples of some channels while the image is being decoded,
+//! for example to scale, bias, or apply a color matrix.
+//! The functions are applied to each decompressed block just before it is copied into the image,
+//! so no separate pass over the whole image is required afterwards.
+//!
+//! Register the functions in a `ChannelMaps` value,
+//! and pass it to a reader using `read().map_channels(maps)`.
+
+use std::fmt::{Debug, Formatter};
+use std::sync::Arc;
+use std::ops::Range;
+use smallvec::SmallVec;
+use half::f16;
+
+use crate::block::UncompressedBlock;
+use crate::block::lines::LineIndex;
+use crate::error::{UnitResult, Error};
+use crate::meta::attribute::{SampleType, Text};
+use crate::meta::header::Header;
+
+
+/// A list of functions that modify the samples of some channels while decoding.
+/// Each function receives the samples of one pixel of all the channels it was registered for,
+/// in the order of the registered channel names, and may modify them.
+/// Samples of type `u32` are converted to `f32` and back.
+/// A function is skipped for layers that do not contain all of its channels.
+/// Deep data is not modified.
+#[derive(Clone, Default)]
+pub struct ChannelMaps {
+    maps: Vec<(SmallVec<[Text; 4]>, Arc<dyn Send + Sync + Fn(&mut [f32])>)>,
+}
+
+impl ChannelMaps {
+
+    /// A list without any functions.
+    pub fn new() -> Self { Self::default() }
+
+    /// Replace each sample of the channel with the result of the function.
+    pub fn with_channel(self, channel: impl Into<Text>, map: impl 'static + Send + Sync + Fn(f32) -> f32) -> Self {
+        self.with_channels(Some(channel), move |samples: &mut [f32]| samples[0] = map(samples[0]))
+    }
+
+    /// Multiply each sample of the channel by `scale`, then add `bias`.
+    pub fn with_scale_and_bias(self, channel: impl Into<Text>, scale: f32, bias: f32) -> Self {
+        self.with_channel(channel, move |sample| sample * scale + bias)
+    }
+
+    /// Modify the samples of each pixel of the channels at once,
+    /// for example to convert between color spaces.
+    /// The function receives one sample per channel, in the order of the specified names.
+    pub fn with_channels<Name: Into<Text>>(
+        mut self, channels: impl IntoIterator<Item = Name>,
+        map: impl 'static + Send + Sync + Fn(&mut [f32])
+    ) -> Self {
+        self.maps.push((channels.into_iter().map(Into::into).collect(), Arc::new(map)));
+        self
+    }
+
+    /// Multiply the samples of each pixel of the three channels by the row-major matrix.
+    pub fn with_matrix<Name: Into<Text>>(self, channels: [Name; 3], matrix: [[f32; 3]; 3]) -> Self {
+        let [a, b, c] = channels;
+
+        self.with_channels(vec![ a, b, c ], move |samples: &mut [f32]| {
+            let input = [ samples[0], samples[1], samples[2] ];
+
+            for (sample, row) in samples.iter_mut().zip(&matrix) {
+                *sample = row[0] * input[0] + row[1] * input[1] + row[2] * input[2];
+            }
+        })
+    }
+
+    /// Whether no function has been registered.
+    pub fn is_empty(&self) -> bool { self.maps.is_empty() }
+
+    /// Apply all functions to the samples in the block.
+    pub fn apply(&self, headers: &[Header], block: &mut UncompressedBlock) -> UnitResult {
+        if self.maps.is_empty() { return Ok(()); }
+
+        let header = headers.get(block.index.layer).ok_or(Error::invalid("block layer index"))?;
+        if header.deep { return Ok(()); }
+
+        let channels = &header.channels;
+        let routes: SmallVec<[(SmallVec<[usize; 4]>, &(dyn Send + Sync + Fn(&mut [f32]))); 4]> = self.maps.iter()
+            .filter_map(|(names, map)| {
+                let indices = names.iter().map(|name| channels.find_index_of_channel(name)).collect::<Option<_>>()?;
+                Some((indices, map.as_ref()))
+            })
+            .collect();
+
+        if routes.is_empty() { return Ok(()); }
+
+        // each row of the block contains one line per channel
+        let lines: Vec<(Range<usize>, LineIndex)> = LineIndex::lines_in_block(block.index, channels).collect();
+
+        for row in lines.chunks(channels.list.len()) {
+            apply_to_row(&routes, header, row, &mut block.data);
+        }
+
+        Ok(())
+    }
+}
+
+/// Apply the functions to all pixels in a single row of the block.
+fn apply_to_row(
+    routes: &[(SmallVec<[usize; 4]>, &(dyn Send + Sync + Fn(&mut [f32])))],
+    header: &Header, row: &[(Range<usize>, LineIndex)], data: &mut [u8]
+) {
+    let mut samples: SmallVec<[f32; 4]> = SmallVec::new();
+
+    for (channel_indices, map) in routes {
+        for x in 0 .. row[0].1.sample_count {
+            samples.clear();
+
+            for &index in channel_indices {
+                let sample_type = header.channels.list[index].sample_type;
+                samples.push(read_sample(&data[row[index].0.clone()], sample_type, x));
+            }
+
+            map(&mut samples);
+
+            for (&index, &sample) in channel_indices.iter().zip(&samples) {
+                let sample_type = header.channels.list[index].sample_type;
+                write_sample(&mut data[row[index].0.clone()], sample_type, x, sample);
+            }
+        }
+    }
+}
+
+fn read_sample(line: &[u8], sample_type: SampleType, index: usize) -> f32 {
+    match sample_type {
+        SampleType::F16 => f16::from_bits(u16::from_le_bytes([ line[index * 2], line[index * 2 + 1] ])).to_f32(),
+        SampleType::F32 => f32::from_le_bytes(four_bytes(line, index)),
+        SampleType::U32 => u32::from_le_bytes(four_bytes(line, index)) as f32,
+    }
+}
+
+fn write_sample(line: &mut [u8], sample_type: SampleType, index: usize, sample: f32) {
+    match sample_type {
+        SampleType::F16 => line[index * 2 .. index * 2 + 2].copy_from_slice(&f16::from_f32(sample).to_bits().to_le_bytes()),
+        SampleType::F32 => line[index * 4 .. index * 4 + 4].copy_from_slice(&sample.to_le_bytes()),
+        SampleType::U32 => line[index * 4 .. index * 4 + 4].copy_from_slice(&(sample as u32).to_le_bytes()),
+    }
+}
+
+fn four_bytes(line: &[u8], index: usize) -> [u8; 4] {
+    [ line[index * 4], line[index * 4 + 1], line[index * 4 + 2], line[index * 4 + 3] ]
+}
+
+impl Debug for ChannelMaps {
+    fn fmt(&self, formatter: &mut Formatter<'_>) -> std::fmt::Result {
+        formatter.debug_list().entries(self.maps.iter().map(|(names, _)| names)).finish()
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use std::io::Cursor;
+    use crate::prelude::*;
+    use super::*;
+
+    fn file() -> Vec<u8> {
+        let size = Vec2(9, 6);
+        let ramp = |offset: f32| (0 .. size.area()).map(move |index| index as f32 + offset);
+
+        let channels = AnyChannels::sort(smallvec::smallvec![
+            AnyChannel::new("R", FlatSamples::F16(ramp(0.0).map(f16::from_f32).collect())),
+            AnyChannel::new("G", FlatSamples::F32(ramp(1.0).collect())),
+            AnyChannel::new("B", FlatSamples::F32(ramp(2.0).collect())),
+            AnyChannel::new("id", FlatSamples::U32((0 .. size.area() as u32).collect())),
+        ]);
+
+        let encoding = Encoding { compression: Compression::RLE, blocks: Blocks::Tiles(Vec2(4, 4)), line_order: LineOrder::Increasing };
+        let mut bytes = Vec::new();
+        Image::from_layer(Layer::new(size, LayerAttributes::default(), encoding, channels))
+            .write().to_buffered(Cursor::new(&mut bytes)).unwrap();
+
+        bytes
+    }
+
+    fn read_with(maps: ChannelMaps) -> Vec<FlatSamples> {
+        let image = read().no_deep_data().largest_resolution_level().all_channels().first_valid_layer().all_attributes()
+            .map_channels(maps).from_buffered(Cursor::new(file())).unwrap();
+
+        image.layer_data.channel_data.list.into_iter().map(|channel| channel.sample_data).collect()
+    }
+
+    #[test]
+    fn map_channels_while_decoding(){
+        let unchanged = read_with(ChannelMaps::new());
+
+        let swap = [ [0.0, 0.0, 1.0], [0.0, 1.0, 0.0], [1.0, 0.0, 0.0] ];
+        let mapped = read_with(ChannelMaps::new()
+            .with_scale_and_bias("id", 2.0, 1.0)
+            .with_matrix([ "R", "G", "B" ], swap)
+            .with_channel("G", |sample| -sample)
+            .with_channel("missing", |_| unreachable!()));
+
+        // the channels are sorted alphabetically: B, G, R, id
+        for index in 0 .. 9 * 6 {
+            assert_eq!(mapped[0].value_by_flat_index(index), unchanged[2].value_by_flat_index(index));
+            assert_eq!(mapped[1].value_by_flat_index(index).to_f32(), -unchanged[1].value_by_flat_index(index).to_f32());
+            assert_eq!(mapped[2].value_by_flat_index(index).to_f32(), unchanged[0].value_by_flat_index(index).to_f32());
+            assert_eq!(mapped[3].value_by_flat_index(index), Sample::U32(index as u32 * 2 + 1));
+        }
+    }
+
+    #[test]
+    fn skip_layers_without_the_channels(){
+        let mut block = UncompressedBlock {
+            index: crate::block::BlockIndex { layer: 0, pixel_position: Vec2(0, 0), pixel_size: Vec2(2, 1), level: Vec2(0, 0) },
+            data: [ 1.0_f32, 2.0, 3.0, 4.0 ].iter().flat_map(|sample| sample.to_le_bytes()).collect(),
+        };
+
+        let channels = smallvec::smallvec![ ChannelDescription::named("A", SampleType::F32), ChannelDescription::named("B", SampleType::F32) ];
+        let header = Header::new(Text::from("layer"), (2, 1), channels);
+
+        let maps = ChannelMaps::new()
+            .with_channels([ "A", "C" ], |_: &mut [f32]| unreachable!())
+            .with_channels([ "B", "A" ], |samples: &mut [f32]| samples.swap(0, 1));
+
+        maps.apply(&[ header ], &mut block).unwrap();
+
+        let expected: Vec<u8> = [ 3.0_f32, 4.0, 1.0, 2.0 ].iter().flat_map(|sample| sample.to_le_bytes()).collect();
+        assert_eq!(block.data, expected);
+    }
+}
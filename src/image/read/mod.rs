@@ -47,6 +47,10 @@ pub mod layers;
 pub mod any_channels;
 pub mod levels;
 pub mod samples;
+pub mod budget;
+pub mod playback;
+pub mod sinks;
+pub mod maps;
 pub mod specific_channels;
 
 use crate::error::{Result};
@@ -0,0 +1,363 @@
+//! Decode a frame within a time budget, for example to scrub through an image sequence in real time.
+//! First decodes a smaller resolution level as a proxy for the whole frame,
+//! then refines the visible region with the full resolution blocks, and then the rest of the layer.
+//! When the time budget is exhausted, the partially refined frame is returned.
+
+use crate::image::*;
+use crate::meta::header::Header;
+use crate::meta::{BlockDescription, compute_level_size};
+use crate::meta::attribute::{IntegerBounds, LevelMode, SampleType};
+use crate::error::{Result, UnitResult, Error};
+use crate::block::{BlockIndex, UncompressedBlock};
+use crate::block::lines::LineRef;
+use crate::block::reader::ChunksReader;
+use crate::image::read::budget::level_count;
+use crate::math::Vec2;
+use std::io::{Read, Seek, BufReader, Cursor};
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+
+/// Specifies which parts of a frame should be decoded first, and when to stop decoding.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub struct PlaybackOptions {
+
+    /// Decoding stops when this duration has passed.
+    /// Pixels that are not refined yet contain the proxy samples, or zero if the proxy was not decoded.
+    pub time_budget: Duration,
+
+    /// How many times the resolution of the proxy level is halved.
+    /// Clamped to the smallest level. Zero disables the proxy.
+    /// Only has an effect if the layer contains mip map or rip map levels.
+    pub proxy_level: usize,
+
+    /// The region that is refined before the rest of the layer, in absolute pixel coordinates,
+    /// the same coordinate system as the display window and the data window.
+    /// If none, the display window of the image is used.
+    pub visible_region: Option<IntegerBounds>,
+
+    /// Index of the layer that is decoded.
+    pub layer_index: usize,
+}
+
+/// A frame that was decoded within a time budget. May be only partially refined.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PlaybackFrame {
+
+    /// Index of the decoded layer in the file.
+    pub layer_index: usize,
+
+    /// The full resolution of the layer.
+    pub size: Vec2<usize>,
+
+    /// The samples of all channels, in the full resolution of the layer.
+    pub channels: AnyChannels<FlatSamples>,
+
+    /// The level index of the proxy, if a proxy was decoded.
+    pub proxy_level: Option<Vec2<usize>>,
+
+    /// The number of full resolution blocks that have been decoded.
+    pub refined_block_count: usize,
+
+    /// The number of full resolution blocks in the layer.
+    pub total_block_count: usize,
+}
+
+impl PlaybackOptions {
+
+    /// Decode the first layer within the specified duration, using the third level as proxy,
+    /// and refining the display window first.
+    pub fn new(time_budget: Duration) -> Self {
+        PlaybackOptions { time_budget, proxy_level: 2, visible_region: None, layer_index: 0 }
+    }
+}
+
+impl PlaybackFrame {
+
+    /// Whether all full resolution blocks have been decoded before the time budget was exhausted.
+    pub fn is_fully_refined(&self) -> bool {
+        self.refined_block_count == self.total_block_count
+    }
+
+    fn new(header: &Header, layer_index: usize, proxy_level: Option<Vec2<usize>>) -> Self {
+        let sample_count = header.layer_size.area();
+
+        let channels = header.channels.list.iter()
+            .map(|channel| AnyChannel {
+                quantize_linearly: channel.quantize_linearly,
+                .. AnyChannel::new(channel.name.clone(), match channel.sample_type {
+                    SampleType::F16 => FlatSamples::F16(vec![ f16::ZERO; sample_count ]),
+                    SampleType::F32 => FlatSamples::F32(vec![ 0.0; sample_count ]),
+                    SampleType::U32 => FlatSamples::U32(vec![ 0; sample_count ]),
+                })
+            })
+            .collect();
+
+        PlaybackFrame {
+            layer_index, proxy_level,
+            size: header.layer_size,
+            channels: AnyChannels::sort(channels),
+            refined_block_count: 0,
+            total_block_count: header.blocks_increasing_y_order()
+                .filter(|tile| tile.location.level_index == Vec2(0, 0))
+                .count(),
+        }
+    }
+
+    /// Write the samples of the block into the frame.
+    /// Samples of smaller levels are repeated to cover the full resolution.
+    fn insert_block(&mut self, header: &Header, block: UncompressedBlock) -> UnitResult {
+        let level = block.index.level;
+        let size = self.size;
+        let scale = Vec2(1 << level.x(), 1 << level.y());
+
+        // levels that were rounded down do not cover the full resolution when scaled up
+        let level_size = match header.blocks {
+            BlockDescription::Tiles(tiles) => Vec2(
+                compute_level_size(tiles.rounding_mode, size.width(), level.x()),
+                compute_level_size(tiles.rounding_mode, size.height(), level.y()),
+            ),
+
+            BlockDescription::ScanLines => size,
+        };
+
+        for line in block.lines(&header.channels) {
+            let target = &mut self.channels.list[line.location.channel].sample_data;
+
+            match target {
+                FlatSamples::F16(samples) => fill_scaled_line(&line, samples, size, level_size, scale),
+                FlatSamples::F32(samples) => fill_scaled_line(&line, samples, size, level_size, scale),
+                FlatSamples::U32(samples) => fill_scaled_line(&line, samples, size, level_size, scale),
+            }?;
+        }
+
+        if block.index.is_largest_resolution_level() {
+            self.refined_block_count += 1;
+        }
+
+        Ok(())
+    }
+}
+
+/// Repeat each sample of the line `scale` times in both dimensions.
+/// The last row and column of the level are repeated up to the edge of the frame.
+fn fill_scaled_line<T: crate::io::Data + Copy>(
+    line: &LineRef<'_>, target: &mut [T],
+    size: Vec2<usize>, level_size: Vec2<usize>, scale: Vec2<usize>
+) -> UnitResult {
+    let position = line.location.position;
+    let start_y = position.y() * scale.y();
+    if start_y >= size.height() { return Ok(()); }
+
+    let end_y = if position.y() + 1 == level_size.height() { size.height() }
+        else { (start_y + scale.y()).min(size.height()) };
+
+    for (index, sample) in line.read_samples::<T>().enumerate() {
+        let sample = sample?;
+        let x = position.x() + index;
+        let start_x = x * scale.x();
+        if start_x >= size.width() { break; }
+
+        let end_x = if x + 1 == level_size.width() { size.width() }
+            else { (start_x + scale.x()).min(size.width()) };
+
+        for y in start_y .. end_y {
+            target[y * size.width() + start_x .. y * size.width() + end_x].fill(sample);
+        }
+    }
+
+    Ok(())
+}
+
+
+/// Decode a flat layer from the file, stopping when the time budget is exhausted.
+/// Opens the file once for each decoding pass.
+/// The time budget includes opening the file and reading the meta data.
+pub fn read_frame_within_time_budget_from_file(path: impl AsRef<Path>, options: PlaybackOptions) -> Result<PlaybackFrame> {
+    let deadline = Instant::now() + options.time_budget;
+    let path = path.as_ref();
+    read_frame_before_deadline(|| Ok(BufReader::new(std::fs::File::open(path)?)), options, deadline)
+}
+
+/// Decode a flat layer from the bytes of a file, stopping when the time budget is exhausted.
+/// Useful if the files of a sequence are already loaded into memory.
+pub fn read_frame_within_time_budget_from_bytes(bytes: &[u8], options: PlaybackOptions) -> Result<PlaybackFrame> {
+    let deadline = Instant::now() + options.time_budget;
+    read_frame_before_deadline(|| Ok(Cursor::new(bytes)), options, deadline)
+}
+
+fn read_frame_before_deadline<R: Read + Seek>(
+    mut open: impl FnMut() -> Result<R>, options: PlaybackOptions, deadline: Instant
+) -> Result<PlaybackFrame>
+{
+    let meta_data = crate::block::read(open()?, false)?.into_meta_data();
+    let header = meta_data.headers.get(options.layer_index)
+        .ok_or(Error::invalid("playback layer index"))?;
+
+    if header.deep { return Err(Error::unsupported("deep data not supported yet")); }
+    if header.channels.list.iter().any(|channel| channel.sampling != Vec2(1, 1)) {
+        return Err(Error::unsupported("playback of subsampled channels"));
+    }
+
+    let proxy_level = proxy_level_index(header, options.proxy_level);
+    let visible_region = options.visible_region.unwrap_or(header.shared_attributes.display_window);
+    let mut frame = PlaybackFrame::new(header, options.layer_index, proxy_level);
+
+    let is_layer = |block: &BlockIndex| block.layer == options.layer_index;
+    let is_visible = |header: &Header, block: &BlockIndex| overlaps(block.data_window_bounds(header), visible_region);
+
+    if let Some(proxy_level) = proxy_level {
+        let is_complete = decode_pass(&mut open, &mut frame, deadline, |_, block|
+            is_layer(block) && block.level == proxy_level
+        )?;
+
+        if !is_complete { return Ok(frame); }
+    }
+
+    let is_complete = decode_pass(&mut open, &mut frame, deadline, |header, block|
+        is_layer(block) && block.is_largest_resolution_level() && is_visible(header, block)
+    )?;
+
+    if is_complete {
+        decode_pass(&mut open, &mut frame, deadline, |header, block|
+            is_layer(block) && block.is_largest_resolution_level() && !is_visible(header, block)
+        )?;
+    }
+
+    Ok(frame)
+}
+
+/// Returns whether all blocks of this pass were decoded before the deadline.
+fn decode_pass<R: Read + Seek>(
+    open: &mut impl FnMut() -> Result<R>, frame: &mut PlaybackFrame, deadline: Instant,
+    filter: impl Fn(&Header, &BlockIndex) -> bool
+) -> Result<bool>
+{
+    if Instant::now() >= deadline { return Ok(false); }
+
+    let chunks = crate::block::read(open()?, false)?
+        .filter_chunks(false, |meta, _, block| filter(&meta.headers[block.layer], &block))?;
+
+    let header = chunks.meta_data().headers[frame.layer_index].clone();
+    let mut decompressor = chunks.sequential_decompressor(false);
+
+    while Instant::now() < deadline {
+        match decompressor.decompress_next_block() {
+            None => return Ok(true),
+            Some(block) => frame.insert_block(&header, block?)?,
+        }
+    }
+
+    Ok(false)
+}
+
+fn proxy_level_index(header: &Header, halving_count: usize) -> Option<Vec2<usize>> {
+    match header.blocks {
+        BlockDescription::Tiles(tiles) if tiles.level_mode != LevelMode::Singular => {
+            let level = halving_count.min(level_count(tiles, header.layer_size) - 1);
+            if level == 0 { None } else { Some(Vec2(level, level)) }
+        },
+
+        _ => None,
+    }
+}
+
+fn overlaps(first: IntegerBounds, second: IntegerBounds) -> bool {
+    let (first_end, second_end) = (first.end(), second.end());
+
+    first.position.x() < second_end.x() && second.position.x() < first_end.x()
+        && first.position.y() < second_end.y() && second.position.y() < first_end.y()
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::prelude::*;
+
+    fn mip_mapped_file() -> (Vec<f32>, Vec<u8>) {
+        let size = Vec2(32, 24);
+        let values: Vec<f32> = (0 .. size.area()).map(|index| (index % 7) as f32).collect();
+        let full = FlatSamples::F32(values.clone());
+        let levels = Levels::Mip {
+            rounding_mode: RoundingMode::Down,
+            level_data: crate::meta::mip_map_levels(RoundingMode::Down, size)
+                .map(|(index, level_size)| if index == 0 { full.clone() } else { FlatSamples::F32(vec![ 100.0; level_size.area() ]) })
+                .collect()
+        };
+
+        let layer = Layer::new(
+            size, LayerAttributes::named("beauty"),
+            Encoding { blocks: Blocks::Tiles(Vec2(8, 8)), .. Encoding::UNCOMPRESSED },
+            AnyChannels::sort(smallvec::smallvec![ AnyChannel::new("Y", levels) ])
+        );
+
+        let mut bytes = Vec::new();
+        Image::from_layer(layer).write().to_buffered(Cursor::new(&mut bytes)).unwrap();
+        (values, bytes)
+    }
+
+    #[test]
+    fn unlimited_budget_refines_everything(){
+        let (values, bytes) = mip_mapped_file();
+        let frame = read_frame_within_time_budget_from_bytes(&bytes, PlaybackOptions::new(Duration::from_secs(3600))).unwrap();
+
+        assert!(frame.is_fully_refined());
+        assert_eq!(frame.total_block_count, 4 * 3);
+        assert_eq!(frame.proxy_level, Some(Vec2(2, 2)));
+        assert_eq!(frame.channels.list[0].sample_data, FlatSamples::F32(values));
+    }
+
+    #[test]
+    fn exhausted_budget_returns_unrefined_frame(){
+        let (_, bytes) = mip_mapped_file();
+        let frame = read_frame_within_time_budget_from_bytes(&bytes, PlaybackOptions::new(Duration::ZERO)).unwrap();
+
+        assert_eq!(frame.refined_block_count, 0);
+        assert!(!frame.is_fully_refined());
+        assert_eq!(frame.size, Vec2(32, 24));
+    }
+
+    #[test]
+    fn proxy_covers_the_whole_frame(){
+        let (_, bytes) = mip_mapped_file();
+        let header = crate::block::read(Cursor::new(&bytes), false).unwrap().headers()[0].clone();
+        let mut frame = PlaybackFrame::new(&header, 0, Some(Vec2(2, 2)));
+
+        let mut open = || Ok(Cursor::new(bytes.as_slice()));
+        let far_future = Instant::now() + Duration::from_secs(3600);
+        assert!(decode_pass(&mut open, &mut frame, far_future, |_, block| block.level == Vec2(2, 2)).unwrap());
+
+        assert_eq!(frame.refined_block_count, 0);
+        assert_eq!(frame.channels.list[0].sample_data, FlatSamples::F32(vec![ 100.0; 32 * 24 ]));
+    }
+
+    #[test]
+    fn proxy_rounded_down_covers_the_edges(){
+        // the proxy level is 7×5 pixels, which only covers 28×20 pixels when scaled up
+        let size = Vec2(30, 22);
+        let levels = Levels::Mip {
+            rounding_mode: RoundingMode::Down,
+            level_data: crate::meta::mip_map_levels(RoundingMode::Down, size)
+                .map(|(index, level_size)| FlatSamples::F32(vec![ 100.0 + index as f32; level_size.area() ]))
+                .collect()
+        };
+
+        let layer = Layer::new(
+            size, LayerAttributes::named("beauty"),
+            Encoding { blocks: Blocks::Tiles(Vec2(8, 8)), .. Encoding::UNCOMPRESSED },
+            AnyChannels::sort(smallvec::smallvec![ AnyChannel::new("Y", levels) ])
+        );
+
+        let mut bytes = Vec::new();
+        Image::from_layer(layer).write().to_buffered(Cursor::new(&mut bytes)).unwrap();
+
+        let header = crate::block::read(Cursor::new(&bytes), false).unwrap().headers()[0].clone();
+        let mut frame = PlaybackFrame::new(&header, 0, Some(Vec2(2, 2)));
+
+        let mut open = || Ok(Cursor::new(bytes.as_slice()));
+        let far_future = Instant::now() + Duration::from_secs(3600);
+        assert!(decode_pass(&mut open, &mut frame, far_future, |_, block| block.level == Vec2(2, 2)).unwrap());
+        assert_eq!(frame.channels.list[0].sample_data, FlatSamples::F32(vec![ 102.0; size.area() ]));
+    }
+}
@@ -0,0 +1,206 @@
+//! Decode a layer once, sending each channel to a different destination.
+//! For example, the color channels can be copied into a staging buffer of a graphics card,
+//! the depth channel into an array, and the object ids into a hash set,
+//! all while reading and decompressing the file only once.
+//! The samples are not collected into an image, so no memory is required except for the destinations.
+
+use std::io::{Read, Seek, BufReader};
+use std::fs::File;
+use std::path::Path;
+use smallvec::SmallVec;
+
+use crate::block::lines::LineRef;
+use crate::block::reader::ChunksReader;
+use crate::error::{UnitResult, Error};
+use crate::math::Vec2;
+use crate::meta::attribute::Text;
+
+
+/// Receives the decoded samples of one or more channels.
+/// Implemented for all closures of type `FnMut(usize, LineRef<'_>) -> UnitResult`.
+pub trait ChannelSink {
+
+    /// Receive a single line of samples.
+    /// The channel is the index within the list of channel names that this sink was registered for.
+    /// The line contains the raw little-endian bytes and the position of the line within the layer.
+    /// Use `line.read_samples_into_slice` to convert the bytes.
+    /// The lines arrive in the order of the file, which is not necessarily from top to bottom.
+    fn write_line(&mut self, channel: usize, line: LineRef<'_>) -> UnitResult;
+}
+
+impl<F> ChannelSink for F where F: FnMut(usize, LineRef<'_>) -> UnitResult {
+    fn write_line(&mut self, channel: usize, line: LineRef<'_>) -> UnitResult {
+        self(channel, line)
+    }
+}
+
+/// A list of destinations for the channels of a single layer.
+/// Channels without a sink are skipped.
+/// Only the largest resolution level is decoded. Deep data is not supported.
+pub struct ChannelSinks<'s> {
+    layer_index: usize,
+    parallel: bool,
+    sinks: Vec<(SmallVec<[Text; 4]>, Box<dyn 's + ChannelSink>)>,
+}
+
+impl<'s> ChannelSinks<'s> {
+
+    /// Decode the first layer, without any sinks yet.
+    pub fn new() -> Self {
+        ChannelSinks { layer_index: 0, parallel: true, sinks: Vec::new() }
+    }
+
+    /// Decode the layer with the specified index instead of the first layer.
+    pub fn layer(self, layer_index: usize) -> Self {
+        ChannelSinks { layer_index, .. self }
+    }
+
+    /// Do not decompress multiple blocks on multiple threads at once.
+    /// The sinks are always called on the current thread.
+    pub fn non_parallel(self) -> Self {
+        ChannelSinks { parallel: false, .. self }
+    }
+
+    /// Send the samples of a single channel to the sink.
+    pub fn with_channel(self, channel: impl Into<Text>, sink: impl 's + ChannelSink) -> Self {
+        self.with_channels(Some(channel), sink)
+    }
+
+    /// Send the samples of all the specified channels to the same sink,
+    /// for example to interleave them into a single buffer.
+    /// The sink receives the index of the channel within this list.
+    pub fn with_channels<Name: Into<Text>>(mut self, channels: impl IntoIterator<Item = Name>, sink: impl 's + ChannelSink) -> Self {
+        self.sinks.push((channels.into_iter().map(Into::into).collect(), Box::new(sink)));
+        self
+    }
+
+    /// Read the file and send the samples to the sinks.
+    pub fn decode_file(&mut self, path: impl AsRef<Path>) -> UnitResult {
+        self.decode_buffered(BufReader::new(File::open(path)?))
+    }
+
+    /// Read the byte source and send the samples to the sinks.
+    /// Fails if the layer does not contain one of the channels of a sink.
+    pub fn decode_buffered(&mut self, buffered: impl Read + Seek) -> UnitResult {
+        let reader = crate::block::read(buffered, false)?;
+        let layer_index = self.layer_index;
+
+        let header = reader.headers().get(layer_index).ok_or(Error::invalid("layer index for channel sinks"))?;
+        if header.deep { return Err(Error::unsupported("channel sinks for deep data")); }
+
+        // for each channel in the file, the sink and the index within the channels of the sink
+        let mut routes: Vec<Option<(usize, usize)>> = vec![ None; header.channels.list.len() ];
+        for (sink_index, (names, _)) in self.sinks.iter().enumerate() {
+            for (index_in_sink, name) in names.iter().enumerate() {
+                let channel_index = header.channels.find_index_of_channel(name)
+                    .ok_or(Error::invalid("channel sink for a channel that does not exist"))?;
+
+                routes[channel_index] = Some((sink_index, index_in_sink));
+            }
+        }
+
+        let sinks = &mut self.sinks;
+        let write_block = |meta: &crate::meta::MetaData, block: crate::block::UncompressedBlock| -> UnitResult {
+            for line in block.lines(&meta.headers[layer_index].channels) {
+                if let Some((sink_index, index_in_sink)) = routes[line.location.channel] {
+                    sinks[sink_index].1.write_line(index_in_sink, line)?;
+                }
+            }
+
+            Ok(())
+        };
+
+        let reader = reader.filter_chunks(false, |_, _, block|
+            block.layer == layer_index && block.level == Vec2(0, 0)
+        )?;
+
+        if self.parallel { reader.decompress_parallel(false, write_block) }
+        else { reader.decompress_sequential(false, write_block) }
+    }
+}
+
+impl Default for ChannelSinks<'_> {
+    fn default() -> Self { Self::new() }
+}
+
+impl std::fmt::Debug for ChannelSinks<'_> {
+    fn fmt(&self, formatter: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        formatter.debug_struct("ChannelSinks")
+            .field("layer_index", &self.layer_index)
+            .field("parallel", &self.parallel)
+            .field("channels", &self.sinks.iter().map(|(names, _)| names).collect::<Vec<_>>())
+            .finish()
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashSet;
+    use std::io::Cursor;
+    use crate::prelude::*;
+    use super::*;
+
+    fn file() -> Vec<u8> {
+        let size = Vec2(7, 5);
+        let channel = |name: &str, samples: FlatSamples| AnyChannel::new(name, samples);
+        let positions = (0 .. size.area()).map(|index| Vec2(index % size.width(), index / size.width()));
+
+        let channels = AnyChannels::sort(smallvec::smallvec![
+            channel("R", FlatSamples::F16(positions.clone().map(|position| f16::from_f32(position.x() as f32)).collect())),
+            channel("G", FlatSamples::F16(positions.clone().map(|position| f16::from_f32(position.y() as f32)).collect())),
+            channel("B", FlatSamples::F16(vec![ f16::ZERO; size.area() ])),
+            channel("A", FlatSamples::F16(vec![ f16::ONE; size.area() ])),
+            channel("Z", FlatSamples::F32(positions.clone().map(|position| (position.x() * position.y()) as f32).collect())),
+            channel("id", FlatSamples::U32(positions.map(|position| (position.x() % 3) as u32).collect())),
+        ]);
+
+        let layer = Layer::new(size, LayerAttributes::default(), Encoding::SMALL_LOSSLESS, channels);
+        let mut bytes = Vec::new();
+        Image::from_layer(layer).write().to_buffered(Cursor::new(&mut bytes)).unwrap();
+        bytes
+    }
+
+    #[test]
+    fn decode_into_multiple_destinations(){
+        let width = 7;
+        let mut staging = vec![ f16::ZERO; 7 * 5 * 4 ];
+        let mut depth = vec![ 0.0_f32; 7 * 5 ];
+        let mut ids = HashSet::new();
+
+        ChannelSinks::new()
+            .with_channels([ "R", "G", "B", "A" ], |channel: usize, line: LineRef<'_>| {
+                for (x, sample) in line.read_samples::<f16>().enumerate() {
+                    let pixel = line.location.position.y() * width + line.location.position.x() + x;
+                    staging[pixel * 4 + channel] = sample?;
+                }
+
+                Ok(())
+            })
+            .with_channel("Z", |_, line: LineRef<'_>| {
+                let start = line.location.position.y() * width + line.location.position.x();
+                line.read_samples_into_slice(&mut depth[start .. start + line.location.sample_count])
+            })
+            .with_channel("id", |_, line: LineRef<'_>| {
+                for id in line.read_samples::<u32>() { ids.insert(id?); }
+                Ok(())
+            })
+            .decode_buffered(Cursor::new(file()))
+            .unwrap();
+
+        let pixel = (3 * width + 2) * 4;
+        assert_eq!(&staging[pixel .. pixel + 4], &[ f16::from_f32(2.0), f16::from_f32(3.0), f16::ZERO, f16::ONE ]);
+        assert_eq!(depth[4 * width + 6], 24.0);
+        assert_eq!(ids, [ 0, 1, 2 ].iter().copied().collect());
+    }
+
+    #[test]
+    fn refuse_missing_channels(){
+        let result = ChannelSinks::new()
+            .with_channel("Y", |_, _: LineRef<'_>| Ok(()))
+            .non_parallel()
+            .decode_buffered(Cursor::new(file()));
+
+        assert!(result.is_err());
+    }
+}
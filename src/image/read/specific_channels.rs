@@ -66,7 +66,7 @@ pub trait ReadSpecificChannel: Sized + CheckDuplicates {
             ) -> PixelStorage,
             SetPixel: Fn(&mut PixelStorage, Vec2<usize>, Pixel),
     {
-        CollectPixels { read_channels: self, set_pixel, create_pixels, px: Default::default() }
+        CollectPixels { read_channels: self, set_pixel, create_pixels, requires_any_channel: false, px: Default::default() }
     }
 }
 
@@ -116,9 +116,19 @@ pub struct CollectPixels<ReadChannels, Pixel, PixelStorage, CreatePixels, SetPix
     read_channels: ReadChannels,
     create_pixels: CreatePixels,
     set_pixel: SetPixel,
+    requires_any_channel: bool,
     px: PhantomData<(Pixel, PixelStorage)>,
 }
 
+impl<ReadChannels, Pixel, PixelStorage, CreatePixels, SetPixel> CollectPixels<ReadChannels, Pixel, PixelStorage, CreatePixels, SetPixel> {
+
+    /// Refuse layers that contain none of the specified channels,
+    /// instead of filling all pixels with the default samples of the optional channels.
+    pub(crate) fn requiring_any_channel(self) -> Self {
+        Self { requires_any_channel: true, ..self }
+    }
+}
+
 impl<Inner: CheckDuplicates, Sample> CheckDuplicates for ReadRequiredChannel<Inner, Sample> {
     fn already_contains(&self, name: &Text) -> bool {
         &self.channel_name == name || self.previous_channels.already_contains(name)
@@ -147,7 +157,13 @@ ReadChannels<'s> for CollectPixels<InnerChannels, Pixel, PixelStorage, CreatePix
     >;
 
     fn create_channels_reader(&'s self, header: &Header) -> Result<Self::Reader> {
-        if header.deep { return Err(Error::invalid("`SpecificChannels` does not support deep data yet")) }
+        if header.deep { return Err(Error::unsupported("`SpecificChannels` does not support deep data yet")) }
+
+        // a layer without any of the channels would only contain default samples
+        let contains_any_channel = header.channels.list.iter().any(|channel| self.read_channels.already_contains(&channel.name));
+        if self.requires_any_channel && !contains_any_channel {
+            return Err(Error::invalid("layer does not contain any of your specified channels"));
+        }
 
         let pixel_reader = self.read_channels.create_recursive_reader(&header.channels)?;
         let channel_descriptions = pixel_reader.get_descriptions().into_non_recursive();// TODO not call this twice
@@ -396,6 +412,55 @@ mod test {
             assert!(out_f16_samples_naive.eq(out_f16_samples_batched));
         }
     }
+
+    #[test]
+    fn fill_missing_rgba_channels_with_defaults(){
+        use std::io::Cursor;
+        use crate::prelude::*;
+        use crate::image::pixel_vec::PixelVec;
+
+        let layer = |name: &str, channels: &[&str]| Layer::new(
+            (2, 2), LayerAttributes::named(name), Encoding::UNCOMPRESSED,
+            AnyChannels::sort(channels.iter().map(|&channel| AnyChannel::new(channel, FlatSamples::F32(vec![ 0.5; 4 ]))).collect())
+        );
+
+        let mut bytes = Vec::new();
+        let layers = vec![ layer("depth", &[ "Z" ]), layer("green", &[ "R", "G" ]) ];
+        Image::from_layers(ImageAttributes::new(IntegerBounds::from_dimensions((2, 2))), layers)
+            .write().to_buffered(Cursor::new(&mut bytes)).unwrap();
+
+        let read_rgba = || read().no_deep_data().largest_resolution_level()
+            .rgba_channels_with_defaults((0.0_f32, 0.0_f32, 0.25_f32, 1.0_f32), PixelVec::constructor, PixelVec::set_pixel);
+
+        let image = read_rgba().first_valid_layer().all_attributes().from_buffered(Cursor::new(bytes.clone())).unwrap();
+        let layer = image.layer_data;
+
+        // the layer without any rgba channel is skipped, and the missing channels are reported as `None`
+        assert_eq!(layer.attributes.layer_name, Some(Text::from("green")));
+        assert!(layer.channel_data.channels.0.is_some() && layer.channel_data.channels.1.is_some());
+        assert!(layer.channel_data.channels.2.is_none() && layer.channel_data.channels.3.is_none());
+        assert_eq!(layer.channel_data.pixels.pixels, vec![ (0.5, 0.5, 0.25, 1.0); 4 ]);
+
+        assert!(read_rgba().all_layers().all_attributes().from_buffered(Cursor::new(bytes)).is_err());
+    }
+
+    #[test]
+    fn optional_channels_accept_layers_without_any_of_them(){
+        use std::io::Cursor;
+        use crate::prelude::*;
+        use crate::image::pixel_vec::PixelVec;
+
+        let mut bytes = Vec::new();
+        let channels = AnyChannels::sort(smallvec::smallvec![ AnyChannel::new("Z", FlatSamples::F32(vec![ 0.5; 4 ])) ]);
+        Image::from_channels((2, 2), channels).write().to_buffered(Cursor::new(&mut bytes)).unwrap();
+
+        let image = read().no_deep_data().largest_resolution_level()
+            .specific_channels().optional("A", 1.0_f32).collect_pixels(PixelVec::constructor, PixelVec::set_pixel)
+            .first_valid_layer().all_attributes().from_buffered(Cursor::new(bytes)).unwrap();
+
+        assert!(image.layer_data.channel_data.channels.0.is_none());
+        assert_eq!(image.layer_data.channel_data.pixels.pixels, vec![ (1.0_f32,); 4 ]);
+    }
 }
 
 
@@ -0,0 +1,464 @@
+//! Store samples sparsely, keeping only a single value for blocks where all samples are equal.
+//! Mattes, masks, and deep shadow style images are often mostly empty,
+//! and take a fraction of the memory when stored this way.
+
+use crate::image::FlatSamples;
+use crate::image::read::any_channels::{ReadSamples, SamplesReader, ReadAnyChannels};
+use crate::image::read::levels::ReadLargestLevel;
+use crate::image::read::samples::ReadFlatSamples;
+use crate::image::write::samples::{WritableSamples, SamplesWriter};
+use crate::block::samples::Sample;
+use crate::block::lines::{LineRef, LineRefMut};
+use crate::block::chunk::TileCoordinates;
+use crate::meta::attribute::{ChannelDescription, SampleType, LevelMode};
+use crate::meta::header::Header;
+use crate::math::{Vec2, RoundingMode};
+use crate::error::{Result, UnitResult};
+use half::f16;
+
+
+/// A grid of samples that is divided into rectangular blocks.
+/// Blocks where all samples are equal only store that single value.
+/// All other blocks store each of their samples.
+/// Blocks at the right and bottom edge may be smaller than the block size.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SparseSamples {
+    resolution: Vec2<usize>,
+    block_size: Vec2<usize>,
+    sample_type: SampleType,
+
+    /// The blocks, row by row.
+    blocks: Vec<SparseBlock>,
+}
+
+/// The samples of a single block in `SparseSamples`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum SparseBlock {
+
+    /// All samples in this block have this value.
+    Constant(Sample),
+
+    /// The samples of this block, row by row, with the width of this block.
+    Dense(FlatSamples),
+}
+
+impl SparseSamples {
+
+    /// Create a grid where all samples have the same value.
+    /// The sample type of the grid is the type of the value.
+    /// Panics if the block size is zero.
+    pub fn new_constant(resolution: impl Into<Vec2<usize>>, block_size: impl Into<Vec2<usize>>, value: Sample) -> Self {
+        let (resolution, block_size) = (resolution.into(), block_size.into());
+        assert!(block_size.area() > 0, "sparse block size must not be zero");
+
+        let block_count = block_count(resolution, block_size);
+        SparseSamples {
+            resolution, block_size,
+            sample_type: sample_type_of(value),
+            blocks: vec![ SparseBlock::Constant(value); block_count.area() ],
+        }
+    }
+
+    /// Divide a grid of samples into blocks, and only keep a single value for each block where all samples are equal.
+    /// Panics if the number of samples does not match the resolution or if the block size is zero.
+    pub fn from_flat(samples: &FlatSamples, resolution: impl Into<Vec2<usize>>, block_size: impl Into<Vec2<usize>>) -> Self {
+        let (resolution, block_size) = (resolution.into(), block_size.into());
+        assert_eq!(samples.len(), resolution.area(), "sample count does not match resolution");
+
+        let mut sparse = Self::new_constant(resolution, block_size, zero_sample(flat_sample_type(samples)));
+
+        for block_index in 0 .. sparse.blocks.len() {
+            let (position, size) = sparse.block_bounds(block_index);
+            let mut block = filled_flat_samples(sparse.sample_type, size.area(), zero_sample(sparse.sample_type));
+
+            for y in 0 .. size.height() {
+                for x in 0 .. size.width() {
+                    let source = (position.y() + y) * resolution.width() + position.x() + x;
+                    set_flat_sample(&mut block, y * size.width() + x, samples.value_by_flat_index(source));
+                }
+            }
+
+            sparse.blocks[block_index] = compact_block(SparseBlock::Dense(block));
+        }
+
+        sparse
+    }
+
+    /// Materialize all samples into a single contiguous grid.
+    pub fn to_flat(&self) -> FlatSamples {
+        let mut flat = filled_flat_samples(self.sample_type, self.resolution.area(), zero_sample(self.sample_type));
+        for (index, value) in self.values().enumerate() {
+            set_flat_sample(&mut flat, index, value);
+        }
+
+        flat
+    }
+
+    /// The width and height of the grid.
+    pub fn resolution(&self) -> Vec2<usize> { self.resolution }
+
+    /// The width and height of each block, except for blocks at the right and bottom edge.
+    pub fn block_size(&self) -> Vec2<usize> { self.block_size }
+
+    /// The type of all samples in this grid.
+    pub fn sample_type(&self) -> SampleType { self.sample_type }
+
+    /// The number of samples in the grid. This is the width times the height.
+    pub fn len(&self) -> usize { self.resolution.area() }
+
+    /// The blocks of this grid, row by row.
+    pub fn blocks(&self) -> &[SparseBlock] { &self.blocks }
+
+    /// The number of blocks that store each of their samples.
+    pub fn dense_block_count(&self) -> usize {
+        self.blocks.iter().filter(|block| matches!(block, SparseBlock::Dense(_))).count()
+    }
+
+    /// The position and size of the block with the specified index, in pixels.
+    pub fn block_bounds(&self, block_index: usize) -> (Vec2<usize>, Vec2<usize>) {
+        let block_count = block_count(self.resolution, self.block_size);
+        let block = Vec2(block_index % block_count.width(), block_index / block_count.width());
+        let position = block * self.block_size;
+        let size = Vec2(
+            self.block_size.width().min(self.resolution.width() - position.x()),
+            self.block_size.height().min(self.resolution.height() - position.y()),
+        );
+
+        (position, size)
+    }
+
+    /// Lookup a single value, by pixel position.
+    pub fn value_by_position(&self, position: Vec2<usize>) -> Sample {
+        debug_assert!(position.x() < self.resolution.width() && position.y() < self.resolution.height(), "sample position out of bounds");
+
+        let (block_index, index_in_block) = self.locate(position);
+        match &self.blocks[block_index] {
+            SparseBlock::Constant(value) => *value,
+            SparseBlock::Dense(samples) => samples.value_by_flat_index(index_in_block),
+        }
+    }
+
+    /// Lookup a single value, by flat index.
+    /// The flat index can be obtained using `Vec2::flatten_for_width`
+    /// which computes the index in a flattened array of pixel rows.
+    pub fn value_by_flat_index(&self, index: usize) -> Sample {
+        self.value_by_position(Vec2(index % self.resolution.width(), index / self.resolution.width()))
+    }
+
+    /// All samples in this grid as iterator, row by row.
+    /// Looks up the block for every sample, does not allocate.
+    pub fn values(&self) -> impl '_ + Iterator<Item = Sample> {
+        (0 .. self.len()).map(move |index| self.value_by_flat_index(index))
+    }
+
+    /// Change a single value, by pixel position. The value is converted to the sample type of this grid.
+    /// Stores each sample of the block if the block previously had a different constant value.
+    pub fn set_value(&mut self, position: Vec2<usize>, value: Sample) {
+        debug_assert!(position.x() < self.resolution.width() && position.y() < self.resolution.height(), "sample position out of bounds");
+
+        let value = convert_sample(value, self.sample_type);
+        let (block_index, index_in_block) = self.locate(position);
+        let block_area = self.block_bounds(block_index).1.area();
+        let sample_type = self.sample_type;
+
+        let block = &mut self.blocks[block_index];
+        if let SparseBlock::Constant(constant) = *block {
+            if same_bits(constant, value) { return; }
+            *block = SparseBlock::Dense(filled_flat_samples(sample_type, block_area, constant));
+        }
+
+        if let SparseBlock::Dense(samples) = block {
+            set_flat_sample(samples, index_in_block, value);
+        }
+    }
+
+    /// Replace each block that stores equal samples by a single value.
+    /// Use this after changing many values with `set_value`.
+    pub fn compact(&mut self) {
+        for block in &mut self.blocks {
+            *block = compact_block(std::mem::replace(block, SparseBlock::Constant(Sample::default())));
+        }
+    }
+
+    fn locate(&self, position: Vec2<usize>) -> (usize, usize) {
+        let block_count = block_count(self.resolution, self.block_size);
+        let block = position / self.block_size;
+        let block_index = block.flat_index_for_size(block_count);
+
+        let (block_position, block_size) = self.block_bounds(block_index);
+        let index_in_block = (position - block_position).flat_index_for_size(block_size);
+        (block_index, index_in_block)
+    }
+}
+
+
+fn block_count(resolution: Vec2<usize>, block_size: Vec2<usize>) -> Vec2<usize> {
+    Vec2(
+        RoundingMode::Up.divide(resolution.width(), block_size.width()),
+        RoundingMode::Up.divide(resolution.height(), block_size.height()),
+    )
+}
+
+fn compact_block(block: SparseBlock) -> SparseBlock {
+    match block {
+        SparseBlock::Dense(samples) => {
+            let first = if samples.len() == 0 { None } else { Some(samples.value_by_flat_index(0)) };
+
+            match first {
+                Some(first) if samples.values().all(|value| same_bits(value, first)) => SparseBlock::Constant(first),
+                _ => SparseBlock::Dense(samples),
+            }
+        },
+
+        constant => constant,
+    }
+}
+
+/// Compares the bits instead of the numbers, such that `NaN` values can be stored as constants.
+fn same_bits(a: Sample, b: Sample) -> bool {
+    match (a, b) {
+        (Sample::F16(a), Sample::F16(b)) => a.to_bits() == b.to_bits(),
+        (Sample::F32(a), Sample::F32(b)) => a.to_bits() == b.to_bits(),
+        (Sample::U32(a), Sample::U32(b)) => a == b,
+        _ => false,
+    }
+}
+
+fn sample_type_of(sample: Sample) -> SampleType {
+    match sample {
+        Sample::F16(_) => SampleType::F16,
+        Sample::F32(_) => SampleType::F32,
+        Sample::U32(_) => SampleType::U32,
+    }
+}
+
+fn flat_sample_type(samples: &FlatSamples) -> SampleType {
+    match samples {
+        FlatSamples::F16(_) => SampleType::F16,
+        FlatSamples::F32(_) => SampleType::F32,
+        FlatSamples::U32(_) => SampleType::U32,
+    }
+}
+
+fn zero_sample(sample_type: SampleType) -> Sample {
+    match sample_type {
+        SampleType::F16 => Sample::F16(f16::ZERO),
+        SampleType::F32 => Sample::F32(0.0),
+        SampleType::U32 => Sample::U32(0),
+    }
+}
+
+fn convert_sample(sample: Sample, sample_type: SampleType) -> Sample {
+    match sample_type {
+        SampleType::F16 => Sample::F16(sample.to_f16()),
+        SampleType::F32 => Sample::F32(sample.to_f32()),
+        SampleType::U32 => Sample::U32(sample.to_u32()),
+    }
+}
+
+fn filled_flat_samples(sample_type: SampleType, count: usize, value: Sample) -> FlatSamples {
+    match sample_type {
+        SampleType::F16 => FlatSamples::F16(vec![ value.to_f16(); count ]),
+        SampleType::F32 => FlatSamples::F32(vec![ value.to_f32(); count ]),
+        SampleType::U32 => FlatSamples::U32(vec![ value.to_u32(); count ]),
+    }
+}
+
+fn set_flat_sample(samples: &mut FlatSamples, index: usize, value: Sample) {
+    match samples {
+        FlatSamples::F16(samples) => samples[index] = value.to_f16(),
+        FlatSamples::F32(samples) => samples[index] = value.to_f32(),
+        FlatSamples::U32(samples) => samples[index] = value.to_u32(),
+    }
+}
+
+
+impl<'samples> WritableSamples<'samples> for SparseSamples {
+    fn sample_type(&self) -> SampleType { self.sample_type }
+
+    fn infer_level_modes(&self) -> (LevelMode, RoundingMode) { (LevelMode::Singular, RoundingMode::Down) }
+
+    type Writer = SparseSamplesWriter<'samples>;
+    fn create_samples_writer(&'samples self, header: &Header) -> Self::Writer {
+        debug_assert_eq!(header.layer_size, self.resolution, "sparse samples resolution does not match layer size");
+        SparseSamplesWriter { samples: self }
+    }
+}
+
+/// A temporary writer for sparse samples.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct SparseSamplesWriter<'samples> {
+    samples: &'samples SparseSamples,
+}
+
+impl<'samples> SamplesWriter for SparseSamplesWriter<'samples> {
+    fn extract_line(&self, line: LineRefMut<'_>) {
+        let position = line.location.position;
+        let sample = |index: usize| self.samples.value_by_position(Vec2(position.x() + index, position.y()));
+
+        match self.samples.sample_type {
+            SampleType::F16 => line.write_samples(|index| sample(index).to_f16()),
+            SampleType::F32 => line.write_samples(|index| sample(index).to_f32()),
+            SampleType::U32 => line.write_samples(|index| sample(index).to_u32()),
+        }.expect("writing line bytes failed");
+    }
+}
+
+
+impl ReadLargestLevel<ReadFlatSamples> {
+
+    /// Read all arbitrary channels in each layer, storing the samples as `SparseSamples` with the specified block size.
+    /// Blocks that only contain a single value never allocate memory for each sample, not even temporarily.
+    pub fn all_sparse_channels(self, block_size: impl Into<Vec2<usize>>) -> ReadAnyChannels<ReadSparseSamples> {
+        let block_size = block_size.into();
+        assert!(block_size.area() > 0, "sparse block size must not be zero");
+        ReadAnyChannels { read_samples: ReadSparseSamples { block_size } }
+    }
+}
+
+/// Specify to read only flat samples of the largest resolution level, storing them as `SparseSamples`.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct ReadSparseSamples {
+    block_size: Vec2<usize>,
+}
+
+/// Processes pixel blocks from a file and accumulates them into sparse samples.
+/// A block stays constant until a different value is read into it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SparseSamplesReader {
+    /// `None` for blocks that have not received any sample yet
+    blocks: Vec<Option<SparseBlock>>,
+    samples: SparseSamples,
+}
+
+impl ReadSamples for ReadSparseSamples {
+    type Reader = SparseSamplesReader;
+
+    fn create_sample_reader(&self, header: &Header, channel: &ChannelDescription) -> Result<Self::Reader> {
+        let samples = SparseSamples::new_constant(header.layer_size, self.block_size, zero_sample(channel.sample_type));
+        Ok(SparseSamplesReader { blocks: vec![ None; samples.blocks.len() ], samples })
+    }
+}
+
+impl SamplesReader for SparseSamplesReader {
+    type Samples = SparseSamples;
+
+    fn filter_block(&self, tile: TileCoordinates) -> bool {
+        tile.level_index == Vec2(0, 0)
+    }
+
+    fn read_line(&mut self, line: LineRef<'_>) -> UnitResult {
+        let location = line.location;
+        let sample_type = self.samples.sample_type;
+
+        let values = read_samples_as(&line, sample_type);
+        for (index, value) in values.enumerate() {
+            let (block_index, index_in_block) = self.samples.locate(Vec2(location.position.x() + index, location.position.y()));
+            let value = value?;
+
+            let block = &mut self.blocks[block_index];
+            match block {
+                None => *block = Some(SparseBlock::Constant(value)),
+
+                Some(SparseBlock::Constant(constant)) => if !same_bits(*constant, value) {
+                    // all previously read samples of this block have the constant value
+                    let block_area = self.samples.block_bounds(block_index).1.area();
+                    let mut dense = filled_flat_samples(sample_type, block_area, *constant);
+                    set_flat_sample(&mut dense, index_in_block, value);
+                    *block = Some(SparseBlock::Dense(dense));
+                },
+
+                Some(SparseBlock::Dense(samples)) => set_flat_sample(samples, index_in_block, value),
+            }
+        }
+
+        Ok(())
+    }
+
+    fn into_samples(self) -> SparseSamples {
+        let Self { blocks, mut samples } = self;
+
+        for (target, block) in samples.blocks.iter_mut().zip(blocks) {
+            if let Some(block) = block { *target = block; }
+        }
+
+        samples
+    }
+}
+
+fn read_samples_as<'l>(line: &'l LineRef<'_>, sample_type: SampleType) -> Box<dyn 'l + Iterator<Item = Result<Sample>>> {
+    match sample_type {
+        SampleType::F16 => Box::new(line.read_samples::<f16>().map(|sample| sample.map(Sample::F16))),
+        SampleType::F32 => Box::new(line.read_samples::<f32>().map(|sample| sample.map(Sample::F32))),
+        SampleType::U32 => Box::new(line.read_samples::<u32>().map(|sample| sample.map(Sample::U32))),
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use crate::prelude::*;
+    use super::*;
+    use std::io::Cursor;
+
+    fn matte(size: Vec2<usize>) -> FlatSamples {
+        // a small opaque square in an otherwise empty image
+        FlatSamples::F16((0 .. size.area())
+            .map(|index| Vec2(index % size.width(), index / size.width()))
+            .map(|Vec2(x, y)| if (60 .. 90).contains(&x) && (10 .. 20).contains(&y) { f16::ONE } else { f16::ZERO })
+            .collect())
+    }
+
+    #[test]
+    fn flat_roundtrip_keeps_samples(){
+        let size = Vec2(200, 100);
+        let flat = matte(size);
+        let sparse = SparseSamples::from_flat(&flat, size, (32, 32));
+
+        assert_eq!(sparse.blocks().len(), 7 * 4);
+        assert_eq!(sparse.dense_block_count(), 2);
+        assert_eq!(sparse.to_flat(), flat);
+        assert_eq!(sparse.value_by_position(Vec2(75, 15)).to_f32(), 1.0);
+        assert_eq!(sparse.value_by_position(Vec2(199, 99)).to_f32(), 0.0);
+    }
+
+    #[test]
+    fn set_value_densifies_and_compacts(){
+        let mut sparse = SparseSamples::new_constant((10, 10), (4, 4), Sample::F32(0.0));
+        assert_eq!(sparse.dense_block_count(), 0);
+
+        sparse.set_value(Vec2(9, 9), Sample::F32(2.0));
+        assert_eq!(sparse.dense_block_count(), 1);
+        assert_eq!(sparse.value_by_position(Vec2(9, 9)).to_f32(), 2.0);
+        assert_eq!(sparse.value_by_position(Vec2(8, 9)).to_f32(), 0.0);
+
+        sparse.set_value(Vec2(9, 9), Sample::F32(0.0));
+        sparse.compact();
+        assert_eq!(sparse.dense_block_count(), 0);
+    }
+
+    #[test]
+    fn write_and_read_sparse_channels(){
+        let size = Vec2(200, 100);
+        let channels = AnyChannels::sort(smallvec::smallvec![
+            AnyChannel::new("A", SparseSamples::from_flat(&matte(size), size, (32, 32))),
+        ]);
+
+        let image = Image::from_encoded_channels(size, Encoding::SMALL_LOSSLESS, channels);
+
+        let mut bytes = Vec::new();
+        image.write().to_buffered(Cursor::new(&mut bytes)).unwrap();
+
+        let flat = read().no_deep_data().largest_resolution_level().all_channels().first_valid_layer().all_attributes()
+            .from_buffered(Cursor::new(&bytes)).unwrap();
+
+        assert_eq!(flat.layer_data.channel_data.list[0].sample_data, matte(size));
+
+        let sparse = read().no_deep_data().largest_resolution_level().all_sparse_channels((32, 32)).first_valid_layer().all_attributes()
+            .from_buffered(Cursor::new(&bytes)).unwrap();
+
+        let samples = &sparse.layer_data.channel_data.list[0].sample_data;
+        assert_eq!(samples.dense_block_count(), 2);
+        assert_eq!(samples, &image.layer_data.channel_data.list[0].sample_data);
+    }
+}
@@ -0,0 +1,178 @@
+
+//! Wrap display-referred integer images, such as 8-bit sRGB textures, into exr channels.
+//! Exr files contain linear light values, so the sRGB transfer function
+//! must be removed from the color channels before storing them.
+//! Alpha and other non-color channels are only normalized to the range `[0, 1]`.
+
+use crate::image::{FlatSamples, AnyChannels, AnyChannel};
+use crate::meta::attribute::Text;
+use crate::math::Vec2;
+use half::f16;
+
+
+/// Remove the sRGB transfer function from a display-referred value in the range `[0, 1]`.
+/// Returns the linear light value.
+#[inline]
+pub fn srgb_to_linear(encoded: f32) -> f32 {
+    if encoded <= 0.04045 { encoded / 12.92 }
+    else { ((encoded + 0.055) / 1.055).powf(2.4) }
+}
+
+/// Apply the sRGB transfer function to a linear value in the range `[0, 1]`.
+/// This is the inverse of `srgb_to_linear`.
+#[inline]
+pub fn linear_to_srgb(linear: f32) -> f32 {
+    if linear <= 0.003_130_8 { linear * 12.92 }
+    else { 1.055 * linear.powf(1.0 / 2.4) - 0.055 }
+}
+
+/// Whether a channel with this name contains display-referred color,
+/// which should be linearized when importing sRGB data.
+/// Returns true for "R", "G", "B", "Y" and "L", ignoring case.
+pub fn is_srgb_encoded_channel(name: &Text) -> bool {
+    name.eq_case_insensitive("R") || name.eq_case_insensitive("G") ||
+        name.eq_case_insensitive("B") || name.eq_case_insensitive("Y") ||
+        name.eq_case_insensitive("L")
+}
+
+
+impl FlatSamples {
+
+    /// Linearize 8-bit sRGB samples. Produces `f16` samples,
+    /// which are precise enough to distinguish all 256 input values.
+    pub fn from_srgb_u8(samples: &[u8]) -> Self {
+        let lookup = srgb_u8_lookup_table();
+        FlatSamples::F16(samples.iter().map(|&sample| lookup[usize::from(sample)]).collect())
+    }
+
+    /// Linearize 16-bit sRGB samples. Produces `f32` samples.
+    pub fn from_srgb_u16(samples: &[u16]) -> Self {
+        FlatSamples::F32(samples.iter().map(|&sample| srgb_to_linear(unorm_u16(sample))).collect())
+    }
+
+    /// Normalize 8-bit samples that are already linear, for example alpha.
+    /// Produces `f16` samples.
+    pub fn from_unorm_u8(samples: &[u8]) -> Self {
+        FlatSamples::F16(samples.iter().map(|&sample| f16::from_f32(unorm_u8(sample))).collect())
+    }
+
+    /// Normalize 16-bit samples that are already linear, for example alpha.
+    /// Produces `f32` samples.
+    pub fn from_unorm_u16(samples: &[u16]) -> Self {
+        FlatSamples::F32(samples.iter().map(|&sample| unorm_u16(sample)).collect())
+    }
+}
+
+impl AnyChannels<FlatSamples> {
+
+    /// Import an interleaved 8-bit sRGB image, such as an `RGBA8` texture.
+    /// The channel names specify the order of the interleaved samples, for example `&["R", "G", "B", "A"]`.
+    /// Channels named "R", "G", "B", "Y", or "L" are linearized, all other channels are only normalized.
+    /// The resulting list of channels is sorted by name.
+    /// Panics if the number of samples does not match the channel count and resolution.
+    pub fn from_interleaved_srgb_u8(resolution: impl Into<Vec2<usize>>, channel_names: &[&str], samples: &[u8]) -> Self {
+        from_interleaved(resolution.into(), channel_names, samples, |is_color, channel_samples: Vec<u8>| {
+            if is_color { FlatSamples::from_srgb_u8(&channel_samples) }
+            else { FlatSamples::from_unorm_u8(&channel_samples) }
+        })
+    }
+
+    /// Import an interleaved 16-bit sRGB image, such as an `RGBA16` texture.
+    /// The channel names specify the order of the interleaved samples, for example `&["R", "G", "B", "A"]`.
+    /// Channels named "R", "G", "B", "Y", or "L" are linearized, all other channels are only normalized.
+    /// The resulting list of channels is sorted by name.
+    /// Panics if the number of samples does not match the channel count and resolution.
+    pub fn from_interleaved_srgb_u16(resolution: impl Into<Vec2<usize>>, channel_names: &[&str], samples: &[u16]) -> Self {
+        from_interleaved(resolution.into(), channel_names, samples, |is_color, channel_samples: Vec<u16>| {
+            if is_color { FlatSamples::from_srgb_u16(&channel_samples) }
+            else { FlatSamples::from_unorm_u16(&channel_samples) }
+        })
+    }
+}
+
+fn from_interleaved<T: Copy>(
+    resolution: Vec2<usize>, channel_names: &[&str], samples: &[T],
+    convert: impl Fn(bool, Vec<T>) -> FlatSamples
+) -> AnyChannels<FlatSamples>
+{
+    let channel_count = channel_names.len();
+    assert_ne!(channel_count, 0, "at least one channel name is required");
+
+    assert_eq!(
+        resolution.area() * channel_count, samples.len(),
+        "expected {} interleaved samples, but slice length is {}",
+        resolution.area() * channel_count, samples.len()
+    );
+
+    let channels = channel_names.iter().enumerate().map(|(channel_index, &name)| {
+        let name = Text::from(name);
+
+        let channel_samples = samples.iter().skip(channel_index)
+            .step_by(channel_count).copied().collect();
+
+        let sample_data = convert(is_srgb_encoded_channel(&name), channel_samples);
+        AnyChannel::new(name, sample_data)
+    });
+
+    AnyChannels::sort(channels.collect())
+}
+
+fn srgb_u8_lookup_table() -> [f16; 256] {
+    let mut table = [f16::ZERO; 256];
+
+    for (value, entry) in table.iter_mut().enumerate() {
+        *entry = f16::from_f32(srgb_to_linear(value as f32 / 255.0));
+    }
+
+    table
+}
+
+#[inline] fn unorm_u8(value: u8) -> f32 { f32::from(value) / f32::from(u8::MAX) }
+#[inline] fn unorm_u16(value: u16) -> f32 { f32::from(value) / f32::from(u16::MAX) }
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn transfer_function_roundtrip(){
+        for step in 0 ..= 100 {
+            let value = step as f32 / 100.0;
+            assert!((linear_to_srgb(srgb_to_linear(value)) - value).abs() < 0.0001, "value {}", value);
+        }
+
+        assert_eq!(srgb_to_linear(0.0), 0.0);
+        assert!((srgb_to_linear(1.0) - 1.0).abs() < 0.000_001);
+        assert!((srgb_to_linear(0.5) - 0.214).abs() < 0.001);
+    }
+
+    #[test]
+    fn srgb_u8_values_stay_distinct(){
+        let table = srgb_u8_lookup_table();
+        assert!(table.windows(2).all(|pair| pair[0] < pair[1]));
+        assert_eq!(table[255], f16::ONE);
+    }
+
+    #[test]
+    fn interleaved_rgba_keeps_alpha_linear(){
+        let pixels = [ 128_u8, 0, 255, 128,   255, 255, 255, 255 ];
+        let channels = AnyChannels::from_interleaved_srgb_u8((2, 1), &["R", "G", "B", "A"], &pixels);
+
+        let names: Vec<String> = channels.list.iter().map(|channel| channel.name.to_string()).collect();
+        assert_eq!(names, vec!["A", "B", "G", "R"]);
+
+        let alpha: Vec<f32> = channels.list[0].sample_data.values_as_f32().collect();
+        let red: Vec<f32> = channels.list[3].sample_data.values_as_f32().collect();
+
+        assert!((alpha[0] - 128.0 / 255.0).abs() < 0.001);
+        assert!((red[0] - 0.2158).abs() < 0.001);
+        assert_eq!(red[1], 1.0);
+    }
+
+    #[test]
+    #[should_panic]
+    fn interleaved_checks_sample_count(){
+        AnyChannels::from_interleaved_srgb_u16((2, 2), &["R", "G", "B"], &[0; 11]);
+    }
+}
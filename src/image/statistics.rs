@@ -0,0 +1,390 @@
+//! Accumulate statistics of each channel across a sequence of frames, for example to create exposure reports.
+//! Frames are added one after another, so only the statistics need to be kept in memory, not the frames.
+//! Statistics of multiple threads can be merged afterwards.
+
+use crate::image::{FlatSamples, FlatImage, Layer, AnyChannels};
+use crate::meta::attribute::Text;
+use crate::error::UnitResult;
+use std::path::Path;
+
+
+/// How the values of a channel are distributed into histogram bins.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct HistogramOptions {
+
+    /// The number of bins between the smallest and the largest value.
+    pub bin_count: usize,
+
+    /// The smallest value of the first bin, on the histogram scale.
+    pub min: f32,
+
+    /// The largest value of the last bin, on the histogram scale.
+    pub max: f32,
+
+    /// Whether the bins are spaced linearly or in photographic stops.
+    pub scale: HistogramScale,
+}
+
+/// How the bins of a histogram are spaced.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub enum HistogramScale {
+
+    /// Each bin covers the same range of values.
+    Linear,
+
+    /// Each bin covers the same range of `log2(value)`, such that each stop of exposure has the same number of bins.
+    /// Zero and negative values are counted as below the histogram range.
+    Stops,
+}
+
+impl Default for HistogramOptions {
+
+    /// 256 bins, covering 16 stops below and above `1.0`.
+    fn default() -> Self {
+        HistogramOptions { bin_count: 256, min: -16.0, max: 16.0, scale: HistogramScale::Stops }
+    }
+}
+
+/// Counts how many values fall into each bin.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Histogram {
+
+    /// How the values are distributed into the bins.
+    pub options: HistogramOptions,
+
+    /// The number of values in each bin, from the smallest to the largest values.
+    pub bins: Vec<u64>,
+
+    /// The number of values that are smaller than the first bin.
+    pub below: u64,
+
+    /// The number of values that are larger than the last bin.
+    pub above: u64,
+}
+
+impl Histogram {
+
+    /// Create a histogram without any values.
+    /// Panics if the bin count is zero or the range is empty.
+    pub fn new(options: HistogramOptions) -> Self {
+        assert!(options.bin_count > 0, "histogram needs at least one bin");
+        assert!(options.min < options.max, "histogram range must not be empty");
+        Histogram { options, bins: vec![0; options.bin_count], below: 0, above: 0 }
+    }
+
+    /// Count a single value. Ignores `NaN`.
+    pub fn add(&mut self, value: f32) {
+        let value = match self.options.scale {
+            HistogramScale::Linear => value,
+            HistogramScale::Stops if value <= 0.0 => f32::NEG_INFINITY,
+            HistogramScale::Stops => value.log2(),
+        };
+
+        if value.is_nan() { return; }
+
+        let HistogramOptions { min, max, bin_count, .. } = self.options;
+        if value < min { self.below += 1; }
+        else if value > max { self.above += 1; }
+        else {
+            let bin = ((value - min) / (max - min) * bin_count as f32) as usize;
+            self.bins[bin.min(bin_count - 1)] += 1;
+        }
+    }
+
+    /// The range of values counted by the bin, not on the histogram scale but as channel values.
+    pub fn bin_range(&self, bin_index: usize) -> std::ops::Range<f32> {
+        let HistogramOptions { min, max, bin_count, scale } = self.options;
+        let step = (max - min) / bin_count as f32;
+        let (start, end) = (min + step * bin_index as f32, min + step * (bin_index + 1) as f32);
+
+        match scale {
+            HistogramScale::Linear => start .. end,
+            HistogramScale::Stops => start.exp2() .. end.exp2(),
+        }
+    }
+
+    /// The total number of counted values, including values outside the histogram range.
+    pub fn count(&self) -> u64 {
+        self.below + self.above + self.bins.iter().sum::<u64>()
+    }
+
+    /// Approximate the value below which the specified fraction of all values lie, for example `0.99`.
+    /// Returns the upper end of the bin containing the percentile.
+    /// Returns `None` if the percentile lies outside of the histogram range.
+    pub fn percentile(&self, fraction: f64) -> Option<f32> {
+        let target = ((fraction.max(0.0).min(1.0) * self.count() as f64).ceil() as u64).max(1);
+        let mut count = self.below;
+        if count >= target { return None; }
+
+        for (bin_index, bin) in self.bins.iter().enumerate() {
+            count += bin;
+            if count >= target { return Some(self.bin_range(bin_index).end); }
+        }
+
+        None
+    }
+
+    /// Add the counts of another histogram with the same options to this histogram.
+    /// Panics if the options differ.
+    pub fn merge(&mut self, other: &Self) {
+        assert_eq!(self.options, other.options, "cannot merge histograms with different options");
+
+        self.below += other.below;
+        self.above += other.above;
+        for (bin, other) in self.bins.iter_mut().zip(&other.bins) { *bin += other; }
+    }
+}
+
+
+/// The statistics of one channel, accumulated over all frames that contain this channel.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChannelStatistics {
+
+    /// The channel name, prefixed with the layer name if the layer has a name.
+    pub name: Text,
+
+    /// The number of samples in all frames, including `NaN` and infinite values.
+    pub sample_count: u64,
+
+    /// The number of `NaN` samples.
+    pub nan_count: u64,
+
+    /// The number of positive or negative infinite samples.
+    pub infinite_count: u64,
+
+    /// The smallest finite sample. `None` if there are no finite samples.
+    pub min: Option<f32>,
+
+    /// The largest finite sample. `None` if there are no finite samples.
+    pub max: Option<f32>,
+
+    /// The sum of all finite samples.
+    pub sum: f64,
+
+    /// The distribution of all samples.
+    pub histogram: Histogram,
+
+    /// The mean of the finite samples of each frame, together with the index of the frame.
+    /// Use this to find exposure jumps within a sequence.
+    pub frame_means: Vec<(usize, f64)>,
+}
+
+impl ChannelStatistics {
+
+    /// Create statistics without any samples.
+    pub fn new(name: Text, histogram: HistogramOptions) -> Self {
+        ChannelStatistics {
+            name, sample_count: 0, nan_count: 0, infinite_count: 0,
+            min: None, max: None, sum: 0.0,
+            histogram: Histogram::new(histogram),
+            frame_means: Vec::new(),
+        }
+    }
+
+    /// The mean of all finite samples. `None` if there are no finite samples.
+    pub fn mean(&self) -> Option<f64> {
+        let finite_count = self.finite_count();
+        if finite_count == 0 { None } else { Some(self.sum / finite_count as f64) }
+    }
+
+    /// The number of samples that are neither `NaN` nor infinite.
+    pub fn finite_count(&self) -> u64 {
+        self.sample_count - self.nan_count - self.infinite_count
+    }
+
+    /// Add the samples of the channel in a single frame.
+    pub fn add_frame(&mut self, frame_index: usize, samples: impl Iterator<Item = f32>) {
+        let (mut frame_sum, mut frame_finite_count) = (0.0_f64, 0_u64);
+
+        for sample in samples {
+            self.sample_count += 1;
+            self.histogram.add(sample);
+
+            if sample.is_nan() { self.nan_count += 1; }
+            else if sample.is_infinite() { self.infinite_count += 1; }
+            else {
+                self.min = Some(self.min.map_or(sample, |min| min.min(sample)));
+                self.max = Some(self.max.map_or(sample, |max| max.max(sample)));
+                frame_sum += sample as f64;
+                frame_finite_count += 1;
+            }
+        }
+
+        self.sum += frame_sum;
+        if frame_finite_count != 0 {
+            self.frame_means.push((frame_index, frame_sum / frame_finite_count as f64));
+        }
+    }
+
+    /// Add the statistics of another set of frames of the same channel.
+    /// The frame indices of the other statistics are offset by the specified frame count.
+    pub fn merge(&mut self, other: &Self, frame_offset: usize) {
+        self.sample_count += other.sample_count;
+        self.nan_count += other.nan_count;
+        self.infinite_count += other.infinite_count;
+        self.sum += other.sum;
+
+        self.min = [self.min, other.min].iter().flatten().copied().reduce(f32::min);
+        self.max = [self.max, other.max].iter().flatten().copied().reduce(f32::max);
+
+        self.histogram.merge(&other.histogram);
+        self.frame_means.extend(other.frame_means.iter().map(|&(frame, mean)| (frame + frame_offset, mean)));
+    }
+}
+
+
+/// Statistics of all channels, accumulated over a sequence of frames.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SequenceStatistics {
+
+    /// How the histogram of each channel is built.
+    pub histogram: HistogramOptions,
+
+    /// The number of frames that were added.
+    pub frame_count: usize,
+
+    /// The statistics of each channel, in the order in which the channels were first encountered.
+    pub channels: Vec<ChannelStatistics>,
+}
+
+impl SequenceStatistics {
+
+    /// Create statistics without any frames.
+    pub fn new(histogram: HistogramOptions) -> Self {
+        SequenceStatistics { histogram, frame_count: 0, channels: Vec::new() }
+    }
+
+    /// Lookup the statistics of a channel, by the channel name prefixed with the layer name.
+    pub fn channel(&self, name: impl Into<Text>) -> Option<&ChannelStatistics> {
+        let name = name.into();
+        self.channels.iter().find(|channel| channel.name == name)
+    }
+
+    /// Add all channels of all layers of the next frame.
+    pub fn add_image(&mut self, image: &FlatImage) {
+        for layer in &image.layer_data { self.add_layer_to_frame(layer); }
+        self.frame_count += 1;
+    }
+
+    /// Add all channels of a single layer as the next frame.
+    pub fn add_layer(&mut self, layer: &Layer<AnyChannels<FlatSamples>>) {
+        self.add_layer_to_frame(layer);
+        self.frame_count += 1;
+    }
+
+    /// Read the file and add all channels of all layers as the next frame.
+    /// Only the largest resolution level is considered. The image is dropped afterwards.
+    pub fn add_file(&mut self, path: impl AsRef<Path>) -> UnitResult {
+        let image = crate::image::read::read_all_flat_layers_from_file(path)?;
+        self.add_image(&image);
+        Ok(())
+    }
+
+    /// Append the frames of other statistics, which were accumulated separately, for example on another thread.
+    /// The frames of the other statistics are considered to come after the frames of these statistics.
+    pub fn merge(&mut self, other: &Self) {
+        let frame_offset = self.frame_count;
+        for other_channel in &other.channels {
+            self.channel_mut(&other_channel.name).merge(other_channel, frame_offset);
+        }
+
+        self.frame_count += other.frame_count;
+    }
+
+    fn add_layer_to_frame(&mut self, layer: &Layer<AnyChannels<FlatSamples>>) {
+        let frame_index = self.frame_count;
+
+        for channel in &layer.channel_data.list {
+            let name = match &layer.attributes.layer_name {
+                Some(layer_name) => Text::from_bytes_unchecked(
+                    layer_name.bytes().iter().chain(b".").chain(channel.name.bytes()).copied().collect()
+                ),
+
+                None => channel.name.clone(),
+            };
+
+            self.channel_mut(&name).add_frame(frame_index, channel.sample_data.values_as_f32());
+        }
+    }
+
+    fn channel_mut(&mut self, name: &Text) -> &mut ChannelStatistics {
+        let index = match self.channels.iter().position(|channel| &channel.name == name) {
+            Some(index) => index,
+            None => {
+                self.channels.push(ChannelStatistics::new(name.clone(), self.histogram));
+                self.channels.len() - 1
+            }
+        };
+
+        &mut self.channels[index]
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use crate::prelude::*;
+    use super::*;
+
+    fn frame(value: f32) -> Layer<AnyChannels<FlatSamples>> {
+        Layer::new(
+            (4, 4), LayerAttributes::default(), Encoding::default(),
+            AnyChannels::sort(smallvec::smallvec![
+                AnyChannel::new("Y", FlatSamples::F32(vec![ value; 16 ])),
+                AnyChannel::new("Z", FlatSamples::F32((0 .. 16).map(|index| index as f32).collect())),
+            ])
+        )
+    }
+
+    #[test]
+    fn accumulates_frames(){
+        let mut statistics = SequenceStatistics::new(HistogramOptions::default());
+        statistics.add_layer(&frame(1.0));
+        statistics.add_layer(&frame(4.0));
+
+        let luma = statistics.channel("Y").unwrap();
+        assert_eq!(luma.sample_count, 32);
+        assert_eq!(luma.min, Some(1.0));
+        assert_eq!(luma.max, Some(4.0));
+        assert_eq!(luma.mean(), Some(2.5));
+        assert_eq!(luma.frame_means, vec![ (0, 1.0), (1, 4.0) ]);
+        assert_eq!(luma.histogram.count(), 32);
+
+        let depth = statistics.channel("Z").unwrap();
+        assert_eq!(depth.histogram.below, 2); // zero is below any stop
+        assert_eq!(depth.min, Some(0.0));
+    }
+
+    #[test]
+    fn merge_equals_sequential(){
+        let frames = [1.0, 2.0, f32::NAN, 8.0];
+
+        let mut sequential = SequenceStatistics::new(HistogramOptions::default());
+        for &value in &frames { sequential.add_layer(&frame(value)); }
+
+        let mut first = SequenceStatistics::new(HistogramOptions::default());
+        let mut second = SequenceStatistics::new(HistogramOptions::default());
+        for &value in &frames[.. 2] { first.add_layer(&frame(value)); }
+        for &value in &frames[2 ..] { second.add_layer(&frame(value)); }
+
+        first.merge(&second);
+        assert_eq!(first, sequential);
+
+        let luma = sequential.channel("Y").unwrap();
+        assert_eq!(luma.nan_count, 16);
+        assert_eq!(luma.frame_means, vec![ (0, 1.0), (1, 2.0), (3, 8.0) ]);
+    }
+
+    #[test]
+    fn histogram_percentiles(){
+        let mut histogram = Histogram::new(HistogramOptions { bin_count: 10, min: 0.0, max: 10.0, scale: HistogramScale::Linear });
+        for value in 0 .. 10 { histogram.add(value as f32 + 0.5); }
+        histogram.add(-1.0);
+
+        assert_eq!(histogram.below, 1);
+        assert_eq!(histogram.bins, vec![ 1; 10 ]);
+        assert_eq!(histogram.percentile(0.5), Some(5.0));
+        assert_eq!(histogram.percentile(0.0), None);
+        assert_eq!(histogram.bin_range(3), 3.0 .. 4.0);
+    }
+}
@@ -0,0 +1,376 @@
+//! Combine images with adjacent data windows, for example strips or buckets rendered on a farm, into one image.
+//! Images can either be stitched in memory, or directly into a file by copying their compressed chunks.
+//! Where the data windows of the images overlap, the `OverlapPolicy` decides which image is kept.
+//! Pixels that are not covered by any image are zero.
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Seek, Write};
+use std::path::Path;
+
+use crate::image::*;
+use crate::block::{BlockIndex, UncompressedBlock};
+use crate::block::chunk::{Chunk, CompressedBlock, TileCoordinates};
+use crate::block::writer::ChunksWriter;
+use crate::meta::compute_chunk_count;
+use crate::meta::header::Header;
+use crate::meta::BlockDescription;
+use crate::error::{Result, UnitResult, Error};
+
+
+/// Decides which image is kept where the data windows of two stitched images overlap.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub enum OverlapPolicy {
+
+    /// Overlapping images are an error.
+    Refuse,
+
+    /// The image that comes first in the list is kept.
+    KeepFirst,
+
+    /// The image that comes last in the list is kept.
+    KeepLast,
+}
+
+
+impl Layer<AnyChannels<FlatSamples>> {
+
+    /// Combine the layers into a single layer that covers the data windows of all layers.
+    /// All layers must have the same channels. Attributes and encoding are taken from the first layer.
+    /// Currently does not support subsampled channels.
+    pub fn stitch(parts: &[Self], overlap: OverlapPolicy) -> Result<Self> {
+        let first = parts.first().ok_or(Error::invalid("no layers to stitch"))?;
+
+        for part in parts {
+            let is_compatible = part.channel_data.list.len() == first.channel_data.list.len()
+                && part.channel_data.list.iter().zip(&first.channel_data.list).all(|(channel, first)|
+                    channel.name == first.name && channel.sample_data.sample_type() == first.sample_data.sample_type()
+                );
+
+            if !is_compatible { return Err(Error::invalid("stitched layers must have the same channels")); }
+
+            if part.channel_data.list.iter().any(|channel| channel.sampling != Vec2(1, 1)) {
+                return Err(Error::unsupported("stitching subsampled channels"));
+            }
+        }
+
+        let all_bounds: Vec<IntegerBounds> = parts.iter().map(|part| part.absolute_bounds()).collect();
+        if overlap == OverlapPolicy::Refuse && has_overlap(&all_bounds) {
+            return Err(Error::invalid("stitched layers overlap"));
+        }
+
+        let bounds = union(&all_bounds);
+
+        let mut channels = first.channel_data.clone();
+        for channel in &mut channels.list {
+            channel.sample_data = match channel.sample_data {
+                FlatSamples::F16(_) => FlatSamples::F16(vec![ f16::ZERO; bounds.size.area() ]),
+                FlatSamples::F32(_) => FlatSamples::F32(vec![ 0.0; bounds.size.area() ]),
+                FlatSamples::U32(_) => FlatSamples::U32(vec![ 0; bounds.size.area() ]),
+            };
+        }
+
+        // the part that is copied last wins
+        let ordered: Box<dyn Iterator<Item = &Self>> = match overlap {
+            OverlapPolicy::KeepFirst => Box::new(parts.iter().rev()),
+            _ => Box::new(parts.iter()),
+        };
+
+        for part in ordered {
+            let offset = (part.attributes.layer_position - bounds.position).to_usize("stitch offset")?;
+
+            for (target, source) in channels.list.iter_mut().zip(&part.channel_data.list) {
+                copy_rows(&mut target.sample_data, bounds.size.width(), &source.sample_data, part.size, offset);
+            }
+        }
+
+        let attributes = LayerAttributes { layer_position: bounds.position, .. first.attributes.clone() };
+        Ok(Layer::new(bounds.size, attributes, first.encoding, channels))
+    }
+}
+
+/// Combine the single-layer files into one file, without decompressing their pixels.
+/// See `stitch_buffered` for the requirements.
+pub fn stitch_files(parts: &[impl AsRef<Path>], output: impl AsRef<Path>, overlap: OverlapPolicy) -> UnitResult {
+    let parts = parts.iter()
+        .map(|path| Ok(BufReader::new(File::open(path)?)))
+        .collect::<Result<Vec<_>>>()?;
+
+    stitch_buffered(parts, BufWriter::new(File::create(output)?), overlap)
+}
+
+/// Combine the single-layer images into one image, by copying their compressed chunks to the output.
+/// The attributes are taken from the first image, the display window covers the display windows of all images.
+/// All images must have the same channels, compression, and blocks, and must not contain deep data or mip maps.
+/// The blocks of each image must align with the blocks of the stitched image,
+/// which means that scan line images must span the full width and start at a multiple of the block height,
+/// and tiled images must start at a multiple of the tile size.
+/// Otherwise, stitch the images in memory using `Layer::stitch`.
+pub fn stitch_buffered(parts: Vec<impl Read + Seek>, output: impl Write + Seek, overlap: OverlapPolicy) -> UnitResult {
+    let readers = parts.into_iter()
+        .map(|part| crate::block::read(part, false))
+        .collect::<Result<Vec<_>>>()?;
+
+    let headers = readers.iter()
+        .map(|reader| match reader.headers() {
+            [ header ] if !header.deep => Ok(header.clone()),
+            [ _ ] => Err(Error::unsupported("stitching deep data")),
+            _ => Err(Error::unsupported("stitching multi-layer images")),
+        })
+        .collect::<Result<Vec<Header>>>()?;
+
+    let first = headers.first().ok_or(Error::invalid("no images to stitch"))?;
+    for header in &headers {
+        if header.channels != first.channels || header.compression != first.compression || header.blocks != first.blocks {
+            return Err(Error::invalid("stitched images must have the same channels, compression, and blocks"));
+        }
+    }
+
+    if let BlockDescription::Tiles(tiles) = first.blocks {
+        if tiles.level_mode != LevelMode::Singular {
+            return Err(Error::unsupported("stitching mip maps or rip maps"));
+        }
+    }
+
+    let bounds = union(&headers.iter().map(Header::data_window).collect::<Vec<_>>());
+    let display_window = union(&headers.iter().map(|header| header.shared_attributes.display_window).collect::<Vec<_>>());
+
+    let mut output_header = first.clone()
+        .with_position(bounds.position)
+        .with_display_window(display_window);
+
+    output_header.layer_size = bounds.size;
+    output_header.line_order = LineOrder::Increasing; // the chunks are written in increasing order
+    output_header.chunk_count = compute_chunk_count(output_header.compression, bounds.size, output_header.blocks);
+
+    let output_blocks: Vec<TileCoordinates> = output_header.blocks_increasing_y_order().map(|tile| tile.location).collect();
+    let output_indices: HashMap<Vec2<usize>, usize> = output_blocks.iter().enumerate()
+        .map(|(index, tile)| (tile.tile_index, index)).collect();
+
+    let offsets = headers.iter()
+        .map(|header| block_offset(header, bounds))
+        .collect::<Result<Vec<_>>>()?;
+
+    // for each block of the output, the index of the image that provides it
+    let mut sources: Vec<Option<usize>> = vec![ None; output_blocks.len() ];
+    for (part_index, (header, &offset)) in headers.iter().zip(&offsets).enumerate() {
+        for tile in header.blocks_increasing_y_order() {
+            let source = &mut sources[output_indices[&(tile.location.tile_index + offset)]];
+
+            match (*source, overlap) {
+                (Some(_), OverlapPolicy::Refuse) => return Err(Error::invalid("stitched images overlap")),
+                (Some(_), OverlapPolicy::KeepFirst) => {},
+                _ => *source = Some(part_index),
+            }
+        }
+    }
+
+    let mut chunk_readers = readers.into_iter().enumerate()
+        .map(|(part_index, reader)| reader.filter_chunks(false, |_, tile, _|
+            sources[output_indices[&(tile.tile_index + offsets[part_index])]] == Some(part_index)
+        ))
+        .collect::<Result<Vec<_>>>()?;
+
+    // chunks that were read before their turn, because the image was not stored in increasing line order
+    let mut pending_chunks: HashMap<usize, Chunk> = HashMap::new();
+
+    crate::block::write(output, smallvec![ output_header ], true, |meta, writer| {
+        for (output_index, &tile) in output_blocks.iter().enumerate() {
+            let chunk = match sources[output_index] {
+                None => empty_chunk(&meta.headers, tile)?,
+
+                Some(part_index) => loop {
+                    if let Some(chunk) = pending_chunks.remove(&output_index) { break chunk; }
+
+                    let chunk = chunk_readers[part_index].next()
+                        .ok_or(Error::invalid("missing chunk in stitched image"))??;
+
+                    let (chunk_index, chunk) = relocate_chunk(chunk, &headers[part_index], offsets[part_index], &output_indices)?;
+                    if chunk_index == output_index { break chunk; }
+                    pending_chunks.insert(chunk_index, chunk);
+                },
+            };
+
+            writer.write_chunk(output_index, chunk)?;
+        }
+
+        Ok(())
+    })
+}
+
+
+/// The smallest rectangle containing all the rectangles.
+fn union(all_bounds: &[IntegerBounds]) -> IntegerBounds {
+    let start = all_bounds.iter().map(|bounds| bounds.position).reduce(Vec2::min).unwrap_or_default();
+    let end = all_bounds.iter().map(|bounds| bounds.end()).reduce(Vec2::max).unwrap_or_default();
+    IntegerBounds::new(start, (end - start).to_usize("stitched size").expect("union bounds bug"))
+}
+
+fn has_overlap(all_bounds: &[IntegerBounds]) -> bool {
+    all_bounds.iter().enumerate().any(|(index, first)| all_bounds[index + 1 ..].iter().any(|second|{
+        let (first_end, second_end) = (first.end(), second.end());
+
+        first.position.x() < second_end.x() && second.position.x() < first_end.x()
+            && first.position.y() < second_end.y() && second.position.y() < first_end.y()
+    }))
+}
+
+/// Copy all rows of a layer into a larger layer, starting at the offset.
+fn copy_rows(target: &mut FlatSamples, target_width: usize, source: &FlatSamples, source_size: Vec2<usize>, offset: Vec2<usize>) {
+    fn copy<T: Copy>(target: &mut [T], target_width: usize, source: &[T], source_size: Vec2<usize>, offset: Vec2<usize>) {
+        for (y, source_row) in source.chunks_exact(source_size.width()).enumerate() {
+            let start = (offset.y() + y) * target_width + offset.x();
+            target[start .. start + source_size.width()].copy_from_slice(source_row);
+        }
+    }
+
+    if source_size.area() == 0 { return; }
+
+    match (target, source) {
+        (FlatSamples::F16(target), FlatSamples::F16(source)) => copy(target, target_width, source, source_size, offset),
+        (FlatSamples::F32(target), FlatSamples::F32(source)) => copy(target, target_width, source, source_size, offset),
+        (FlatSamples::U32(target), FlatSamples::U32(source)) => copy(target, target_width, source, source_size, offset),
+        _ => unreachable!("stitch sample type bug"),
+    }
+}
+
+/// The tile index of the first block of the image within the blocks of the stitched image.
+/// Fails if the blocks of the image do not align with the blocks of the stitched image.
+fn block_offset(header: &Header, bounds: IntegerBounds) -> Result<Vec2<usize>> {
+    let block_size = match header.blocks {
+        BlockDescription::ScanLines => Vec2(bounds.size.width(), header.compression.scan_lines_per_block()),
+        BlockDescription::Tiles(tiles) => tiles.tile_size,
+    };
+
+    let offset = (header.own_attributes.layer_position - bounds.position).to_usize("stitch offset")?;
+    let end = offset + header.layer_size;
+
+    let is_aligned = |offset: usize, end: usize, block_size: usize, total_size: usize|
+        offset % block_size == 0 && (end % block_size == 0 || end == total_size);
+
+    let is_aligned = is_aligned(offset.x(), end.x(), block_size.width(), bounds.size.width())
+        && is_aligned(offset.y(), end.y(), block_size.height(), bounds.size.height());
+
+    if !is_aligned {
+        return Err(Error::unsupported("stitching images whose blocks do not align (stitch them in memory instead)"));
+    }
+
+    Ok(Vec2(offset.x() / block_size.width(), offset.y() / block_size.height()))
+}
+
+/// Move a chunk of an image to its position in the stitched image.
+/// Returns the index of the chunk within the stitched image.
+fn relocate_chunk(mut chunk: Chunk, header: &Header, offset: Vec2<usize>, output_indices: &HashMap<Vec2<usize>, usize>) -> Result<(usize, Chunk)> {
+    let tile_index = header.get_block_data_indices(&chunk.compressed_block)?.tile_index + offset;
+    let output_index = *output_indices.get(&tile_index).ok_or(Error::invalid("chunk position"))?;
+
+    // scan line blocks store their absolute position, which does not change
+    if let CompressedBlock::Tile(tile) = &mut chunk.compressed_block {
+        tile.coordinates.tile_index = tile_index;
+    }
+
+    chunk.layer_index = 0;
+    Ok((output_index, chunk))
+}
+
+/// A block of zeroes, for the parts of the stitched image that are not covered by any image.
+fn empty_chunk(headers: &[Header], tile: TileCoordinates) -> Result<Chunk> {
+    let header = &headers[0];
+    let bounds = header.get_absolute_block_pixel_coordinates(tile)?;
+
+    let block = UncompressedBlock {
+        data: vec![ 0; bounds.size.area() * header.channels.bytes_per_pixel ],
+        index: BlockIndex {
+            layer: 0, level: tile.level_index, pixel_size: bounds.size,
+            pixel_position: bounds.position.to_usize("block position")?,
+        },
+    };
+
+    block.compress_to_chunk(headers)
+}
+
+
+#[cfg(test)]
+mod test {
+    use std::io::Cursor;
+    use crate::prelude::*;
+    use super::*;
+
+    fn strip(position: (i32, i32), size: (usize, usize), value: f32, encoding: Encoding) -> Layer<AnyChannels<FlatSamples>> {
+        let size = Vec2(size.0, size.1);
+        let samples = (0 .. size.area()).map(|index| value + index as f32).collect();
+
+        Layer::new(
+            size, LayerAttributes { layer_position: position.into(), .. LayerAttributes::default() }, encoding,
+            AnyChannels::sort(smallvec![ AnyChannel::new("Y", FlatSamples::F32(samples)) ])
+        )
+    }
+
+    fn bytes(layer: &Layer<AnyChannels<FlatSamples>>) -> Cursor<Vec<u8>> {
+        let mut bytes = Vec::new();
+        Image::from_layer(layer.clone()).write().to_buffered(Cursor::new(&mut bytes)).unwrap();
+        Cursor::new(bytes)
+    }
+
+    fn read_layer(bytes: Vec<u8>) -> Layer<AnyChannels<FlatSamples>> {
+        crate::image::read::read().no_deep_data().largest_resolution_level().all_channels().first_valid_layer().all_attributes()
+            .pedantic().from_buffered(Cursor::new(bytes)).unwrap().layer_data
+    }
+
+    #[test]
+    fn stitch_in_memory(){
+        let encoding = Encoding::UNCOMPRESSED;
+        let parts = [ strip((-2, 5), (4, 2), 100.0, encoding), strip((0, 6), (4, 2), 200.0, encoding) ];
+
+        assert!(Layer::stitch(&parts, OverlapPolicy::Refuse).is_err());
+
+        let first = Layer::stitch(&parts, OverlapPolicy::KeepFirst).unwrap();
+        assert_eq!(first.absolute_bounds(), IntegerBounds::new((-2, 5), (6, 3)));
+        assert_eq!(first.channel_data.list[0].sample_data, FlatSamples::F32(vec![
+            100.0, 101.0, 102.0, 103.0,   0.0,   0.0,
+            104.0, 105.0, 106.0, 107.0, 202.0, 203.0,
+              0.0,   0.0, 204.0, 205.0, 206.0, 207.0,
+        ]));
+
+        let last = Layer::stitch(&parts, OverlapPolicy::KeepLast).unwrap();
+        assert_eq!(last.channel_data.list[0].sample_data.value_by_flat_index(6 + 2), Sample::F32(200.0));
+    }
+
+    #[test]
+    fn stitch_chunks_of_strips_and_tiles(){
+        let scan_lines = Encoding { compression: Compression::ZIP16, blocks: Blocks::ScanLines, line_order: LineOrder::Increasing };
+        let tiles = Encoding { compression: Compression::RLE, blocks: Blocks::Tiles(Vec2(4, 4)), line_order: LineOrder::Unspecified };
+
+        let strips = [ strip((3, 20), (9, 16), 0.0, scan_lines), strip((3, 36), (9, 10), 1000.0, scan_lines), strip((3, 4), (9, 16), 500.0, scan_lines) ];
+        let buckets = [ strip((0, 0), (8, 4), 0.0, tiles), strip((8, 4), (3, 6), 100.0, tiles) ];
+
+        for parts in [ &strips[..], &buckets[..] ] {
+            let mut output = Vec::new();
+            stitch_buffered(parts.iter().map(bytes).collect(), Cursor::new(&mut output), OverlapPolicy::Refuse).unwrap();
+
+            let expected = Layer::stitch(parts, OverlapPolicy::Refuse).unwrap();
+            let stitched = read_layer(output);
+
+            assert_eq!(stitched.absolute_bounds(), expected.absolute_bounds());
+            assert_eq!(stitched.channel_data, expected.channel_data);
+        }
+    }
+
+    #[test]
+    fn refuse_misaligned_or_overlapping_chunks(){
+        let scan_lines = Encoding { compression: Compression::ZIP16, blocks: Blocks::ScanLines, line_order: LineOrder::Increasing };
+        let stitch = |parts: &[Layer<AnyChannels<FlatSamples>>], overlap| {
+            stitch_buffered(parts.iter().map(bytes).collect(), Cursor::new(Vec::new()), overlap)
+        };
+
+        let misaligned = [ strip((0, 0), (4, 10), 0.0, scan_lines), strip((0, 10), (4, 10), 0.0, scan_lines) ];
+        assert!(matches!(stitch(&misaligned, OverlapPolicy::Refuse), Err(Error::NotSupported(_))));
+
+        let overlapping = [ strip((0, 0), (4, 32), 0.0, scan_lines), strip((0, 16), (4, 16), 50.0, scan_lines) ];
+        assert!(matches!(stitch(&overlapping, OverlapPolicy::Refuse), Err(Error::Invalid(_))));
+
+        let mut output = Vec::new();
+        stitch_buffered(overlapping.iter().map(bytes).collect(), Cursor::new(&mut output), OverlapPolicy::KeepLast).unwrap();
+        assert_eq!(read_layer(output).channel_data, Layer::stitch(&overlapping, OverlapPolicy::KeepLast).unwrap().channel_data);
+    }
+}
@@ -0,0 +1,120 @@
+//! Select some of the channels or layers of an image, without cloning their samples.
+//! The subset borrows the samples of the original image and can be written like any other image,
+//! for example to export only the beauty layer from an image with many render passes.
+
+use crate::image::*;
+
+
+impl<Samples> Layer<AnyChannels<Samples>> {
+
+    /// Borrow the channels that match the predicate, without cloning their samples.
+    pub fn channel_subset(&self, mut keep: impl FnMut(&AnyChannel<Samples>) -> bool) -> Layer<AnyChannels<&Samples>> {
+        let list = self.channel_data.list.iter()
+            .filter(|channel| keep(channel))
+            .map(|channel| AnyChannel {
+                name: channel.name.clone(),
+                sample_data: &channel.sample_data,
+                quantize_linearly: channel.quantize_linearly,
+                sampling: channel.sampling,
+            })
+            .collect();
+
+        Layer {
+            channel_data: AnyChannels { list }, // the original list is already sorted
+            attributes: self.attributes.clone(),
+            size: self.size,
+            encoding: self.encoding,
+        }
+    }
+}
+
+impl<Samples> Image<Layers<AnyChannels<Samples>>> {
+
+    /// Borrow the channels that match the predicate, without cloning their samples.
+    /// Layers without any matching channel are not included.
+    pub fn channel_subset(
+        &self, mut keep: impl FnMut(&Layer<AnyChannels<Samples>>, &AnyChannel<Samples>) -> bool
+    ) -> Image<Layers<AnyChannels<&Samples>>>
+    {
+        let layers = self.layer_data.iter()
+            .map(|layer| layer.channel_subset(|channel| keep(layer, channel)))
+            .filter(|layer| !layer.channel_data.list.is_empty())
+            .collect();
+
+        Image { attributes: self.attributes.clone(), layer_data: layers }
+    }
+
+    /// Borrow all channels of the layers that match the predicate, without cloning their samples.
+    pub fn layer_subset(&self, mut keep: impl FnMut(&Layer<AnyChannels<Samples>>) -> bool) -> Image<Layers<AnyChannels<&Samples>>> {
+        self.channel_subset(|layer, _| keep(layer))
+    }
+}
+
+impl<Samples> Image<Layer<AnyChannels<Samples>>> {
+
+    /// Borrow the channels that match the predicate, without cloning their samples.
+    pub fn channel_subset(&self, keep: impl FnMut(&AnyChannel<Samples>) -> bool) -> Image<Layer<AnyChannels<&Samples>>> {
+        Image {
+            attributes: self.attributes.clone(),
+            layer_data: self.layer_data.channel_subset(keep),
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use crate::prelude::*;
+    use std::io::Cursor;
+
+    fn layer(name: &str, channels: &[&str]) -> Layer<AnyChannels<FlatSamples>> {
+        Layer::new(
+            (3, 2), LayerAttributes::named(name), Encoding::FAST_LOSSLESS,
+            AnyChannels::sort(channels.iter()
+                .map(|&channel| AnyChannel::new(channel, FlatSamples::F32(vec![ channel.len() as f32; 6 ])))
+                .collect())
+        )
+    }
+
+    fn read_back(bytes: Vec<u8>) -> Image<Layers<AnyChannels<FlatSamples>>> {
+        read().no_deep_data().largest_resolution_level().all_channels().all_layers().all_attributes()
+            .from_buffered(Cursor::new(bytes)).unwrap()
+    }
+
+    #[test]
+    fn write_only_beauty_layer(){
+        let image = Image::from_layers(
+            ImageAttributes::new(IntegerBounds::from_dimensions((3, 2))),
+            smallvec::smallvec![ layer("beauty", &["R", "G", "B"]), layer("normals", &["X", "Y", "Z"]) ]
+        );
+
+        let subset = image.layer_subset(|layer| layer.attributes.layer_name == Some(Text::from("beauty")));
+        assert!(std::ptr::eq(subset.layer_data[0].channel_data.list[0].sample_data, &image.layer_data[0].channel_data.list[0].sample_data));
+
+        let mut bytes = Vec::new();
+        subset.write().to_buffered(Cursor::new(&mut bytes)).unwrap();
+
+        let result = read_back(bytes);
+        assert_eq!(result.layer_data.len(), 1);
+        assert_eq!(result.layer_data[0].channel_data, image.layer_data[0].channel_data);
+    }
+
+    #[test]
+    fn skip_layers_without_selected_channels(){
+        let image = Image::from_layers(
+            ImageAttributes::new(IntegerBounds::from_dimensions((3, 2))),
+            smallvec::smallvec![ layer("beauty", &["A", "B", "G", "R"]), layer("depth", &["Z"]) ]
+        );
+
+        let subset = image.channel_subset(|_, channel| channel.name != Text::from("A") && channel.name != Text::from("Z"));
+        assert_eq!(subset.layer_data.len(), 1);
+
+        let mut bytes = Vec::new();
+        subset.write().to_buffered(Cursor::new(&mut bytes)).unwrap();
+
+        let names: Vec<Text> = read_back(bytes).layer_data[0].channel_data.list.iter()
+            .map(|channel| channel.name.clone()).collect();
+
+        assert_eq!(names, vec![ Text::from("B"), Text::from("G"), Text::from("R") ]);
+    }
+}
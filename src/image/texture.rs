@@ -0,0 +1,266 @@
+//! Hand the pixels of a layer to a texture compressor, for example a BC6H encoder, one tile at a time.
+//! The tiles of all mip levels are cut from the decoded layer, converted to interleaved `f32` samples,
+//! and passed to the encoder, on multiple threads at once. The encoded tiles are collected in order.
+//!
+//! Read a file with `all_resolution_levels()`, or use `generate_mip_maps` to create the mip levels,
+//! then call `layer.encode_tiles(...)` to convert an exr file to a compressed gpu texture in a single pass.
+
+use rayon_core::{ThreadPool, ThreadPoolBuildError};
+
+use crate::image::*;
+use crate::meta::mip_map_levels;
+use crate::error::{Result, Error};
+
+
+/// The pixels of a single tile that should be encoded.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TileToEncode<'s> {
+
+    /// The index of the mip level. The level `0` has the full resolution.
+    pub level: usize,
+
+    /// The size of the whole mip level, in pixels.
+    pub level_size: Vec2<usize>,
+
+    /// The position of the tile within the level, counted in tiles.
+    pub tile_index: Vec2<usize>,
+
+    /// The size of the tile, in pixels. Always the requested tile size, even at the edges of a level,
+    /// where the pixels outside the level repeat the last pixel of the level.
+    /// This is what most block compressors expect.
+    pub size: Vec2<usize>,
+
+    /// The samples of all pixels, row by row, from left to right.
+    /// Each pixel contains one sample per requested channel, in the order of the requested channels.
+    pub samples: &'s [f32],
+}
+
+/// The result of encoding a single tile.
+#[derive(Debug, Clone, PartialEq)]
+pub struct EncodedTile<Output> {
+
+    /// The index of the mip level. The level `0` has the full resolution.
+    pub level: usize,
+
+    /// The position of the tile within the level, counted in tiles.
+    pub tile_index: Vec2<usize>,
+
+    /// The result of the encoder.
+    pub encoded: Output,
+}
+
+
+impl Layer<AnyChannels<Levels<FlatSamples>>> {
+
+    /// Cut all mip levels of the specified channels into tiles, and encode each tile in parallel.
+    /// Returns the encoded tiles ordered by level, then by row, then by column.
+    /// Use `encode_tiles_with_thread_pool` to customize the threadpool.
+    /// Fails if a channel is missing, or if the layer contains rip maps.
+    pub fn encode_tiles<Output: Send>(
+        &self, channels: &[Text], tile_size: Vec2<usize>,
+        encode: impl Sync + Fn(TileToEncode<'_>) -> Result<Output>
+    ) -> Result<Vec<EncodedTile<Output>>>
+    {
+        self.encode_tiles_with_thread_pool(channels, tile_size, encode, ||{
+            rayon_core::ThreadPoolBuilder::new()
+                .thread_name(|index| format!("OpenEXR Tile Encoder Thread #{}", index))
+                .build()
+        })
+    }
+
+    /// Cut all mip levels of the specified channels into tiles, and encode each tile in parallel.
+    /// Reverts to sequential encoding if the thread pool cannot be created.
+    /// See `encode_tiles`.
+    pub fn encode_tiles_with_thread_pool<Output: Send, CreatePool>(
+        &self, channels: &[Text], tile_size: Vec2<usize>,
+        encode: impl Sync + Fn(TileToEncode<'_>) -> Result<Output>,
+        try_create_thread_pool: CreatePool
+    ) -> Result<Vec<EncodedTile<Output>>>
+        where CreatePool: FnOnce() -> std::result::Result<ThreadPool, ThreadPoolBuildError>
+    {
+        let tiles = self.tiles_to_encode(channels, tile_size)?;
+
+        // in case thread pool creation fails (for example on WASM currently),
+        // we revert to sequential encoding
+        let pool: ThreadPool = match try_create_thread_pool() {
+            Ok(pool) => pool,
+            Err(_) => return self.encode_tiles_sequential(channels, tile_size, encode),
+        };
+
+        let (sender, receiver) = flume::unbounded();
+
+        pool.scope(|scope|{
+            for (tile_number, tile) in tiles.into_iter().enumerate() {
+                let sender = sender.clone();
+                let encode = &encode;
+
+                scope.spawn(move |_| {
+                    let result = tile.encode(encode);
+                    sender.send((tile_number, result)).expect("receiver hung up before encoding finished");
+                });
+            }
+        });
+
+        drop(sender);
+
+        let mut results: Vec<(usize, Result<EncodedTile<Output>>)> = receiver.into_iter().collect();
+        results.sort_unstable_by_key(|(tile_number, _)| *tile_number);
+        results.into_iter().map(|(_, result)| result).collect()
+    }
+
+    /// Cut all mip levels of the specified channels into tiles, and encode one tile after another.
+    /// See `encode_tiles`.
+    pub fn encode_tiles_sequential<Output>(
+        &self, channels: &[Text], tile_size: Vec2<usize>,
+        encode: impl Fn(TileToEncode<'_>) -> Result<Output>
+    ) -> Result<Vec<EncodedTile<Output>>>
+    {
+        self.tiles_to_encode(channels, tile_size)?.into_iter()
+            .map(|tile| tile.encode(&encode))
+            .collect()
+    }
+
+    /// The location of all tiles, without extracting their pixels yet.
+    fn tiles_to_encode<'s>(&'s self, channels: &[Text], tile_size: Vec2<usize>) -> Result<Vec<TileLocation<'s>>> {
+        if tile_size.area() == 0 { return Err(Error::invalid("tile size")); }
+
+        let channels = channels.iter()
+            .map(|name| self.channel_data.list.iter().find(|channel| &channel.name == name)
+                .map(|channel| &channel.sample_data)
+                .ok_or(Error::invalid("channel to encode is missing")))
+            .collect::<Result<Vec<&Levels<FlatSamples>>>>()?;
+
+        if channels.iter().any(|levels| matches!(levels, Levels::Rip { .. })) {
+            return Err(Error::unsupported("encoding tiles of rip maps"));
+        }
+
+        let level_sizes: Vec<Vec2<usize>> = match channels.first() {
+            Some(Levels::Mip { rounding_mode, .. }) => mip_map_levels(*rounding_mode, self.size).map(|(_, size)| size).collect(),
+            _ => vec![ self.size ],
+        };
+
+        let mut tiles = Vec::new();
+
+        for (level, &level_size) in level_sizes.iter().enumerate() {
+            let level_samples = channels.iter()
+                .map(|levels| levels.levels_as_slice().get(level).ok_or(Error::invalid("channels with different mip levels")))
+                .collect::<Result<Vec<&FlatSamples>>>()?;
+
+            let tile_count = Vec2(
+                crate::math::RoundingMode::Up.divide(level_size.width(), tile_size.width()),
+                crate::math::RoundingMode::Up.divide(level_size.height(), tile_size.height()),
+            );
+
+            for tile_y in 0 .. tile_count.height() {
+                for tile_x in 0 .. tile_count.width() {
+                    tiles.push(TileLocation {
+                        level, level_size, tile_size, samples: level_samples.clone(),
+                        tile_index: Vec2(tile_x, tile_y),
+                    });
+                }
+            }
+        }
+
+        Ok(tiles)
+    }
+}
+
+/// A tile whose pixels have not been extracted yet.
+struct TileLocation<'s> {
+    level: usize,
+    level_size: Vec2<usize>,
+    tile_index: Vec2<usize>,
+    tile_size: Vec2<usize>,
+    samples: Vec<&'s FlatSamples>,
+}
+
+impl TileLocation<'_> {
+
+    /// Extract the interleaved pixels and encode them.
+    fn encode<Output>(self, encode: &impl Fn(TileToEncode<'_>) -> Result<Output>) -> Result<EncodedTile<Output>> {
+        let start = self.tile_index * self.tile_size;
+        let mut samples = Vec::with_capacity(self.tile_size.area() * self.samples.len());
+
+        for y in 0 .. self.tile_size.height() {
+            // pixels outside the level repeat the edge
+            let y = (start.y() + y).min(self.level_size.height() - 1);
+
+            for x in 0 .. self.tile_size.width() {
+                let x = (start.x() + x).min(self.level_size.width() - 1);
+                let index = y * self.level_size.width() + x;
+                samples.extend(self.samples.iter().map(|channel| channel.value_by_flat_index(index).to_f32()));
+            }
+        }
+
+        let encoded = encode(TileToEncode {
+            level: self.level, level_size: self.level_size,
+            tile_index: self.tile_index, size: self.tile_size,
+            samples: &samples,
+        })?;
+
+        Ok(EncodedTile { level: self.level, tile_index: self.tile_index, encoded })
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use crate::image::mip::MipGenerationOptions;
+    use super::*;
+
+    fn layer() -> Layer<AnyChannels<Levels<FlatSamples>>> {
+        let size = Vec2(10, 6);
+        let red = (0 .. size.area()).map(|index| index as f32).collect();
+        let alpha = (0 .. size.area()).map(|_| f16::ONE).collect();
+
+        Layer::new(
+            size, LayerAttributes::default(), Encoding::UNCOMPRESSED,
+            AnyChannels::sort(smallvec::smallvec![
+                AnyChannel::new("R", FlatSamples::F32(red)),
+                AnyChannel::new("A", FlatSamples::F16(alpha)),
+            ])
+        ).generate_mip_maps(&MipGenerationOptions::default()).unwrap()
+    }
+
+    #[test]
+    fn encode_tiles_of_all_levels(){
+        let channels = [ Text::from("R"), Text::from("A") ];
+
+        // the "encoder" sums the red samples and counts the pixels
+        let encode = |tile: TileToEncode<'_>| -> Result<(f32, usize)> {
+            assert_eq!(tile.samples.len(), tile.size.area() * 2);
+            assert!(tile.samples.chunks(2).all(|pixel| pixel[1] == 1.0));
+            Ok((tile.samples.chunks(2).map(|pixel| pixel[0]).sum(), tile.samples.len() / 2))
+        };
+
+        let layer = layer();
+        let parallel = layer.encode_tiles(&channels, Vec2(4, 4), encode).unwrap();
+        let sequential = layer.encode_tiles_sequential(&channels, Vec2(4, 4), encode).unwrap();
+        assert_eq!(parallel, sequential);
+
+        // levels of 10×6, 5×3, 2×1 and 1×1 pixels
+        let tiles: Vec<(usize, Vec2<usize>)> = parallel.iter().map(|tile| (tile.level, tile.tile_index)).collect();
+        assert_eq!(tiles, vec![
+            (0, Vec2(0, 0)), (0, Vec2(1, 0)), (0, Vec2(2, 0)), (0, Vec2(0, 1)), (0, Vec2(1, 1)), (0, Vec2(2, 1)),
+            (1, Vec2(0, 0)), (1, Vec2(1, 0)), (2, Vec2(0, 0)), (3, Vec2(0, 0)),
+        ]);
+
+        assert!(parallel.iter().all(|tile| tile.encoded.1 == 16));
+
+        // the tile at the right edge repeats the last column, which contains 9, 19, 29, and 39
+        let edge = &parallel[2];
+        assert_eq!(edge.encoded.0, (8.0 + 9.0 * 3.0) + (18.0 + 19.0 * 3.0) + (28.0 + 29.0 * 3.0) + (38.0 + 39.0 * 3.0));
+    }
+
+    #[test]
+    fn refuse_missing_channels_and_encoder_errors(){
+        let layer = layer();
+        let encode = |_: TileToEncode<'_>| -> Result<()> { Ok(()) };
+
+        assert!(layer.encode_tiles(&[ Text::from("Z") ], Vec2(4, 4), encode).is_err());
+        assert!(layer.encode_tiles(&[ Text::from("R") ], Vec2(0, 4), encode).is_err());
+
+        let failing = |tile: TileToEncode<'_>| if tile.level == 2 { Err(Error::unsupported("tiny tiles")) } else { Ok(()) };
+        assert!(matches!(layer.encode_tiles(&[ Text::from("R") ], Vec2(4, 4), failing), Err(Error::NotSupported(_))));
+    }
+}
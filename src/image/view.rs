@@ -0,0 +1,193 @@
+//! Borrow a rectangular part of a layer without copying any samples.
+//! Views can be iterated and analyzed directly, so that many regions of interest
+//! of a large plate can be inspected while only the original samples are kept in memory.
+//! Currently does not support subsampled channels.
+
+use crate::image::*;
+use crate::image::statistics::{ChannelStatistics, HistogramOptions};
+use crate::error::{Result, Error};
+
+
+/// A rectangular part of a layer, borrowing the samples of the layer.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LayerView<'l> {
+    layer: &'l Layer<AnyChannels<FlatSamples>>,
+
+    /// The position of the view within the layer, where `(0,0)` is the top left pixel of the layer.
+    offset: Vec2<usize>,
+    size: Vec2<usize>,
+}
+
+/// A rectangular part of a single channel, borrowing the samples of the channel.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ChannelView<'l> {
+    channel: &'l AnyChannel<FlatSamples>,
+    layer_width: usize,
+    offset: Vec2<usize>,
+    size: Vec2<usize>,
+}
+
+
+impl Layer<AnyChannels<FlatSamples>> {
+
+    /// Borrow a rectangular part of this layer, without copying any samples.
+    /// The bounds are specified in absolute coordinates, like the bounds of `crop`.
+    /// Fails if the bounds are not inside this layer, or if the layer has subsampled channels.
+    pub fn view(&self, bounds: IntegerBounds) -> Result<LayerView<'_>> {
+        if self.channel_data.list.iter().any(|channel| channel.sampling != Vec2(1, 1)) {
+            return Err(Error::unsupported("views of subsampled channels"));
+        }
+
+        LayerView { layer: self, offset: Vec2(0, 0), size: self.size }.view(bounds)
+    }
+}
+
+impl<'l> LayerView<'l> {
+
+    /// The bounds of this view in absolute coordinates.
+    pub fn bounds(&self) -> IntegerBounds {
+        let position = self.layer.attributes.layer_position + self.offset.to_i32();
+        IntegerBounds::new(position, self.size)
+    }
+
+    /// The number of pixels in this view.
+    pub fn size(&self) -> Vec2<usize> {
+        self.size
+    }
+
+    /// Borrow a smaller part of this view, in absolute coordinates.
+    /// Fails if the bounds are not inside this view.
+    pub fn view(&self, bounds: IntegerBounds) -> Result<LayerView<'l>> {
+        if !self.bounds().contains(bounds) {
+            return Err(Error::invalid("view bounds must be inside the viewed layer"));
+        }
+
+        let offset = (bounds.position - self.layer.attributes.layer_position)
+            .to_usize("view position").expect("view bounds bug");
+
+        Ok(LayerView { layer: self.layer, offset, size: bounds.size })
+    }
+
+    /// The part of the channel with the specified name. Returns `None` if the layer does not contain the channel.
+    pub fn channel(&self, name: impl Into<Text>) -> Option<ChannelView<'l>> {
+        let name = name.into();
+        self.channels().find(|channel| channel.name() == &name)
+    }
+
+    /// The part of each channel of the layer, in the order of the layer.
+    pub fn channels(&self) -> impl 'l + Iterator<Item = ChannelView<'l>> {
+        let Self { layer, offset, size } = *self;
+
+        layer.channel_data.list.iter().map(move |channel| ChannelView {
+            channel, layer_width: layer.size.width(), offset, size
+        })
+    }
+
+    /// The samples of all channels of a single pixel.
+    /// The position is relative to the top left pixel of this view.
+    pub fn pixel(&self, position: Vec2<usize>) -> FlatSamplesPixel {
+        self.channels().map(|channel| channel.sample(position)).collect()
+    }
+}
+
+impl<'l> ChannelView<'l> {
+
+    /// The name of the viewed channel.
+    pub fn name(&self) -> &'l Text {
+        &self.channel.name
+    }
+
+    /// The number of pixels in this view.
+    pub fn size(&self) -> Vec2<usize> {
+        self.size
+    }
+
+    /// The sample at the specified position, relative to the top left pixel of this view.
+    /// Panics if the position is outside of this view.
+    pub fn sample(&self, position: Vec2<usize>) -> Sample {
+        assert!(position.x() < self.size.width() && position.y() < self.size.height(), "view position out of bounds");
+        let position = self.offset + position;
+        self.channel.sample_data.value_by_flat_index(position.y() * self.layer_width + position.x())
+    }
+
+    /// All samples of this view, row by row.
+    pub fn samples(&self) -> impl 'l + Iterator<Item = Sample> {
+        let Self { channel, layer_width, offset, size } = *self;
+
+        (offset.y() .. offset.y() + size.height()).flat_map(move |y|
+            (offset.x() .. offset.x() + size.width())
+                .map(move |x| channel.sample_data.value_by_flat_index(y * layer_width + x))
+        )
+    }
+
+    /// All samples of this view, row by row, converted to `f32`.
+    pub fn values_as_f32(&self) -> impl 'l + Iterator<Item = f32> {
+        self.samples().map(|sample| sample.to_f32())
+    }
+
+    /// Compute the minimum, maximum, mean and histogram of the samples in this view.
+    pub fn statistics(&self, histogram: HistogramOptions) -> ChannelStatistics {
+        let mut statistics = ChannelStatistics::new(self.channel.name.clone(), histogram);
+        statistics.add_frame(0, self.values_as_f32());
+        statistics
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use crate::prelude::*;
+    use crate::image::statistics::HistogramOptions;
+
+    fn plate() -> Layer<AnyChannels<FlatSamples>> {
+        let values = (0 .. 6 * 4).map(|index| index as f32).collect();
+
+        Layer::new(
+            (6, 4), LayerAttributes { layer_position: Vec2(10, 20), .. LayerAttributes::default() },
+            Encoding::default(),
+            AnyChannels::sort(smallvec::smallvec![
+                AnyChannel::new("Y", FlatSamples::F32(values)),
+                AnyChannel::new("Z", FlatSamples::F16(vec![ f16::ONE; 6 * 4 ])),
+            ])
+        )
+    }
+
+    #[test]
+    fn view_samples_without_copying(){
+        let layer = plate();
+        let view = layer.view(IntegerBounds::new((12, 21), (3, 2))).unwrap();
+        assert_eq!(view.size(), Vec2(3, 2));
+
+        let luma = view.channel("Y").unwrap();
+        assert_eq!(luma.values_as_f32().collect::<Vec<f32>>(), vec![ 8.0, 9.0, 10.0,   14.0, 15.0, 16.0 ]);
+        assert_eq!(luma.sample(Vec2(2, 1)), Sample::F32(16.0));
+        assert_eq!(view.pixel(Vec2(0, 0)).as_slice(), &[ Sample::F32(8.0), Sample::F16(f16::ONE) ]);
+
+        let nested = view.view(IntegerBounds::new((13, 22), (2, 1))).unwrap();
+        assert_eq!(nested.bounds(), IntegerBounds::new((13, 22), (2, 1)));
+        assert_eq!(nested.channel("Y").unwrap().values_as_f32().collect::<Vec<f32>>(), vec![ 15.0, 16.0 ]);
+    }
+
+    #[test]
+    fn view_statistics(){
+        let layer = plate();
+        let view = layer.view(IntegerBounds::new((10, 22), (6, 2))).unwrap();
+        let statistics = view.channel("Y").unwrap().statistics(HistogramOptions::default());
+
+        assert_eq!(statistics.sample_count, 12);
+        assert_eq!(statistics.min, Some(12.0));
+        assert_eq!(statistics.max, Some(23.0));
+        assert_eq!(statistics.mean(), Some(17.5));
+    }
+
+    #[test]
+    fn reject_views_outside_the_layer(){
+        let layer = plate();
+        assert!(layer.view(IntegerBounds::new((9, 20), (2, 2))).is_err());
+        assert!(layer.view(IntegerBounds::new((14, 20), (3, 2))).is_err());
+
+        let view = layer.view(IntegerBounds::new((10, 20), (2, 2))).unwrap();
+        assert!(view.view(IntegerBounds::new((11, 21), (2, 1))).is_err());
+        assert!(view.channel("X").is_none());
+    }
+}
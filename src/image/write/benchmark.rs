@@ -0,0 +1,319 @@
+//! Measure how fast each compression method encodes and decodes your own images, and how small the files get,
+//! so that the default compression of a pipeline can be chosen based on the actual content.
+//! The whole image is compressed into memory and decompressed again, with each of the specified thread counts.
+//! Only available with the `benchmark` feature.
+
+use std::fmt::{Display, Formatter};
+use std::io::Cursor;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use crate::block::{BlockIndex, UncompressedBlock};
+use crate::block::lines::LineRefMut;
+use crate::block::reader::{ChunksReader, ParallelBlockDecompressor};
+use crate::block::writer::{ChunksWriter, ParallelBlocksCompressor};
+use crate::compression::Compression;
+use crate::error::{Result, UnitResult};
+use crate::image::write::{WritableImage, WriteImageWithOptions};
+use crate::image::write::layers::{WritableLayers, LayersWriter};
+use crate::image::write::probe::SUPPORTED_COMPRESSION_METHODS;
+use crate::meta::header::Header;
+use rayon_core::{ThreadPool, ThreadPoolBuildError};
+
+
+/// Which compression methods and thread counts are measured.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BenchmarkOptions {
+
+    /// The compression methods to measure.
+    pub compression_methods: Vec<Compression>,
+
+    /// The number of threads to measure each compression method with.
+    /// A thread count of `1` measures sequential compression.
+    /// The thread pool is created before measuring, so its start-up time is not included.
+    pub thread_counts: Vec<usize>,
+
+    /// How often each measurement is repeated. The fastest repetition is reported.
+    pub repetitions: usize,
+}
+
+/// The measurements of one compression method with one thread count.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CodecBenchmark {
+
+    /// The measured compression method.
+    pub compression: Compression,
+
+    /// The number of threads that were actually used to encode and decode the image.
+    /// Is `1` instead of the requested thread count if the blocks were compressed sequentially,
+    /// which happens for uncompressed images, images with a single block,
+    /// or if the thread pool could not be created.
+    pub thread_count: usize,
+
+    /// Whether this method reproduces all channels of the image exactly.
+    pub is_lossless: bool,
+
+    /// The size of the pixels before compression.
+    pub uncompressed_byte_size: usize,
+
+    /// The size of the file, including the meta data.
+    pub file_byte_size: usize,
+
+    /// The time it took to compress all blocks and write the file to memory.
+    pub encode_duration: Duration,
+
+    /// The time it took to read the file from memory and decompress all blocks.
+    pub decode_duration: Duration,
+}
+
+/// The measurements of all compression methods and thread counts.
+/// Use `to_string()` to obtain a plain text table.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct BenchmarkTable {
+
+    /// One row per compression method and thread count, ordered by compression method.
+    pub rows: Vec<CodecBenchmark>,
+}
+
+
+impl Default for BenchmarkOptions {
+    fn default() -> Self {
+        let available_threads = std::thread::available_parallelism().map_or(1, |threads| threads.get());
+        let mut thread_counts = vec![ 1, available_threads ];
+        thread_counts.dedup();
+
+        BenchmarkOptions { compression_methods: SUPPORTED_COMPRESSION_METHODS.to_vec(), thread_counts, repetitions: 3 }
+    }
+}
+
+impl CodecBenchmark {
+
+    /// The file size divided by the uncompressed size. Smaller is better.
+    pub fn compression_ratio(&self) -> f64 {
+        if self.uncompressed_byte_size == 0 { 1.0 }
+        else { self.file_byte_size as f64 / self.uncompressed_byte_size as f64 }
+    }
+}
+
+impl BenchmarkTable {
+
+    /// The smallest file, preferring lossless methods and faster decoding for equal sizes.
+    pub fn smallest(&self) -> Option<&CodecBenchmark> {
+        self.rows.iter().min_by_key(|row| (!row.is_lossless, row.file_byte_size, row.decode_duration))
+    }
+
+    /// The fastest decoding lossless method.
+    pub fn fastest_lossless_decode(&self) -> Option<&CodecBenchmark> {
+        self.rows.iter().filter(|row| row.is_lossless).min_by_key(|row| row.decode_duration)
+    }
+}
+
+impl Display for BenchmarkTable {
+    fn fmt(&self, formatter: &mut Formatter<'_>) -> std::fmt::Result {
+        writeln!(formatter, "{:<14} {:>7} {:>12} {:>7} {:>12} {:>12} {:>9}", "compression", "threads", "bytes", "ratio", "encode (ms)", "decode (ms)", "lossless")?;
+
+        for row in &self.rows {
+            writeln!(
+                formatter, "{:<14} {:>7} {:>12} {:>7.3} {:>12.3} {:>12.3} {:>9}",
+                row.compression.to_string(), row.thread_count, row.file_byte_size, row.compression_ratio(),
+                row.encode_duration.as_secs_f64() * 1000.0, row.decode_duration.as_secs_f64() * 1000.0,
+                if row.is_lossless { "yes" } else { "no" }
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+
+/// Read all layers and resolution levels of the file, and measure each compression method on them.
+/// Deep data is not supported.
+pub fn benchmark_file(path: impl AsRef<Path>, options: &BenchmarkOptions) -> Result<BenchmarkTable> {
+    let image = crate::image::read::read_all_data_from_file(path)?;
+    image.write().benchmark_compression(options)
+}
+
+impl<'img, L, F, T> WriteImageWithOptions<'img, L, F, T>
+    where L: WritableLayers<'img>, F: FnMut(f64), T: FnMut(LineRefMut<'_>) -> UnitResult
+{
+    /// Encode and decode the whole image in memory with each compression method and thread count.
+    /// Nothing is written to a file. The progress and line functions of this writer are not called.
+    pub fn benchmark_compression(&self, options: &BenchmarkOptions) -> Result<BenchmarkTable> {
+        let mut table = BenchmarkTable::default();
+
+        for &compression in &options.compression_methods {
+            // the number of lines per block depends on the compression method
+            let headers: Vec<Header> = self.infer_meta_data().into_iter()
+                .map(|header| {
+                    let (blocks, line_order) = (header.blocks, header.line_order);
+                    header.with_encoding(compression, blocks, line_order)
+                })
+                .collect();
+
+            let layers = self.image.layer_data.create_writer(&headers);
+            let blocks: Vec<(usize, UncompressedBlock)> = crate::block::enumerate_ordered_header_block_indices(&headers)
+                .map(|(index_in_header_increasing_y, index): (usize, BlockIndex)|
+                    (index_in_header_increasing_y, UncompressedBlock { index, data: layers.extract_uncompressed_block(&headers, index) })
+                )
+                .collect();
+
+            let uncompressed_byte_size = blocks.iter().map(|(_, block)| block.data.len()).sum();
+            let is_lossless = headers.iter().all(|header| header.channels.list.iter()
+                .all(|channel| compression.is_lossless_for(channel.sample_type)));
+
+            for &thread_count in &options.thread_counts {
+                // the pool is lent to each encoder and decoder, and returned afterwards
+                let mut pool = if thread_count > 1 { create_thread_pool(thread_count).ok() } else { None };
+
+                let mut row = CodecBenchmark {
+                    compression, thread_count: 1, is_lossless, uncompressed_byte_size,
+                    file_byte_size: 0, encode_duration: Duration::MAX, decode_duration: Duration::MAX,
+                };
+
+                for _ in 0 .. options.repetitions.max(1) {
+                    let (bytes, encode_duration, encode_threads) = encode(&headers, blocks.clone(), &mut pool)?;
+                    let (decode_duration, decode_threads) = decode(&bytes, &mut pool)?;
+                    debug_assert_eq!(encode_threads, decode_threads, "encoding and decoding should both be parallel or both sequential");
+
+                    row.thread_count = encode_threads;
+                    row.file_byte_size = bytes.len();
+                    row.encode_duration = row.encode_duration.min(encode_duration);
+                    row.decode_duration = row.decode_duration.min(decode_duration);
+                }
+
+                table.rows.push(row);
+            }
+        }
+
+        Ok(table)
+    }
+}
+
+
+/// Created once per thread count, so that spawning threads is not part of the measured durations.
+fn create_thread_pool(thread_count: usize) -> std::result::Result<ThreadPool, ThreadPoolBuildError> {
+    rayon_core::ThreadPoolBuilder::new()
+        .num_threads(thread_count)
+        .thread_name(|index| format!("OpenEXR Benchmark Thread #{}", index))
+        .build()
+}
+
+/// Write the file to memory and return its bytes, and the number of threads that were used.
+/// Uses the pool if parallel compression is worth it, and puts it back afterwards.
+fn encode(headers: &[Header], blocks: Vec<(usize, UncompressedBlock)>, pool: &mut Option<ThreadPool>) -> Result<(Vec<u8>, Duration, usize)> {
+    let mut bytes = Vec::new();
+    let mut thread_count = 1;
+    let start = Instant::now();
+
+    crate::block::write(Cursor::new(&mut bytes), headers.into(), true, |meta, chunk_writer| {
+        // the pool is only taken if the compressor decides to use it
+        let parallel = if pool.is_none() { None } else {
+            ParallelBlocksCompressor::new_with_thread_pool(&meta, chunk_writer, || Ok(pool.take().expect("thread pool already taken")))
+        };
+
+        match parallel {
+            Some(mut compressor) => {
+                for (index_in_header_increasing_y, block) in blocks {
+                    compressor.add_block_to_compression_queue(index_in_header_increasing_y, block)?;
+                }
+
+                let returned_pool = compressor.into_thread_pool();
+                thread_count = returned_pool.current_num_threads();
+                *pool = Some(returned_pool);
+            },
+
+            None => for (index_in_header_increasing_y, block) in blocks {
+                chunk_writer.write_chunk(index_in_header_increasing_y, block.compress_to_chunk(&meta.headers)?)?;
+            },
+        }
+
+        Ok(())
+    })?;
+
+    let duration = start.elapsed();
+    Ok((bytes, duration, thread_count))
+}
+
+/// Read the file from memory and decompress all blocks. Returns the number of threads that were used.
+/// Uses the pool if parallel decompression is worth it, and puts it back afterwards.
+fn decode(bytes: &[u8], pool: &mut Option<ThreadPool>) -> Result<(Duration, usize)> {
+    let start = Instant::now();
+    let chunks = crate::block::read(Cursor::new(bytes), false)?.all_chunks(false)?;
+
+    let chunks = if pool.is_none() { chunks } else {
+        match ParallelBlockDecompressor::new_with_thread_pool(chunks, false, || Ok(pool.take().expect("thread pool already taken"))) {
+            Ok(mut decompressor) => {
+                for block in &mut decompressor { block?; }
+                let duration = start.elapsed();
+
+                let returned_pool = decompressor.into_thread_pool();
+                let thread_count = returned_pool.current_num_threads();
+                *pool = Some(returned_pool);
+                return Ok((duration, thread_count));
+            },
+
+            Err(chunks) => chunks,
+        }
+    };
+
+    for block in chunks.sequential_decompressor(false) { block?; }
+    Ok((start.elapsed(), 1))
+}
+
+
+#[cfg(test)]
+mod test {
+    use crate::prelude::*;
+    use super::*;
+
+    #[test]
+    fn benchmark_each_method_and_thread_count(){
+        let channels = AnyChannels::sort(smallvec::smallvec![
+            AnyChannel::new("flat", FlatSamples::F16(vec![ f16::ONE; 64 * 64 ])),
+            AnyChannel::new("noise", FlatSamples::F32((0 .. 64 * 64).map(|index| ((index * 7919) % 257) as f32).collect())),
+        ]);
+
+        let image = Image::from_encoded_channels((64, 64), Encoding::UNCOMPRESSED, channels);
+        let options = BenchmarkOptions {
+            compression_methods: vec![ Compression::Uncompressed, Compression::ZIP16, Compression::B44 ],
+            thread_counts: vec![ 1, 2 ], repetitions: 1,
+        };
+
+        let table = image.write().benchmark_compression(&options).unwrap();
+        assert_eq!(table.rows.len(), 6);
+
+        for row in &table.rows {
+            assert_eq!(row.uncompressed_byte_size, 64 * 64 * (2 + 4));
+            assert!(row.file_byte_size > 0);
+        }
+
+        // uncompressed images are always written sequentially
+        let thread_counts: Vec<(Compression, usize)> = table.rows.iter().map(|row| (row.compression, row.thread_count)).collect();
+        assert_eq!(thread_counts, vec![
+            (Compression::Uncompressed, 1), (Compression::Uncompressed, 1),
+            (Compression::ZIP16, 1), (Compression::ZIP16, 2),
+            (Compression::B44, 1), (Compression::B44, 2),
+        ]);
+
+        let zip = table.rows.iter().filter(|row| row.compression == Compression::ZIP16).collect::<Vec<_>>();
+        assert_eq!(zip[0].file_byte_size, zip[1].file_byte_size);
+        assert!(zip[0].compression_ratio() < 1.0);
+
+        assert_eq!(table.smallest().unwrap().compression, Compression::ZIP16);
+        assert!(!table.rows.iter().find(|row| row.compression == Compression::B44).unwrap().is_lossless);
+        assert_eq!(table.to_string().lines().count(), 7);
+    }
+
+    #[test]
+    fn default_options_and_empty_table(){
+        let options = BenchmarkOptions::default();
+        assert_eq!(options.compression_methods.len(), SUPPORTED_COMPRESSION_METHODS.len());
+        assert_eq!(options.thread_counts[0], 1);
+        assert!(options.thread_counts.len() <= 2);
+
+        let table = BenchmarkTable::default();
+        assert_eq!(table.smallest(), None);
+        assert_eq!(table.fastest_lossless_decode(), None);
+        assert_eq!(table.to_string().lines().count(), 1);
+    }
+}
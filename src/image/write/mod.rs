@@ -15,17 +15,24 @@
 pub mod layers;
 pub mod samples;
 pub mod channels;
+pub mod probe;
+
+#[cfg(feature = "benchmark")]
+pub mod benchmark;
 
 
 
 use crate::meta::Headers;
-use crate::error::UnitResult;
+use crate::meta::header::Header;
+use crate::error::{Error, UnitResult};
 use std::io::{Seek, BufWriter};
 use crate::io::Write;
 use crate::image::{Image, ignore_progress, SpecificChannels, IntoSample};
 use crate::image::write::layers::{WritableLayers, LayersWriter};
 use crate::math::Vec2;
 use crate::block::writer::ChunksWriter;
+use crate::block::{BlockIndex, UncompressedBlock};
+use crate::block::lines::{LineIndex, LineRefMut, LineSlice};
 
 /// An oversimplified function for "just write the damn file already" use cases.
 /// Have a look at the examples to see how you can write an image with more flexibility (it's not that hard).
@@ -73,8 +80,10 @@ impl<'img, WritableLayers> WritableImage<'img, WritableLayers> for &'img Image<W
         WriteImageWithOptions {
             image: self,
             check_compatibility: true,
+            validate: true,
             parallel: true,
-            on_progress: ignore_progress
+            on_progress: ignore_progress,
+            on_line: None,
         }
     }
 }
@@ -82,16 +91,18 @@ impl<'img, WritableLayers> WritableImage<'img, WritableLayers> for &'img Image<W
 /// A temporary writer which can be configured and used to write an image to a file.
 // temporary writer with options
 #[derive(Debug, Clone, PartialEq)]
-pub struct WriteImageWithOptions<'img, Layers, OnProgress> {
+pub struct WriteImageWithOptions<'img, Layers, OnProgress, OnLine = fn(LineRefMut<'_>) -> UnitResult> {
     image: &'img Image<Layers>,
     on_progress: OnProgress,
+    on_line: Option<OnLine>,
     check_compatibility: bool,
+    validate: bool,
     parallel: bool,
 }
 
 
-impl<'img, L, F> WriteImageWithOptions<'img, L, F>
-    where L: WritableLayers<'img>, F: FnMut(f64)
+impl<'img, L, F, T> WriteImageWithOptions<'img, L, F, T>
+    where L: WritableLayers<'img>, F: FnMut(f64), T: FnMut(LineRefMut<'_>) -> UnitResult
 {
     /// Generate file meta data for this image. The meta data structure is close to the data in the file.
     pub fn infer_meta_data(&self) -> Headers { // TODO this should perform all validity checks? and none after that?
@@ -112,15 +123,43 @@ impl<'img, L, F> WriteImageWithOptions<'img, L, F>
     /// __You must care for not producing an invalid file yourself.__
     pub fn skip_compatibility_checks(self) -> Self { Self { check_compatibility: false, ..self } }
 
+    /// Skip all validation of the meta data, including the name and window checks.
+    /// This is measurable when writing thousands of tiny images per second, for example light probes,
+    /// where the time spent checking the headers is close to the time spent writing the pixels.
+    /// __You must guarantee that the image is valid yourself,
+    /// otherwise a broken file will be written without any error.__
+    pub fn unchecked(self) -> Self { Self { validate: false, check_compatibility: false, ..self } }
+
     /// Specify a function to be called regularly throughout the writing process.
     /// Replaces all previously specified progress functions in this reader.
-    pub fn on_progress<OnProgress>(self, on_progress: OnProgress) -> WriteImageWithOptions<'img, L, OnProgress>
+    pub fn on_progress<OnProgress>(self, on_progress: OnProgress) -> WriteImageWithOptions<'img, L, OnProgress, T>
         where OnProgress: FnMut(f64)
     {
         WriteImageWithOptions {
             on_progress,
+            on_line: self.on_line,
+            image: self.image,
+            check_compatibility: self.check_compatibility,
+            validate: self.validate,
+            parallel: self.parallel
+        }
+    }
+
+    /// Specify a function that can modify each line of samples just before it is compressed,
+    /// for example to burn in a watermark or slate text, without copying the whole image first.
+    /// The line contains the channel index, the position within the layer, and the mutable little-endian sample bytes.
+    /// Use `line.write_samples` to replace the samples. Deep layers are not passed to this function.
+    /// Writing stops at the first error of this function. Subsampled channels are not supported yet.
+    /// Replaces all previously specified line functions in this writer.
+    pub fn on_line<OnLine>(self, on_line: OnLine) -> WriteImageWithOptions<'img, L, F, OnLine>
+        where OnLine: FnMut(LineRefMut<'_>) -> UnitResult
+    {
+        WriteImageWithOptions {
+            on_line: Some(on_line),
+            on_progress: self.on_progress,
             image: self.image,
             check_compatibility: self.check_compatibility,
+            validate: self.validate,
             parallel: self.parallel
         }
     }
@@ -155,17 +194,35 @@ impl<'img, L, F> WriteImageWithOptions<'img, L, F>
         let headers = self.infer_meta_data();
         let layers = self.image.layer_data.create_writer(&headers);
 
-        crate::block::write(
-            write, headers, self.check_compatibility,
+        let validation = if self.validate { Some(self.check_compatibility) } else { None };
+
+        crate::block::writer::write_chunks_with_validation(
+            write, headers, validation,
             move |meta, chunk_writer|{
 
-                let blocks = meta.collect_ordered_block_data(|block_index|
-                     layers.extract_uncompressed_block(&meta.headers, block_index)
-                );
+                // the blocks are extracted on this thread, so the first error of the line function
+                // can be remembered, and no further blocks are extracted after that error
+                let mut on_line = self.on_line;
+                let mut line_error = None;
+
+                let blocks = meta.enumerate_ordered_header_block_indices().map_while(|(index_in_header_increasing_y, block_index)| {
+                    let mut data = layers.extract_uncompressed_block(&meta.headers, block_index);
+
+                    if let Some(on_line) = &mut on_line {
+                        if let Err(error) = transform_lines(on_line, &meta.headers, block_index, &mut data) {
+                            line_error = Some(error);
+                            return None;
+                        }
+                    }
+
+                    Some((index_in_header_increasing_y, UncompressedBlock { index: block_index, data }))
+                });
 
                 let chunk_writer = chunk_writer.on_progress(self.on_progress);
                 if self.parallel { chunk_writer.compress_all_blocks_parallel(&meta, blocks)?; }
                 else { chunk_writer.compress_all_blocks_sequential(&meta, blocks)?; }
+
+                if let Some(error) = line_error { return Err(error); }
                 /*let blocks_writer = chunk_writer.as_blocks_writer(&meta);
 
                 // TODO propagate send requirement further upwards
@@ -182,3 +239,102 @@ impl<'img, L, F> WriteImageWithOptions<'img, L, F>
     }
 }
 
+
+/// Call the function for each line in the block.
+fn transform_lines(
+    on_line: &mut impl FnMut(LineRefMut<'_>) -> UnitResult,
+    headers: &[Header], block_index: BlockIndex, data: &mut [u8]
+) -> UnitResult {
+    let header = &headers[block_index.layer];
+    if header.deep { return Ok(()); }
+
+    // the lines of a block do not yet respect subsampling
+    if header.channels.list.iter().any(|channel| channel.sampling != Vec2(1, 1)) {
+        return Err(Error::unsupported("line function for subsampled channels"));
+    }
+
+    for (byte_range, location) in LineIndex::lines_in_block(block_index, &header.channels) {
+        on_line(LineSlice { location, value: &mut data[byte_range] })?;
+    }
+
+    Ok(())
+}
+
+
+#[cfg(test)]
+mod test {
+    use std::io::Cursor;
+    use crate::prelude::*;
+    use crate::block::lines::LineRefMut;
+
+    fn image() -> Image<Layer<AnyChannels<FlatSamples>>> {
+        let encoding = Encoding { compression: Compression::ZIP16, blocks: Blocks::Tiles(Vec2(8, 8)), line_order: LineOrder::Increasing };
+        let y = (0 .. 20 * 12).map(|index| (index % 20) as f32).collect();
+        let z = (0 .. 20 * 12).map(|index| (index / 20) as f32).collect();
+
+        let channels = AnyChannels::sort(smallvec::smallvec![
+            AnyChannel::new("Y", FlatSamples::F32(y)), AnyChannel::new("Z", FlatSamples::F32(z)),
+        ]);
+
+        Image::from_layer(Layer::new((20, 12), LayerAttributes::default(), encoding, channels))
+    }
+
+    fn read_samples(bytes: Vec<u8>) -> Vec<FlatSamples> {
+        let image = crate::image::read::read().no_deep_data().largest_resolution_level().all_channels()
+            .first_valid_layer().all_attributes().from_buffered(Cursor::new(bytes)).unwrap();
+
+        image.layer_data.channel_data.list.into_iter().map(|channel| channel.sample_data).collect()
+    }
+
+    #[test]
+    fn modify_lines_before_compression(){
+        let mut bytes = Vec::new();
+
+        image().write().non_parallel()
+            .on_line(|line: LineRefMut<'_>| {
+                // burn a horizontal bar into the first channel
+                if line.location.channel == 0 && (4 .. 6).contains(&line.location.position.y()) {
+                    line.write_samples(|_| -1.0_f32)?;
+                }
+
+                Ok(())
+            })
+            .to_buffered(Cursor::new(&mut bytes)).unwrap();
+
+        let samples = read_samples(bytes);
+        let expected_y = (0 .. 20 * 12).map(|index| if (4 .. 6).contains(&(index / 20)) { -1.0 } else { (index % 20) as f32 });
+        let expected_z = (0 .. 20 * 12).map(|index| (index / 20) as f32);
+
+        assert_eq!(samples, vec![ FlatSamples::F32(expected_y.collect()), FlatSamples::F32(expected_z.collect()) ]);
+    }
+
+    #[test]
+    fn line_errors_abort_writing(){
+        let mut progress = 0.0;
+
+        // fail in the first tile of the second row of tiles
+        let result = image().write().non_parallel()
+            .on_progress(|value| progress = value)
+            .on_line(|line: LineRefMut<'_>| {
+                if line.location.position.y() < 8 { Ok(()) }
+                else { Err(Error::invalid("watermark")) }
+            })
+            .to_buffered(Cursor::new(Vec::new()));
+
+        assert!(matches!(result, Err(Error::Invalid(_))));
+        assert_eq!(progress, 0.5, "blocks after the error were written");
+    }
+
+    #[test]
+    fn line_functions_refuse_subsampled_channels(){
+        let encoding = Encoding { compression: Compression::Uncompressed, blocks: Blocks::ScanLines, line_order: LineOrder::Increasing };
+        let mut chroma = AnyChannel::new("RY", FlatSamples::F32(vec![ 0.0; 10 * 6 ]));
+        chroma.sampling = Vec2(2, 2);
+
+        let channels = AnyChannels::sort(smallvec::smallvec![ AnyChannel::new("Y", FlatSamples::F32(vec![ 0.0; 20 * 12 ])), chroma ]);
+        let image = Image::from_layer(Layer::new((20, 12), LayerAttributes::default(), encoding, channels));
+
+        let result = image.write().on_line(|_: LineRefMut<'_>| Ok(())).to_buffered(Cursor::new(Vec::new()));
+        assert!(matches!(result, Err(Error::NotSupported(_))));
+    }
+}
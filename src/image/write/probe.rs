@@ -0,0 +1,231 @@
+//! Find out how well each compression method works for an image, without writing a file.
+//! A few blocks of each layer are compressed with every method, and the sizes and durations are reported.
+
+use crate::compression::Compression;
+use crate::meta::header::Header;
+use crate::meta::attribute::{Text, ChannelList};
+use crate::block::chunk::TileCoordinates;
+use crate::block::{BlockIndex, UncompressedBlock};
+use crate::block::lines::LineRefMut;
+use crate::error::{Result, UnitResult};
+use crate::image::write::WriteImageWithOptions;
+use crate::image::write::layers::{WritableLayers, LayersWriter};
+use std::time::{Duration, Instant};
+use smallvec::smallvec;
+
+
+/// All compression methods that this library can compress with.
+pub const SUPPORTED_COMPRESSION_METHODS: [Compression; 8] = [
+    Compression::Uncompressed, Compression::RLE,
+    Compression::ZIP1, Compression::ZIP16, Compression::PIZ,
+    Compression::PXR24, Compression::B44, Compression::B44A,
+];
+
+/// How well a compression method performed on the sampled blocks of a single layer.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CompressionProbe {
+
+    /// The index of the probed layer in the image.
+    pub layer_index: usize,
+
+    /// The probed compression method.
+    pub compression: Compression,
+
+    /// Whether this method reproduces all channels of the layer exactly.
+    pub is_lossless: bool,
+
+    /// How many blocks of the layer have been compressed.
+    pub sampled_block_count: usize,
+
+    /// How many blocks the layer consists of with this compression method.
+    pub total_block_count: usize,
+
+    /// The summed size of the sampled blocks before compression.
+    pub uncompressed_byte_size: usize,
+
+    /// The summed size of the sampled blocks after compression.
+    pub compressed_byte_size: usize,
+
+    /// The time it took to compress all sampled blocks, on a single thread.
+    pub duration: Duration,
+
+    /// The results of compressing each channel on its own.
+    /// Shows which channels compress badly, for example noisy ones.
+    pub channels: Vec<ChannelCompressionProbe>,
+}
+
+/// How well a compression method performed on one channel of the sampled blocks.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChannelCompressionProbe {
+
+    /// The name of the channel.
+    pub name: Text,
+
+    /// The summed size of this channel in the sampled blocks before compression.
+    pub uncompressed_byte_size: usize,
+
+    /// The summed size of this channel in the sampled blocks, when compressed as if it were the only channel.
+    pub compressed_byte_size: usize,
+}
+
+impl CompressionProbe {
+
+    /// The compressed size divided by the uncompressed size. Smaller is better.
+    pub fn compression_ratio(&self) -> f64 {
+        ratio(self.compressed_byte_size, self.uncompressed_byte_size)
+    }
+
+    /// The uncompressed bytes processed per second.
+    pub fn bytes_per_second(&self) -> f64 {
+        self.uncompressed_byte_size as f64 / self.duration.as_secs_f64().max(f64::MIN_POSITIVE)
+    }
+}
+
+impl ChannelCompressionProbe {
+
+    /// The compressed size divided by the uncompressed size. Smaller is better.
+    pub fn compression_ratio(&self) -> f64 {
+        ratio(self.compressed_byte_size, self.uncompressed_byte_size)
+    }
+}
+
+fn ratio(compressed: usize, uncompressed: usize) -> f64 {
+    if uncompressed == 0 { 1.0 } else { compressed as f64 / uncompressed as f64 }
+}
+
+
+impl<'img, L, F, T> WriteImageWithOptions<'img, L, F, T>
+    where L: WritableLayers<'img>, F: FnMut(f64), T: FnMut(LineRefMut<'_>) -> UnitResult
+{
+    /// Compress a few blocks of each layer with all supported compression methods.
+    /// Nothing is written. Returns one probe per layer and compression method.
+    /// The blocks are chosen evenly across each layer, at most `blocks_per_layer` per layer.
+    pub fn probe_compression(&self, blocks_per_layer: usize) -> Result<Vec<CompressionProbe>> {
+        self.probe_compression_methods(&SUPPORTED_COMPRESSION_METHODS, blocks_per_layer)
+    }
+
+    /// Compress a few blocks of each layer with each of the specified compression methods.
+    /// Nothing is written. Returns one probe per layer and compression method.
+    /// The blocks are chosen evenly across each layer, at most `blocks_per_layer` per layer.
+    pub fn probe_compression_methods(&self, methods: &[Compression], blocks_per_layer: usize) -> Result<Vec<CompressionProbe>> {
+        let mut probes = Vec::with_capacity(methods.len() * self.image.layer_data.infer_headers(&self.image.attributes).len());
+
+        for &compression in methods {
+            // the number of lines per block depends on the compression method
+            let headers: Vec<Header> = self.infer_meta_data().into_iter()
+                .map(|header| {
+                    let (blocks, line_order) = (header.blocks, header.line_order);
+                    header.with_encoding(compression, blocks, line_order)
+                })
+                .collect();
+
+            let layers = self.image.layer_data.create_writer(&headers);
+
+            for (layer_index, header) in headers.iter().enumerate() {
+                let block_indices: Vec<BlockIndex> = crate::block::enumerate_ordered_header_block_indices(&headers)
+                    .map(|(_, block)| block)
+                    .filter(|block| block.layer == layer_index)
+                    .collect();
+
+                let sampled: Vec<UncompressedBlock> = sample_evenly(&block_indices, blocks_per_layer)
+                    .map(|&index| UncompressedBlock { index, data: layers.extract_uncompressed_block(&headers, index) })
+                    .collect();
+
+                probes.push(probe_layer(layer_index, header, compression, &sampled, block_indices.len())?);
+            }
+        }
+
+        Ok(probes)
+    }
+}
+
+fn sample_evenly<T>(items: &[T], count: usize) -> impl '_ + Iterator<Item=&T> {
+    let count = count.min(items.len());
+    (0 .. count).map(move |sample| &items[sample * items.len() / count])
+}
+
+fn probe_layer(
+    layer_index: usize, header: &Header, compression: Compression,
+    blocks: &[UncompressedBlock], total_block_count: usize
+) -> Result<CompressionProbe>
+{
+    let mut probe = CompressionProbe {
+        layer_index, compression, total_block_count,
+        is_lossless: header.channels.list.iter().all(|channel| compression.is_lossless_for(channel.sample_type)),
+        sampled_block_count: blocks.len(),
+        uncompressed_byte_size: 0,
+        compressed_byte_size: 0,
+        duration: Duration::default(),
+
+        channels: header.channels.list.iter().map(|channel| ChannelCompressionProbe {
+            name: channel.name.clone(), uncompressed_byte_size: 0, compressed_byte_size: 0
+        }).collect(),
+    };
+
+    for block in blocks {
+        let bounds = header.get_absolute_block_pixel_coordinates(TileCoordinates {
+            tile_index: block.index.pixel_position / header.max_block_pixel_size(),
+            level_index: block.index.level,
+        })?;
+
+        let start = Instant::now();
+        let compressed = compression.compress_image_section(header, block.data.clone(), bounds)?;
+        probe.duration += start.elapsed();
+
+        probe.uncompressed_byte_size += block.data.len();
+        probe.compressed_byte_size += compressed.len();
+
+        for (channel_index, (channel, channel_probe)) in header.channels.list.iter().zip(&mut probe.channels).enumerate() {
+            let channel_bytes: Vec<u8> = block.lines(&header.channels)
+                .filter(|line| line.location.channel == channel_index)
+                .flat_map(|line| line.value.iter().copied())
+                .collect();
+
+            let channel_header = Header {
+                channels: ChannelList::new(smallvec![ channel.clone() ]),
+                .. header.clone()
+            };
+
+            channel_probe.uncompressed_byte_size += channel_bytes.len();
+            channel_probe.compressed_byte_size += compression
+                .compress_image_section(&channel_header, channel_bytes, bounds)?.len();
+        }
+    }
+
+    Ok(probe)
+}
+
+
+#[cfg(test)]
+mod test {
+    use crate::prelude::*;
+    use super::SUPPORTED_COMPRESSION_METHODS;
+
+    #[test]
+    fn probes_every_method_without_writing(){
+        let channels = AnyChannels::sort(smallvec::smallvec![
+            AnyChannel::new("flat", FlatSamples::F16(vec![ f16::ONE; 64 * 64 ])),
+            AnyChannel::new("noise", FlatSamples::F32((0 .. 64 * 64).map(|index| ((index * 7919) % 257) as f32).collect())),
+        ]);
+
+        let image = Image::from_encoded_channels((64, 64), Encoding::UNCOMPRESSED, channels);
+        let probes = image.write().probe_compression(2).unwrap();
+        assert_eq!(probes.len(), SUPPORTED_COMPRESSION_METHODS.len());
+
+        for probe in &probes {
+            assert_eq!(probe.sampled_block_count, 2);
+            assert!(probe.total_block_count >= 2);
+            assert_eq!(probe.channels.len(), 2);
+
+            let channel_bytes: usize = probe.channels.iter().map(|channel| channel.uncompressed_byte_size).sum();
+            assert_eq!(channel_bytes, probe.uncompressed_byte_size);
+        }
+
+        let zip = probes.iter().find(|probe| probe.compression == Compression::ZIP16).unwrap();
+        assert!(zip.is_lossless);
+        assert!(zip.channels[0].compression_ratio() < zip.channels[1].compression_ratio());
+
+        let uncompressed = probes.iter().find(|probe| probe.compression == Compression::Uncompressed).unwrap();
+        assert_eq!(uncompressed.compression_ratio(), 1.0);
+    }
+}
@@ -56,6 +56,19 @@ pub struct FlatSamplesWriter<'samples> {
 
 
 
+// used to write borrowed samples, for example a subset of the channels of an image
+impl<'slf, 'samples: 'slf, Samples> WritableSamples<'slf> for &'samples Samples
+    where Samples: WritableSamples<'samples>
+{
+    fn sample_type(&self) -> SampleType { (*self).sample_type() }
+    fn infer_level_modes(&self) -> (LevelMode, RoundingMode) { (*self).infer_level_modes() }
+
+    type Writer = Samples::Writer;
+    fn create_samples_writer(&'slf self, header: &Header) -> Self::Writer {
+        (*self).create_samples_writer(header)
+    }
+}
+
 // used if no layers are used and the flat samples are directly inside the channels
 impl<'samples> WritableSamples<'samples> for FlatSamples {
     fn sample_type(&self) -> SampleType {
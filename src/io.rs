@@ -1,8 +1,41 @@
 
 //! Specialized binary input and output.
 //! Uses the error handling for this crate.
+//!
+//! All values are stored in little endian byte order, as required by the exr specification,
+//! independent of the processor architecture.
+//! The `Data` trait is implemented for all primitive types that appear in exr files.
+//! Use it to serialize custom attribute values or custom codec data consistently with the rest of this crate.
+//!
+//! Reading never panics when the bytes run out. Instead, `Error::Invalid` is returned.
+//! When reading a number of elements that is stored in the file itself,
+//! specify a `hard_max` to reject absurd sizes before allocating memory.
+//!
+//! ```
+//! use exr::prelude::*;
+//! use exr::io::Data;
+//!
+//! // a studio-specific attribute, containing a version and a list of frame numbers
+//! let mut bytes = Vec::new();
+//! 2_u16.write(&mut bytes)?;
+//! i32::write_i32_sized_slice(&mut bytes, &[ 1001, 1002, 1010 ])?;
+//!
+//! let attribute = AttributeValue::Custom { kind: Text::from("frameList"), bytes };
+//!
+//! if let AttributeValue::Custom { bytes, .. } = &attribute {
+//!     let mut remaining_bytes = bytes.as_slice();
+//!
+//!     let version = u16::read(&mut remaining_bytes)?;
+//!     let frames = i32::read_i32_sized_vec(&mut remaining_bytes, 1024, Some(4096), "frame list size")?;
+//!
+//!     assert_eq!(version, 2);
+//!     assert_eq!(frames, vec![ 1001, 1002, 1010 ]);
+//!     assert!(u32::read(&mut remaining_bytes).is_err(), "all bytes have been consumed");
+//! }
+//!
+//! # Ok::<(), exr::error::Error>(())
+//! ```
 
-#![doc(hidden)]
 pub use ::std::io::{Read, Write};
 
 use half::slice::{HalfFloatSliceExt};
@@ -39,6 +72,7 @@ pub fn skip_bytes(read: &mut impl Read, count: usize) -> IoResult<()> {
 
 /// If an error occurs while writing, attempts to delete the partially written file.
 /// Creates a file just before the first write operation, not when this function is called.
+#[doc(hidden)]
 #[inline]
 pub fn attempt_delete_file_on_write_error<'p>(path: &'p Path, write: impl FnOnce(LateFile<'p>) -> UnitResult) -> UnitResult {
     match write(LateFile::from(path)) {
@@ -51,6 +85,8 @@ pub fn attempt_delete_file_on_write_error<'p>(path: &'p Path, write: impl FnOnce
     }
 }
 
+/// A file that is only created when the first byte is written.
+#[doc(hidden)]
 #[derive(Debug)]
 pub struct LateFile<'p> {
     path: &'p Path,
@@ -416,9 +452,42 @@ impl Data for f16 {
 
 #[cfg(test)]
 mod test {
-    use crate::io::PeekRead;
+    use crate::io::{PeekRead, Data};
+    use crate::error::Error;
     use std::io::Read;
 
+    #[test]
+    fn read_reports_missing_bytes(){
+        let mut bytes: &[u8] = &[ 1, 0, 0 ];
+        assert!(matches!(u32::read(&mut bytes), Err(Error::Invalid(_))));
+
+        let mut bytes: &[u8] = &[ 1, 0, 2, 0, 3 ];
+        let mut slice = [ 0_u16; 3 ];
+        assert!(matches!(u16::read_slice(&mut bytes, &mut slice), Err(Error::Invalid(_))));
+    }
+
+    #[test]
+    fn read_sized_vec_respects_hard_max(){
+        let mut bytes = Vec::new();
+        i32::write_i32_sized_slice(&mut bytes, &[ 7_i32; 5 ]).unwrap();
+
+        let too_large = i32::read_i32_sized_vec(&mut bytes.as_slice(), 16, Some(4), "test");
+        assert!(matches!(too_large, Err(Error::Invalid(_))));
+
+        let values = i32::read_i32_sized_vec(&mut bytes.as_slice(), 2, Some(5), "test").unwrap();
+        assert_eq!(values, vec![ 7_i32; 5 ]);
+    }
+
+    #[test]
+    fn values_are_little_endian(){
+        let mut bytes = Vec::new();
+        0x0102_u16.write(&mut bytes).unwrap();
+        (-2_i32).write(&mut bytes).unwrap();
+        1.0_f32.write(&mut bytes).unwrap();
+
+        assert_eq!(bytes, vec![ 0x02, 0x01,   0xfe, 0xff, 0xff, 0xff,   0, 0, 0x80, 0x3f ]);
+    }
+
     #[test]
     fn peek(){
         use lebe::prelude::*;
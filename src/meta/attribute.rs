@@ -93,6 +93,10 @@ pub enum AttributeValue {
         /// Use the `exr::io::Data` trait to extract binary values from this vector.
         bytes: Vec<u8>
     },
+
+    /// A custom attribute, parsed by a handler registered on the reader.
+    /// Contains the type name and the parsed value. See `exr::meta::custom`.
+    Structured(crate::meta::custom::StructuredAttribute),
 }
 
 /// A byte array with each byte being a char.
@@ -830,14 +834,18 @@ impl IntegerBounds {
 
         let min_i64 = Vec2(self.position.x() as i64, self.position.y() as i64);
 
+        // the maximum is inclusive, just like in the file
         let max_i64 = Vec2(
-            self.position.x() as i64 + self.size.width() as i64,
-            self.position.y() as i64 + self.size.height() as i64,
+            self.position.x() as i64 + self.size.width() as i64 - 1,
+            self.position.y() as i64 + self.size.height() as i64 - 1,
         );
 
         Self::validate_min_max_u64(min_i64, max_i64)
     }
 
+    /// Both the minimum and the inclusive maximum must be strictly within `i32::MAX / 2`.
+    /// This guarantees that the end and the size of the window, as well as the difference
+    /// between any two valid coordinates, can be computed without overflowing an `i32`.
     fn validate_min_max_u64(min: Vec2<i64>, max: Vec2<i64>) -> UnitResult {
         let max_box_size_as_i64 = (i32::MAX / 2) as i64; // as defined in the original c++ library
 
@@ -1173,6 +1181,51 @@ impl TimeCode {
     }
 
 
+    /// Advance this time code by the specified number of frames, wrapping around after 24 hours.
+    /// The frame rate is rounded up to the nominal rate, for example 24 for `(24000, 1001)`.
+    /// If this is a drop frame time code, the frame numbers dropped at the start of each minute
+    /// (except every tenth minute) are skipped, as done for 29.97 fps video.
+    /// Only the time is changed, all flags and binary groups are kept.
+    pub fn with_frames_added(self, frames: usize, frames_per_second: Rational) -> Result<Self> {
+        let (numerator, denominator) = frames_per_second;
+        if numerator <= 0 || denominator == 0 { return Err(Error::invalid("time code frame rate")); }
+
+        let nominal_rate = (numerator as u64 + denominator as u64 - 1) / denominator as u64;
+        if nominal_rate > 30 { return Err(Error::unsupported("time code frame rate above 30 fps")); }
+
+        let dropped_per_minute = if self.drop_frame { nominal_rate / 15 } else { 0 };
+        let frames_per_day = nominal_rate * 60 * 60 * 24 - dropped_per_minute * 9 * 6 * 24;
+
+        let minutes = self.hours as u64 * 60 + self.minutes as u64;
+        let frame_number = (minutes * 60 + self.seconds as u64) * nominal_rate + self.frame as u64
+            - dropped_per_minute * (minutes - minutes / 10);
+
+        let mut frame_number = (frame_number + frames as u64 % frames_per_day) % frames_per_day;
+
+        // insert the dropped frame numbers again, to obtain the displayed frame number
+        if dropped_per_minute != 0 {
+            let frames_per_minute = nominal_rate * 60 - dropped_per_minute;
+            let frames_per_ten_minutes = nominal_rate * 60 * 10 - dropped_per_minute * 9;
+
+            let ten_minutes = frame_number / frames_per_ten_minutes;
+            let remainder = frame_number % frames_per_ten_minutes;
+
+            frame_number += dropped_per_minute * 9 * ten_minutes;
+            if remainder > dropped_per_minute {
+                frame_number += dropped_per_minute * ((remainder - dropped_per_minute) / frames_per_minute);
+            }
+        }
+
+        let seconds = frame_number / nominal_rate;
+        Ok(TimeCode {
+            hours: (seconds / 3600) as u8,
+            minutes: (seconds / 60 % 60) as u8,
+            seconds: (seconds % 60) as u8,
+            frame: (frame_number % nominal_rate) as u8,
+            .. self
+        })
+    }
+
     /// Pack the SMPTE time code into a u32 value, according to TV60 packing.
     /// This is the encoding which is used within a binary exr file.
     pub fn pack_time_as_tv60_u32(&self) -> Result<u32> {
@@ -1688,6 +1741,7 @@ impl AttributeValue {
             TextVector(ref value) => value.iter().map(self::Text::i32_sized_byte_size).sum(),
             TileDescription(_) => self::TileDescription::byte_size(),
             Custom { ref bytes, .. } => bytes.len(),
+            Structured(ref value) => value.to_bytes().map_or(0, |bytes| bytes.len()), // errors are returned when writing
             BlockType(ref kind) => kind.byte_size()
         }
     }
@@ -1722,6 +1776,7 @@ impl AttributeValue {
             TextVector(_) =>  ty::TEXT_VECTOR,
             TileDescription(_) =>  ty::TILES,
             Custom { ref kind, .. } => &kind.bytes,
+            Structured(ref value) => &value.kind().bytes,
             BlockType(_) => super::BlockType::TYPE_NAME,
         }
     }
@@ -1765,6 +1820,7 @@ impl AttributeValue {
             TextVector(ref value) => self::Text::write_vec_of_i32_sized_texts(write, value)?,
             TileDescription(ref value) => value.write(write)?,
             Custom { ref bytes, .. } => u8::write_slice(write, &bytes)?, // write.write(&bytes).map(|_| ()),
+            Structured(ref value) => u8::write_slice(write, &value.to_bytes()?)?,
             BlockType(kind) => kind.write(write)?
         };
 
@@ -2047,6 +2103,26 @@ mod test {
         }
     }
 
+    #[test]
+    fn integer_bounds_at_specification_limits(){
+        let limit = i32::MAX / 2;
+        let largest = IntegerBounds::new((-limit + 1, -limit + 1), ((2 * limit - 1) as usize, (2 * limit - 1) as usize));
+        assert!(largest.validate(None).is_ok());
+        assert_eq!(largest.max(), Vec2(limit - 1, limit - 1));
+
+        let mut bytes = Vec::new();
+        largest.write(&mut bytes).unwrap();
+        assert_eq!(IntegerBounds::read(&mut bytes.as_slice()).unwrap(), largest);
+
+        assert!(IntegerBounds::new((limit - 1, 0), (2, 1)).validate(None).is_err());
+        assert!(IntegerBounds::new((-limit, 0), (1, 1)).validate(None).is_err());
+        assert!(IntegerBounds::new((i32::MAX, i32::MIN), (1, 1)).validate(None).is_err());
+
+        let mut bytes = Vec::new();
+        for value in [ i32::MIN, i32::MIN, i32::MAX, i32::MAX ] { value.write(&mut bytes).unwrap(); }
+        assert!(IntegerBounds::read(&mut bytes.as_slice()).is_err());
+    }
+
     #[test]
     fn attribute_write_read_roundtrip_and_byte_size(){
         let attributes = [
@@ -2223,4 +2299,23 @@ mod test {
         }
     }
 
+    #[test]
+    fn time_code_add_frames(){
+        let time = |hours, minutes, seconds, frame, drop_frame| TimeCode {
+            hours, minutes, seconds, frame, drop_frame, .. TimeCode::default()
+        };
+
+        assert_eq!(time(0,0,0,23, false).with_frames_added(1, (24, 1)).unwrap(), time(0,0,1,0, false));
+        assert_eq!(time(0,0,0,0, false).with_frames_added(24 * 3600 + 5, (24000, 1001)).unwrap(), time(1,0,0,5, false));
+        assert_eq!(time(23,59,59,24, false).with_frames_added(2, (25, 1)).unwrap(), time(0,0,0,1, false));
+
+        // frames 0 and 1 do not exist at the start of a minute, except for every tenth minute
+        assert_eq!(time(0,0,59,29, true).with_frames_added(1, (30000, 1001)).unwrap(), time(0,1,0,2, true));
+        assert_eq!(time(0,9,59,29, true).with_frames_added(1, (30000, 1001)).unwrap(), time(0,10,0,0, true));
+        assert_eq!(time(0,0,0,0, true).with_frames_added(17982, (30000, 1001)).unwrap(), time(0,10,0,0, true));
+
+        assert!(time(0,0,0,0, false).with_frames_added(1, (60, 1)).is_err());
+        assert!(time(0,0,0,0, false).with_frames_added(1, (0, 1)).is_err());
+    }
+
 }
@@ -0,0 +1,236 @@
+//! Parse studio-specific attribute types into structured values instead of opaque bytes.
+//! Implement `CustomAttribute` for your own type, register it in an `AttributeHandlers` registry,
+//! and pass the registry to a reader using `read().with_attribute_handlers(handlers)`.
+//! The registry only affects the reader it is passed to, there is no global state.
+//!
+//! Structured values serialize themselves, so they can be written by any writer
+//! without registering anything, and round-trip through the file unchanged.
+
+use std::any::Any;
+use std::collections::HashMap;
+use std::fmt::{Debug, Formatter};
+use std::panic::{RefUnwindSafe, UnwindSafe};
+use std::sync::Arc;
+
+use crate::error::{Result, UnitResult};
+use crate::meta::attribute::{AttributeValue, Text};
+use crate::meta::header::Header;
+
+
+/// A value of a custom attribute type that can be parsed from and serialized to bytes.
+/// Use the `exr::io::Data` trait to implement the byte conversions.
+pub trait CustomAttribute: 'static + Debug + PartialEq + Send + Sync + UnwindSafe + RefUnwindSafe + Sized {
+
+    /// The name of the attribute type in the file, for example `shotInfo`.
+    /// Must not be one of the standard type names.
+    const KIND: &'static str;
+
+    /// Parse the value from the bytes of the attribute.
+    fn parse(bytes: &[u8]) -> Result<Self>;
+
+    /// Append the bytes of the attribute to the vector.
+    fn serialize(&self, bytes: &mut Vec<u8>) -> UnitResult;
+}
+
+/// A custom attribute value that was parsed by a registered handler,
+/// or created from a `CustomAttribute` to be written.
+/// Use `get` to obtain the original value.
+#[derive(Clone)]
+pub struct StructuredAttribute {
+    kind: Text,
+    value: Arc<dyn DynamicValue>,
+}
+
+/// The registered custom attribute types of a reader.
+#[derive(Clone, Default)]
+pub struct AttributeHandlers {
+    parsers: HashMap<Text, fn(&[u8]) -> Result<StructuredAttribute>>,
+}
+
+
+/// Object safe part of `CustomAttribute`.
+/// Unwind safety keeps images with structured attributes usable with `catch_unwind`.
+trait DynamicValue: Debug + Send + Sync + UnwindSafe + RefUnwindSafe {
+    fn as_any(&self) -> &dyn Any;
+    fn equals(&self, other: &dyn Any) -> bool;
+    fn serialize(&self, bytes: &mut Vec<u8>) -> UnitResult;
+}
+
+impl<T: CustomAttribute> DynamicValue for T {
+    fn as_any(&self) -> &dyn Any { self }
+    fn equals(&self, other: &dyn Any) -> bool { other.downcast_ref::<T>() == Some(self) }
+    fn serialize(&self, bytes: &mut Vec<u8>) -> UnitResult { CustomAttribute::serialize(self, bytes) }
+}
+
+
+impl StructuredAttribute {
+
+    /// Wrap a custom value, so that it can be inserted into the attributes of an image.
+    pub fn new<T: CustomAttribute>(value: T) -> Self {
+        StructuredAttribute { kind: Text::from(T::KIND), value: Arc::new(value) }
+    }
+
+    /// The name of the attribute type in the file.
+    pub fn kind(&self) -> &Text { &self.kind }
+
+    /// The value, if it has the specified type.
+    pub fn get<T: CustomAttribute>(&self) -> Option<&T> {
+        self.value.as_any().downcast_ref()
+    }
+
+    /// Serialize the value to the bytes that will be stored in the file.
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        let mut bytes = Vec::new();
+        self.value.serialize(&mut bytes)?;
+        Ok(bytes)
+    }
+}
+
+impl Debug for StructuredAttribute {
+    fn fmt(&self, formatter: &mut Formatter<'_>) -> std::fmt::Result {
+        Debug::fmt(&self.value, formatter)
+    }
+}
+
+impl PartialEq for StructuredAttribute {
+    fn eq(&self, other: &Self) -> bool {
+        self.kind == other.kind && self.value.equals(other.value.as_any())
+    }
+}
+
+
+impl AttributeHandlers {
+
+    /// A registry without any custom attribute types.
+    pub fn new() -> Self { Self::default() }
+
+    /// Register a custom attribute type. Replaces any previous handler with the same type name.
+    pub fn with<T: CustomAttribute>(mut self) -> Self {
+        self.parsers.insert(Text::from(T::KIND), |bytes| T::parse(bytes).map(StructuredAttribute::new));
+        self
+    }
+
+    /// Whether a handler for the attribute type name is registered.
+    pub fn handles(&self, kind: &Text) -> bool {
+        self.parsers.contains_key(kind)
+    }
+
+    /// Parse the value if it is a custom attribute with a registered type.
+    /// Returns `None` for all other values.
+    pub fn parse(&self, value: &AttributeValue) -> Option<Result<AttributeValue>> {
+        match value {
+            AttributeValue::Custom { kind, bytes } => self.parsers.get(kind)
+                .map(|parse| parse(bytes).map(AttributeValue::Structured)),
+
+            _ => None,
+        }
+    }
+
+    /// Replace the custom attributes of all headers with structured values, where a handler is registered.
+    /// If pedantic, returns an error when a handler fails to parse an attribute.
+    /// Otherwise, such attributes remain unparsed.
+    pub fn parse_headers(&self, headers: &mut [Header], pedantic: bool) -> UnitResult {
+        if self.parsers.is_empty() { return Ok(()); }
+
+        for header in headers {
+            let attributes = header.shared_attributes.other.values_mut()
+                .chain(header.own_attributes.other.values_mut());
+
+            for value in attributes {
+                match self.parse(value) {
+                    Some(Ok(parsed)) => *value = parsed,
+                    Some(Err(error)) if pedantic => return Err(error),
+                    _ => {},
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Debug for AttributeHandlers {
+    fn fmt(&self, formatter: &mut Formatter<'_>) -> std::fmt::Result {
+        formatter.debug_set().entries(self.parsers.keys()).finish()
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use std::io::Cursor;
+    use crate::prelude::*;
+    use crate::io::Data;
+    use super::*;
+
+    #[derive(Debug, Clone, PartialEq)]
+    struct ShotInfo { frame: i32, camera: Text }
+
+    impl CustomAttribute for ShotInfo {
+        const KIND: &'static str = "shotInfo";
+
+        fn parse(bytes: &[u8]) -> Result<Self> {
+            let read = &mut &bytes[..];
+            Ok(ShotInfo { frame: i32::read(read)?, camera: Text::read_i32_sized(read, bytes.len())? })
+        }
+
+        fn serialize(&self, bytes: &mut Vec<u8>) -> UnitResult {
+            self.frame.write(bytes)?;
+            self.camera.write_i32_sized(bytes)
+        }
+    }
+
+    fn image_bytes(value: AttributeValue) -> Vec<u8> {
+        let mut attributes = LayerAttributes::default();
+        attributes.other.insert(Text::from("shot"), value);
+
+        let image = Image::from_layer(Layer::new(
+            (2, 2), attributes, Encoding::UNCOMPRESSED,
+            SpecificChannels::rgb(|_| (0.5_f32, 0.5_f32, 0.5_f32))
+        ));
+
+        let mut bytes = Vec::new();
+        image.write().to_buffered(Cursor::new(&mut bytes)).unwrap();
+        bytes
+    }
+
+    fn read_shot(bytes: Vec<u8>, handlers: AttributeHandlers) -> AttributeValue {
+        let image = read().no_deep_data().largest_resolution_level().all_channels().first_valid_layer().all_attributes()
+            .with_attribute_handlers(handlers)
+            .from_buffered(Cursor::new(bytes)).unwrap();
+
+        image.layer_data.attributes.other.get(&Text::from("shot")).unwrap().clone()
+    }
+
+    #[test]
+    fn structured_attribute_round_trip(){
+        let shot = ShotInfo { frame: 1001, camera: Text::from("main") };
+        let bytes = image_bytes(AttributeValue::Structured(StructuredAttribute::new(shot.clone())));
+
+        match read_shot(bytes.clone(), AttributeHandlers::new()) {
+            AttributeValue::Custom { kind, bytes } => {
+                assert_eq!(kind, Text::from("shotInfo"));
+                assert_eq!(ShotInfo::parse(&bytes).unwrap(), shot);
+            },
+            other => panic!("expected opaque bytes, got {:?}", other),
+        }
+
+        let parsed = read_shot(bytes, AttributeHandlers::new().with::<ShotInfo>());
+        assert_eq!(parsed, AttributeValue::Structured(StructuredAttribute::new(shot.clone())));
+
+        match parsed {
+            AttributeValue::Structured(value) => assert_eq!(value.get::<ShotInfo>(), Some(&shot)),
+            other => panic!("expected structured value, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn keep_unparsable_bytes(){
+        let broken = AttributeValue::Custom { kind: Text::from("shotInfo"), bytes: vec![ 1, 2 ] };
+        let handlers = AttributeHandlers::new().with::<ShotInfo>();
+
+        assert!(handlers.parse(&broken).unwrap().is_err());
+        assert!(handlers.parse(&AttributeValue::I32(3)).is_none());
+        assert_eq!(read_shot(image_bytes(broken.clone()), handlers), broken);
+    }
+}
@@ -527,27 +527,10 @@ impl Header {
     /// Starts at `0` and is not negative.
     pub fn get_block_data_indices(&self, block: &CompressedBlock) -> Result<TileCoordinates> {
         Ok(match block {
-            CompressedBlock::Tile(ref tile) => {
-                tile.coordinates
-            },
-
-            CompressedBlock::ScanLine(ref block) => {
-                let size = self.compression.scan_lines_per_block() as i32;
-
-                let diff = block.y_coordinate.checked_sub(self.own_attributes.layer_position.y()).ok_or(Error::invalid("invalid header"))?;
-                let y = diff.checked_div(size).ok_or(Error::invalid("invalid header"))?;
-
-                if y < 0 {
-                    return Err(Error::invalid("scan block y coordinate"));
-                }
-
-                TileCoordinates {
-                    tile_index: Vec2(0, y as usize),
-                    level_index: Vec2(0, 0)
-                }
-            },
-
-            _ => return Err(Error::unsupported("deep data not supported yet"))
+            CompressedBlock::Tile(ref tile) => tile.coordinates,
+            CompressedBlock::DeepTile(ref tile) => tile.coordinates,
+            CompressedBlock::ScanLine(ref block) => self.get_scan_line_block_tile_coordinates(block.y_coordinate)?,
+            CompressedBlock::DeepScanLine(ref block) => self.get_scan_line_block_tile_coordinates(block.y_coordinate)?,
         })
     }
 
@@ -556,12 +539,14 @@ impl Header {
         let size = self.compression.scan_lines_per_block() as i32;
 
         let diff = block_y_coordinate.checked_sub(self.own_attributes.layer_position.1).ok_or(Error::invalid("invalid header"))?;
-        let y = diff.checked_div(size).ok_or(Error::invalid("invalid header"))?;
 
-        if y < 0 {
+        // check before dividing, as the division rounds small negative values towards zero
+        if diff < 0 {
             return Err(Error::invalid("scan block y coordinate"));
         }
 
+        let y = diff.checked_div(size).ok_or(Error::invalid("invalid header"))?;
+
         Ok(TileCoordinates {
             tile_index: Vec2(0, y as usize),
             level_index: Vec2(0, 0)
@@ -579,8 +564,15 @@ impl Header {
 
     /// Returns the number of bytes that the pixels of this header will require
     /// when stored without compression. Respects multi-resolution levels and subsampling.
+    /// For deep data, this is the size of the sample count tables and of
+    /// `max_samples_per_pixel` samples in every pixel. Without that attribute,
+    /// the size of deep data is unknown, and `usize::MAX` is returned.
     pub fn total_pixel_bytes(&self) -> usize {
-        assert!(!self.deep);
+        let samples_per_pixel = match (self.deep, self.max_samples_per_pixel) {
+            (false, _) => 1,
+            (true, Some(max_samples_per_pixel)) => max_samples_per_pixel,
+            (true, None) => return usize::MAX,
+        };
 
         let pixel_count_of_levels = |size: Vec2<usize>| -> usize {
             match self.blocks {
@@ -597,21 +589,22 @@ impl Header {
             }
         };
 
+        // deep blocks store the number of samples of each pixel as an `i32`
+        let sample_count_table_bytes = if self.deep { pixel_count_of_levels(self.layer_size) * 4 } else { 0 };
+
         self.channels.list.iter()
             .map(|channel: &ChannelDescription|
                 pixel_count_of_levels(channel.subsampled_resolution(self.layer_size)) * channel.sample_type.bytes_per_sample()
             )
-            .sum()
-
+            .fold(sample_count_table_bytes, |sum, channel_bytes| sum.saturating_add(channel_bytes.saturating_mul(samples_per_pixel)))
     }
 
     /// Approximates the maximum number of bytes that the pixels of this header will consume in a file.
     /// Due to compression, the actual byte size may be smaller.
+    /// Returns `usize::MAX` for deep data without a maximum sample count.
     pub fn max_pixel_file_bytes(&self) -> usize {
-        assert!(!self.deep);
-
-        self.chunk_count * 64 // at most 64 bytes overhead for each chunk (header index, tile description, chunk size, and more)
-            + self.total_pixel_bytes()
+        (self.chunk_count * 64) // at most 64 bytes overhead for each chunk (header index, tile description, chunk size, and more)
+            .saturating_add(self.total_pixel_bytes())
     }
 
     /// Validate this instance.
@@ -710,14 +703,23 @@ impl Header {
 
     /// Read the headers without validating them.
     pub fn read_all(read: &mut PeekRead<impl Read>, version: &Requirements, pedantic: bool) -> Result<Headers> {
+        Self::read_all_with_warnings(read, version, pedantic, &mut Vec::new())
+    }
+
+    /// Read the headers without validating them.
+    /// Adds a warning for each tolerated anomaly, if not pedantic.
+    pub(crate) fn read_all_with_warnings(
+        read: &mut PeekRead<impl Read>, version: &Requirements, pedantic: bool, warnings: &mut Vec<Warning>
+    ) -> Result<Headers> {
         if !version.is_multilayer() {
-            Ok(smallvec![ Header::read(read, version, pedantic)? ])
+            Ok(smallvec![ Header::read_with_warnings(read, version, pedantic, 0, warnings)? ])
         }
         else {
             let mut headers = SmallVec::new();
 
             while !sequence_end::has_come(read)? {
-                headers.push(Header::read(read, version, pedantic)?);
+                let header = Header::read_with_warnings(read, version, pedantic, headers.len(), warnings)?;
+                headers.push(header);
             }
 
             Ok(headers)
@@ -739,6 +741,15 @@ impl Header {
 
     /// Read the value without validating.
     pub fn read(read: &mut PeekRead<impl Read>, requirements: &Requirements, pedantic: bool) -> Result<Self> {
+        Self::read_with_warnings(read, requirements, pedantic, 0, &mut Vec::new())
+    }
+
+    /// Read the value without validating.
+    /// Adds a warning for each tolerated anomaly, if not pedantic.
+    fn read_with_warnings(
+        read: &mut PeekRead<impl Read>, requirements: &Requirements, pedantic: bool,
+        layer_index: usize, warnings: &mut Vec<Warning>
+    ) -> Result<Self> {
         let max_string_len = if requirements.has_long_names { 256 } else { 32 }; // TODO DRY this information
 
         // these required attributes will be filled when encountered while parsing
@@ -853,6 +864,14 @@ impl Header {
                 // only abort reading the image if desired
                 Err(error) => {
                     if pedantic { return Err(error); }
+
+                    warnings.push(Warning::SkippedAttribute {
+                        layer_index, name: attribute_name,
+                        message: match error {
+                            Error::Invalid(message) | Error::NotSupported(message) => message,
+                            other => other.to_string().into(),
+                        },
+                    });
                 }
             }
         }
@@ -887,8 +906,14 @@ impl Header {
         };
 
         let computed_chunk_count = compute_chunk_count(compression, data_window.size, blocks);
-        if chunk_count.is_some() && pedantic && chunk_count != Some(computed_chunk_count) {
-            return Err(Error::invalid("chunk count not matching data size"));
+        if let Some(declared_chunk_count) = chunk_count {
+            if declared_chunk_count != computed_chunk_count {
+                if pedantic { return Err(Error::invalid("chunk count not matching data size")); }
+
+                warnings.push(Warning::ChunkCountMismatch {
+                    layer_index, declared: declared_chunk_count, computed: computed_chunk_count
+                });
+            }
         }
 
         let header = Header {
@@ -935,6 +960,8 @@ impl Header {
         use AttributeValue::*;
 
         let (block_type, tiles) = match self.blocks {
+            BlockDescription::ScanLines if self.deep => (attribute::BlockType::DeepScanLine, None),
+            BlockDescription::Tiles(tiles) if self.deep => (attribute::BlockType::DeepTile, Some(tiles)),
             BlockDescription::ScanLines => (attribute::BlockType::ScanLine, None),
             BlockDescription::Tiles(tiles) => (attribute::BlockType::Tile, Some(tiles))
         };
@@ -5,6 +5,9 @@
 
 pub mod attribute;
 pub mod header;
+pub mod scan;
+pub mod rewrite;
+pub mod custom;
 
 
 use crate::io::*;
@@ -374,12 +377,14 @@ impl MetaData {
     #[must_use]
     pub fn read_from_buffered(buffered: impl Read, pedantic: bool) -> Result<Self> {
         let mut read = PeekRead::new(buffered);
-        MetaData::read_unvalidated_from_buffered_peekable(&mut read, pedantic)
+        MetaData::read_unvalidated_from_buffered_peekable(&mut read, pedantic, &mut Vec::new())
     }
 
     /// Does __not validate__ the meta data completely.
     #[must_use]
-    pub(crate) fn read_unvalidated_from_buffered_peekable(read: &mut PeekRead<impl Read>, pedantic: bool) -> Result<Self> {
+    pub(crate) fn read_unvalidated_from_buffered_peekable(
+        read: &mut PeekRead<impl Read>, pedantic: bool, warnings: &mut Vec<Warning>
+    ) -> Result<Self> {
         magic_number::validate_exr(read)?;
 
         let requirements = Requirements::read(read)?;
@@ -387,19 +392,27 @@ impl MetaData {
         // do this check now in order to fast-fail for newer versions and features than version 2
         requirements.validate()?;
 
-        let headers = Header::read_all(read, &requirements, pedantic)?;
+        let headers = Header::read_all_with_warnings(read, &requirements, pedantic, warnings)?;
 
         // TODO check if supporting requirements 2 always implies supporting requirements 1
         Ok(MetaData { requirements, headers })
     }
 
     /// Validates the meta data.
+    /// If not pedantic, adds a warning for each tolerated anomaly.
     #[must_use]
     pub(crate) fn read_validated_from_buffered_peekable(
-        read: &mut PeekRead<impl Read>, pedantic: bool
+        read: &mut PeekRead<impl Read>, pedantic: bool, warnings: &mut Vec<Warning>
     ) -> Result<Self> {
-        let meta_data = Self::read_unvalidated_from_buffered_peekable(read, !pedantic)?;
+        let meta_data = Self::read_unvalidated_from_buffered_peekable(read, pedantic, warnings)?;
         MetaData::validate(meta_data.headers.as_slice(), pedantic)?;
+
+        if !pedantic {
+            if let Err(Error::Invalid(message)) = MetaData::validate(meta_data.headers.as_slice(), true) {
+                warnings.push(Warning::InvalidMetaData(message));
+            }
+        }
+
         Ok(meta_data)
     }
 
@@ -417,6 +430,17 @@ impl MetaData {
         Ok(minimal_requirements)
     }
 
+    /// Writes the meta data to the stream without validating it.
+    /// Returns the automatically detected minimum requirement flags.
+    pub(crate) fn write_unvalidated_to_buffered(write: &mut impl Write, headers: &[Header]) -> Result<Requirements> {
+        let minimal_requirements = Self::infer_requirements(headers);
+
+        magic_number::write(write)?;
+        minimal_requirements.write(write)?;
+        Header::write_all(headers, write, minimal_requirements.has_multiple_layers)?;
+        Ok(minimal_requirements)
+    }
+
     /// Read one offset table from the reader for each header.
     pub fn read_offset_tables(read: &mut PeekRead<impl Read>, headers: &Headers) -> Result<OffsetTables> {
         headers.iter()
@@ -464,35 +488,45 @@ impl MetaData {
         )
     }
 
-    /// Validates this meta data. Returns the minimal possible requirements.
-    pub fn validate(headers: &[Header], pedantic: bool) -> Result<Requirements> {
-        if headers.len() == 0 {
-            return Err(Error::invalid("at least one layer is required"));
-        }
-
-        let deep = false; // TODO deep data
+    /// Computes the minimal requirements for writing these headers, without validating them.
+    /// Useful if the headers are known to be valid, for example when writing
+    /// thousands of small images that were all constructed by the same code.
+    pub fn infer_requirements(headers: &[Header]) -> Requirements {
+        let deep = headers.iter().any(|header| header.deep);
         let is_multilayer = headers.len() > 1;
         let first_header_has_tiles = headers.iter().next()
             .map_or(false, |header| header.blocks.has_tiles());
 
-        let mut minimal_requirements = Requirements {
+        // only custom attribute names can be long, the standard names are all short
+        let has_long_names = headers.iter()
+            .flat_map(|header| header.shared_attributes.other.keys().chain(header.own_attributes.other.keys()))
+            .any(|name| name.as_slice().len() >= 32);
+
+        Requirements {
             // according to the spec, version 2  should only be necessary if `is_multilayer || deep`.
             // but the current open exr library does not support images with version 1, so always use version 2.
             file_format_version: 2,
+            has_long_names,
 
-            // start as low as possible, later increasing if required
-            has_long_names: false,
-
-            is_single_layer_and_tiled: !is_multilayer && first_header_has_tiles,
+            // single-part deep files are identified by the deep flag only
+            is_single_layer_and_tiled: !is_multilayer && !deep && first_header_has_tiles,
             has_multiple_layers: is_multilayer,
             has_deep_data: deep,
-        };
+        }
+    }
 
-        for header in headers {
-            if header.deep { // TODO deep data (and then remove this check)
-                return Err(Error::unsupported("deep data not supported yet"));
-            }
+    /// Validates this meta data. Returns the minimal possible requirements.
+    pub fn validate(headers: &[Header], pedantic: bool) -> Result<Requirements> {
+        if headers.len() == 0 {
+            return Err(Error::invalid("at least one layer is required"));
+        }
+
+        let is_multilayer = headers.len() > 1;
 
+        // start as low as possible, later increasing if required
+        let mut minimal_requirements = Requirements { has_long_names: false, .. Self::infer_requirements(headers) };
+
+        for header in headers {
             header.validate(is_multilayer, &mut minimal_requirements.has_long_names, pedantic)?;
         }
 
@@ -808,14 +842,31 @@ mod test {
         let mut layer_2 = header_version_2_long_names.clone();
         layer_2.own_attributes.layer_name = Some(Text::new_or_panic("anythingelse"));
 
-        let low_requirements = MetaData::validate(
-            &[header_version_2_long_names, layer_2], true
-        ).unwrap();
+        let headers = [header_version_2_long_names, layer_2];
+        let low_requirements = MetaData::validate(&headers, true).unwrap();
 
         assert_eq!(low_requirements.has_long_names, true);
         assert_eq!(low_requirements.file_format_version, 2);
         assert_eq!(low_requirements.has_deep_data, false);
         assert_eq!(low_requirements.has_multiple_layers, true);
+        assert_eq!(MetaData::infer_requirements(&headers), low_requirements);
+    }
+
+    #[test]
+    fn unchecked_write_matches_validated_write() {
+        use crate::prelude::*;
+
+        let image = Image::from_channels((7, 5), SpecificChannels::rgb(|position: Vec2<usize>| (
+            position.x() as f32, position.y() as f32, 0.5_f32
+        )));
+
+        let mut validated = Vec::new();
+        image.write().non_parallel().to_buffered(std::io::Cursor::new(&mut validated)).unwrap();
+
+        let mut unchecked = Vec::new();
+        image.write().non_parallel().unchecked().to_buffered(std::io::Cursor::new(&mut unchecked)).unwrap();
+
+        assert_eq!(validated, unchecked);
     }
 }
 
@@ -0,0 +1,325 @@
+
+//! Change the meta data of exr files without decompressing or recompressing any pixels.
+//! The compressed chunks are copied byte for byte, only the headers and the offset tables are rewritten.
+//! This makes it cheap to retime a sequence of frames, for example when an edit changes the time codes.
+
+use crate::meta::{MetaData, Headers};
+use crate::meta::header::Header;
+use crate::meta::attribute::{TimeCode, Rational};
+use crate::io::{PeekRead, Tracking, Data};
+use crate::error::{UnitResult, Error, usize_to_u64};
+use rayon_core::{ThreadPool, ThreadPoolBuildError};
+use std::io::{Read, Write, BufReader, BufWriter};
+use std::convert::TryFrom;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+
+
+/// Read the meta data from the reader, modify the headers with the closure,
+/// and write the modified meta data to the writer, followed by the unchanged chunks.
+/// The offset tables are adjusted to the new size of the headers.
+///
+/// The closure may change any attribute that does not affect how the pixels are stored.
+/// Changing the channels, the compression, the block layout, the line order or the data window
+/// is an error, because the copied chunks would not match the headers anymore.
+pub fn rewrite_headers(
+    read: impl Read, write: impl Write, pedantic: bool,
+    update: impl FnOnce(&mut Headers) -> UnitResult
+) -> UnitResult
+{
+    let mut read = PeekRead::new(Tracking::new(read));
+    let MetaData { headers: original_headers, .. } = MetaData::read_validated_from_buffered_peekable(&mut read, pedantic, &mut Vec::new())?;
+    let original_meta_data_byte_size = read.byte_position();
+    let offset_tables = MetaData::read_offset_tables(&mut read, &original_headers)?;
+
+    let mut headers = original_headers.clone();
+    update(&mut headers)?;
+
+    let layout_unchanged = headers.len() == original_headers.len() && headers.iter().zip(&original_headers)
+        .all(|(header, original)| has_same_chunk_layout(header, original));
+
+    if !layout_unchanged {
+        return Err(Error::invalid("meta data rewrite must not change the chunk layout"));
+    }
+
+    let mut meta_data_bytes = Vec::with_capacity(original_meta_data_byte_size);
+    MetaData::write_validating_to_buffered(&mut meta_data_bytes, headers.as_slice(), pedantic)?;
+
+    // the offsets point to absolute byte positions in the file,
+    // so all chunks move by the size difference of the meta data
+    let added_bytes = i128::from(usize_to_u64(meta_data_bytes.len())) - i128::from(usize_to_u64(original_meta_data_byte_size));
+
+    let mut write = BufWriter::new(write);
+    write.write_all(&meta_data_bytes)?;
+
+    for offset_table in offset_tables {
+        for offset in offset_table {
+            // zero offsets mark missing chunks in incomplete files
+            let offset = if offset == 0 { 0 } else {
+                u64::try_from(i128::from(offset) + added_bytes)
+                    .map_err(|_| Error::invalid("chunk offset"))?
+            };
+
+            offset.write(&mut write)?;
+        }
+    }
+
+    std::io::copy(&mut read, &mut write)?;
+    write.flush()?;
+    Ok(())
+}
+
+/// Modify the headers of the file with the closure, leaving all chunks untouched.
+/// The file is written to a temporary file in the same directory first,
+/// which then replaces the original file. The original file is unchanged if an error occurs.
+/// See `rewrite_headers` for which changes are allowed.
+pub fn rewrite_headers_of_file(
+    path: impl AsRef<Path>, pedantic: bool,
+    update: impl FnOnce(&mut Headers) -> UnitResult
+) -> UnitResult
+{
+    let path = path.as_ref();
+    let file_name = path.file_name().ok_or_else(|| Error::invalid("file path without file name"))?;
+
+    let mut temporary_name = std::ffi::OsString::from(".");
+    temporary_name.push(file_name);
+    temporary_name.push(".rewrite");
+    let temporary_path = path.with_file_name(temporary_name);
+
+    let read = BufReader::new(File::open(path)?);
+    crate::io::attempt_delete_file_on_write_error(&temporary_path, move |write|{
+        rewrite_headers(read, write, pedantic, update)
+    })?;
+
+    std::fs::rename(&temporary_path, path).map_err(|error| {
+        let _deleted = std::fs::remove_file(&temporary_path); // ignore deletion errors
+        Error::from(error)
+    })
+}
+
+fn has_same_chunk_layout(header: &Header, original: &Header) -> bool {
+    header.channels == original.channels
+        && header.compression == original.compression
+        && header.blocks == original.blocks
+        && header.line_order == original.line_order
+        && header.layer_size == original.layer_size
+        && header.own_attributes.layer_position == original.own_attributes.layer_position
+        && header.deep == original.deep
+        && header.deep_data_version == original.deep_data_version
+        && header.max_samples_per_pixel == original.max_samples_per_pixel
+        && header.chunk_count == original.chunk_count
+}
+
+
+/// The new timing of a sequence of frames, where each file contains one frame.
+/// Attributes that are `None` are not changed.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Retiming {
+
+    /// The time code of the first file in the sequence.
+    /// The following files receive consecutive time codes, counted with the frame rate.
+    pub first_time_code: Option<TimeCode>,
+
+    /// The frame rate of the sequence.
+    /// If not specified, the existing frame rate of each file is used to count the time codes.
+    pub frames_per_second: Option<Rational>,
+}
+
+impl Retiming {
+
+    /// Set the time code and frame rate in all headers of the frame with the specified index in the sequence.
+    pub fn apply_to_headers(&self, headers: &mut [Header], frame_index: usize) -> UnitResult {
+        for header in headers.iter_mut() {
+            if let Some(frames_per_second) = self.frames_per_second {
+                header.own_attributes.frames_per_second = Some(frames_per_second);
+            }
+        }
+
+        if let Some(first_time_code) = self.first_time_code {
+            let frames_per_second = headers.iter()
+                .find_map(|header| header.own_attributes.frames_per_second)
+                .ok_or_else(|| Error::invalid("retiming requires a frame rate to count time codes"))?;
+
+            let time_code = first_time_code.with_frames_added(frame_index, frames_per_second)?;
+            for header in headers.iter_mut() {
+                header.shared_attributes.time_code = Some(time_code);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Retime a sequence of files in parallel, rewriting only the meta data of each file.
+/// The files must be ordered by frame. Returns the result of each file, in the order of the paths.
+/// Use `retime_files_with_thread_pool` to customize the threadpool.
+pub fn retime_files(paths: &[PathBuf], retiming: Retiming, pedantic: bool) -> Vec<UnitResult> {
+    retime_files_with_thread_pool(paths, retiming, pedantic, ||{
+        rayon_core::ThreadPoolBuilder::new()
+            .thread_name(|index| format!("OpenEXR Retiming Thread #{}", index))
+            .build()
+    })
+}
+
+/// Retime a sequence of files in parallel, rewriting only the meta data of each file.
+/// Reverts to sequential rewriting if the thread pool cannot be created.
+/// The files must be ordered by frame. Returns the result of each file, in the order of the paths.
+pub fn retime_files_with_thread_pool<CreatePool>(paths: &[PathBuf], retiming: Retiming, pedantic: bool, try_create_thread_pool: CreatePool)
+    -> Vec<UnitResult> where CreatePool: FnOnce() -> std::result::Result<ThreadPool, ThreadPoolBuildError>
+{
+    // in case thread pool creation fails (for example on WASM currently),
+    // we revert to sequential rewriting
+    let pool: ThreadPool = match try_create_thread_pool() {
+        Ok(pool) => pool,
+
+        // TODO print warning?
+        Err(_) => return retime_files_sequential(paths, retiming, pedantic),
+    };
+
+    let (sender, receiver) = flume::unbounded();
+
+    pool.scope(|scope|{
+        for (frame_index, path) in paths.iter().enumerate() {
+            let sender = sender.clone();
+
+            scope.spawn(move |_| {
+                let result = retime_file(path, frame_index, retiming, pedantic);
+                sender.send((frame_index, result)).expect("receiver hung up before retiming finished");
+            });
+        }
+    });
+
+    drop(sender);
+
+    let mut results: Vec<(usize, UnitResult)> = receiver.into_iter().collect();
+    results.sort_by_key(|(frame_index, _)| *frame_index);
+    debug_assert_eq!(results.len(), paths.len(), "retiming result count bug");
+
+    results.into_iter().map(|(_, result)| result).collect()
+}
+
+/// Retime a sequence of files one after another, rewriting only the meta data of each file.
+/// The files must be ordered by frame. Returns the result of each file, in the order of the paths.
+pub fn retime_files_sequential(paths: &[PathBuf], retiming: Retiming, pedantic: bool) -> Vec<UnitResult> {
+    paths.iter().enumerate()
+        .map(|(frame_index, path)| retime_file(path, frame_index, retiming, pedantic))
+        .collect()
+}
+
+fn retime_file(path: &Path, frame_index: usize, retiming: Retiming, pedantic: bool) -> UnitResult {
+    rewrite_headers_of_file(path, pedantic, |headers| retiming.apply_to_headers(headers, frame_index))
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::prelude::*;
+    use std::io::Cursor;
+
+    fn frame_bytes(time_code: Option<TimeCode>) -> Vec<u8> {
+        let channels = AnyChannels::sort(smallvec::smallvec![
+            AnyChannel::new("Y", FlatSamples::F32((0 .. 40 * 30).map(|index| index as f32).collect())),
+        ]);
+
+        let encoding = Encoding { compression: Compression::ZIP16, .. Encoding::default() };
+        let mut image = Image::from_encoded_channels((40, 30), encoding, channels);
+        image.attributes.time_code = time_code;
+
+        let mut bytes = Vec::new();
+        image.write().to_buffered(Cursor::new(&mut bytes)).unwrap();
+        bytes
+    }
+
+    fn read_headers(bytes: &[u8]) -> Headers {
+        MetaData::read_from_buffered(bytes, true).unwrap().headers
+    }
+
+    #[test]
+    fn rewrite_keeps_chunk_bytes(){
+        let original = frame_bytes(None);
+        let time_code = TimeCode { hours: 1, minutes: 2, seconds: 3, frame: 4, .. TimeCode::default() };
+
+        let mut rewritten = Vec::new();
+        rewrite_headers(original.as_slice(), &mut rewritten, true, |headers|{
+            Retiming { first_time_code: Some(time_code), frames_per_second: Some((24, 1)) }
+                .apply_to_headers(headers, 2)
+        }).unwrap();
+
+        let headers = read_headers(&rewritten);
+        let expected_time_code = TimeCode { frame: 6, .. time_code };
+        assert_eq!(headers[0].shared_attributes.time_code, Some(expected_time_code));
+        assert_eq!(headers[0].own_attributes.frames_per_second, Some((24, 1)));
+
+        let chunk_byte_size = original.len() - read_chunks_start(&original);
+        assert_eq!(&original[original.len() - chunk_byte_size ..], &rewritten[rewritten.len() - chunk_byte_size ..]);
+
+        let original_image = read_all_flat_layers_from_buffered(&original);
+        let rewritten_image = read_all_flat_layers_from_buffered(&rewritten);
+        assert_eq!(original_image.layer_data[0].channel_data, rewritten_image.layer_data[0].channel_data);
+    }
+
+    #[test]
+    fn rewrite_can_shrink_headers(){
+        let original = frame_bytes(Some(TimeCode::default()));
+
+        let mut rewritten = Vec::new();
+        rewrite_headers(original.as_slice(), &mut rewritten, true, |headers|{
+            headers[0].shared_attributes.time_code = None;
+            Ok(())
+        }).unwrap();
+
+        assert!(rewritten.len() < original.len());
+        assert_eq!(read_headers(&rewritten)[0].shared_attributes.time_code, None);
+        assert_eq!(read_all_flat_layers_from_buffered(&original).layer_data, read_all_flat_layers_from_buffered(&rewritten).layer_data);
+    }
+
+    #[test]
+    fn rewrite_rejects_layout_changes(){
+        let original = frame_bytes(None);
+
+        let result = rewrite_headers(original.as_slice(), Vec::new(), true, |headers|{
+            headers[0].compression = Compression::RLE;
+            Ok(())
+        });
+
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn retime_sequence_of_files(){
+        let directory = std::env::temp_dir().join(format!("exrs-retime-test-{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+
+        let paths: Vec<PathBuf> = (0 .. 4).map(|index| directory.join(format!("frame.{}.exr", index))).collect();
+        for path in &paths { std::fs::write(path, frame_bytes(None)).unwrap(); }
+
+        let retiming = Retiming {
+            first_time_code: Some(TimeCode { seconds: 10, frame: 23, .. TimeCode::default() }),
+            frames_per_second: Some((24, 1)),
+        };
+
+        let results = retime_files(&paths, retiming, true);
+        assert!(results.iter().all(|result| result.is_ok()));
+
+        let time_codes: Vec<(u8, u8)> = paths.iter()
+            .map(|path| MetaData::read_from_file(path, true).unwrap().headers[0].shared_attributes.time_code.unwrap())
+            .map(|time_code| (time_code.seconds, time_code.frame))
+            .collect();
+
+        std::fs::remove_dir_all(&directory).unwrap();
+        assert_eq!(time_codes, vec![ (10, 23), (11, 0), (11, 1), (11, 2) ]);
+    }
+
+    fn read_chunks_start(bytes: &[u8]) -> usize {
+        let mut read = PeekRead::new(Tracking::new(bytes));
+        let meta = MetaData::read_validated_from_buffered_peekable(&mut read, true, &mut Vec::new()).unwrap();
+        MetaData::skip_offset_tables(&mut read, &meta.headers).unwrap();
+        read.byte_position()
+    }
+
+    fn read_all_flat_layers_from_buffered(bytes: &[u8]) -> FlatImage {
+        read().no_deep_data().largest_resolution_level().all_channels().all_layers().all_attributes()
+            .from_buffered(Cursor::new(bytes)).unwrap()
+    }
+}
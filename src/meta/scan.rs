@@ -0,0 +1,216 @@
+
+//! Read the meta data of all exr files in a directory, for example to build an asset database.
+//! Only the headers are read, the pixel data of the files is not touched.
+
+use crate::meta::MetaData;
+use crate::error::{Result, Error};
+use rayon_core::{ThreadPool, ThreadPoolBuildError};
+use std::path::{Path, PathBuf};
+
+
+/// The path of a file found by the `DirectoryScanner`, and the meta data of that file.
+/// Unreadable subdirectories are reported with the path of the directory and the error.
+pub type ScannedFile = (PathBuf, Result<MetaData>);
+
+/// Reads the headers of all exr files in a directory and its subdirectories.
+/// A file is considered an exr file if its extension is `exr`, ignoring case.
+/// Symbolic links are not followed.
+///
+/// When reading in parallel, the files are yielded in the order in which they finish.
+/// Implements iterator.
+#[derive(Debug)]
+pub struct DirectoryScanner {
+    remaining: usize,
+    strategy: ScanStrategy,
+}
+
+#[derive(Debug)]
+enum ScanStrategy {
+    Sequential {
+        directory_errors: std::vec::IntoIter<(PathBuf, Error)>,
+        paths: std::vec::IntoIter<PathBuf>,
+        pedantic: bool,
+    },
+
+    Parallel {
+        receiver: flume::Receiver<ScannedFile>,
+
+        // the pool finishes all spawned jobs when dropped,
+        // but we keep it until all files have been yielded
+        _pool: ThreadPool,
+    },
+}
+
+impl DirectoryScanner {
+
+    /// Find all exr files in the directory, and start reading their meta data in parallel.
+    /// Fails if the directory itself cannot be read.
+    /// Use `new_with_thread_pool` to customize the threadpool.
+    pub fn new(directory: impl AsRef<Path>, pedantic: bool) -> Result<Self> {
+        Self::new_with_thread_pool(directory, pedantic, ||{
+            rayon_core::ThreadPoolBuilder::new()
+                .thread_name(|index| format!("OpenEXR Directory Scanner Thread #{}", index))
+                .build()
+        })
+    }
+
+    /// Find all exr files in the directory, and start reading their meta data in parallel.
+    /// Reverts to sequential reading if the thread pool cannot be created.
+    /// Fails if the directory itself cannot be read.
+    pub fn new_with_thread_pool<CreatePool>(directory: impl AsRef<Path>, pedantic: bool, try_create_thread_pool: CreatePool)
+        -> Result<Self> where CreatePool: FnOnce() -> std::result::Result<ThreadPool, ThreadPoolBuildError>
+    {
+        let (paths, directory_errors) = find_exr_files(directory.as_ref())?;
+
+        // in case thread pool creation fails (for example on WASM currently),
+        // we revert to sequential reading
+        let pool = match try_create_thread_pool() {
+            Ok(pool) => pool,
+
+            // TODO print warning?
+            Err(_) => return Ok(Self::sequential(paths, directory_errors, pedantic)),
+        };
+
+        let remaining = paths.len() + directory_errors.len();
+        let (sender, receiver) = flume::unbounded();
+
+        for (path, error) in directory_errors {
+            sender.send((path, Err(error))).expect("receiver hung up before scanning started");
+        }
+
+        for path in paths {
+            let sender = sender.clone();
+
+            pool.spawn(move || {
+                let meta_data = MetaData::read_from_file(&path, pedantic);
+
+                // the scanner may have been dropped by now,
+                // in which case nobody is interested in the result
+                let _ = sender.send((path, meta_data));
+            });
+        }
+
+        Ok(Self { remaining, strategy: ScanStrategy::Parallel { receiver, _pool: pool } })
+    }
+
+    /// Find all exr files in the directory, and read their meta data one file after another.
+    /// Fails if the directory itself cannot be read.
+    pub fn new_sequential(directory: impl AsRef<Path>, pedantic: bool) -> Result<Self> {
+        let (paths, directory_errors) = find_exr_files(directory.as_ref())?;
+        Ok(Self::sequential(paths, directory_errors, pedantic))
+    }
+
+    fn sequential(paths: Vec<PathBuf>, directory_errors: Vec<(PathBuf, Error)>, pedantic: bool) -> Self {
+        Self {
+            remaining: paths.len() + directory_errors.len(),
+            strategy: ScanStrategy::Sequential {
+                directory_errors: directory_errors.into_iter(),
+                paths: paths.into_iter(),
+                pedantic
+            }
+        }
+    }
+
+    /// Read the next header. Returns `None` when all files have been read.
+    pub fn scan_next_file(&mut self) -> Option<ScannedFile> {
+        if self.remaining == 0 { return None; }
+        self.remaining -= 1;
+
+        match &mut self.strategy {
+            ScanStrategy::Sequential { directory_errors, paths, pedantic } => {
+                if let Some((path, error)) = directory_errors.next() {
+                    return Some((path, Err(error)));
+                }
+
+                let path = paths.next()?;
+                let meta_data = MetaData::read_from_file(&path, *pedantic);
+                Some((path, meta_data))
+            },
+
+            ScanStrategy::Parallel { receiver, .. } => Some(
+                receiver.recv().expect("all scanning threads hung up but more files were expected")
+            ),
+        }
+    }
+}
+
+impl ExactSizeIterator for DirectoryScanner {}
+impl Iterator for DirectoryScanner {
+    type Item = ScannedFile;
+    fn next(&mut self) -> Option<Self::Item> { self.scan_next_file() }
+    fn size_hint(&self) -> (usize, Option<usize>) { (self.remaining, Some(self.remaining)) }
+}
+
+
+/// Collect all exr file paths below the directory, sorted by path.
+/// Errors for the root directory are returned, errors for subdirectories are collected.
+fn find_exr_files(directory: &Path) -> Result<(Vec<PathBuf>, Vec<(PathBuf, Error)>)> {
+    let mut paths = Vec::new();
+    let mut errors = Vec::new();
+    let mut directories = vec![ directory.to_path_buf() ];
+    let mut is_root = true;
+
+    while let Some(directory) = directories.pop() {
+        let entries = match std::fs::read_dir(&directory) {
+            Ok(entries) => entries,
+            Err(error) if is_root => return Err(Error::from(error)),
+            Err(error) => { errors.push((directory, Error::from(error))); continue; }
+        };
+
+        is_root = false;
+
+        for entry in entries {
+            let entry = match entry {
+                Ok(entry) => entry,
+                Err(error) => { errors.push((directory.clone(), Error::from(error))); continue; }
+            };
+
+            let path = entry.path();
+            let file_type = match entry.file_type() {
+                Ok(file_type) => file_type,
+                Err(error) => { errors.push((path, Error::from(error))); continue; }
+            };
+
+            if file_type.is_dir() { directories.push(path); }
+            else if file_type.is_file() && has_exr_extension(&path) { paths.push(path); }
+        }
+    }
+
+    paths.sort();
+    Ok((paths, errors))
+}
+
+fn has_exr_extension(path: &Path) -> bool {
+    path.extension()
+        .and_then(|extension| extension.to_str())
+        .map_or(false, |extension| extension.eq_ignore_ascii_case("exr"))
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parallel_and_sequential_find_the_same_headers(){
+        let directory = "tests/images/valid/openexr/MultiResolution";
+
+        let mut parallel: Vec<ScannedFile> = DirectoryScanner::new(directory, false).unwrap().collect();
+        let sequential: Vec<ScannedFile> = DirectoryScanner::new_sequential(directory, false).unwrap().collect();
+
+        assert!(!sequential.is_empty());
+        parallel.sort_by(|(a, _), (b, _)| a.cmp(b));
+
+        assert_eq!(parallel.len(), sequential.len());
+        for ((parallel_path, parallel_meta), (sequential_path, sequential_meta)) in parallel.iter().zip(&sequential) {
+            assert_eq!(parallel_path, sequential_path);
+            assert!(has_exr_extension(parallel_path));
+            assert_eq!(parallel_meta.as_ref().unwrap().headers, sequential_meta.as_ref().unwrap().headers);
+        }
+    }
+
+    #[test]
+    fn missing_directory_fails(){
+        assert!(DirectoryScanner::new_sequential("tests/images/does-not-exist", false).is_err());
+    }
+}
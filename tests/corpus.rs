@@ -0,0 +1,146 @@
+//! Checks the reader against the official OpenEXR test image corpus.
+//! This test is ignored by default, as it requires downloading more than a gigabyte of images.
+//!
+//! Run it using `cargo test --test corpus -- --ignored --nocapture`.
+//! To use an existing copy of the corpus, set `EXR_CORPUS` to its directory.
+//! Otherwise, the corpus is cloned into `target/openexr-images` using `git`.
+//! The compatibility report is printed and also written to `target/corpus-report.txt`.
+
+extern crate exr;
+
+use std::{panic, fs};
+use std::ffi::OsStr;
+use std::fmt::Write;
+use std::panic::catch_unwind;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use exr::prelude::*;
+use exr::error::Error;
+use exr::block::deep::DeepReport;
+use rayon::prelude::IntoParallelIterator;
+use rayon::iter::ParallelIterator;
+
+const CORPUS_REPOSITORY: &str = "https://github.com/AcademySoftwareFoundation/openexr-images.git";
+
+#[derive(Debug, Eq, PartialEq, Ord, PartialOrd)]
+enum Outcome { Ok, Unsupported(String), Invalid(String), Io(String), Panic }
+
+#[test]
+#[ignore]
+fn read_official_corpus() {
+    let corpus = corpus_directory();
+    let files: Vec<PathBuf> = walkdir::WalkDir::new(&corpus).into_iter()
+        .map(std::result::Result::unwrap)
+        .filter(|entry| entry.path().extension() == Some(OsStr::new("exr")))
+        .map(walkdir::DirEntry::into_path)
+        .collect();
+
+    assert!(!files.is_empty(), "no exr files found in {:?}", corpus);
+
+    let mut results: Vec<(PathBuf, Outcome)> = files.into_par_iter()
+        .map(|file| {
+            let outcome = check_file(&file);
+            (file.strip_prefix(&corpus).unwrap().to_owned(), outcome)
+        })
+        .collect();
+
+    results.sort_by(|(path_a, a), (path_b, b)| a.cmp(b).then(path_a.cmp(path_b)));
+
+    let report = report(&results);
+    println!("{}", report);
+
+    fs::create_dir_all("target").unwrap();
+    fs::write("target/corpus-report.txt", &report).unwrap();
+
+    assert!(
+        results.iter().all(|(_, outcome)| *outcome != Outcome::Panic),
+        "a file in the corpus triggered a panic"
+    );
+}
+
+/// Reads the meta data and all pixels of the file, including deep data.
+fn check_file(path: &Path) -> Outcome {
+    let result = catch_unwind(|| {
+        let prev_hook = panic::take_hook();
+        panic::set_hook(Box::new(|_| (/* do not println panics */)));
+
+        let result = (|| {
+            let meta = MetaData::read_from_file(path, false)?;
+
+            if meta.headers.iter().any(|header| header.deep) {
+                DeepReport::read_from_file(path)?;
+            }
+
+            if meta.headers.iter().any(|header| !header.deep) {
+                read().no_deep_data().all_resolution_levels().all_channels().all_layers().all_attributes()
+                    .from_file(path)?;
+            }
+
+            Ok(())
+        })();
+
+        panic::set_hook(prev_hook);
+        result
+    });
+
+    match result {
+        Ok(Ok(())) => Outcome::Ok,
+        Ok(Err(Error::NotSupported(message))) => Outcome::Unsupported(message.to_string()),
+        Ok(Err(Error::Invalid(message))) => Outcome::Invalid(message.to_string()),
+        Ok(Err(Error::Io(io))) => Outcome::Io(io.to_string()),
+        Ok(Err(Error::Aborted)) => panic!("reading produced `Error::Aborted`"),
+        Err(_) => Outcome::Panic,
+    }
+}
+
+fn report(results: &[(PathBuf, Outcome)]) -> String {
+    let count = |predicate: fn(&Outcome) -> bool| results.iter().filter(|(_, outcome)| predicate(outcome)).count();
+
+    let mut report = String::new();
+    writeln!(report, "OpenEXR corpus compatibility report").unwrap();
+    writeln!(report, "{} files, {} ok, {} unsupported, {} invalid, {} io errors, {} panics\n",
+        results.len(),
+        count(|outcome| *outcome == Outcome::Ok),
+        count(|outcome| matches!(outcome, Outcome::Unsupported(_))),
+        count(|outcome| matches!(outcome, Outcome::Invalid(_))),
+        count(|outcome| matches!(outcome, Outcome::Io(_))),
+        count(|outcome| *outcome == Outcome::Panic),
+    ).unwrap();
+
+    for (path, outcome) in results {
+        let (symbol, reason) = match outcome {
+            Outcome::Ok => ("✓", String::new()),
+            Outcome::Unsupported(message) => ("~", format!(" (not supported: {})", message)),
+            Outcome::Invalid(message) => ("✗", format!(" (invalid: {})", message)),
+            Outcome::Io(message) => ("✗", format!(" (io error: {})", message)),
+            Outcome::Panic => ("✗", " (panic)".to_owned()),
+        };
+
+        writeln!(report, "{} {}{}", symbol, path.display(), reason).unwrap();
+    }
+
+    report
+}
+
+/// The directory in `EXR_CORPUS`, or a fresh clone of the corpus repository.
+fn corpus_directory() -> PathBuf {
+    if let Some(directory) = std::env::var_os("EXR_CORPUS") {
+        return PathBuf::from(directory);
+    }
+
+    let directory = PathBuf::from("target/openexr-images");
+
+    if !directory.exists() {
+        println!("downloading the OpenEXR test images from {} ...", CORPUS_REPOSITORY);
+
+        let status = Command::new("git")
+            .args(&["clone", "--depth", "1", CORPUS_REPOSITORY])
+            .arg(&directory)
+            .status().expect("`git` is required to download the corpus, or set `EXR_CORPUS` to a local copy");
+
+        assert!(status.success(), "failed to clone the corpus, set `EXR_CORPUS` to a local copy instead");
+    }
+
+    directory
+}
@@ -11,7 +11,7 @@ use std::ffi::OsStr;
 use exr::prelude::*;
 use exr::error::{Error, UnitResult};
 use exr::prelude::pixel_vec::PixelVec;
-use exr::image::validate_results::ValidateResult;
+use exr::image::validate_results::{ValidateResult, ValidationOptions};
 use rayon::prelude::IntoParallelIterator;
 use rayon::iter::ParallelIterator;
 use exr::block::samples::IntoNativeSample;
@@ -43,7 +43,7 @@ fn round_trip_full(file: &[u8]) -> Result<()> {
 
     let image2 = read_image.from_buffered(Cursor::new(tmp_bytes))?;
 
-    image.assert_equals_result(&image2);
+    image.validate_result(&image2, ValidationOptions::bit_exact(), String::new).unwrap();
     Ok(())
 }
 
@@ -288,6 +288,60 @@ fn roundtrip_unusual_7() -> UnitResult {
     Ok(())
 }
 
+// stitched panorama plates may use windows at the very limits of the specification
+#[test]
+fn roundtrip_extreme_windows() -> UnitResult {
+    let limit = i32::MAX / 2; // coordinates must be strictly within this limit
+    let display_size = (2 * limit - 1) as usize;
+    let display_window = IntegerBounds::new((-limit + 1, -limit + 1), (display_size, display_size));
+    assert_eq!(display_window.max(), Vec2(limit - 1, limit - 1), "test is buggy");
+
+    let size = Vec2(37, 21);
+    let positions = [
+        Vec2(-limit + 1, -limit + 1),
+        Vec2(limit - size.width() as i32, limit - size.height() as i32),
+        Vec2(-limit + 1, limit - size.height() as i32),
+    ];
+
+    let compressions = [ Compression::Uncompressed, Compression::RLE, Compression::ZIP16, Compression::PIZ, Compression::PXR24, Compression::B44 ];
+
+    for &compression in &compressions {
+        for &blocks in &[ Blocks::ScanLines, Blocks::Tiles(Vec2(16, 16)) ] {
+            for &position in &positions {
+                let samples = (0 .. size.area()).map(|index| (index % 7) as f32).collect();
+                let channels = AnyChannels::sort(smallvec::smallvec![ AnyChannel::new("Y", FlatSamples::F32(samples)) ]);
+
+                let encoding = Encoding { compression, blocks, line_order: LineOrder::Increasing };
+                let attributes = LayerAttributes { layer_position: position, .. LayerAttributes::named("plate") };
+                let image = Image::new(ImageAttributes::new(display_window), Layer::new(size, attributes, encoding, channels));
+
+                let mut bytes = Vec::new();
+                image.write().to_buffered(Cursor::new(&mut bytes))?;
+
+                let image2 = read().no_deep_data().largest_resolution_level().all_channels().first_valid_layer().all_attributes()
+                    .pedantic().from_buffered(Cursor::new(&bytes))?;
+
+                assert_eq!(image2.attributes.display_window, display_window);
+                assert_eq!(image2.layer_data.absolute_bounds(), IntegerBounds::new(position, size));
+                assert_eq!(image2.layer_data.channel_data, image.layer_data.channel_data, "{:?} at {:?}", encoding, position);
+
+                let cropped = image2.layer_data.crop(IntegerBounds::new(position + Vec2(3, 4), (5, 6)));
+                assert_eq!(cropped.absolute_bounds(), IntegerBounds::new(position + Vec2(3, 4), (5, 6)));
+            }
+        }
+    }
+
+    // windows beyond the limit are refused instead of overflowing
+    for &position in &[ Vec2(limit - 1, 0), Vec2(0, -limit), Vec2(i32::MAX - 5, i32::MIN) ] {
+        let channels = AnyChannels::sort(smallvec::smallvec![ AnyChannel::new("Y", FlatSamples::F32(vec![ 0.0; size.area() ])) ]);
+        let attributes = LayerAttributes { layer_position: position, .. LayerAttributes::named("plate") };
+        let image = Image::new(ImageAttributes::new(display_window), Layer::new(size, attributes, Encoding::UNCOMPRESSED, channels));
+        assert!(image.write().to_buffered(Cursor::new(Vec::new())).is_err());
+    }
+
+    Ok(())
+}
+
 #[test]
 #[cfg(target_endian = "big")] // TODO big endian pxr24
 fn pxr24_expect_error_on_big_endian(){